@@ -201,6 +201,10 @@ pub fn fedimint_get_supported_events() -> Vec<String> {
         String::from("communityMetadataUpdated"),
         String::from("nonceReuseCheckFailed"),
         String::from("communityMigratedToV2"),
+        String::from("federationEvacuation"),
+        String::from("federationEndOfLife"),
+        String::from("guardianHealth"),
+        String::from("walletRepairNeeded"),
     ]
 }
 
@@ -46,6 +46,9 @@ use multispend::{
 use rpc_types::communities::RpcCommunity;
 use rpc_types::error::{ErrorCode, RpcError};
 use rpc_types::event::{Event, EventSink, PanicEvent, SocialRecoveryEvent, TypedEventExt};
+use rpc_types::lnurl::{
+    RpcLnurlPayParams, RpcLnurlPayResponse, RpcLnurlPayerData, RpcLnurlWithdrawParams,
+};
 use rpc_types::matrix::{
     RpcBackPaginationStatus, RpcComposerDraft, RpcMatrixAccountSession, RpcMatrixInitializeStatus,
     RpcMatrixUploadResult, RpcMatrixUserDirectorySearchResponse, RpcPublicRoomInfo, RpcRoomId,
@@ -53,26 +56,30 @@ use rpc_types::matrix::{
     RpcSyncIndicator, RpcTimelineEventItemId, RpcTimelineItem, RpcUserId,
 };
 use rpc_types::nostril::{RpcNostrPubkey, RpcNostrSecret};
+use rpc_types::payment_string::RpcParsedPaymentString;
 use rpc_types::sp_transfer::{RpcAccountId, RpcSpTransferState, SpMatrixTransferId};
 use rpc_types::spv2_transfer_meta::Spv2TransferTxMeta;
 use rpc_types::{
-    FrontendMetadata, GuardianStatus, NetworkError, RpcAmount, RpcAppFlavor, RpcEcashInfo,
-    RpcEventId, RpcFederation, RpcFederationId, RpcFederationMaybeLoading, RpcFederationPreview,
-    RpcFediFeeStream, RpcFeeDetails, RpcFiatAmount, RpcGenerateEcashResponse,
-    RpcGuardianRemittanceAccountInfo, RpcGuardianRemittanceDashboard, RpcInvoice,
-    RpcLightningGateway, RpcLightningGatewayId, RpcMediaUploadParams, RpcOperationId,
-    RpcParseInviteCodeResult, RpcPayInvoiceResponse, RpcPeerId, RpcPrevPayInvoiceResult,
-    RpcPublicKey, RpcReclaimLnReceiveOutcome, RpcRecoveryId, RpcRegisteredDevice,
+    FrontendMetadata, GuardianStatus, NetworkError, RpcAmount, RpcAppFlavor, RpcBolt12Offer,
+    RpcEcashInfo, RpcEvacuationTarget, RpcEventId, RpcFederation, RpcFederationId,
+    RpcFederationMaybeLoading, RpcFederationPreview, RpcFederationTransfer, RpcFediFeeStream,
+    RpcFeeDetails, RpcFeeStatement, RpcFeeStatementPeriod, RpcFiatAmount, RpcGenerateEcashResponse,
+    RpcGuardianHealthHistory, RpcGuardianRemittanceAccountInfo, RpcGuardianRemittanceDashboard,
+    RpcLightningGateway, RpcLightningGatewayId, RpcMediaUploadParams, RpcOnchainFeeQuote,
+    RpcOnchainFeeTier, RpcOperationId, RpcParseInviteCodeResult, RpcParsedInvoice,
+    RpcPayInvoiceResponse, RpcPeerId, RpcPrevPayInvoiceResult, RpcPublicKey,
+    RpcReclaimLnReceiveOutcome, RpcRecoveryId, RpcRecoveryProgress, RpcRegisteredDevice,
     RpcSPv2CachedSyncResponse, RpcSPv2SyncResponse, RpcSignature, RpcSignedLnurlMessage,
-    RpcStabilityPoolAccountInfo, RpcTransaction, RpcTransactionDirection, RpcTransactionListEntry,
-    SocialRecoveryQr,
+    RpcStabilityPoolAccountInfo, RpcTransaction, RpcTransactionDirection,
+    RpcTransactionHistoryExport, RpcTransactionHistoryFormat, RpcTransactionListEntry,
+    RpcTransactionSearchFilter, RpcUnifiedReceive, RpcWalletRepairReport, SocialRecoveryQr,
 };
 use runtime::api::{IFediApi, LiveFediApi, MockFediApi};
 use runtime::bridge_runtime::Runtime;
 use runtime::event::IEventSink;
 use runtime::features::{FeatureCatalog, RuntimeEnvironment};
 use runtime::rpc_stream::{RpcStreamId, RpcVecDiffStreamId};
-use runtime::storage::state::FiatFXInfo;
+use runtime::storage::state::{FiatFXInfo, SpendingLimits};
 use runtime::storage::{BRIDGE_DB_PREFIX, OnboardingCompletionMethod, Storage};
 use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
@@ -312,6 +319,23 @@ async fn getGuardianStatus(federation: Arc<FederationV2>) -> anyhow::Result<Vec<
     federation.guardian_status().await
 }
 
+/// Guardian reachability over the last week, checked every few minutes.
+#[macro_rules_derive(federation_rpc_method!)]
+async fn getGuardianHealthHistory(
+    federation: Arc<FederationV2>,
+) -> anyhow::Result<RpcGuardianHealthHistory> {
+    Ok(federation.get_guardian_health_history().await)
+}
+
+/// Sessions each module processed while the federation is recovering, `null`
+/// once recovery completed. Also sent as part of the `federation` event.
+#[macro_rules_derive(federation_recovering_rpc_method!)]
+async fn getRecoveryProgress(
+    federation: Arc<FederationV2>,
+) -> anyhow::Result<Option<RpcRecoveryProgress>> {
+    Ok(federation.get_recovery_progress().await)
+}
+
 #[macro_rules_derive(rpc_method!)]
 pub(crate) async fn joinFederation(
     bridge: &BridgeFull,
@@ -367,6 +391,31 @@ async fn leaveFederation(
     federations.leave_federation(&federation_id.0).await
 }
 
+/// Withdraws the stable balance, waits for pending operations, sweeps the
+/// balance to `target` and then leaves the federation. Returns once started;
+/// progress is reported with `federationEvacuation` events.
+#[macro_rules_derive(rpc_method!)]
+async fn evacuateFederation(
+    bridge: &BridgeFull,
+    federation_id: RpcFederationId,
+    target: RpcEvacuationTarget,
+) -> anyhow::Result<()> {
+    bridge
+        .federations
+        .evacuate_federation(&federation_id.0, target)
+        .await
+}
+
+/// Whether to evacuate into the successor the federation announced once its
+/// final day starts, see `RpcFederation::end_of_life`.
+#[macro_rules_derive(federation_rpc_method!)]
+async fn setFederationAutoEvacuate(
+    federation: Arc<FederationV2>,
+    enabled: bool,
+) -> anyhow::Result<()> {
+    federation.set_auto_evacuate(enabled).await
+}
+
 // TODO: generateInvoice should return OperationId
 // so frontend can subscribe to the operation.
 // TODO: actually return the RpcInvoice (frontend expects string)
@@ -389,11 +438,46 @@ async fn generateInvoice(
     Ok(invoice.to_string())
 }
 
+/// One receive request that can be paid over lightning, onchain or, with
+/// `includeStableBalanceAddress`, into the stable balance. Once one leg is
+/// paid the others show up as superseded in transaction history.
+#[macro_rules_derive(federation_rpc_method!)]
+async fn generateUnifiedReceive(
+    federation: Arc<FederationV2>,
+    amount: RpcAmount,
+    description: String,
+    include_stable_balance_address: bool,
+    frontend_metadata: FrontendMetadata,
+) -> anyhow::Result<RpcUnifiedReceive> {
+    federation
+        .generate_unified_receive(
+            amount,
+            description,
+            include_stable_balance_address,
+            frontend_metadata,
+        )
+        .await
+}
+
 #[macro_rules_derive(rpc_method!)]
-async fn parseInvoice(_runtime: Arc<Runtime>, invoice: String) -> anyhow::Result<RpcInvoice> {
-    let invoice: Bolt11Invoice = invoice.trim().parse().context(ErrorCode::InvalidInvoice)?;
-    let bridge_invoice = RpcInvoice::try_from(invoice)?;
-    Ok(bridge_invoice)
+async fn parseInvoice(_runtime: Arc<Runtime>, invoice: String) -> anyhow::Result<RpcParsedInvoice> {
+    let invoice = invoice.trim();
+    let lowercase = invoice.to_lowercase();
+    if lowercase
+        .strip_prefix("lightning:")
+        .unwrap_or(&lowercase)
+        .starts_with("lno1")
+    {
+        let offer = invoice.parse().context(ErrorCode::InvalidInvoice)?;
+        return Ok(RpcParsedInvoice::Offer(offer));
+    }
+    let invoice: Bolt11Invoice = invoice.parse().context(ErrorCode::InvalidInvoice)?;
+    Ok(RpcParsedInvoice::from(invoice))
+}
+
+#[macro_rules_derive(rpc_method!)]
+async fn parseOffer(_runtime: Arc<Runtime>, offer: String) -> anyhow::Result<RpcBolt12Offer> {
+    offer.parse().context(ErrorCode::InvalidInvoice)
 }
 
 #[macro_rules_derive(federation_rpc_method!)]
@@ -405,14 +489,23 @@ async fn estimateLnFees(
     federation.estimate_ln_fees(&invoice).await
 }
 
+/// Gateways are tried best first, see `RpcLightningGateway::score`, until one
+/// routes the payment. Those charging more than `max_network_fee` are skipped.
 #[macro_rules_derive(federation_rpc_method!)]
 async fn payInvoice(
     federation: Arc<FederationV2>,
     invoice: String,
+    max_network_fee: Option<RpcAmount>,
     frontend_metadata: FrontendMetadata,
 ) -> anyhow::Result<RpcPayInvoiceResponse> {
     let invoice: Bolt11Invoice = invoice.trim().parse().context(ErrorCode::InvalidInvoice)?;
-    federation.pay_invoice(&invoice, frontend_metadata).await
+    federation
+        .pay_invoice(
+            &invoice,
+            max_network_fee.map(|fee| fee.0),
+            frontend_metadata,
+        )
+        .await
 }
 
 #[macro_rules_derive(federation_rpc_method!)]
@@ -480,30 +573,57 @@ async fn reclaimLnReceive(
     federation.reclaim_ln_receive(operation_id.0).await
 }
 
+/// `fee_tier` defaults to economy, the federation's own fee estimate.
 #[macro_rules_derive(federation_rpc_method!)]
 async fn previewPayAddress(
     federation: Arc<FederationV2>,
     address: String,
     // TODO: parse this as bitcoin::Amount
     sats: u64,
+    fee_tier: Option<RpcOnchainFeeTier>,
 ) -> anyhow::Result<RpcFeeDetails> {
     let address = address.trim().parse().context("Invalid Bitcoin Address")?;
     let amount: Amount = Amount::from_sat(sats);
-    federation.preview_pay_address(address, amount).await
+    federation
+        .preview_pay_address(address, amount, fee_tier.unwrap_or_default())
+        .await
+}
+
+/// Fee quotes with estimated confirmation times for every tier the balance
+/// covers, cheapest first.
+#[macro_rules_derive(federation_rpc_method!)]
+async fn previewPayAddressFeeTiers(
+    federation: Arc<FederationV2>,
+    address: String,
+    // TODO: parse this as bitcoin::Amount
+    sats: u64,
+) -> anyhow::Result<Vec<RpcOnchainFeeQuote>> {
+    let address = address.trim().parse().context("Invalid Bitcoin Address")?;
+    let amount: Amount = Amount::from_sat(sats);
+    federation
+        .preview_pay_address_fee_tiers(address, amount)
+        .await
 }
 
+/// `fee_tier` defaults to economy, the federation's own fee estimate.
 #[macro_rules_derive(federation_rpc_method!)]
 async fn payAddress(
     federation: Arc<FederationV2>,
     address: String,
     // TODO: parse this as bitcoin::Amount
     sats: u64,
+    fee_tier: Option<RpcOnchainFeeTier>,
     frontend_metadata: FrontendMetadata,
 ) -> anyhow::Result<RpcOperationId> {
     let address = address.trim().parse().context("Invalid Bitcoin Address")?;
     let amount: Amount = Amount::from_sat(sats);
     federation
-        .pay_address(address, amount, frontend_metadata)
+        .pay_address(
+            address,
+            amount,
+            fee_tier.unwrap_or_default(),
+            frontend_metadata,
+        )
         .await
         .map(Into::into)
 }
@@ -526,10 +646,16 @@ async fn generateEcash(
     federation: Arc<FederationV2>,
     amount: RpcAmount,
     include_invite: bool,
+    expiry: Option<u32>,
     frontend_metadata: FrontendMetadata,
 ) -> anyhow::Result<RpcGenerateEcashResponse> {
     federation
-        .generate_ecash(amount.0, include_invite, frontend_metadata)
+        .generate_ecash(
+            amount.0,
+            include_invite,
+            expiry.map(Into::into),
+            frontend_metadata,
+        )
         .await
 }
 
@@ -562,6 +688,14 @@ async fn parseInviteCode(
     })
 }
 
+#[macro_rules_derive(rpc_method!)]
+async fn parsePaymentString(
+    federations: &Federations,
+    payment_string: String,
+) -> anyhow::Result<RpcParsedPaymentString> {
+    federations.parse_payment_string(&payment_string).await
+}
+
 #[macro_rules_derive(rpc_method!)]
 async fn updateCachedFiatFXInfo(
     runtime: Arc<Runtime>,
@@ -602,6 +736,22 @@ async fn listTransactions(
     Ok(txs)
 }
 
+#[macro_rules_derive(federation_rpc_method!)]
+async fn searchTransactions(
+    federation: Arc<FederationV2>,
+    filter: RpcTransactionSearchFilter,
+    start_after: Option<RpcOperationId>,
+    limit: Option<u32>,
+) -> anyhow::Result<Vec<Result<RpcTransactionListEntry, String>>> {
+    federation
+        .search_transactions(
+            &filter,
+            limit.map_or(usize::MAX, |l| l as usize),
+            start_after.map(|id| id.0),
+        )
+        .await
+}
+
 #[macro_rules_derive(federation_rpc_method!)]
 async fn cancelEcash(
     federation: Arc<FederationV2>,
@@ -616,6 +766,13 @@ async fn repairWallet(federation: Arc<FederationV2>) -> anyhow::Result<()> {
     federation.repair_wallet().await
 }
 
+/// Backs up proofs of reused ecash, repairs the wallet and reports how the
+/// balance changed. Answers the `walletRepairNeeded` event.
+#[macro_rules_derive(federation_rpc_method!)]
+async fn runWalletRepair(federation: Arc<FederationV2>) -> anyhow::Result<RpcWalletRepairReport> {
+    federation.run_wallet_repair().await
+}
+
 #[macro_rules_derive(federation_rpc_method!)]
 async fn updateTransactionNotes(
     federation: Arc<FederationV2>,
@@ -627,6 +784,67 @@ async fn updateTransactionNotes(
         .await
 }
 
+#[macro_rules_derive(rpc_method!)]
+async fn exportTransactionHistory(
+    federations: &Federations,
+    format: RpcTransactionHistoryFormat,
+) -> anyhow::Result<RpcTransactionHistoryExport> {
+    federations.export_transaction_history(format).await
+}
+
+/// Global limits when `federationId` is null, otherwise those of the given
+/// federation alone.
+#[macro_rules_derive(rpc_method!)]
+async fn getSpendingLimits(
+    federations: &Federations,
+    federation_id: Option<RpcFederationId>,
+) -> anyhow::Result<SpendingLimits> {
+    federations
+        .get_spending_limits(federation_id.as_ref().map(|id| id.0.as_str()))
+        .await
+}
+
+/// Allows a single `setSpendingLimits` call that loosens limits within the
+/// next few minutes. The frontend asks for the user's PIN before calling this.
+#[macro_rules_derive(rpc_method!)]
+async fn unlockSpendingLimits(federations: &Federations) -> anyhow::Result<()> {
+    federations.unlock_spending_limits();
+    Ok(())
+}
+
+#[macro_rules_derive(rpc_method!)]
+async fn setSpendingLimits(
+    federations: &Federations,
+    federation_id: Option<RpcFederationId>,
+    limits: SpendingLimits,
+) -> anyhow::Result<()> {
+    federations
+        .set_spending_limits(federation_id.as_ref().map(|id| id.0.as_str()), limits)
+        .await
+}
+
+/// Starts paying `amount` from one joined federation into another. Both legs
+/// show up in transaction history, linked by the returned transfer id.
+#[macro_rules_derive(rpc_method!)]
+async fn transferBetweenFederations(
+    federations: &Federations,
+    from_federation_id: RpcFederationId,
+    to_federation_id: RpcFederationId,
+    amount: RpcAmount,
+) -> anyhow::Result<RpcFederationTransfer> {
+    federations
+        .transfer_between_federations(&from_federation_id.0, &to_federation_id.0, amount.0)
+        .await
+}
+
+#[macro_rules_derive(rpc_method!)]
+async fn getFederationTransfer(
+    federations: &Federations,
+    transfer_id: String,
+) -> anyhow::Result<RpcFederationTransfer> {
+    federations.get_federation_transfer(&transfer_id).await
+}
+
 #[macro_rules_derive(rpc_method!)]
 async fn getMnemonic(runtime: Arc<Runtime>) -> anyhow::Result<Vec<String>> {
     runtime.get_mnemonic_words().await
@@ -764,6 +982,62 @@ async fn signLnurlMessage(
     runtime.sign_lnurl_message(message, domain).await
 }
 
+#[macro_rules_derive(rpc_method!)]
+async fn previewLnurlPay(
+    federations: &Federations,
+    lnurl: String,
+) -> anyhow::Result<RpcLnurlPayParams> {
+    federations.preview_lnurl_pay(&lnurl).await
+}
+
+#[macro_rules_derive(rpc_method!)]
+async fn payLnurl(
+    federations: &Federations,
+    federation_id: RpcFederationId,
+    lnurl: String,
+    amount: RpcAmount,
+    comment: Option<String>,
+    payer_data: Option<RpcLnurlPayerData>,
+    frontend_metadata: FrontendMetadata,
+) -> anyhow::Result<RpcLnurlPayResponse> {
+    federations
+        .pay_lnurl(
+            &federation_id.0,
+            &lnurl,
+            amount.0,
+            comment,
+            payer_data.unwrap_or_default(),
+            frontend_metadata,
+        )
+        .await
+}
+
+#[macro_rules_derive(rpc_method!)]
+async fn previewLnurlWithdraw(
+    federations: &Federations,
+    lnurl: String,
+) -> anyhow::Result<RpcLnurlWithdrawParams> {
+    federations.preview_lnurl_withdraw(&lnurl).await
+}
+
+#[macro_rules_derive(rpc_method!)]
+async fn claimLnurlWithdraw(
+    federations: &Federations,
+    federation_id: RpcFederationId,
+    lnurl: String,
+    amount: Option<RpcAmount>,
+    frontend_metadata: FrontendMetadata,
+) -> anyhow::Result<String> {
+    federations
+        .claim_lnurl_withdraw(
+            &federation_id.0,
+            &lnurl,
+            amount.map(|amount| amount.0),
+            frontend_metadata,
+        )
+        .await
+}
+
 #[macro_rules_derive(federation_rpc_method!)]
 async fn supportsRecurringdLnurl(federation: Arc<FederationV2>) -> anyhow::Result<bool> {
     Ok(federation.supports_recurringd_lnurl().await)
@@ -1105,20 +1379,7 @@ async fn spv2OurPaymentAddress(
     federation: Arc<FederationV2>,
     include_invite: bool,
 ) -> anyhow::Result<String> {
-    let federation_invite = if include_invite {
-        federation.get_invite_code().await.parse().ok()
-    } else {
-        None
-    };
-    let address = Spv2PaymentAddress {
-        account_id: federation
-            .client
-            .spv2()?
-            .our_account(AccountType::Seeker)
-            .id(),
-        federation_id_prefix: federation.federation_id().to_prefix(),
-        federation_invite,
-    };
+    let address = federation.spv2_payment_address(include_invite).await?;
     Ok(address.to_string())
 }
 
@@ -1211,6 +1472,18 @@ async fn setSensitiveLog(runtime: Arc<Runtime>, enable: bool) -> anyhow::Result<
     runtime.set_sensitive_log(enable).await
 }
 
+#[macro_rules_derive(rpc_method!)]
+async fn getEsploraUrl(runtime: Arc<Runtime>) -> anyhow::Result<Option<String>> {
+    Ok(runtime.esplora_url().await)
+}
+
+/// Esplora API to follow onchain withdrawals with, `null` to stop looking
+/// them up.
+#[macro_rules_derive(rpc_method!)]
+async fn setEsploraUrl(runtime: Arc<Runtime>, esplora_url: Option<String>) -> anyhow::Result<()> {
+    runtime.set_esplora_url(esplora_url).await
+}
+
 #[macro_rules_derive(rpc_method!)]
 async fn internalMarkBridgeExport(runtime: Arc<Runtime>) -> anyhow::Result<()> {
     runtime.app_state.set_internal_bridge_export(true).await
@@ -1338,6 +1611,21 @@ async fn getAccruedPendingFediFeesPerTXTypeByStream(
         .collect())
 }
 
+/// Fees of `stream` accrued and remitted within `[from, to)`, in unix seconds.
+#[macro_rules_derive(federation_rpc_method!)]
+async fn getFeeStatement(
+    federation: Arc<FederationV2>,
+    stream: RpcFediFeeStream,
+    from: u32,
+    to: u32,
+    period: RpcFeeStatementPeriod,
+) -> anyhow::Result<RpcFeeStatement> {
+    anyhow::ensure!(from <= to, "statement must not end before it starts");
+    Ok(federation
+        .get_fee_statement(stream.into(), from.into(), to.into(), period)
+        .await)
+}
+
 #[macro_rules_derive(rpc_method!)]
 async fn dumpDb(
     bridge: &BridgeFull,
@@ -2600,12 +2888,18 @@ rpc_methods!(RpcMethods {
     joinFederation,
     federationPreview,
     leaveFederation,
+    evacuateFederation,
+    setFederationAutoEvacuate,
     listFederations,
     getGuardianStatus,
+    getGuardianHealthHistory,
+    getRecoveryProgress,
     listFederationsPendingRejoinFromScratch,
     // Lightning
     generateInvoice,
+    generateUnifiedReceive,
     parseInvoice,
+    parseOffer,
     estimateLnFees,
     payInvoice,
     getPrevPayInvoiceResult,
@@ -2618,6 +2912,7 @@ rpc_methods!(RpcMethods {
     getPegInFees,
     recheckPeginAddress,
     previewPayAddress,
+    previewPayAddressFeeTiers,
     payAddress,
     // Ecash
     calculateMaxGenerateEcash,
@@ -2626,14 +2921,25 @@ rpc_methods!(RpcMethods {
     receiveEcash,
     parseEcash,
     parseInviteCode,
+    parsePaymentString,
     cancelEcash,
     repairWallet,
+    runWalletRepair,
     reclaimLnReceive,
     // Transactions
     updateCachedFiatFXInfo,
     listTransactions,
+    searchTransactions,
     getTransaction,
     updateTransactionNotes,
+    exportTransactionHistory,
+    // Spending limits
+    getSpendingLimits,
+    unlockSpendingLimits,
+    setSpendingLimits,
+    // Federation transfers
+    transferBetweenFederations,
+    getFederationTransfer,
     // Recovery
     backupNow,
     getMnemonic,
@@ -2656,6 +2962,10 @@ rpc_methods!(RpcMethods {
     getGuardianPassword,
     // LNURL
     signLnurlMessage,
+    previewLnurlPay,
+    payLnurl,
+    previewLnurlWithdraw,
+    claimLnurlWithdraw,
     supportsRecurringdLnurl,
     getRecurringdLnurl,
     // Nostr
@@ -2702,6 +3012,8 @@ rpc_methods!(RpcMethods {
     // Developer
     getSensitiveLog,
     setSensitiveLog,
+    getEsploraUrl,
+    setEsploraUrl,
     internalMarkBridgeExport,
     internalExportBridgeState,
     setMintModuleFediFeeSchedule,
@@ -2711,6 +3023,7 @@ rpc_methods!(RpcMethods {
     setSPv2ModuleFediFeeSchedule,
     getAccruedOutstandingFediFeesPerTXTypeByStream,
     getAccruedPendingFediFeesPerTXTypeByStream,
+    getFeeStatement,
     dumpDb,
 
     // Device Registration
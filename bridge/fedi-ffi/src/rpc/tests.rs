@@ -30,10 +30,13 @@ use fedimint_logging::TracingSetup;
 use nostr::nips::nip44;
 use rpc_types::communities::{CommunityInvite, CommunityInviteV1};
 use rpc_types::event::TransactionEvent;
+use rpc_types::lnurl::RpcLnurlSuccessAction;
 use rpc_types::{
-    RpcEcashInfo, RpcLightningGatewayId, RpcLnPayState, RpcLnReceiveState, RpcOOBReissueState,
-    RpcOnchainDepositState, RpcOnchainWithdrawState, RpcReturningMemberStatus,
+    RpcEcashInfo, RpcEvacuationTarget, RpcGuardianHealthLevel, RpcInvoice, RpcLightningGatewayId,
+    RpcLnPayState, RpcLnReceiveState, RpcOOBReissueState, RpcOOBSpendState, RpcOnchainDepositState,
+    RpcOnchainFeeTier, RpcOnchainWithdrawState, RpcReceiveSource, RpcReturningMemberStatus,
     RpcSPV2TransferInState, RpcTransactionDirection, RpcTransactionKind,
+    RpcTransactionSearchFilter,
 };
 use runtime::constants::{
     COMMUNITY_V1_TO_V2_MIGRATION_KEY, FEDI_FILE_V0_PATH, MILLION, REISSUE_ECASH_TIMEOUT,
@@ -41,7 +44,7 @@ use runtime::constants::{
 use runtime::db::BridgeDbPrefix;
 use runtime::envs::USE_UPSTREAM_FEDIMINTD_ENV;
 use runtime::storage::BRIDGE_DB_PREFIX;
-use runtime::storage::state::CommunityJson;
+use runtime::storage::state::{CommunityJson, SpendingLimit};
 use stability_pool_client::common::Account;
 use tokio::sync::Semaphore;
 use tokio::task::JoinSet;
@@ -282,6 +285,7 @@ async fn tests_wrapper_for_bridge() -> anyhow::Result<()> {
     let tests = tests_array![
         test_join_and_leave_and_join,
         test_join_concurrent,
+        test_evacuate_empty_federation,
         matrix::test_matrix_login,
         matrix::test_matrix_access_token_expiry_repro,
         matrix::test_matrix_dms,
@@ -298,6 +302,9 @@ async fn tests_wrapper_for_bridge() -> anyhow::Result<()> {
         sp_transfer_tests::test_receiver_joins_federation_later,
         test_lightning_send_and_receive,
         test_lnurl_receive,
+        test_lnurl_pay,
+        test_lnurl_withdraw,
+        test_parse_payment_string,
         test_ecash,
         test_ecash_duplicate_receive_rejected,
         test_ecash_overissue,
@@ -305,6 +312,12 @@ async fn tests_wrapper_for_bridge() -> anyhow::Result<()> {
         test_on_chain_v2,
         test_walletv2_awaiting_deposit,
         test_ecash_cancel,
+        test_ecash_expiry,
+        test_ecash_receive_queue,
+        test_spending_limits,
+        test_federation_transfer_rejects_same_federation,
+        test_guardian_health_history,
+        test_wallet_repair,
         test_backup_and_recovery,
         test_backup_and_recovery_from_scratch,
         test_parse_ecash,
@@ -446,6 +459,59 @@ async fn test_join_and_leave_and_join(_dev_fed: DevFed) -> anyhow::Result<()> {
     Ok(())
 }
 
+async fn test_evacuate_empty_federation(_dev_fed: DevFed) -> anyhow::Result<()> {
+    let td = TestDevice::new().await?;
+    let bridge = td.bridge_full().await?;
+    // joined directly, as leaving waits for every handle to be dropped
+    let invite_code = std::env::var("FM_INVITE_CODE").unwrap();
+    let federation_id = joinFederation(bridge, invite_code, false).await?.id;
+
+    assert!(
+        evacuateFederation(
+            bridge,
+            federation_id.clone(),
+            RpcEvacuationTarget::Federation {
+                federation_id: federation_id.clone(),
+            },
+        )
+        .await
+        .is_err()
+    );
+
+    // nothing to sweep, so the federation is left right away
+    let address = bitcoin_cli_new_address().await?;
+    evacuateFederation(
+        bridge,
+        federation_id.clone(),
+        RpcEvacuationTarget::Onchain {
+            address: address.clone(),
+        },
+    )
+    .await?;
+    // the first evacuation is still leaving
+    assert!(
+        evacuateFederation(
+            bridge,
+            federation_id,
+            RpcEvacuationTarget::Onchain { address },
+        )
+        .await
+        .is_err()
+    );
+    devimint::util::poll("waiting for the federation to be left", || async {
+        let federations = listFederations(&bridge.federations)
+            .await
+            .map_err(ControlFlow::Break)?;
+        if federations.is_empty() {
+            Ok(())
+        } else {
+            Err(ControlFlow::Continue(anyhow!("federation not left yet")))
+        }
+    })
+    .await?;
+    Ok(())
+}
+
 async fn test_join_concurrent(_dev_fed: DevFed) -> anyhow::Result<()> {
     let mut tb = TestDevice::new().await?;
     let federation_id;
@@ -661,6 +727,7 @@ async fn test_lightning_send_and_receive_with_fedi_fees(
     payInvoice(
         federation.clone(),
         invoice.to_string(),
+        None,
         FrontendMetadata::default(),
     )
     .await?;
@@ -1273,6 +1340,7 @@ async fn test_ecash_with_fedi_fees(
                 federation.clone(),
                 RpcAmount(ecash_receive_amount),
                 false,
+                None,
                 FrontendMetadata::default()
             )
             .await
@@ -1296,6 +1364,7 @@ async fn test_ecash_with_fedi_fees(
         federation.clone(),
         RpcAmount(ecash_send_amount),
         false,
+        None,
         FrontendMetadata::default(),
     )
     .await?
@@ -1491,13 +1560,14 @@ async fn test_ecash_overissue(_dev_fed: DevFed) -> anyhow::Result<()> {
     let fedi_fee_ppm = bridge
         .federations
         .fedi_fee_helper
-        .get_fee_ppm(
+        .get_fee_rule(
             FediFeeStream::App,
             federation.rpc_federation_id().0,
             fedimint_mint_client::KIND,
             RpcTransactionDirection::Send,
         )
-        .await?;
+        .await?
+        .ppm;
     let iterations = 100;
     let iteration_amount = Amount::from_msats(ecash_receive_amount.msats / (iterations * 2));
     let iteration_expected_fee =
@@ -1508,6 +1578,7 @@ async fn test_ecash_overissue(_dev_fed: DevFed) -> anyhow::Result<()> {
             federation.clone(),
             RpcAmount(iteration_amount),
             false,
+            None,
             FrontendMetadata::default(),
         )
         .await
@@ -1614,6 +1685,7 @@ async fn test_on_chain_v2(_dev_fed: DevFed) -> anyhow::Result<()> {
         federation.clone(),
         withdraw_address.clone(),
         withdraw_amount.sats_round_down(),
+        None,
     )
     .await?;
     assert!(
@@ -1621,10 +1693,38 @@ async fn test_on_chain_v2(_dev_fed: DevFed) -> anyhow::Result<()> {
         "walletv2 send_fee must report a non-zero network fee"
     );
 
+    let quotes = previewPayAddressFeeTiers(
+        federation.clone(),
+        withdraw_address.clone(),
+        withdraw_amount.sats_round_down(),
+    )
+    .await?;
+    assert_eq!(
+        quotes.iter().map(|quote| quote.tier).collect::<Vec<_>>(),
+        [
+            RpcOnchainFeeTier::Economy,
+            RpcOnchainFeeTier::Normal,
+            RpcOnchainFeeTier::Priority
+        ]
+    );
+    assert_eq!(quotes[0].fee_details.network_fee, preview.network_fee);
+    assert!(
+        quotes
+            .windows(2)
+            .all(|pair| { pair[0].fee_details.network_fee.0 < pair[1].fee_details.network_fee.0 })
+    );
+    // no esplora was chosen to estimate confirmation times with
+    assert!(
+        quotes
+            .iter()
+            .all(|quote| quote.estimated_confirmation_mins.is_none())
+    );
+
     payAddress(
         federation.clone(),
         withdraw_address.clone(),
         withdraw_amount.sats_round_down(),
+        Some(RpcOnchainFeeTier::Priority),
         FrontendMetadata::default(),
     )
     .await?;
@@ -2015,6 +2115,7 @@ async fn test_ecash_cancel(_dev_fed: DevFed) -> anyhow::Result<()> {
         federation.clone(),
         RpcAmount(Amount::from_msats(ecash_receive_amount.msats / 2)),
         false,
+        None,
         FrontendMetadata::default(),
     )
     .await?
@@ -2025,6 +2126,253 @@ async fn test_ecash_cancel(_dev_fed: DevFed) -> anyhow::Result<()> {
     Ok(())
 }
 
+async fn test_ecash_expiry(_dev_fed: DevFed) -> anyhow::Result<()> {
+    let td = TestDevice::new().await?;
+    let federation = td.join_default_fed().await?;
+
+    let ecash = cli_generate_ecash(Amount::from_msats(10_000)).await?;
+    let ecash_receive_amount = amount_from_ecash(ecash.clone()).await?;
+    receiveEcash(federation.clone(), ecash, FrontendMetadata::default()).await?;
+    wait_for_ecash_reissue(federation.as_ref()).await?;
+    balance_after_receiving_ecash(federation.as_ref(), ecash_receive_amount).await;
+
+    // never claimed, so it must come back on its own
+    let send = generateEcash(
+        federation.clone(),
+        RpcAmount(Amount::from_msats(ecash_receive_amount.msats / 2)),
+        false,
+        Some(1),
+        FrontendMetadata::default(),
+    )
+    .await?;
+    devimint::util::poll("waiting for expired ecash to be reclaimed", || async {
+        let transaction = federation
+            .get_transaction(send.operation_id.0)
+            .await
+            .map_err(ControlFlow::Continue)?;
+        match transaction.kind {
+            RpcTransactionKind::OobSend {
+                state: Some(RpcOOBSpendState::UserCanceledSuccess | RpcOOBSpendState::Refunded),
+                ..
+            } => Ok(()),
+            _ => Err(ControlFlow::Continue(anyhow!("not reclaimed yet"))),
+        }
+    })
+    .await?;
+    balance_after_receiving_ecash(federation.as_ref(), ecash_receive_amount).await;
+    Ok(())
+}
+
+async fn test_ecash_receive_queue(_dev_fed: DevFed) -> anyhow::Result<()> {
+    // mintv2 notes can't be verified offline, so they are never queued
+    if devimint::util::supports_mint_v2() {
+        let td = TestDevice::new().await?;
+        let federation = td.join_default_fed().await?;
+        let ecash = cli_generate_ecash(Amount::from_msats(10_000)).await?;
+        assert!(
+            federation
+                .queue_ecash_receive(ecash, FrontendMetadata::default())
+                .await?
+                .is_none()
+        );
+        return Ok(());
+    }
+
+    let td = TestDevice::new().await?;
+    let federation = td.join_default_fed().await?;
+
+    // queue as receiveEcash does when the guardians can't be reached; the
+    // federation is up, so the service drains it right away
+    let ecash = cli_generate_ecash(Amount::from_msats(10_000)).await?;
+    let ecash_receive_amount = amount_from_ecash(ecash.clone()).await?;
+    let (amount, queued_id) = federation
+        .queue_ecash_receive(ecash.clone(), FrontendMetadata::default())
+        .await?
+        .expect("mint notes can be queued");
+    assert_eq!(amount, ecash_receive_amount);
+    wait_for_ecash_reissue(federation.as_ref()).await?;
+    balance_after_receiving_ecash(federation.as_ref(), ecash_receive_amount).await;
+    assert!(federation.get_transaction(queued_id).await.is_err());
+    let searched = federation
+        .search_transactions(&RpcTransactionSearchFilter::default(), usize::MAX, None)
+        .await?;
+    assert!(
+        searched
+            .iter()
+            .flatten()
+            .all(|entry| entry.transaction.id != queued_id.fmt_full().to_string()),
+        "drained receive must leave the transaction index"
+    );
+
+    // the notes are spent now, so the federation rejects them
+    let (_, queued_id) = federation
+        .queue_ecash_receive(ecash, FrontendMetadata::default())
+        .await?
+        .expect("mint notes can be queued");
+    devimint::util::poll("waiting for queued receive to be rejected", || async {
+        let transaction = federation
+            .get_transaction(queued_id)
+            .await
+            .map_err(ControlFlow::Break)?;
+        match transaction.kind {
+            RpcTransactionKind::OobReceive {
+                state: Some(RpcOOBReissueState::Failed { .. }),
+            } => Ok(()),
+            _ => Err(ControlFlow::Continue(anyhow!("not rejected yet"))),
+        }
+    })
+    .await?;
+    balance_after_receiving_ecash(federation.as_ref(), ecash_receive_amount).await;
+    Ok(())
+}
+
+async fn test_spending_limits(_dev_fed: DevFed) -> anyhow::Result<()> {
+    let td = TestDevice::new().await?;
+    let (bridge, federation) = (td.bridge_full().await?, td.join_default_fed().await?);
+
+    let ecash = cli_generate_ecash(Amount::from_msats(10_000)).await?;
+    let ecash_receive_amount = amount_from_ecash(ecash.clone()).await?;
+    receiveEcash(federation.clone(), ecash, FrontendMetadata::default()).await?;
+    wait_for_ecash_reissue(federation.as_ref()).await?;
+    balance_after_receiving_ecash(federation.as_ref(), ecash_receive_amount).await;
+
+    let limits = SpendingLimits {
+        per_transaction: Some(SpendingLimit::Msats { msats: 2_000 }),
+        daily: Some(SpendingLimit::Msats { msats: 3_000 }),
+        weekly: None,
+    };
+    // tightening needs no unlock
+    setSpendingLimits(&bridge.federations, None, limits.clone()).await?;
+    assert_eq!(getSpendingLimits(&bridge.federations, None).await?, limits);
+    let error = setSpendingLimits(&bridge.federations, None, SpendingLimits::default())
+        .await
+        .unwrap_err();
+    assert_eq!(
+        error.downcast_ref::<ErrorCode>(),
+        Some(&ErrorCode::SpendingLimitsLocked)
+    );
+
+    let generate = |msats| {
+        generateEcash(
+            federation.clone(),
+            RpcAmount(Amount::from_msats(msats)),
+            false,
+            None,
+            FrontendMetadata::default(),
+        )
+    };
+    let error = generate(2_500).await.unwrap_err();
+    assert_eq!(
+        error.downcast_ref::<ErrorCode>(),
+        Some(&ErrorCode::SpendingLimitExceeded)
+    );
+    generate(2_000).await?;
+    // over the daily limit together with the previous spend
+    let error = generate(1_500).await.unwrap_err();
+    assert_eq!(
+        error.downcast_ref::<ErrorCode>(),
+        Some(&ErrorCode::SpendingLimitExceeded)
+    );
+    generate(1_000).await?;
+
+    // federation limits apply on top of the global ones
+    unlockSpendingLimits(&bridge.federations).await?;
+    setSpendingLimits(&bridge.federations, None, SpendingLimits::default()).await?;
+    // a single unlock allows a single loosening
+    assert!(
+        setSpendingLimits(&bridge.federations, None, limits.clone())
+            .await
+            .is_ok()
+    );
+    let error = setSpendingLimits(&bridge.federations, None, SpendingLimits::default())
+        .await
+        .unwrap_err();
+    assert_eq!(
+        error.downcast_ref::<ErrorCode>(),
+        Some(&ErrorCode::SpendingLimitsLocked)
+    );
+    unlockSpendingLimits(&bridge.federations).await?;
+    setSpendingLimits(&bridge.federations, None, SpendingLimits::default()).await?;
+    setSpendingLimits(
+        &bridge.federations,
+        Some(federation.rpc_federation_id()),
+        SpendingLimits {
+            per_transaction: Some(SpendingLimit::Msats { msats: 500 }),
+            ..SpendingLimits::default()
+        },
+    )
+    .await?;
+    assert!(generate(1_000).await.is_err());
+    generate(500).await?;
+    Ok(())
+}
+
+async fn test_federation_transfer_rejects_same_federation(_dev_fed: DevFed) -> anyhow::Result<()> {
+    let td = TestDevice::new().await?;
+    let (bridge, federation) = (td.bridge_full().await?, td.join_default_fed().await?);
+
+    // devimint runs a single federation, so only the checks before paying
+    // can be exercised here
+    assert!(
+        transferBetweenFederations(
+            &bridge.federations,
+            federation.rpc_federation_id(),
+            federation.rpc_federation_id(),
+            RpcAmount(Amount::from_msats(1_000)),
+        )
+        .await
+        .is_err()
+    );
+    assert!(
+        getFederationTransfer(&bridge.federations, "not a transfer id".to_owned())
+            .await
+            .is_err()
+    );
+    Ok(())
+}
+
+async fn test_guardian_health_history(_dev_fed: DevFed) -> anyhow::Result<()> {
+    let td = TestDevice::new().await?;
+    let federation = td.join_default_fed().await?;
+
+    // the monitor checks right after joining
+    let history = loop {
+        let history = getGuardianHealthHistory(federation.clone()).await?;
+        if !history.checks.is_empty() {
+            break history;
+        }
+        fedimint_core::task::sleep_in_test(
+            "waiting for guardian health check",
+            Duration::from_millis(100),
+        )
+        .await;
+    };
+    assert_eq!(history.level, Some(RpcGuardianHealthLevel::Healthy));
+    assert!(history.threshold <= history.total);
+    let check = &history.checks[0];
+    assert_eq!(check.guardians.len(), history.total as usize);
+    assert!(check.guardians.iter().all(|g| g.latency_ms.is_some()));
+    Ok(())
+}
+
+async fn test_wallet_repair(_dev_fed: DevFed) -> anyhow::Result<()> {
+    let td = TestDevice::new().await?;
+    let federation = td.join_default_fed().await?;
+    let ecash = cli_generate_ecash(Amount::from_msats(100_000)).await?;
+    receiveEcash(federation.clone(), ecash, FrontendMetadata::default()).await?;
+    wait_for_ecash_reissue(&federation).await?;
+    let balance = federation.get_balance().await;
+
+    // nothing was reused, so the repair must keep the whole balance
+    let report = runWalletRepair(federation.clone()).await?;
+    assert_eq!(report.reused_ecash, RpcAmount(Amount::ZERO));
+    assert_eq!(report.balance_before, RpcAmount(balance));
+    assert_eq!(report.recovered, RpcAmount(Amount::ZERO));
+    assert_eq!(report.lost, RpcAmount(Amount::ZERO));
+    assert_eq!(federation.get_balance().await, balance);
+    Ok(())
+}
+
 async fn test_backup_and_recovery(_dev_fed: DevFed) -> anyhow::Result<()> {
     if should_skip_test_using_stock_fedimintd() {
         return Ok(());
@@ -2060,13 +2408,14 @@ async fn test_backup_and_recovery_inner(from_scratch: bool) -> anyhow::Result<()
         let fedi_fee_ppm = bridge
             .federations
             .fedi_fee_helper
-            .get_fee_ppm(
+            .get_fee_rule(
                 FediFeeStream::App,
                 federation.rpc_federation_id().0,
                 stability_pool_client_old::common::KIND,
                 RpcTransactionDirection::Send,
             )
-            .await?;
+            .await?
+            .ppm;
         expected_fedi_fee =
             Amount::from_msats((fedi_fee_ppm * sp_amount_to_deposit.msats).div_ceil(MILLION));
         let deposit_op =
@@ -2184,6 +2533,7 @@ async fn test_parse_ecash_v2_embeds_invite(_dev_fed: DevFed) -> anyhow::Result<(
         federation.clone(),
         RpcAmount(send_amount),
         true,
+        None,
         FrontendMetadata::default(),
     )
     .await?
@@ -2236,13 +2586,14 @@ async fn test_social_backup_and_recovery(_dev_fed: DevFed) -> anyhow::Result<()>
     let fedi_fee_ppm = original_bridge
         .federations
         .fedi_fee_helper
-        .get_fee_ppm(
+        .get_fee_rule(
             FediFeeStream::App,
             federation.rpc_federation_id().0,
             stability_pool_client_old::common::KIND,
             RpcTransactionDirection::Send,
         )
-        .await?;
+        .await?
+        .ppm;
     let expected_fedi_fee =
         Amount::from_msats((fedi_fee_ppm * amount_to_deposit.msats).div_ceil(MILLION));
     let deposit_op =
@@ -2820,6 +3171,286 @@ async fn test_spv2_with_fedi_fees(
     Ok(())
 }
 
+async fn test_lnurl_pay(dev_fed: DevFed) -> anyhow::Result<()> {
+    let td = TestDevice::new().await?;
+    let (bridge, federation) = (td.bridge_full().await?, td.join_default_fed().await?);
+    // The invoice below is issued by the LDK gateway's node, so don't let
+    // automatic selection route the payment through that same gateway.
+    use_lnd_gateway(federation).await?;
+
+    let ecash = cli_generate_ecash(Amount::from_sats(1_000)).await?;
+    receiveEcash(federation.clone(), ecash, FrontendMetadata::default()).await?;
+    wait_for_ecash_reissue(federation).await?;
+
+    // Local stand-in for the lightning address service. Loopback hosts are
+    // reached over plain http, everything else needs https.
+    let mut server = mockito::Server::new_async().await;
+    let address = format!("alice@{}", server.host_with_port());
+    let metadata = r#"[["text/plain","Coffee for alice"],["text/identifier","alice@fedi.test"]]"#;
+    server
+        .mock("GET", "/.well-known/lnurlp/alice")
+        .with_status(200)
+        .with_header("content-type", "application/json")
+        .with_body(
+            serde_json::json!({
+                "tag": "payRequest",
+                "callback": format!("{}/lnurlp/alice/callback", server.url()),
+                "minSendable": 1_000,
+                "maxSendable": 100_000_000,
+                "metadata": metadata,
+                "commentAllowed": 32,
+                "payerData": { "name": { "mandatory": false } },
+            })
+            .to_string(),
+        )
+        .create_async()
+        .await;
+
+    let params = previewLnurlPay(&bridge.federations, address.clone()).await?;
+    assert_eq!(params.description, "Coffee for alice");
+    assert_eq!(params.identifier.as_deref(), Some("alice@fedi.test"));
+    assert_eq!(params.min_sendable, RpcAmount(Amount::from_msats(1_000)));
+    assert_eq!(params.comment_allowed, 32);
+
+    let send_amount = Amount::from_sats(50);
+    let invoice = dev_fed
+        .gw_ldk
+        .client()
+        .create_invoice(send_amount.msats)
+        .await?;
+    let callback_mock = server
+        .mock("GET", "/lnurlp/alice/callback")
+        .match_query(mockito::Matcher::AllOf(vec![
+            mockito::Matcher::UrlEncoded("amount".into(), send_amount.msats.to_string()),
+            mockito::Matcher::UrlEncoded("comment".into(), "thanks".into()),
+            mockito::Matcher::UrlEncoded("payerdata".into(), r#"{"name":"Bob"}"#.into()),
+        ]))
+        .with_status(200)
+        .with_header("content-type", "application/json")
+        .with_body(
+            serde_json::json!({
+                "pr": invoice.to_string(),
+                "routes": [],
+                "successAction": { "tag": "message", "message": "Enjoy!" },
+            })
+            .to_string(),
+        )
+        .expect(1)
+        .create_async()
+        .await;
+
+    // Amounts outside the advertised range never reach the callback.
+    assert!(
+        payLnurl(
+            &bridge.federations,
+            federation.rpc_federation_id(),
+            address.clone(),
+            RpcAmount(Amount::from_msats(999)),
+            None,
+            None,
+            FrontendMetadata::default(),
+        )
+        .await
+        .is_err()
+    );
+
+    let response = payLnurl(
+        &bridge.federations,
+        federation.rpc_federation_id(),
+        address,
+        RpcAmount(send_amount),
+        Some("thanks".to_owned()),
+        Some(RpcLnurlPayerData {
+            name: Some("Bob".to_owned()),
+            ..Default::default()
+        }),
+        FrontendMetadata::default(),
+    )
+    .await?;
+    callback_mock.assert_async().await;
+    assert_eq!(response.invoice, invoice.to_string());
+    assert_eq!(
+        response.success_action,
+        Some(RpcLnurlSuccessAction::Message {
+            message: "Enjoy!".to_owned()
+        })
+    );
+
+    dev_fed
+        .gw_ldk
+        .client()
+        .wait_bolt11_invoice(invoice.payment_hash().consensus_encode_to_vec())
+        .await?;
+
+    // A service error is surfaced with its reason.
+    let error_address = format!("broken@{}", server.host_with_port());
+    server
+        .mock("GET", "/.well-known/lnurlp/broken")
+        .with_status(404)
+        .with_header("content-type", "application/json")
+        .with_body(r#"{"status":"ERROR","reason":"unknown user"}"#)
+        .create_async()
+        .await;
+    let error = previewLnurlPay(&bridge.federations, error_address)
+        .await
+        .unwrap_err();
+    assert_eq!(
+        error.downcast_ref::<ErrorCode>(),
+        Some(&ErrorCode::LnurlServiceError("unknown user".to_owned()))
+    );
+
+    Ok(())
+}
+
+async fn test_lnurl_withdraw(dev_fed: DevFed) -> anyhow::Result<()> {
+    let td = TestDevice::new().await?;
+    let (bridge, federation) = (td.bridge_full().await?, td.join_default_fed().await?);
+    // The stand-in service below pays from the LDK gateway's node, so the
+    // invoice must not be issued through that same gateway.
+    use_lnd_gateway(federation).await?;
+
+    let mut server = mockito::Server::new_async().await;
+    let lnurl = format!("lnurlw://{}/withdraw", server.host_with_port());
+    server
+        .mock("GET", "/withdraw")
+        .with_status(200)
+        .with_header("content-type", "application/json")
+        .with_body(
+            serde_json::json!({
+                "tag": "withdrawRequest",
+                "callback": format!("{}/withdraw/callback", server.url()),
+                "k1": "voucher-1",
+                "defaultDescription": "Voucher",
+                "minWithdrawable": 1_000,
+                "maxWithdrawable": 100_000,
+            })
+            .to_string(),
+        )
+        .create_async()
+        .await;
+
+    let params = previewLnurlWithdraw(&bridge.federations, lnurl.clone()).await?;
+    assert_eq!(params.default_description, "Voucher");
+    assert_eq!(params.max_withdrawable, RpcAmount(Amount::from_sats(100)));
+
+    let callback_mock = server
+        .mock("GET", "/withdraw/callback")
+        .match_query(mockito::Matcher::AllOf(vec![
+            mockito::Matcher::UrlEncoded("k1".into(), "voucher-1".into()),
+            mockito::Matcher::Regex("pr=ln".into()),
+        ]))
+        .with_status(200)
+        .with_header("content-type", "application/json")
+        .with_body(r#"{"status":"OK"}"#)
+        .expect(1)
+        .create_async()
+        .await;
+
+    let invoice = claimLnurlWithdraw(
+        &bridge.federations,
+        federation.rpc_federation_id(),
+        lnurl,
+        None,
+        FrontendMetadata::default(),
+    )
+    .await?;
+    callback_mock.assert_async().await;
+    let bolt11 = Bolt11Invoice::from_str(&invoice)?;
+    assert_eq!(bolt11.amount_milli_satoshis(), Some(100_000));
+
+    // The service pays the invoice it was handed.
+    dev_fed.gw_ldk.client().pay_invoice(bolt11).await?;
+
+    devimint::util::poll("waiting for lnurl withdraw to be claimed", || async {
+        let claimed = federation
+            .list_transactions(usize::MAX, None)
+            .await
+            .iter()
+            .any(|entry| {
+                matches!(
+                    entry,
+                    Ok(RpcTransactionListEntry {
+                        transaction: RpcTransaction {
+                            kind: RpcTransactionKind::LnReceive {
+                                ln_invoice,
+                                state: Some(RpcLnReceiveState::Claimed),
+                                ..
+                            },
+                            frontend_metadata: FrontendMetadata {
+                                receive_source: Some(RpcReceiveSource::LnurlWithdraw { domain }),
+                                ..
+                            },
+                            ..
+                        },
+                        ..
+                    }) if *ln_invoice == invoice && domain == "127.0.0.1"
+                )
+            });
+        if claimed {
+            Ok(())
+        } else {
+            Err(ControlFlow::Continue(anyhow!("withdraw not claimed yet")))
+        }
+    })
+    .await?;
+
+    Ok(())
+}
+
+async fn test_parse_payment_string(dev_fed: DevFed) -> anyhow::Result<()> {
+    let td = TestDevice::new().await?;
+    let (bridge, federation) = (td.bridge_full().await?, td.join_default_fed().await?);
+    let federation_id = federation.rpc_federation_id();
+
+    let invoice = dev_fed.gw_ldk.client().create_invoice(50_000).await?;
+    assert_matches!(
+        parsePaymentString(&bridge.federations, format!("LIGHTNING:{invoice}")).await?,
+        RpcParsedPaymentString::Bolt11 {
+            invoice: RpcParsedInvoice::Fixed(RpcInvoice { amount, .. }),
+            payable_by,
+        } if amount == RpcAmount(Amount::from_msats(50_000))
+            && payable_by == [federation_id.clone()]
+    );
+
+    let address = federation
+        .generate_address(FrontendMetadata::default())
+        .await?;
+    assert_matches!(
+        parsePaymentString(
+            &bridge.federations,
+            format!("bitcoin:{address}?amount=0.0001&lightning={invoice}")
+        )
+        .await?,
+        RpcParsedPaymentString::Bip21 {
+            amount: Some(amount),
+            lightning: Some(_),
+            payable_by,
+            ..
+        } if amount == RpcAmount(Amount::from_sats(10_000))
+            && payable_by == [federation_id.clone()]
+    );
+
+    assert_matches!(
+        parsePaymentString(&bridge.federations, std::env::var("FM_INVITE_CODE")?).await?,
+        RpcParsedPaymentString::FederationInvite {
+            federation_id: invite_federation_id,
+            joined: true,
+            ..
+        } if invite_federation_id == federation_id
+    );
+    assert_matches!(
+        parsePaymentString(&bridge.federations, "alice@example.com".into()).await?,
+        RpcParsedPaymentString::LightningAddress { payable_by, .. }
+            if payable_by == [federation_id.clone()]
+    );
+    assert!(
+        parsePaymentString(&bridge.federations, "not a payment".into())
+            .await
+            .is_err()
+    );
+    Ok(())
+}
+
 async fn test_lnurl_sign_message(_dev_fed: DevFed) -> anyhow::Result<()> {
     let td = TestDevice::new().await?;
     let bridge = td.bridge_full().await?;
@@ -3036,13 +3667,14 @@ async fn test_transfer_device_registration_post_recovery(_dev_fed: DevFed) -> an
     let fedi_fee_ppm = backup_bridge
         .federations
         .fedi_fee_helper
-        .get_fee_ppm(
+        .get_fee_rule(
             FediFeeStream::App,
             federation.rpc_federation_id().0,
             stability_pool_client_old::common::KIND,
             RpcTransactionDirection::Send,
         )
-        .await?;
+        .await?
+        .ppm;
     let expected_fedi_fee =
         Amount::from_msats((fedi_fee_ppm * amount_to_deposit.msats).div_ceil(MILLION));
     let deposit_op =
@@ -4089,6 +4721,7 @@ async fn test_bridge_handles_federation_offline() -> anyhow::Result<()> {
                 federation.clone(),
                 RpcAmount(Amount::from_msats(3)),
                 false,
+                None,
                 FrontendMetadata::default(),
             )
             .await
@@ -4274,6 +4907,7 @@ async fn test_bridge_handles_federation_offline_v2() -> anyhow::Result<()> {
                 // balance check instead of exercising the send)
                 RpcAmount(Amount::from_msats(original_balance.msats * 3 / 4)),
                 false,
+                None,
                 FrontendMetadata::default(),
             ),
         )
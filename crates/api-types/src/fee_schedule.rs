@@ -1,10 +1,18 @@
 use serde::{Deserialize, Serialize};
 
+use crate::invoice_generator::TransactionDirection;
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 #[serde(tag = "type", rename = "v0")]
 pub struct FeesV0 {
     pub remittance_threshold_msat: u64,
     pub modules: ModulesFeesV0,
+    #[serde(default)]
+    pub promotions: Vec<FeePromotionV0>,
+    /// Stability pool btc-balance account that app fees are deposited to
+    /// when they can't be remitted over Lightning.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub remittance_fallback_account: Option<serde_json::Value>,
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -20,4 +28,50 @@ pub struct ModulesFeesV0 {
 pub struct PPMs {
     pub send_ppm: u64,
     pub receive_ppm: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub send_policy: Option<FeePolicyV0>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub receive_policy: Option<FeePolicyV0>,
+}
+
+/// Base fee, caps and volume tiers on top of the ppm of one direction.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct FeePolicyV0 {
+    #[serde(default)]
+    pub base_msat: u64,
+    #[serde(default)]
+    pub min_msat: u64,
+    #[serde(default)]
+    pub max_msat: Option<u64>,
+    #[serde(default)]
+    pub volume_tiers: Vec<VolumeTierV0>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct VolumeTierV0 {
+    pub min_volume_msat: u64,
+    pub ppm: u64,
+}
+
+/// Discount on the app fee within a time window. Unset scope fields match
+/// every transaction.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct FeePromotionV0 {
+    pub id: String,
+    /// Unix timestamp in seconds, inclusive.
+    pub starts_at: u64,
+    /// Unix timestamp in seconds, exclusive.
+    pub ends_at: u64,
+    /// Share of the fee waived, 1_000_000 waives the whole fee.
+    pub discount_ppm: u64,
+    #[serde(default)]
+    pub federation_id: Option<String>,
+    #[serde(default)]
+    pub module: Option<String>,
+    #[serde(default)]
+    pub direction: Option<TransactionDirection>,
+    /// Hash of the invite code of a community the user must have joined, as
+    /// in `GenerateInvoiceRequestV2::other_comm_invite_codes_hashes`.
+    #[serde(default)]
+    pub community_invite_code_hash: Option<String>,
 }
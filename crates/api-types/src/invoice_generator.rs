@@ -24,7 +24,7 @@ pub struct GenerateInvoiceRequestV1 {
     pub tx_direction: TransactionDirection,
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub enum TransactionDirection {
     Send,
     Receive,
@@ -350,10 +350,7 @@ impl BridgeFull {
             .set_app_module_fee_schedule(
                 federation_id.0,
                 module_kind,
-                ModuleFediFeeSchedule {
-                    send_ppm,
-                    receive_ppm,
-                },
+                ModuleFediFeeSchedule::flat(send_ppm, receive_ppm),
             )
             .await
     }
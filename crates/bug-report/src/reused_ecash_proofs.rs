@@ -85,6 +85,14 @@ impl ReusedEcashProof {
         }
     }
 
+    pub fn amount(&self) -> Amount {
+        self.amount
+    }
+
+    pub fn blind_nonce(&self) -> &BlindNonce {
+        &self.blind_nonce
+    }
+
     pub fn verify(&self) -> anyhow::Result<()> {
         let message = Self::signing_message(self.blind_nonce, self.blinding_key, self.amount);
         self.signature
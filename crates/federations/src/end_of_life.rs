@@ -0,0 +1,316 @@
+//! Federations announcing in their meta that they shut down.
+//!
+//! The meta gives when the federation ends and optionally the invite code of
+//! a successor federation and instructions for withdrawing. As the end
+//! approaches, warnings of increasing urgency are sent, each level once per
+//! announced end. If the user opted in, every check from the final day on
+//! starts evacuating into the successor once it is joined, unless an
+//! evacuation is running already, so one that failed is retried until the
+//! federation is left.
+
+use std::collections::BTreeMap;
+use std::str::FromStr as _;
+use std::sync::Arc;
+use std::time::Duration;
+
+use fedimint_core::db::{Database, IDatabaseTransactionOpsCoreTyped};
+use fedimint_core::encoding::{Decodable, Encodable};
+use fedimint_core::impl_db_record;
+use fedimint_core::invite_code::InviteCode;
+use rpc_types::event::{Event, FederationEndOfLifeWarning, TypedEventExt as _};
+use rpc_types::{RpcEvacuationTarget, RpcFederationEndOfLife, RpcFederationId};
+use runtime::utils::to_unix_time;
+use tracing::{info, warn};
+
+use crate::Federations;
+use crate::federation_sm::FederationState;
+use crate::federation_v2::FederationV2;
+use crate::federation_v2::db::FederationDataDbPrefix;
+
+const DAY_SECS: u64 = 24 * 60 * 60;
+
+/// Well within the shortest warning period.
+const CHECK_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+// The first key present wins. The app already shows a countdown to
+// `popup_end_timestamp`; `federation_expiry_timestamp` and
+// `federation_successor` are the fedimint meta fields.
+const END_TIMESTAMP_META_KEYS: &[&str] = &[
+    "fedi:popup_end_timestamp",
+    "popup_end_timestamp",
+    "federation_expiry_timestamp",
+];
+const SUCCESSOR_META_KEYS: &[&str] = &["fedi:federation_successor", "federation_successor"];
+const WITHDRAWAL_INSTRUCTIONS_META_KEYS: &[&str] =
+    &["fedi:withdrawal_instructions", "withdrawal_instructions"];
+
+#[repr(u8)]
+pub enum EndOfLifeDbPrefix {
+    // the last warning sent
+    Warned = 0x01,
+    // present if the user opted into evacuating into the successor
+    AutoEvacuate = 0x02,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Encodable, Decodable)]
+pub enum EndOfLifeWarning {
+    Upcoming,
+    Imminent,
+    Final,
+    Ended,
+}
+
+#[derive(Debug, Clone, Encodable, Decodable)]
+pub struct EndOfLifeWarned {
+    /// The end the warning was about, so a postponed end starts over.
+    pub ends_at: u64,
+    pub warning: EndOfLifeWarning,
+}
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct EndOfLifeWarnedKey;
+
+impl_db_record!(
+    key = EndOfLifeWarnedKey,
+    value = EndOfLifeWarned,
+    db_prefix = EndOfLifeDbPrefix::Warned,
+);
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct AutoEvacuateKey;
+
+impl_db_record!(
+    key = AutoEvacuateKey,
+    value = (),
+    db_prefix = EndOfLifeDbPrefix::AutoEvacuate,
+);
+
+impl EndOfLifeWarning {
+    /// The warning due at `now` for an end at `ends_at`, both in unix
+    /// seconds.
+    fn due(ends_at: u64, now: u64) -> Option<Self> {
+        let remaining = ends_at.checked_sub(now).filter(|secs| *secs != 0);
+        match remaining {
+            None => Some(Self::Ended),
+            Some(secs) if secs <= DAY_SECS => Some(Self::Final),
+            Some(secs) if secs <= 7 * DAY_SECS => Some(Self::Imminent),
+            Some(secs) if secs <= 30 * DAY_SECS => Some(Self::Upcoming),
+            Some(_) => None,
+        }
+    }
+
+    fn to_rpc(self) -> FederationEndOfLifeWarning {
+        match self {
+            Self::Upcoming => FederationEndOfLifeWarning::Upcoming,
+            Self::Imminent => FederationEndOfLifeWarning::Imminent,
+            Self::Final => FederationEndOfLifeWarning::Final,
+            Self::Ended => FederationEndOfLifeWarning::Ended,
+        }
+    }
+}
+
+fn meta_field(meta: &BTreeMap<String, String>, keys: &[&str]) -> Option<String> {
+    keys.iter().find_map(|key| {
+        meta.get(*key)
+            .map(|value| value.trim())
+            .filter(|value| !value.is_empty())
+            .map(ToOwned::to_owned)
+    })
+}
+
+/// The shutdown announced in `meta`, if any.
+fn parse_end_of_life(meta: &BTreeMap<String, String>) -> Option<RpcFederationEndOfLife> {
+    let ends_at = meta_field(meta, END_TIMESTAMP_META_KEYS)?;
+    let ends_at = match ends_at.parse() {
+        Ok(ends_at) => ends_at,
+        Err(error) => {
+            warn!(?error, %ends_at, "invalid federation end timestamp in meta");
+            return None;
+        }
+    };
+    Some(RpcFederationEndOfLife {
+        ends_at,
+        successor_invite_code: meta_field(meta, SUCCESSOR_META_KEYS),
+        withdrawal_instructions: meta_field(meta, WITHDRAWAL_INSTRUCTIONS_META_KEYS),
+        auto_evacuate: false,
+    })
+}
+
+impl FederationV2 {
+    fn end_of_life_db(&self) -> Database {
+        self.federation_data_db(FederationDataDbPrefix::EndOfLife)
+    }
+
+    /// The shutdown announced in `meta`, the federation's cached meta.
+    pub async fn end_of_life(
+        &self,
+        meta: &BTreeMap<String, String>,
+    ) -> Option<RpcFederationEndOfLife> {
+        let mut end_of_life = parse_end_of_life(meta)?;
+        end_of_life.auto_evacuate = self
+            .end_of_life_db()
+            .begin_transaction_nc()
+            .await
+            .get_value(&AutoEvacuateKey)
+            .await
+            .is_some();
+        Some(end_of_life)
+    }
+
+    pub async fn set_auto_evacuate(&self, enabled: bool) -> anyhow::Result<()> {
+        let mut dbtx = self.end_of_life_db().begin_transaction().await;
+        if enabled {
+            dbtx.insert_entry(&AutoEvacuateKey, &()).await;
+        } else {
+            dbtx.remove_entry(&AutoEvacuateKey).await;
+        }
+        dbtx.commit_tx_result().await?;
+        self.send_federation_event().await;
+        Ok(())
+    }
+
+    /// Sends the warning due for the announced end, unless it was sent
+    /// already. Returns the successor's invite code if the final day has
+    /// started and the user opted into evacuating.
+    async fn warn_end_of_life(&self) -> anyhow::Result<Option<String>> {
+        let meta = self.get_cached_meta().await;
+        let Some(end_of_life) = self.end_of_life(&meta).await else {
+            return Ok(None);
+        };
+        let now = to_unix_time(fedimint_core::time::now())?;
+        let Some(warning) = EndOfLifeWarning::due(end_of_life.ends_at, now) else {
+            return Ok(None);
+        };
+
+        let evacuate_to = end_of_life
+            .successor_invite_code
+            .clone()
+            .filter(|_| EndOfLifeWarning::Final <= warning && end_of_life.auto_evacuate);
+
+        let mut dbtx = self.end_of_life_db().begin_transaction().await;
+        let warned = dbtx
+            .get_value(&EndOfLifeWarnedKey)
+            .await
+            .filter(|warned| warned.ends_at == end_of_life.ends_at)
+            .map(|warned| warned.warning);
+        if warned.is_some_and(|warned| warning <= warned) {
+            return Ok(evacuate_to);
+        }
+        dbtx.insert_entry(
+            &EndOfLifeWarnedKey,
+            &EndOfLifeWarned {
+                ends_at: end_of_life.ends_at,
+                warning,
+            },
+        )
+        .await;
+        dbtx.commit_tx_result().await?;
+        info!(
+            ?warning,
+            ends_at = end_of_life.ends_at,
+            "federation is shutting down"
+        );
+        self.runtime
+            .event_sink
+            .typed_event(&Event::federation_end_of_life(
+                self.rpc_federation_id(),
+                warning.to_rpc(),
+                end_of_life,
+            ));
+        Ok(evacuate_to)
+    }
+}
+
+impl Federations {
+    /// Checks every joined federation for an announced end, for as long as
+    /// the bridge runs.
+    pub(crate) async fn watch_end_of_life(self: &Arc<Self>) {
+        loop {
+            for (federation_id, state) in self.get_federations_map() {
+                let FederationState::Ready(federation) = state else {
+                    continue;
+                };
+                match federation.warn_end_of_life().await {
+                    Ok(Some(successor_invite_code)) => {
+                        drop(federation);
+                        self.evacuate_to_successor(&federation_id, &successor_invite_code)
+                            .await;
+                    }
+                    Ok(None) => {}
+                    Err(error) => warn!(?error, %federation_id, "failed to check end of life"),
+                }
+            }
+            fedimint_core::task::sleep(CHECK_INTERVAL).await;
+        }
+    }
+
+    async fn evacuate_to_successor(self: &Arc<Self>, federation_id: &str, invite_code: &str) {
+        if self.evacuations.in_progress(federation_id) {
+            return;
+        }
+        let successor_id = match InviteCode::from_str(invite_code) {
+            Ok(invite_code) => invite_code.federation_id().to_string(),
+            Err(error) => {
+                warn!(?error, %federation_id, "invalid successor invite code in meta");
+                return;
+            }
+        };
+        if !self.get_federations_map().contains_key(&successor_id) {
+            info!(%federation_id, "successor federation not joined, not evacuating");
+            return;
+        }
+        info!(%federation_id, %successor_id, "evacuating into successor federation");
+        let target = RpcEvacuationTarget::Federation {
+            federation_id: RpcFederationId(successor_id),
+        };
+        if let Err(error) = self.evacuate_federation(federation_id, target).await {
+            warn!(?error, %federation_id, "failed to start evacuation");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_end_of_life_warning_due() {
+        let ends_at = 100 * DAY_SECS;
+        let due = |days_left: u64| EndOfLifeWarning::due(ends_at, ends_at - days_left * DAY_SECS);
+        assert_eq!(due(31), None);
+        assert_eq!(due(30), Some(EndOfLifeWarning::Upcoming));
+        assert_eq!(due(7), Some(EndOfLifeWarning::Imminent));
+        assert_eq!(due(1), Some(EndOfLifeWarning::Final));
+        assert_eq!(due(0), Some(EndOfLifeWarning::Ended));
+        assert_eq!(
+            EndOfLifeWarning::due(ends_at, ends_at + 1),
+            Some(EndOfLifeWarning::Ended)
+        );
+    }
+
+    #[test]
+    fn test_parse_end_of_life() {
+        let meta = |entries: &[(&str, &str)]| {
+            entries
+                .iter()
+                .map(|(key, value)| ((*key).to_owned(), (*value).to_owned()))
+                .collect::<BTreeMap<_, _>>()
+        };
+        assert!(parse_end_of_life(&meta(&[])).is_none());
+        assert!(parse_end_of_life(&meta(&[("federation_expiry_timestamp", "soon")])).is_none());
+
+        let end_of_life = parse_end_of_life(&meta(&[
+            ("federation_expiry_timestamp", "2000"),
+            ("fedi:popup_end_timestamp", "1000"),
+            ("federation_successor", "fed11successor"),
+            ("fedi:withdrawal_instructions", " "),
+        ]))
+        .unwrap();
+        assert_eq!(end_of_life.ends_at, 1000);
+        assert_eq!(
+            end_of_life.successor_invite_code.as_deref(),
+            Some("fed11successor")
+        );
+        assert_eq!(end_of_life.withdrawal_instructions, None);
+    }
+}
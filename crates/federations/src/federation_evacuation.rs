@@ -0,0 +1,325 @@
+//! Emptying a federation and leaving it, e.g. once it announced shutdown.
+//!
+//! The stable balance is withdrawn first and ecash sent out of band that
+//! nobody claimed yet is reclaimed. Once pending operations, reissues and
+//! queued offline receives have settled, the ecash balance is swept to
+//! another joined federation over lightning or to an onchain address, and the
+//! federation is left. Each step is reported with a `federationEvacuation`
+//! event.
+//!
+//! An evacuation is not resumed after a restart. Starting it again carries on
+//! with whatever is left in the federation. Only one evacuation of a
+//! federation runs at a time.
+
+use std::collections::BTreeSet;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use anyhow::{Context as _, bail, ensure};
+use bitcoin::Address;
+use bitcoin::address::NetworkUnchecked;
+use fedimint_core::Amount;
+use rpc_types::error::ErrorCode;
+use rpc_types::event::{Event, FederationEvacuationState, TypedEventExt as _};
+use rpc_types::{
+    FrontendMetadata, RpcAmount, RpcEvacuationTarget, RpcFederationId, RpcFederationTransferState,
+    RpcOnchainFeeTier,
+};
+use runtime::utils::PoisonedLockExt as _;
+use stability_pool_client::common::FiatOrAll;
+use tracing::{info, warn};
+
+use crate::Federations;
+use crate::federation_v2::FederationV2;
+use crate::federation_v2::client::ClientExt as _;
+
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+/// How long pending operations get to settle before the evacuation gives up.
+const PENDING_OPERATIONS_TIMEOUT: Duration = Duration::from_secs(60 * 60);
+
+/// Federations with an evacuation in progress.
+#[derive(Default)]
+pub(crate) struct Evacuations(Arc<Mutex<BTreeSet<String>>>);
+
+/// Marks an evacuation as in progress until dropped.
+struct EvacuationGuard {
+    evacuations: Arc<Mutex<BTreeSet<String>>>,
+    federation_id: String,
+}
+
+impl Evacuations {
+    pub(crate) fn in_progress(&self, federation_id: &str) -> bool {
+        self.0.ensure_lock().contains(federation_id)
+    }
+
+    fn start(&self, federation_id: &str) -> anyhow::Result<EvacuationGuard> {
+        ensure!(
+            self.0.ensure_lock().insert(federation_id.to_owned()),
+            "federation evacuation already in progress"
+        );
+        Ok(EvacuationGuard {
+            evacuations: self.0.clone(),
+            federation_id: federation_id.to_owned(),
+        })
+    }
+}
+
+impl Drop for EvacuationGuard {
+    fn drop(&mut self) {
+        self.evacuations.ensure_lock().remove(&self.federation_id);
+    }
+}
+
+enum EvacuationTarget {
+    Federation(String),
+    Onchain(Address<NetworkUnchecked>),
+}
+
+impl Federations {
+    /// Starts evacuating federation `federation_id` into `target`. Returns
+    /// once the target is checked; the evacuation runs in the background.
+    pub async fn evacuate_federation(
+        self: &Arc<Self>,
+        federation_id: &str,
+        target: RpcEvacuationTarget,
+    ) -> anyhow::Result<()> {
+        self.get_federation(federation_id)?;
+        let target = match target {
+            RpcEvacuationTarget::Federation { federation_id: to } => {
+                ensure!(
+                    to.0 != federation_id,
+                    "cannot evacuate into the same federation"
+                );
+                self.get_federation(&to.0)?;
+                EvacuationTarget::Federation(to.0)
+            }
+            RpcEvacuationTarget::Onchain { address } => {
+                EvacuationTarget::Onchain(address.parse().context("invalid onchain address")?)
+            }
+        };
+
+        let guard = self.evacuations.start(federation_id)?;
+        let this = self.clone();
+        let federation_id = federation_id.to_owned();
+        self.runtime
+            .task_group
+            .clone()
+            .spawn_cancellable("federation evacuation", async move {
+                let _guard = guard;
+                let state = match this.evacuate(&federation_id, target).await {
+                    Ok(()) => FederationEvacuationState::Left,
+                    Err(error) => {
+                        warn!(?error, %federation_id, "federation evacuation failed");
+                        FederationEvacuationState::Failed {
+                            error: error.to_string(),
+                        }
+                    }
+                };
+                this.send_evacuation_event(&federation_id, state);
+            });
+        Ok(())
+    }
+
+    async fn evacuate(&self, federation_id: &str, target: EvacuationTarget) -> anyhow::Result<()> {
+        let federation = self.get_federation(federation_id)?;
+        if federation.client.spv2().is_ok() {
+            let account_info = federation.spv2_account_info().await?.value;
+            if account_info.staged_balance + account_info.locked_balance != Amount::ZERO {
+                self.send_evacuation_event(
+                    federation_id,
+                    FederationEvacuationState::WithdrawingStableBalance,
+                );
+                federation
+                    .spv2_withdraw(FiatOrAll::All, FrontendMetadata::default())
+                    .await?;
+            }
+        }
+        let unreclaimed = federation.reclaim_unclaimed_ecash_sends().await;
+        ensure!(
+            unreclaimed == Amount::ZERO,
+            "failed to reclaim {unreclaimed} of unclaimed ecash"
+        );
+        self.wait_for_pending_operations(&federation).await?;
+
+        let balance = federation.get_balance().await;
+        if balance != Amount::ZERO {
+            match &target {
+                EvacuationTarget::Federation(to) => {
+                    self.sweep_to_federation(&federation, to).await?
+                }
+                EvacuationTarget::Onchain(address) => {
+                    self.sweep_onchain(&federation, address.clone()).await?;
+                    self.wait_for_pending_operations(&federation).await?;
+                }
+            }
+        }
+
+        self.send_evacuation_event(federation_id, FederationEvacuationState::Leaving);
+        // leaving waits for every handle to the federation to be dropped
+        drop(federation);
+        self.leave_federation(federation_id).await?;
+        info!(%federation_id, "federation evacuated");
+        Ok(())
+    }
+
+    /// Waits until the federation has no pending operations or queued
+    /// offline receives left. Fails with the amount they still hold if some
+    /// are pending after [`PENDING_OPERATIONS_TIMEOUT`].
+    async fn wait_for_pending_operations(&self, federation: &FederationV2) -> anyhow::Result<()> {
+        let started = fedimint_core::time::now();
+        let mut reported = None;
+        loop {
+            let (remaining, outstanding) = federation.pending_operations().await;
+            if remaining == 0 {
+                return Ok(());
+            }
+            let waited = fedimint_core::time::now()
+                .duration_since(started)
+                .unwrap_or_default();
+            ensure!(
+                waited < PENDING_OPERATIONS_TIMEOUT,
+                "timed out waiting for {remaining} pending operations, {outstanding} outstanding"
+            );
+            if reported != Some(remaining) {
+                self.send_evacuation_event(
+                    &federation.federation_id().to_string(),
+                    FederationEvacuationState::WaitingForPendingOperations { remaining },
+                );
+                reported = Some(remaining);
+            }
+            fedimint_core::task::sleep(POLL_INTERVAL).await;
+        }
+    }
+
+    /// The fees of a transfer depend on its invoice, so the balance is first
+    /// offered in full and, if that does not cover the fees, once more with
+    /// what is left after them.
+    async fn sweep_to_federation(&self, federation: &FederationV2, to: &str) -> anyhow::Result<()> {
+        let from = federation.federation_id().to_string();
+        let mut amount = floor_to_sats(federation.get_balance().await);
+        let transfer = loop {
+            ensure!(amount != Amount::ZERO, "balance does not cover the fees");
+            self.send_evacuation_event(
+                &from,
+                FederationEvacuationState::Sweeping {
+                    amount: RpcAmount(amount),
+                },
+            );
+            match self.transfer_between_federations(&from, to, amount).await {
+                Ok(transfer) => break transfer,
+                Err(error) => match error.downcast_ref::<ErrorCode>() {
+                    Some(ErrorCode::InsufficientBalance(max)) if floor_to_sats(max.0) < amount => {
+                        amount = floor_to_sats(max.0);
+                    }
+                    _ => return Err(error),
+                },
+            }
+        };
+
+        loop {
+            match self
+                .get_federation_transfer(&transfer.transfer_id)
+                .await?
+                .state
+            {
+                RpcFederationTransferState::Completed => return Ok(()),
+                RpcFederationTransferState::Failed { error } => {
+                    bail!("transfer to {to} failed: {error}")
+                }
+                RpcFederationTransferState::Paying | RpcFederationTransferState::Paid => {}
+            }
+            fedimint_core::task::sleep(POLL_INTERVAL).await;
+        }
+    }
+
+    async fn sweep_onchain(
+        &self,
+        federation: &FederationV2,
+        address: Address<NetworkUnchecked>,
+    ) -> anyhow::Result<()> {
+        let balance = federation.get_balance().await;
+        let fees = federation
+            .preview_pay_address(
+                address.clone(),
+                bitcoin::Amount::from_sat(balance.msats / 1000),
+                RpcOnchainFeeTier::Economy,
+            )
+            .await?;
+        let fees = fees.fedi_app_fee.0
+            + fees.fedi_guardian_fee.0
+            + fees.network_fee.0
+            + fees.federation_fee.0;
+        let amount = floor_to_sats(balance.saturating_sub(fees));
+        ensure!(amount != Amount::ZERO, "balance does not cover the fees");
+        self.send_evacuation_event(
+            &federation.federation_id().to_string(),
+            FederationEvacuationState::Sweeping {
+                amount: RpcAmount(amount),
+            },
+        );
+        federation
+            .pay_address(
+                address,
+                bitcoin::Amount::from_sat(amount.msats / 1000),
+                RpcOnchainFeeTier::Economy,
+                FrontendMetadata::default(),
+            )
+            .await?;
+        Ok(())
+    }
+
+    fn send_evacuation_event(&self, federation_id: &str, state: FederationEvacuationState) {
+        self.runtime
+            .event_sink
+            .typed_event(&Event::federation_evacuation(
+                RpcFederationId(federation_id.to_owned()),
+                state,
+            ));
+    }
+}
+
+fn floor_to_sats(amount: Amount) -> Amount {
+    Amount::from_sats(amount.msats / 1000)
+}
+
+impl FederationV2 {
+    /// How many operations and queued offline receives are pending, and the
+    /// amount they hold.
+    async fn pending_operations(&self) -> (u32, Amount) {
+        let mut count = 0;
+        let mut amount = Amount::ZERO;
+        for operation_id in self.client.get_active_operations().await {
+            count += 1;
+            if let Ok(transaction) = self.get_transaction(operation_id).await {
+                amount += transaction.amount.0;
+            }
+        }
+        for queued in self.pending_queued_ecash_receives().await {
+            count += 1;
+            amount += queued;
+        }
+        (count, amount)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_one_evacuation_per_federation() {
+        let evacuations = Evacuations::default();
+        let guard = evacuations.start("a").unwrap();
+        assert!(evacuations.in_progress("a"));
+        assert!(!evacuations.in_progress("b"));
+        assert!(evacuations.start("a").is_err());
+        // other federations are unaffected
+        let other = evacuations.start("b").unwrap();
+        drop(guard);
+        assert!(!evacuations.in_progress("a"));
+        let _guard = evacuations.start("a").unwrap();
+        assert!(evacuations.start("b").is_err());
+        drop(other);
+        evacuations.start("b").unwrap();
+    }
+}
@@ -0,0 +1,519 @@
+//! Moving funds between two joined federations as one operation.
+//!
+//! The destination federation creates an invoice which the source federation
+//! pays over lightning. The transfer is recorded in the global database
+//! before anything is paid and advanced by a driver task, which is started
+//! again for unfinished transfers once the federations have loaded. A payment
+//! the destination gave up on before claiming it, e.g. because the app was
+//! closed until its invoice expired, is claimed with
+//! [`FederationV2::reclaim_ln_receive`].
+//!
+//! Paying is retried with backoff until it succeeds or is rejected for good,
+//! e.g. for lack of balance, so an unreachable gateway or federation only
+//! delays the transfer.
+//!
+//! Transfers are keyed by the payment hash of their invoice, which links the
+//! two legs in transaction history.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{Context as _, bail, ensure};
+use bitcoin::hashes::sha256;
+use fedimint_client::db::ChronologicalOperationLogKey;
+use fedimint_core::Amount;
+use fedimint_core::core::OperationId;
+use fedimint_core::db::{Database, IDatabaseTransactionOpsCoreTyped};
+use fedimint_core::encoding::{Decodable, Encodable};
+use fedimint_core::{impl_db_lookup, impl_db_record};
+use futures::StreamExt;
+use lightning_invoice::Bolt11Invoice;
+use rpc_types::error::ErrorCode;
+use rpc_types::{
+    FrontendMetadata, RpcAmount, RpcFederationId, RpcFederationTransfer, RpcFederationTransferLeg,
+    RpcFederationTransferState, RpcLnPayState, RpcLnReceiveState, RpcReclaimLnReceiveOutcome,
+    RpcTransaction, RpcTransactionKind,
+};
+use runtime::utils::to_unix_time;
+use tracing::{info, warn};
+
+use crate::Federations;
+use crate::federation_sm::FederationState;
+use crate::federation_v2::FederationV2;
+
+const MIN_POLL_INTERVAL: Duration = Duration::from_secs(1);
+const MAX_POLL_INTERVAL: Duration = Duration::from_secs(60);
+/// Operations read per page while looking for a payment of a resumed transfer.
+const TRANSFER_SCAN_PAGE_SIZE: usize = 100;
+
+#[repr(u8)]
+pub enum FederationTransfersDbPrefix {
+    // invoice payment hash => the transfer
+    Transfer = 0x01,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Encodable, Decodable)]
+pub enum FederationTransferState {
+    /// Recorded before paying, so a restart knows a payment may be in flight.
+    Paying,
+    Paid,
+    Completed,
+    Failed {
+        error: String,
+    },
+}
+
+#[derive(Debug, Clone, Encodable, Decodable)]
+pub struct FederationTransfer {
+    pub from_federation_id: String,
+    pub to_federation_id: String,
+    pub invoice: String,
+    /// The receive operation of `invoice` in the destination federation.
+    pub receive_operation_id: OperationId,
+    pub amount: Amount,
+    pub estimated_fees: Amount,
+    /// Unix time in seconds.
+    pub created_at: u64,
+    pub state: FederationTransferState,
+}
+
+#[derive(Debug, Clone, Encodable, Decodable)]
+pub struct FederationTransferKey(pub sha256::Hash);
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct FederationTransferKeyPrefix;
+
+impl_db_record!(
+    key = FederationTransferKey,
+    value = FederationTransfer,
+    db_prefix = FederationTransfersDbPrefix::Transfer,
+);
+
+impl_db_lookup!(
+    key = FederationTransferKey,
+    query_prefix = FederationTransferKeyPrefix
+);
+
+impl FederationTransfer {
+    fn to_rpc(&self, payment_hash: sha256::Hash) -> RpcFederationTransfer {
+        RpcFederationTransfer {
+            transfer_id: payment_hash.to_string(),
+            from_federation_id: RpcFederationId(self.from_federation_id.clone()),
+            to_federation_id: RpcFederationId(self.to_federation_id.clone()),
+            amount: RpcAmount(self.amount),
+            estimated_fees: RpcAmount(self.estimated_fees),
+            state: match &self.state {
+                FederationTransferState::Paying => RpcFederationTransferState::Paying,
+                FederationTransferState::Paid => RpcFederationTransferState::Paid,
+                FederationTransferState::Completed => RpcFederationTransferState::Completed,
+                FederationTransferState::Failed { error } => RpcFederationTransferState::Failed {
+                    error: error.clone(),
+                },
+            },
+        }
+    }
+}
+
+async fn get_transfer(db: &Database, payment_hash: sha256::Hash) -> Option<FederationTransfer> {
+    db.begin_transaction_nc()
+        .await
+        .get_value(&FederationTransferKey(payment_hash))
+        .await
+}
+
+impl Federations {
+    /// Pays `amount` from federation `from` into federation `to`. Returns
+    /// once the transfer is recorded; it completes in the background.
+    pub async fn transfer_between_federations(
+        &self,
+        from: &str,
+        to: &str,
+        amount: Amount,
+    ) -> anyhow::Result<RpcFederationTransfer> {
+        ensure!(from != to, "cannot transfer within the same federation");
+        let from_federation = self.get_federation(from)?;
+        let to_federation = self.get_federation(to)?;
+
+        let (receive_operation_id, invoice) = to_federation
+            .generate_invoice_operation(
+                RpcAmount(amount),
+                "Transfer between federations".to_owned(),
+                None,
+                FrontendMetadata::default(),
+            )
+            .await?;
+        // the invoice rounds up to whole sats
+        let amount = Amount::from_msats(
+            invoice
+                .amount_milli_satoshis()
+                .context("transfer invoice has no amount")?,
+        );
+        let fees = from_federation.estimate_ln_fees(&invoice).await?;
+        let estimated_fees = fees.fedi_app_fee.0
+            + fees.fedi_guardian_fee.0
+            + fees.network_fee.0
+            + fees.federation_fee.0;
+        let balance = from_federation.get_balance().await;
+        if balance < amount + estimated_fees {
+            bail!(ErrorCode::InsufficientBalance(RpcAmount(
+                balance.saturating_sub(estimated_fees)
+            )));
+        }
+
+        let payment_hash = *invoice.payment_hash();
+        let transfer = FederationTransfer {
+            from_federation_id: from.to_owned(),
+            to_federation_id: to.to_owned(),
+            invoice: invoice.to_string(),
+            receive_operation_id,
+            amount,
+            estimated_fees,
+            created_at: to_unix_time(fedimint_core::time::now())?,
+            state: FederationTransferState::Paying,
+        };
+        let db = self.runtime.federation_transfers_db();
+        let mut dbtx = db.begin_transaction().await;
+        dbtx.insert_entry(&FederationTransferKey(payment_hash), &transfer)
+            .await;
+        dbtx.commit_tx_result().await?;
+
+        self.spawn_federation_transfer(payment_hash, from_federation, to_federation);
+        Ok(transfer.to_rpc(payment_hash))
+    }
+
+    pub async fn get_federation_transfer(
+        &self,
+        transfer_id: &str,
+    ) -> anyhow::Result<RpcFederationTransfer> {
+        let payment_hash = transfer_id.parse()?;
+        get_transfer(&self.runtime.federation_transfers_db(), payment_hash)
+            .await
+            .context("transfer not found")
+            .map(|transfer| transfer.to_rpc(payment_hash))
+    }
+
+    /// Restarts the driver of every unfinished transfer whose federations
+    /// are both loaded. Others are picked up on a later start.
+    pub(crate) async fn resume_federation_transfers(&self) {
+        let unfinished = self
+            .runtime
+            .federation_transfers_db()
+            .begin_transaction_nc()
+            .await
+            .find_by_prefix(&FederationTransferKeyPrefix)
+            .await
+            .filter(|(_, transfer)| {
+                std::future::ready(matches!(
+                    transfer.state,
+                    FederationTransferState::Paying | FederationTransferState::Paid
+                ))
+            })
+            .collect::<Vec<_>>()
+            .await;
+        for (key, transfer) in unfinished {
+            let federations = self.get_federations_map();
+            let (
+                Some(FederationState::Ready(from_federation)),
+                Some(FederationState::Ready(to_federation)),
+            ) = (
+                federations.get(&transfer.from_federation_id).cloned(),
+                federations.get(&transfer.to_federation_id).cloned(),
+            )
+            else {
+                warn!(transfer_id = %key.0, "federations of transfer not loaded, not resuming");
+                continue;
+            };
+            self.spawn_federation_transfer(key.0, from_federation, to_federation);
+        }
+    }
+
+    fn spawn_federation_transfer(
+        &self,
+        payment_hash: sha256::Hash,
+        from_federation: Arc<FederationV2>,
+        to_federation: Arc<FederationV2>,
+    ) {
+        let db = self.runtime.federation_transfers_db();
+        self.runtime
+            .task_group
+            .clone()
+            .spawn_cancellable("federation transfer", async move {
+                if let Err(error) =
+                    drive_transfer(&db, payment_hash, &from_federation, &to_federation).await
+                {
+                    warn!(?error, transfer_id = %payment_hash, "federation transfer stalled");
+                }
+            });
+    }
+}
+
+/// Advances the transfer until it is completed or failed.
+async fn drive_transfer(
+    db: &Database,
+    payment_hash: sha256::Hash,
+    from_federation: &FederationV2,
+    to_federation: &FederationV2,
+) -> anyhow::Result<()> {
+    loop {
+        let transfer = get_transfer(db, payment_hash)
+            .await
+            .context("transfer not found")?;
+        let state = match transfer.state {
+            FederationTransferState::Paying => pay(&transfer, from_federation).await?,
+            FederationTransferState::Paid => claim(&transfer, to_federation).await?,
+            FederationTransferState::Completed | FederationTransferState::Failed { .. } => {
+                return Ok(());
+            }
+        };
+        info!(transfer_id = %payment_hash, ?state, "federation transfer advanced");
+        let mut dbtx = db.begin_transaction().await;
+        dbtx.insert_entry(
+            &FederationTransferKey(payment_hash),
+            &FederationTransfer { state, ..transfer },
+        )
+        .await;
+        dbtx.commit_tx_result().await?;
+    }
+}
+
+/// What a pay operation left behind by an earlier run means for the
+/// transfer, or None while it is still in flight.
+fn resumed_pay_state(state: &RpcLnPayState) -> Option<Option<FederationTransferState>> {
+    match state {
+        RpcLnPayState::Success { .. } => Some(Some(FederationTransferState::Paid)),
+        // the funds came back, so paying again is safe
+        RpcLnPayState::Canceled | RpcLnPayState::Refunded { .. } => Some(None),
+        RpcLnPayState::Failed => Some(Some(FederationTransferState::Failed {
+            error: "payment failed unexpectedly".to_owned(),
+        })),
+        RpcLnPayState::Created
+        | RpcLnPayState::Funded { .. }
+        | RpcLnPayState::WaitingForRefund { .. }
+        | RpcLnPayState::AwaitingChange => None,
+    }
+}
+
+/// Whether paying can't succeed by trying again later. Anything else, such
+/// as an unreachable gateway or federation, is retried.
+fn is_definitive_pay_error(error: &anyhow::Error) -> bool {
+    matches!(
+        error.downcast_ref::<ErrorCode>(),
+        Some(
+            ErrorCode::InsufficientBalance(_)
+                | ErrorCode::SpendingLimitExceeded
+                | ErrorCode::InvalidInvoice
+                | ErrorCode::InvoiceAmountRequired
+        )
+    )
+}
+
+async fn pay(
+    transfer: &FederationTransfer,
+    from_federation: &FederationV2,
+) -> anyhow::Result<FederationTransferState> {
+    let invoice: Bolt11Invoice = transfer.invoice.parse()?;
+    let mut retry_delay = MIN_POLL_INTERVAL;
+    loop {
+        // an earlier attempt may have started paying before failing or the
+        // app being closed
+        if let Some(operation_id) = from_federation
+            .find_ln_pay_operation(&transfer.invoice, transfer.created_at)
+            .await
+        {
+            info!(op = %operation_id.fmt_short(), "waiting on earlier transfer payment");
+            let mut poll_interval = MIN_POLL_INTERVAL;
+            loop {
+                let transaction = from_federation.get_transaction(operation_id).await?;
+                if let RpcTransactionKind::LnPay {
+                    state: Some(state), ..
+                } = &transaction.kind
+                    && let Some(resumed) = resumed_pay_state(state)
+                {
+                    match resumed {
+                        Some(state) => return Ok(state),
+                        None => break,
+                    }
+                }
+                fedimint_core::task::sleep(poll_interval).await;
+                poll_interval = (poll_interval * 2).min(MAX_POLL_INTERVAL);
+            }
+        }
+        match from_federation
+            .pay_invoice(&invoice, None, FrontendMetadata::default())
+            .await
+        {
+            Ok(_) => return Ok(FederationTransferState::Paid),
+            Err(error) if is_definitive_pay_error(&error) => {
+                return Ok(FederationTransferState::Failed {
+                    error: error.to_string(),
+                });
+            }
+            Err(error) => {
+                warn!(?error, ?retry_delay, "transfer payment failed, will retry");
+            }
+        }
+        fedimint_core::task::sleep(retry_delay).await;
+        retry_delay = (retry_delay * 2).min(MAX_POLL_INTERVAL);
+    }
+}
+
+/// Waits for the destination to claim the paid invoice, reclaiming the
+/// payment if its receive was canceled first.
+async fn claim(
+    transfer: &FederationTransfer,
+    to_federation: &FederationV2,
+) -> anyhow::Result<FederationTransferState> {
+    let mut poll_interval = MIN_POLL_INTERVAL;
+    loop {
+        let transaction = to_federation
+            .get_transaction(transfer.receive_operation_id)
+            .await?;
+        match transaction.kind {
+            RpcTransactionKind::LnReceive {
+                state: Some(RpcLnReceiveState::Claimed),
+                ..
+            } => return Ok(FederationTransferState::Completed),
+            RpcTransactionKind::LnReceive {
+                state: Some(RpcLnReceiveState::Canceled { .. }),
+                ..
+            } => {
+                return Ok(
+                    match to_federation
+                        .reclaim_ln_receive(transfer.receive_operation_id)
+                        .await?
+                    {
+                        // a pending reclaim carries on as a receive of its own
+                        RpcReclaimLnReceiveOutcome::Reclaimed
+                        | RpcReclaimLnReceiveOutcome::Pending => FederationTransferState::Completed,
+                        RpcReclaimLnReceiveOutcome::NothingToReclaim { reason } => {
+                            FederationTransferState::Failed { error: reason }
+                        }
+                    },
+                );
+            }
+            _ => {}
+        }
+        fedimint_core::task::sleep(poll_interval).await;
+        poll_interval = (poll_interval * 2).min(MAX_POLL_INTERVAL);
+    }
+}
+
+impl FederationV2 {
+    /// The newest operation paying `invoice`, looking no further back than
+    /// `since` (unix seconds).
+    async fn find_ln_pay_operation(&self, invoice: &str, since: u64) -> Option<OperationId> {
+        let mut start_after = None;
+        loop {
+            let page = self
+                .client
+                .operation_log()
+                .paginate_operations_rev(TRANSFER_SCAN_PAGE_SIZE, start_after)
+                .await;
+            let page_len = page.len();
+            for (key, _) in &page {
+                if to_unix_time(key.creation_time).is_ok_and(|created_at| created_at < since) {
+                    return None;
+                }
+                if let Ok(RpcTransaction {
+                    kind: RpcTransactionKind::LnPay { ln_invoice, .. },
+                    ..
+                }) = self.get_transaction(key.operation_id).await
+                    && ln_invoice == invoice
+                {
+                    return Some(key.operation_id);
+                }
+            }
+            if page_len < TRANSFER_SCAN_PAGE_SIZE {
+                return None;
+            }
+            start_after = page.last().map(|(last, _)| ChronologicalOperationLogKey {
+                creation_time: last.creation_time,
+                operation_id: last.operation_id,
+            });
+        }
+    }
+
+    /// Links `transaction` to the other leg if it is part of a transfer
+    /// between federations.
+    pub(crate) async fn decorate_federation_transfer_leg(&self, transaction: &mut RpcTransaction) {
+        let (ln_invoice, outgoing) = match &transaction.kind {
+            RpcTransactionKind::LnPay { ln_invoice, .. } => (ln_invoice, true),
+            RpcTransactionKind::LnReceive { ln_invoice, .. } => (ln_invoice, false),
+            _ => return,
+        };
+        let Ok(invoice) = ln_invoice.parse::<Bolt11Invoice>() else {
+            return;
+        };
+        let payment_hash = *invoice.payment_hash();
+        let Some(transfer) =
+            get_transfer(&self.runtime.federation_transfers_db(), payment_hash).await
+        else {
+            return;
+        };
+        let federation_id = self.federation_id().to_string();
+        let counterparty_federation_id = if outgoing {
+            (transfer.from_federation_id == federation_id).then_some(transfer.to_federation_id)
+        } else {
+            (transfer.to_federation_id == federation_id).then_some(transfer.from_federation_id)
+        };
+        transaction.federation_transfer =
+            counterparty_federation_id.map(|id| RpcFederationTransferLeg {
+                transfer_id: payment_hash.to_string(),
+                counterparty_federation_id: RpcFederationId(id),
+            });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resumed_pay_state() {
+        assert_eq!(
+            resumed_pay_state(&RpcLnPayState::Success {
+                preimage: String::new()
+            }),
+            Some(Some(FederationTransferState::Paid))
+        );
+        // refunded or canceled payments are paid again
+        assert_eq!(resumed_pay_state(&RpcLnPayState::Canceled), Some(None));
+        assert!(matches!(
+            resumed_pay_state(&RpcLnPayState::Failed),
+            Some(Some(FederationTransferState::Failed { .. }))
+        ));
+        // still in flight, keep waiting instead of paying twice
+        assert_eq!(resumed_pay_state(&RpcLnPayState::Created), None);
+        assert_eq!(
+            resumed_pay_state(&RpcLnPayState::Funded { block_height: 1 }),
+            None
+        );
+        assert_eq!(
+            resumed_pay_state(&RpcLnPayState::WaitingForRefund {
+                error_reason: String::new()
+            }),
+            None
+        );
+        assert_eq!(resumed_pay_state(&RpcLnPayState::AwaitingChange), None);
+    }
+
+    #[test]
+    fn test_is_definitive_pay_error() {
+        assert!(is_definitive_pay_error(&anyhow::anyhow!(
+            ErrorCode::InsufficientBalance(RpcAmount(Amount::ZERO))
+        )));
+        assert!(is_definitive_pay_error(
+            &anyhow::anyhow!(ErrorCode::SpendingLimitExceeded).context("global spending limit")
+        ));
+        // gateways and guardians may be reachable again later
+        assert!(!is_definitive_pay_error(&anyhow::anyhow!(
+            ErrorCode::NoLnGatewayAvailable
+        )));
+        assert!(!is_definitive_pay_error(&anyhow::anyhow!(
+            ErrorCode::Timeout
+        )));
+        assert!(!is_definitive_pay_error(&anyhow::anyhow!(
+            "connection refused"
+        )));
+    }
+}
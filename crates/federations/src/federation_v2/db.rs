@@ -145,12 +145,46 @@ pub enum BridgeDbPrefix {
     // defaulting it to the log's start.
     FedimintEventLogCursor = 0xcd,
 
+    // Prefix partition for the secondary transaction index used to search the
+    // operation log. See `transaction_index`.
+    TransactionIndexPrefix = 0xce,
+
+    // Last byte before fedimint's reserved range, so rather than spending it
+    // on a single feature it partitions further by [`FederationDataDbPrefix`].
+    FederationDataPrefix = 0xcf,
+
     // Do not use anything after this key (inclusive)
     // see https://github.com/fedimint/fedimint/pull/4445
     #[allow(dead_code)]
     FedimintInternalReservedStart = 0xd0,
 }
 
+/// Subprefixes inside [`BridgeDbPrefix::FederationDataPrefix`]. Each feature
+/// gets its own partition, see [`FederationV2::federation_data_db`].
+///
+/// [`FederationV2::federation_data_db`]: super::FederationV2::federation_data_db
+#[repr(u8)]
+pub enum FederationDataDbPrefix {
+    // See `unified_receive`.
+    UnifiedReceive = 0x01,
+    // See `spending_limits`.
+    SpendingLimits = 0x02,
+    // See `ecash_expiry_service`.
+    EcashExpiry = 0x03,
+    // See `ecash_receive_queue_service`.
+    EcashReceiveQueue = 0x04,
+    // See `end_of_life`.
+    EndOfLife = 0x05,
+    // See `guardian_health`.
+    GuardianHealth = 0x06,
+    // See `ln_gateway_service`.
+    GatewayStats = 0x07,
+    // See `wallet_repair`.
+    WalletRepair = 0x08,
+    // See `withdraw_confirmations`.
+    WithdrawConfirmations = 0x09,
+}
+
 #[derive(Debug, Decodable, Encodable)]
 pub struct LnurlReceivePendingKey(pub OperationId);
 
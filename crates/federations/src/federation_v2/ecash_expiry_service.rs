@@ -0,0 +1,305 @@
+//! Reclaims ecash sent with an expiry once it has expired without being
+//! claimed, which mostly matters for ecash sent in chat.
+//!
+//! Expiring sends are kept in the federation db until reclaimed, so expiries
+//! that pass while the app is closed are handled on the next start. Attempts
+//! that fail for reasons other than the recipient having claimed the ecash
+//! are retried with backoff.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use fedimint_client::db::ChronologicalOperationLogKey;
+use fedimint_core::Amount;
+use fedimint_core::core::OperationId;
+use fedimint_core::db::{Database, IDatabaseTransactionOpsCoreTyped};
+use fedimint_core::encoding::{Decodable, Encodable};
+use fedimint_core::{impl_db_lookup, impl_db_record};
+use futures::StreamExt;
+use rpc_types::error::ErrorCode;
+use rpc_types::{RpcOOBSpendState, RpcTransactionKind};
+use runtime::utils::to_unix_time;
+use tokio::sync::Notify;
+use tracing::{info, warn};
+
+use super::FederationV2;
+use super::db::FederationDataDbPrefix;
+
+/// Upper bound on how long the service sleeps, so a changed system clock is
+/// noticed eventually.
+const MAX_SLEEP: Duration = Duration::from_secs(10 * 60);
+
+/// Delay before retrying a failed reclaim, doubling with every failure.
+const RETRY_DELAY_SECS: u64 = 30;
+
+const MAX_RETRY_DELAY_SECS: u64 = 60 * 60;
+
+/// Operations read per page while looking for unclaimed ecash sends.
+const SEND_SCAN_PAGE_SIZE: usize = 100;
+
+#[repr(u8)]
+pub enum EcashExpiryDbPrefix {
+    // send operation id => ecash to reclaim and when
+    Expiry = 0x01,
+}
+
+#[derive(Debug, Clone, Encodable, Decodable)]
+pub struct EcashExpiry {
+    /// Unix time in seconds.
+    pub expires_at: u64,
+    pub ecash: String,
+    pub failed_attempts: u32,
+}
+
+impl EcashExpiry {
+    /// When to next try reclaiming, in unix seconds.
+    fn due_at(&self) -> u64 {
+        let retry_delay = match self.failed_attempts {
+            0 => 0,
+            failed_attempts => RETRY_DELAY_SECS
+                .saturating_mul(1 << (failed_attempts - 1).min(16))
+                .min(MAX_RETRY_DELAY_SECS),
+        };
+        self.expires_at.saturating_add(retry_delay)
+    }
+}
+
+#[derive(Debug, Clone, Encodable, Decodable)]
+pub struct EcashExpiryKey(pub OperationId);
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct EcashExpiryKeyPrefix;
+
+impl_db_record!(
+    key = EcashExpiryKey,
+    value = EcashExpiry,
+    db_prefix = EcashExpiryDbPrefix::Expiry,
+);
+
+impl_db_lookup!(key = EcashExpiryKey, query_prefix = EcashExpiryKeyPrefix);
+
+pub struct EcashExpiryService {
+    expiries_changed: Arc<Notify>,
+}
+
+impl EcashExpiryService {
+    pub fn new(fed: &FederationV2) -> Self {
+        let expiries_changed = Arc::new(Notify::new());
+        let notify = expiries_changed.clone();
+        fed.spawn_cancellable("ecash_expiry_service", |fed| async move {
+            loop {
+                let now = to_unix_time(fedimint_core::time::now()).unwrap_or_default();
+                let sleep = match fed.next_ecash_expiry().await {
+                    Some((operation_id, expiry)) if expiry.due_at() <= now => {
+                        fed.reclaim_expired_ecash(operation_id, expiry).await;
+                        continue;
+                    }
+                    Some((_, expiry)) => Duration::from_secs(expiry.due_at() - now).min(MAX_SLEEP),
+                    None => MAX_SLEEP,
+                };
+                let _ = fedimint_core::task::timeout(sleep, notify.notified()).await;
+            }
+        });
+        Self { expiries_changed }
+    }
+
+    fn wake(&self) {
+        self.expiries_changed.notify_one();
+    }
+}
+
+impl FederationV2 {
+    fn ecash_expiry_db(&self) -> Database {
+        self.federation_data_db(FederationDataDbPrefix::EcashExpiry)
+    }
+
+    /// Reclaims the ecash of send `operation_id` once `expiry_secs` have
+    /// passed, unless it has been claimed or canceled by then.
+    pub(super) async fn schedule_ecash_expiry(
+        &self,
+        operation_id: OperationId,
+        ecash: String,
+        expiry_secs: u64,
+    ) -> anyhow::Result<()> {
+        let expires_at = to_unix_time(fedimint_core::time::now())?.saturating_add(expiry_secs);
+        let mut dbtx = self.ecash_expiry_db().begin_transaction().await;
+        dbtx.insert_entry(
+            &EcashExpiryKey(operation_id),
+            &EcashExpiry {
+                expires_at,
+                ecash,
+                failed_attempts: 0,
+            },
+        )
+        .await;
+        dbtx.commit_tx_result().await?;
+        if let Some(service) = self.ecash_expiry_service.get() {
+            service.wake();
+        }
+        Ok(())
+    }
+
+    /// Forgets the expiry of `ecash`, for when it is canceled by hand.
+    pub(super) async fn unschedule_ecash_expiry(&self, ecash: &str) {
+        let db = self.ecash_expiry_db();
+        let mut dbtx = db.begin_transaction().await;
+        let keys = dbtx
+            .find_by_prefix(&EcashExpiryKeyPrefix)
+            .await
+            .filter_map(|(key, expiry)| async move { (expiry.ecash == ecash).then_some(key) })
+            .collect::<Vec<_>>()
+            .await;
+        for key in keys {
+            dbtx.remove_entry(&key).await;
+        }
+        if let Err(error) = dbtx.commit_tx_result().await {
+            warn!(?error, "failed to remove ecash expiry");
+        }
+    }
+
+    /// Reclaims every ecash send that was not reclaimed already, e.g. before
+    /// leaving the federation. Sends the recipient claimed are rejected by
+    /// the federation and skipped. Returns the amount of sends that could not
+    /// be reclaimed for other reasons.
+    pub(crate) async fn reclaim_unclaimed_ecash_sends(&self) -> Amount {
+        let mut sends = vec![];
+        let mut start_after = None;
+        loop {
+            let page = self
+                .client
+                .operation_log()
+                .paginate_operations_rev(SEND_SCAN_PAGE_SIZE, start_after)
+                .await;
+            for (key, _) in &page {
+                let Ok(transaction) = self.get_transaction(key.operation_id).await else {
+                    continue;
+                };
+                if let RpcTransactionKind::OobSend {
+                    state,
+                    oob_notes: Some(ecash),
+                } = transaction.kind
+                    && !matches!(
+                        state,
+                        Some(
+                            RpcOOBSpendState::UserCanceledProcessing
+                                | RpcOOBSpendState::UserCanceledSuccess
+                                | RpcOOBSpendState::UserCanceledFailure
+                                | RpcOOBSpendState::Refunded
+                        )
+                    )
+                {
+                    sends.push((key.operation_id, ecash, transaction.amount.0));
+                }
+            }
+            if page.len() < SEND_SCAN_PAGE_SIZE {
+                break;
+            }
+            start_after = page.last().map(|(last, _)| ChronologicalOperationLogKey {
+                creation_time: last.creation_time,
+                operation_id: last.operation_id,
+            });
+        }
+
+        let mut unreclaimed = Amount::ZERO;
+        for (operation_id, ecash, amount) in sends {
+            match self.cancel_ecash(ecash).await {
+                Ok(()) => {
+                    info!(operation_id = %operation_id.fmt_short(), "reclaimed unclaimed ecash");
+                }
+                Err(error) if is_ecash_claimed_error(&error) => {}
+                Err(error) => {
+                    warn!(?error, operation_id = %operation_id.fmt_short(), "failed to reclaim ecash");
+                    unreclaimed += amount;
+                }
+            }
+            self.send_transaction_event(operation_id).await;
+        }
+        unreclaimed
+    }
+
+    async fn next_ecash_expiry(&self) -> Option<(OperationId, EcashExpiry)> {
+        self.ecash_expiry_db()
+            .begin_transaction_nc()
+            .await
+            .find_by_prefix(&EcashExpiryKeyPrefix)
+            .await
+            .map(|(key, expiry)| (key.0, expiry))
+            .collect::<Vec<_>>()
+            .await
+            .into_iter()
+            .min_by_key(|(_, expiry)| expiry.due_at())
+    }
+
+    /// Cancelling reissues the notes, which the federation rejects with
+    /// [`ErrorCode::EcashCancelFailed`] if the recipient claimed them first.
+    /// Either way the expiry is settled; any other error is retried later.
+    async fn reclaim_expired_ecash(&self, operation_id: OperationId, mut expiry: EcashExpiry) {
+        info!(operation_id = %operation_id.fmt_short(), "reclaiming expired ecash");
+        let settled = match self.mint_ops.cancel_ecash(self, expiry.ecash.clone()).await {
+            Ok(()) => true,
+            Err(error) if is_ecash_claimed_error(&error) => {
+                info!(?error, "expired ecash was already claimed by the recipient");
+                true
+            }
+            Err(error) => {
+                warn!(
+                    ?error,
+                    failed_attempts = expiry.failed_attempts,
+                    "failed to reclaim expired ecash, will retry"
+                );
+                false
+            }
+        };
+        let mut dbtx = self.ecash_expiry_db().begin_transaction().await;
+        if settled {
+            dbtx.remove_entry(&EcashExpiryKey(operation_id)).await;
+        } else {
+            expiry.failed_attempts = expiry.failed_attempts.saturating_add(1);
+            dbtx.insert_entry(&EcashExpiryKey(operation_id), &expiry)
+                .await;
+        }
+        if let Err(error) = dbtx.commit_tx_result().await {
+            warn!(?error, "failed to update ecash expiry");
+        }
+        if settled {
+            self.send_transaction_event(operation_id).await;
+        }
+    }
+}
+
+fn is_ecash_claimed_error(error: &anyhow::Error) -> bool {
+    error.downcast_ref::<ErrorCode>() == Some(&ErrorCode::EcashCancelFailed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn failed_reclaims_back_off() {
+        let expiry = |failed_attempts| EcashExpiry {
+            expires_at: 1_000,
+            ecash: String::new(),
+            failed_attempts,
+        };
+        assert_eq!(expiry(0).due_at(), 1_000);
+        assert_eq!(expiry(1).due_at(), 1_030);
+        assert_eq!(expiry(2).due_at(), 1_060);
+        assert_eq!(expiry(3).due_at(), 1_120);
+        assert_eq!(expiry(50).due_at(), 1_000 + MAX_RETRY_DELAY_SECS);
+    }
+
+    #[test]
+    fn only_rejected_cancels_count_as_claimed() {
+        assert!(is_ecash_claimed_error(&anyhow::anyhow!(
+            ErrorCode::EcashCancelFailed
+        )));
+        assert!(is_ecash_claimed_error(
+            &anyhow::anyhow!("federation rejected the transaction")
+                .context(ErrorCode::EcashCancelFailed)
+        ));
+        assert!(!is_ecash_claimed_error(&anyhow::anyhow!(
+            "failed to reach the federation"
+        )));
+    }
+}
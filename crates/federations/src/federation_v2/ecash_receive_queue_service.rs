@@ -0,0 +1,351 @@
+//! Accepts ecash while the federation is unreachable.
+//!
+//! Reissuing needs a threshold of guardians, so when a receive fails to reach
+//! them the notes are only checked locally and queued in the federation db.
+//! This service retries the reissue, backing off while the federation stays
+//! unreachable. Until then a queued receive shows up in transaction history as
+//! offline; a receive the federation rejects, for example because the notes
+//! were spent meanwhile, stays there as failed.
+//!
+//! Only mint notes are queued. mintv2 notes can't be checked offline, so
+//! their receives fail while the federation is unreachable.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use bitcoin::hashes::{Hash as _, sha256};
+use fedimint_api_client::api::FederationError;
+use fedimint_connectors::error::ServerError;
+use fedimint_core::Amount;
+use fedimint_core::core::OperationId;
+use fedimint_core::db::{Database, IDatabaseTransactionOpsCoreTyped};
+use fedimint_core::encoding::{Decodable, Encodable};
+use fedimint_core::{impl_db_lookup, impl_db_record};
+use futures::StreamExt;
+use rpc_types::{
+    FrontendMetadata, RpcAmount, RpcOOBReissueState, RpcTransaction, RpcTransactionKind,
+    RpcTransactionListEntry,
+};
+use runtime::utils::to_unix_time;
+use tokio::sync::Notify;
+use tracing::{info, warn};
+
+use super::FederationV2;
+use super::db::{FederationDataDbPrefix, TransactionNotesKey};
+
+const MIN_RETRY_DELAY: Duration = Duration::from_secs(5);
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(5 * 60);
+
+#[repr(u8)]
+pub enum EcashReceiveQueueDbPrefix {
+    // queued receive id => the notes and their receive metadata
+    Queued = 0x01,
+}
+
+#[derive(Debug, Clone, Encodable, Decodable)]
+pub struct QueuedEcashReceive {
+    pub ecash: String,
+    pub amount: Amount,
+    /// Json, as frontend metadata has no consensus encoding.
+    pub frontend_meta: String,
+    /// Unix time in seconds.
+    pub created_at: u64,
+    /// Why the federation rejected the notes once it was reachable again.
+    pub failure: Option<String>,
+}
+
+#[derive(Debug, Clone, Encodable, Decodable)]
+pub struct QueuedEcashReceiveKey(pub OperationId);
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct QueuedEcashReceiveKeyPrefix;
+
+impl_db_record!(
+    key = QueuedEcashReceiveKey,
+    value = QueuedEcashReceive,
+    db_prefix = EcashReceiveQueueDbPrefix::Queued,
+);
+
+impl_db_lookup!(
+    key = QueuedEcashReceiveKey,
+    query_prefix = QueuedEcashReceiveKeyPrefix
+);
+
+pub struct EcashReceiveQueueService {
+    queued: Arc<Notify>,
+}
+
+impl EcashReceiveQueueService {
+    pub fn new(fed: &FederationV2) -> Self {
+        let queued = Arc::new(Notify::new());
+        let notify = queued.clone();
+        fed.spawn_cancellable("ecash_receive_queue_service", |fed| async move {
+            let mut delay = MIN_RETRY_DELAY;
+            loop {
+                let pending = fed
+                    .list_queued_ecash_receives()
+                    .await
+                    .into_iter()
+                    .filter(|(_, queued)| queued.failure.is_none())
+                    .collect::<Vec<_>>();
+                if pending.is_empty() {
+                    notify.notified().await;
+                    delay = MIN_RETRY_DELAY;
+                    continue;
+                }
+                let mut settled = true;
+                for (id, queued) in pending {
+                    settled &= fed.reissue_queued_ecash(id, queued).await;
+                }
+                if settled {
+                    delay = MIN_RETRY_DELAY;
+                    continue;
+                }
+                let _ = fedimint_core::task::timeout(delay, notify.notified()).await;
+                delay = (delay * 2).min(MAX_RETRY_DELAY);
+            }
+        });
+        Self { queued }
+    }
+
+    fn wake(&self) {
+        self.queued.notify_one();
+    }
+}
+
+/// Whether `error` comes from failing to reach the guardians, rather than from
+/// the notes being invalid or rejected.
+pub(super) fn is_federation_network_error(error: &anyhow::Error) -> bool {
+    error
+        .chain()
+        .any(|cause| cause.is::<FederationError>() || cause.is::<ServerError>())
+}
+
+impl FederationV2 {
+    fn ecash_receive_queue_db(&self) -> Database {
+        self.federation_data_db(FederationDataDbPrefix::EcashReceiveQueue)
+    }
+
+    /// Derived from the notes, so queueing the same ecash twice is a no-op.
+    fn queued_ecash_receive_id(ecash: &str) -> OperationId {
+        OperationId(sha256::Hash::hash(ecash.as_bytes()).to_byte_array())
+    }
+
+    /// Checks `ecash` against the federation's keys and queues it for
+    /// reissue once the federation is reachable. None if the mint can't check
+    /// notes offline, so nothing is queued.
+    pub async fn queue_ecash_receive(
+        &self,
+        ecash: String,
+        frontend_meta: FrontendMetadata,
+    ) -> anyhow::Result<Option<(Amount, OperationId)>> {
+        let Some(amount) = self.mint_ops.validate_ecash(self, &ecash).await else {
+            return Ok(None);
+        };
+        let amount = amount?;
+        let id = Self::queued_ecash_receive_id(&ecash);
+        let queued = QueuedEcashReceive {
+            ecash,
+            amount,
+            frontend_meta: serde_json::to_string(&frontend_meta)?,
+            created_at: to_unix_time(fedimint_core::time::now())?,
+            failure: None,
+        };
+        let mut dbtx = self.ecash_receive_queue_db().begin_transaction().await;
+        if dbtx
+            .insert_entry(&QueuedEcashReceiveKey(id), &queued)
+            .await
+            .is_some()
+        {
+            // keep the original entry, and with it its creation time
+            return Ok(Some((amount, id)));
+        }
+        dbtx.commit_tx_result().await?;
+        info!(id = %id.fmt_short(), "queued ecash receive");
+        if let Some(service) = self.ecash_receive_queue_service.get() {
+            service.wake();
+        }
+        self.send_transaction_event(id).await;
+        Ok(Some((amount, id)))
+    }
+
+    /// Amounts of the queued receives still waiting to be reissued.
+    pub(crate) async fn pending_queued_ecash_receives(&self) -> Vec<Amount> {
+        self.list_queued_ecash_receives()
+            .await
+            .into_iter()
+            .filter(|(_, queued)| queued.failure.is_none())
+            .map(|(_, queued)| queued.amount)
+            .collect()
+    }
+
+    async fn list_queued_ecash_receives(&self) -> Vec<(OperationId, QueuedEcashReceive)> {
+        self.ecash_receive_queue_db()
+            .begin_transaction_nc()
+            .await
+            .find_by_prefix(&QueuedEcashReceiveKeyPrefix)
+            .await
+            .map(|(key, queued)| (key.0, queued))
+            .collect()
+            .await
+    }
+
+    /// Returns whether the receive is settled: reissued, or rejected by the
+    /// federation. Network errors leave it queued for the next try.
+    async fn reissue_queued_ecash(&self, id: OperationId, queued: QueuedEcashReceive) -> bool {
+        let frontend_meta = serde_json::from_str(&queued.frontend_meta).unwrap_or_default();
+        let key = QueuedEcashReceiveKey(id);
+        let mut dbtx = self.ecash_receive_queue_db().begin_transaction().await;
+        let rejected = match self
+            .mint_ops
+            .receive_ecash(self, queued.ecash.clone(), frontend_meta)
+            .await
+        {
+            Ok((_, operation_id)) => {
+                info!(
+                    id = %id.fmt_short(),
+                    operation_id = %operation_id.fmt_short(),
+                    "reissued queued ecash receive"
+                );
+                dbtx.remove_entry(&key).await;
+                false
+            }
+            Err(error) if is_federation_network_error(&error) => {
+                info!(?error, id = %id.fmt_short(), "federation still unreachable");
+                return false;
+            }
+            Err(error) => {
+                warn!(?error, id = %id.fmt_short(), "queued ecash receive rejected");
+                dbtx.insert_entry(
+                    &key,
+                    &QueuedEcashReceive {
+                        failure: Some(error.to_string()),
+                        ..queued
+                    },
+                )
+                .await;
+                true
+            }
+        };
+        if let Err(error) = dbtx.commit_tx_result().await {
+            warn!(?error, "failed to update queued ecash receive");
+            return false;
+        }
+        // a reissued receive reports through its own operation, which
+        // replaces the queued entry in transaction history
+        if rejected {
+            self.send_transaction_event(id).await;
+        } else {
+            self.remove_indexed_transaction(id).await;
+        }
+        true
+    }
+
+    /// The queued receive `id` as shown in transaction history, if there is
+    /// one.
+    pub(super) async fn queued_ecash_receive_tx(&self, id: OperationId) -> Option<RpcTransaction> {
+        let queued = self
+            .ecash_receive_queue_db()
+            .begin_transaction_nc()
+            .await
+            .get_value(&QueuedEcashReceiveKey(id))
+            .await?;
+        Some(self.queued_ecash_receive_to_tx(id, queued).await)
+    }
+
+    async fn queued_ecash_receive_to_tx(
+        &self,
+        id: OperationId,
+        queued: QueuedEcashReceive,
+    ) -> RpcTransaction {
+        let state = match queued.failure {
+            None => RpcOOBReissueState::Offline,
+            Some(error) => RpcOOBReissueState::Failed { error },
+        };
+        RpcTransaction {
+            id: id.fmt_full().to_string(),
+            amount: RpcAmount(queued.amount),
+            fedi_app_fee_status: None,
+            fedi_guardian_fee_status: None,
+            fedi_app_fee_discount: None,
+            txn_notes: self.dbtx().await.get_value(&TransactionNotesKey(id)).await,
+            tx_date_fiat_info: None,
+            frontend_metadata: serde_json::from_str(&queued.frontend_meta).unwrap_or_default(),
+            kind: RpcTransactionKind::OobReceive { state: Some(state) },
+            outcome_time: None,
+            unified_receive: None,
+            federation_transfer: None,
+        }
+    }
+
+    /// Queued receives have no operation yet, so they are invisible without
+    /// this. First page only, since this truncates. `oldest_op_time` is None
+    /// when the page reached the end of the log.
+    pub(super) async fn merge_queued_ecash_receives(
+        &self,
+        entries: &mut Vec<Result<RpcTransactionListEntry, String>>,
+        limit: usize,
+        oldest_op_time: Option<u64>,
+    ) {
+        let queued = self.list_queued_ecash_receives().await;
+        if queued.is_empty() {
+            return;
+        }
+        for (id, queued) in queued {
+            // it belongs on a later page
+            if oldest_op_time.is_some_and(|oldest| queued.created_at < oldest) {
+                continue;
+            }
+            entries.push(Ok(RpcTransactionListEntry {
+                created_at: queued.created_at,
+                transaction: self.queued_ecash_receive_to_tx(id, queued).await,
+            }));
+        }
+        entries.sort_by(|a, b| Self::entry_created_at(b).cmp(&Self::entry_created_at(a)));
+        entries.truncate(limit);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::anyhow;
+    use rpc_types::error::ErrorCode;
+
+    use super::*;
+
+    #[test]
+    fn test_detects_federation_network_errors() {
+        let error = anyhow!(FederationError::new_one_peer(
+            0_u16.into(),
+            "submit_transaction",
+            (),
+            ServerError::Connection(anyhow!("guardian offline")),
+        ))
+        .context("reissue failed");
+        assert!(is_federation_network_error(&error));
+
+        let error = anyhow!(ServerError::Connection(anyhow!("guardian offline")));
+        assert!(is_federation_network_error(&error));
+    }
+
+    #[test]
+    fn test_rejections_are_not_network_errors() {
+        let error = anyhow!("operation already exists").context(ErrorCode::EcashAlreadySpent);
+        assert!(!is_federation_network_error(&error));
+
+        let error = anyhow!(ErrorCode::InvalidEcash);
+        assert!(!is_federation_network_error(&error));
+    }
+
+    #[test]
+    fn test_queued_ecash_receive_id_is_stable() {
+        assert_eq!(
+            FederationV2::queued_ecash_receive_id("ecash"),
+            FederationV2::queued_ecash_receive_id("ecash"),
+        );
+        assert_ne!(
+            FederationV2::queued_ecash_receive_id("ecash"),
+            FederationV2::queued_ecash_receive_id("other ecash"),
+        );
+    }
+}
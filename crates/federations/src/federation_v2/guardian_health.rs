@@ -0,0 +1,241 @@
+//! Records how reachable each guardian is over time and alerts when a
+//! federation gets close to losing consensus.
+//!
+//! Every few minutes all guardians are asked for their status and the result
+//! is kept in the federation db for a week. Whenever the health level changes
+//! a `guardianHealth` event is sent, so users learn that the federation is at
+//! risk before their payments start failing.
+
+use std::time::Duration;
+
+use fedimint_core::db::{Database, IDatabaseTransactionOpsCoreTyped};
+use fedimint_core::encoding::{Decodable, Encodable};
+use fedimint_core::{impl_db_lookup, impl_db_record};
+use futures::StreamExt;
+use rpc_types::event::{Event, TypedEventExt as _};
+use rpc_types::{
+    GuardianStatus, RpcGuardianHealth, RpcGuardianHealthCheck, RpcGuardianHealthHistory,
+    RpcGuardianHealthLevel,
+};
+use runtime::utils::to_unix_time;
+use tracing::{info, warn};
+
+use super::FederationV2;
+use super::db::FederationDataDbPrefix;
+
+const CHECK_INTERVAL: Duration = Duration::from_secs(5 * 60);
+const RETENTION_SECS: u64 = 7 * 24 * 60 * 60;
+
+#[repr(u8)]
+pub enum GuardianHealthDbPrefix {
+    // unix time of the check => latency of each guardian
+    Check = 0x01,
+    // level of the latest check
+    Level = 0x02,
+}
+
+#[derive(Debug, Clone, Encodable, Decodable)]
+pub struct GuardianHealthCheck {
+    pub guardians: Vec<GuardianHealthEntry>,
+}
+
+#[derive(Debug, Clone, Encodable, Decodable)]
+pub struct GuardianHealthEntry {
+    pub guardian: String,
+    /// `None` if the guardian did not answer.
+    pub latency_ms: Option<u32>,
+}
+
+#[derive(Debug, Clone, Encodable, Decodable)]
+pub struct GuardianHealthCheckKey(pub u64);
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct GuardianHealthCheckKeyPrefix;
+
+impl_db_record!(
+    key = GuardianHealthCheckKey,
+    value = GuardianHealthCheck,
+    db_prefix = GuardianHealthDbPrefix::Check,
+);
+
+impl_db_lookup!(
+    key = GuardianHealthCheckKey,
+    query_prefix = GuardianHealthCheckKeyPrefix
+);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Encodable, Decodable)]
+pub enum GuardianHealthLevel {
+    Healthy,
+    Degraded,
+    AtRisk,
+    BelowThreshold,
+}
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct GuardianHealthLevelKey;
+
+impl_db_record!(
+    key = GuardianHealthLevelKey,
+    value = GuardianHealthLevel,
+    db_prefix = GuardianHealthDbPrefix::Level,
+);
+
+/// Guardians that must be online for `total` guardians to reach consensus.
+fn consensus_threshold(total: usize) -> usize {
+    total - total.saturating_sub(1) / 3
+}
+
+impl GuardianHealthLevel {
+    fn new(online: usize, total: usize) -> Self {
+        let threshold = consensus_threshold(total);
+        if online >= total {
+            Self::Healthy
+        } else if online > threshold {
+            Self::Degraded
+        } else if online == threshold {
+            Self::AtRisk
+        } else {
+            Self::BelowThreshold
+        }
+    }
+
+    fn to_rpc(self) -> RpcGuardianHealthLevel {
+        match self {
+            Self::Healthy => RpcGuardianHealthLevel::Healthy,
+            Self::Degraded => RpcGuardianHealthLevel::Degraded,
+            Self::AtRisk => RpcGuardianHealthLevel::AtRisk,
+            Self::BelowThreshold => RpcGuardianHealthLevel::BelowThreshold,
+        }
+    }
+}
+
+impl FederationV2 {
+    fn guardian_health_db(&self) -> Database {
+        self.federation_data_db(FederationDataDbPrefix::GuardianHealth)
+    }
+
+    pub(super) async fn monitor_guardian_health(&self) {
+        loop {
+            if let Err(error) = self.check_guardian_health().await {
+                warn!(?error, "failed to check guardian health");
+            }
+            fedimint_core::task::sleep(CHECK_INTERVAL).await;
+        }
+    }
+
+    async fn check_guardian_health(&self) -> anyhow::Result<()> {
+        let now = fedimint_core::time::now();
+        let statuses = self.guardian_status_no_cache().await?;
+        // spare the next `guardian_status` caller a round of requests
+        *self.guardian_status_cache.lock().await = Some((now, Ok(statuses.clone())));
+
+        let guardians = statuses
+            .into_iter()
+            .map(|status| match status {
+                GuardianStatus::Online {
+                    guardian,
+                    latency_ms,
+                } => GuardianHealthEntry {
+                    guardian,
+                    latency_ms: Some(latency_ms),
+                },
+                GuardianStatus::Error { guardian, .. }
+                | GuardianStatus::Timeout { guardian, .. } => GuardianHealthEntry {
+                    guardian,
+                    latency_ms: None,
+                },
+            })
+            .collect::<Vec<_>>();
+        let total = guardians.len();
+        let online = guardians
+            .iter()
+            .filter(|entry| entry.latency_ms.is_some())
+            .count();
+        let level = GuardianHealthLevel::new(online, total);
+
+        let checked_at = to_unix_time(now)?;
+        let mut dbtx = self.guardian_health_db().begin_transaction().await;
+        dbtx.insert_entry(
+            &GuardianHealthCheckKey(checked_at),
+            &GuardianHealthCheck { guardians },
+        )
+        .await;
+        let expired = dbtx
+            .find_by_prefix(&GuardianHealthCheckKeyPrefix)
+            .await
+            .filter_map(|(key, _)| async move {
+                (key.0.saturating_add(RETENTION_SECS) < checked_at).then_some(key)
+            })
+            .collect::<Vec<_>>()
+            .await;
+        for key in expired {
+            dbtx.remove_entry(&key).await;
+        }
+        let previous = dbtx.insert_entry(&GuardianHealthLevelKey, &level).await;
+        dbtx.commit_tx_result().await?;
+
+        if previous.unwrap_or(GuardianHealthLevel::Healthy) != level {
+            info!(?level, online, total, "guardian health changed");
+            self.runtime.event_sink.typed_event(&Event::guardian_health(
+                self.rpc_federation_id(),
+                level.to_rpc(),
+                online as u32,
+                consensus_threshold(total) as u32,
+                total as u32,
+            ));
+        }
+        Ok(())
+    }
+
+    pub async fn get_guardian_health_history(&self) -> RpcGuardianHealthHistory {
+        let total = self.client.get_peer_urls().await.len();
+        let mut dbtx = self.guardian_health_db().begin_transaction_nc().await;
+        let level = dbtx.get_value(&GuardianHealthLevelKey).await;
+        let mut checks = dbtx
+            .find_by_prefix(&GuardianHealthCheckKeyPrefix)
+            .await
+            .map(|(key, check)| RpcGuardianHealthCheck {
+                checked_at: key.0,
+                guardians: check
+                    .guardians
+                    .into_iter()
+                    .map(|entry| RpcGuardianHealth {
+                        guardian: entry.guardian,
+                        latency_ms: entry.latency_ms,
+                    })
+                    .collect(),
+            })
+            .collect::<Vec<_>>()
+            .await;
+        // keys are not stored in time order
+        checks.sort_by_key(|check| check.checked_at);
+        RpcGuardianHealthHistory {
+            total: total as u32,
+            threshold: consensus_threshold(total) as u32,
+            level: level.map(GuardianHealthLevel::to_rpc),
+            checks,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_guardian_health_level() {
+        use GuardianHealthLevel::*;
+
+        assert_eq!(GuardianHealthLevel::new(4, 4), Healthy);
+        assert_eq!(GuardianHealthLevel::new(3, 4), AtRisk);
+        assert_eq!(GuardianHealthLevel::new(2, 4), BelowThreshold);
+
+        // 7 guardians tolerate 2 failures
+        assert_eq!(GuardianHealthLevel::new(6, 7), Degraded);
+        assert_eq!(GuardianHealthLevel::new(5, 7), AtRisk);
+        assert_eq!(GuardianHealthLevel::new(4, 7), BelowThreshold);
+
+        assert_eq!(GuardianHealthLevel::new(1, 1), Healthy);
+        assert_eq!(GuardianHealthLevel::new(0, 1), BelowThreshold);
+    }
+}
@@ -1,31 +1,158 @@
-use std::collections::HashSet;
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, HashSet};
 use std::str::FromStr;
 use std::time::Duration;
 
 use anyhow::Context as _;
 use bitcoin::secp256k1::{self, PublicKey};
 use fedimint_client::Client;
-use fedimint_core::db::IDatabaseTransactionOpsCoreTyped;
+use fedimint_core::Amount;
+use fedimint_core::db::{Database, IDatabaseTransactionOpsCoreTyped};
+use fedimint_core::encoding::{Decodable, Encodable};
+use fedimint_core::{impl_db_lookup, impl_db_record};
+use fedimint_ln_common::config::FeeToAmount as _;
 use fedimint_ln_common::{LightningGateway, LightningGatewayAnnouncement};
+use futures::StreamExt as _;
+use rpc_types::{RpcAmount, RpcGatewayScore};
+use runtime::constants::MILLION;
+use runtime::utils::to_unix_time;
 use tokio::sync::Mutex;
 use tracing::warn;
 
 use super::FederationV2;
 use super::client::ClientExt;
-use super::db::{LightningGatewayOverride, LightningGatewayOverrideKey};
+use super::db::{FederationDataDbPrefix, LightningGatewayOverride, LightningGatewayOverrideKey};
 
 pub const META_VETTED_GATEWAYS_KEY: &str = "vetted_gateways";
 
 #[derive(Debug)]
 pub struct LnGatewayService {
     last_gateway: Mutex<Option<secp256k1::PublicKey>>,
+    stats_db: Database,
 }
 
 /// Duration to fetch updates before gateways are about to expire
 const ABOUT_TO_EXPIRE_DURATION: Duration = Duration::from_secs(30);
 
+/// How long a failed gateway is ranked below gateways that did not fail.
+const RECENT_FAILURE_SECS: u64 = 10 * 60;
+
+/// Gateway fees are compared at this amount when the payment amount is not
+/// known, e.g. when creating an invoice.
+const REFERENCE_AMOUNT: Amount = Amount::from_sats(10_000);
+
+#[repr(u8)]
+pub enum GatewayStatsDbPrefix {
+    // gateway id => payments sent through it
+    Stats = 0x01,
+}
+
+/// Payments sent through a gateway by this client.
+#[derive(Debug, Clone, Encodable, Decodable)]
+pub struct GatewayStats {
+    pub attempts: u32,
+    pub successes: u32,
+    pub failures: u32,
+    /// Network fees of successful payments.
+    pub fees_paid: Amount,
+    /// Sum over successful payments.
+    pub total_latency_ms: u64,
+    /// Unix time in seconds.
+    pub last_failure_at: Option<u64>,
+}
+
+#[derive(Debug, Clone, Encodable, Decodable)]
+pub struct GatewayStatsKey(pub PublicKey);
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct GatewayStatsKeyPrefix;
+
+impl_db_record!(
+    key = GatewayStatsKey,
+    value = GatewayStats,
+    db_prefix = GatewayStatsDbPrefix::Stats,
+);
+
+impl_db_lookup!(key = GatewayStatsKey, query_prefix = GatewayStatsKeyPrefix);
+
+/// How a payment through a gateway ended.
+pub enum GatewayOutcome {
+    Success {
+        fee: Amount,
+        latency: Duration,
+    },
+    /// The gateway was unreachable or could not route the payment.
+    Failure,
+    /// The payment failed for reasons unrelated to the gateway.
+    Unknown,
+}
+
+impl Default for GatewayStats {
+    fn default() -> Self {
+        Self {
+            attempts: 0,
+            successes: 0,
+            failures: 0,
+            fees_paid: Amount::ZERO,
+            total_latency_ms: 0,
+            last_failure_at: None,
+        }
+    }
+}
+
+impl GatewayStats {
+    /// Chance in ppm that a payment through the gateway succeeds. One
+    /// imaginary success and failure are counted, so an unknown gateway
+    /// starts at half and a single payment does not decide. Halved for a
+    /// while after a failure, so other gateways are tried first.
+    fn score_ppm(&self, now: u64) -> u32 {
+        let successes = u64::from(self.successes);
+        let failures = u64::from(self.failures);
+        let score = (successes + 1) * MILLION / (successes + failures + 2);
+        let recently_failed = self
+            .last_failure_at
+            .is_some_and(|failed_at| now < failed_at.saturating_add(RECENT_FAILURE_SECS));
+        let score = if recently_failed { score / 2 } else { score };
+        score as u32
+    }
+
+    fn record(&mut self, outcome: &GatewayOutcome, now: u64) {
+        self.attempts = self.attempts.saturating_add(1);
+        match outcome {
+            GatewayOutcome::Success { fee, latency } => {
+                self.successes = self.successes.saturating_add(1);
+                self.fees_paid += *fee;
+                self.total_latency_ms = self
+                    .total_latency_ms
+                    .saturating_add(latency.as_millis().try_into().unwrap_or(u64::MAX));
+            }
+            GatewayOutcome::Failure => {
+                self.failures = self.failures.saturating_add(1);
+                self.last_failure_at = Some(now);
+            }
+            GatewayOutcome::Unknown => {}
+        }
+    }
+
+    pub fn to_rpc(&self, now: u64) -> RpcGatewayScore {
+        RpcGatewayScore {
+            score_ppm: self.score_ppm(now),
+            attempts: self.attempts,
+            successes: self.successes,
+            failures: self.failures,
+            fees_paid: RpcAmount(self.fees_paid),
+            average_latency_ms: (self.successes != 0).then(|| {
+                (self.total_latency_ms / u64::from(self.successes))
+                    .try_into()
+                    .unwrap_or(u32::MAX)
+            }),
+        }
+    }
+}
+
 impl LnGatewayService {
     pub fn new(fed: &FederationV2) -> Self {
+        let stats_db = fed.federation_data_db(FederationDataDbPrefix::GatewayStats);
         fed.spawn_cancellable("gateway_update_cache", |fed| async move {
             if let Ok(ln) = fed.client.ln() {
                 ln.update_gateway_cache_continuously(|gws| {
@@ -36,9 +163,39 @@ impl LnGatewayService {
         });
         Self {
             last_gateway: Mutex::new(None),
+            stats_db,
         }
     }
 
+    pub async fn gateway_stats(&self) -> BTreeMap<PublicKey, GatewayStats> {
+        self.stats_db
+            .begin_transaction_nc()
+            .await
+            .find_by_prefix(&GatewayStatsKeyPrefix)
+            .await
+            .map(|(key, stats)| (key.0, stats))
+            .collect::<BTreeMap<_, _>>()
+            .await
+    }
+
+    pub async fn record_gateway_outcome(
+        &self,
+        gateway_id: PublicKey,
+        outcome: GatewayOutcome,
+    ) -> anyhow::Result<()> {
+        let now = to_unix_time(fedimint_core::time::now())?;
+        let mut dbtx = self.stats_db.begin_transaction().await;
+        let mut stats = dbtx
+            .get_value(&GatewayStatsKey(gateway_id))
+            .await
+            .unwrap_or_default();
+        stats.record(&outcome, now);
+        dbtx.insert_entry(&GatewayStatsKey(gateway_id), &stats)
+            .await;
+        dbtx.commit_tx_result().await?;
+        Ok(())
+    }
+
     pub async fn get_gateway_override(&self, client: &Client) -> Option<secp256k1::PublicKey> {
         let mut dbtx = client.db().begin_transaction_nc().await;
         match dbtx.get_value(&LightningGatewayOverrideKey).await {
@@ -98,8 +255,10 @@ impl LnGatewayService {
         client: &Client,
     ) -> anyhow::Result<Option<LightningGateway>> {
         let gateway_override = self.get_gateway_override(client).await;
-        self.select_gateway_excluding(client, None, gateway_override)
-            .await
+        let gateways = self
+            .rank_gateways(client, gateway_override, REFERENCE_AMOUNT)
+            .await?;
+        Ok(gateways.into_iter().next())
     }
 
     pub async fn select_gateway_with_override(
@@ -107,30 +266,29 @@ impl LnGatewayService {
         client: &Client,
         gateway_override: Option<PublicKey>,
     ) -> anyhow::Result<Option<LightningGateway>> {
-        self.select_gateway_excluding(client, None, gateway_override)
-            .await
+        let gateways = self
+            .rank_gateways(client, gateway_override, REFERENCE_AMOUNT)
+            .await?;
+        Ok(gateways.into_iter().next())
     }
 
-    pub async fn refresh_cache_and_select_gateway_excluding(
+    /// Gateways to pay `amount` through, best first. Only the override if
+    /// one is set.
+    pub async fn ranked_gateways(
         &self,
         client: &Client,
-        excluded_gateway_id: Option<secp256k1::PublicKey>,
-    ) -> anyhow::Result<Option<LightningGateway>> {
-        let ln = client.ln()?;
+        amount: Amount,
+    ) -> anyhow::Result<Vec<LightningGateway>> {
         let gateway_override = self.get_gateway_override(client).await;
-        if let Err(error) = ln.update_gateway_cache().await {
-            warn!(?error, "updating gateway cache failed");
-        }
-        self.select_gateway_excluding(client, excluded_gateway_id, gateway_override)
-            .await
+        self.rank_gateways(client, gateway_override, amount).await
     }
 
-    async fn select_gateway_excluding(
+    async fn rank_gateways(
         &self,
         client: &Client,
-        excluded_gateway_id: Option<secp256k1::PublicKey>,
         gateway_override: Option<PublicKey>,
-    ) -> anyhow::Result<Option<LightningGateway>> {
+        amount: Amount,
+    ) -> anyhow::Result<Vec<LightningGateway>> {
         let ln = client.ln()?;
         let mut gws = Self::selectable_gateways(client, ln.list_gateways().await).await;
 
@@ -142,41 +300,90 @@ impl LnGatewayService {
             gws = Self::selectable_gateways(client, ln.list_gateways().await).await;
         }
 
-        if let Some(excluded_gateway_id) = excluded_gateway_id {
-            gws.retain(|g| g.info.gateway_id != excluded_gateway_id);
-        }
-
         // If override is set, it must be available or we error out
         if let Some(override_id) = gateway_override {
             let gw = gws
                 .iter()
                 .find(|g| g.info.gateway_id == override_id)
                 .context("gateway override is set but gateway is unavailable")?;
-            return Ok(Some(gw.info.clone()));
+            return Ok(vec![gw.info.clone()]);
         }
 
         if gws.is_empty() {
-            return Ok(None);
+            return Ok(vec![]);
         }
 
-        // Round-robin gateway selection:
-        // Gateways are sorted by ID for a stable ordering. We track the last
+        // Gateways are ranked by their score, then by their fee for `amount`.
+        let stats = self.gateway_stats().await;
+        let now = to_unix_time(fedimint_core::time::now())?;
+        let unknown_score = GatewayStats::default().score_ppm(now);
+        let rank = |g: &LightningGatewayAnnouncement| {
+            let score = stats
+                .get(&g.info.gateway_id)
+                .map_or(unknown_score, |stats| stats.score_ppm(now));
+            (Reverse(score), g.info.fees.to_amount(&amount))
+        };
+        gws.sort_by_key(|g| (rank(g), g.info.gateway_id));
+
+        // Round-robin among the best gateways that rank equally, which is all
+        // of them before any payment was made:
+        // Ties are sorted by ID for a stable ordering. We track the last
         // selected gateway ID in memory and pick the first gateway whose ID is
         // strictly greater than it. When we reach the end (no ID is greater),
         // we wrap around to index 0. This naturally handles gateways being
         // added or removed: if the last gateway disappears, we still advance
         // to the next one in sorted order rather than getting stuck.
-        gws.sort_by_key(|g| g.info.gateway_id);
+        let best = rank(&gws[0]);
+        let tied = gws.iter().take_while(|g| rank(g) == best).count();
         let mut last = self.last_gateway.lock().await;
         let idx = match *last {
-            Some(prev) => gws
+            Some(prev) => gws[..tied]
                 .iter()
                 .position(|g| g.info.gateway_id > prev)
                 .unwrap_or(0),
             None => 0,
         };
-        let chosen = &gws[idx];
-        *last = Some(chosen.info.gateway_id);
-        Ok(Some(chosen.info.clone()))
+        gws[..tied].rotate_left(idx);
+        *last = Some(gws[0].info.gateway_id);
+        Ok(gws.into_iter().map(|g| g.info).collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_gateway_score() {
+        let now = 1_000_000;
+        let unknown = GatewayStats::default();
+        assert_eq!(unknown.score_ppm(now), 500_000);
+
+        let mut reliable = GatewayStats::default();
+        for _ in 0..8 {
+            reliable.record(
+                &GatewayOutcome::Success {
+                    fee: Amount::from_sats(1),
+                    latency: Duration::from_millis(500),
+                },
+                now,
+            );
+        }
+        assert_eq!(reliable.score_ppm(now), 900_000);
+
+        // a failure ranks the gateway below unknown ones for a while
+        let mut failed = reliable.clone();
+        failed.record(&GatewayOutcome::Failure, now);
+        assert!(failed.score_ppm(now) < unknown.score_ppm(now));
+        assert!(failed.score_ppm(now + RECENT_FAILURE_SECS) > unknown.score_ppm(now));
+
+        failed.record(&GatewayOutcome::Unknown, now);
+        let score = failed.to_rpc(now + RECENT_FAILURE_SECS);
+        assert_eq!(
+            (score.attempts, score.successes, score.failures),
+            (10, 8, 1)
+        );
+        assert_eq!(score.fees_paid, RpcAmount(Amount::from_sats(8)));
+        assert_eq!(score.average_latency_ms, Some(500));
     }
 }
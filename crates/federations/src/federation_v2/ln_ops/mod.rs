@@ -10,6 +10,7 @@ use fedimint_core::util::SafeUrl;
 use fedimint_core::{Amount, apply, async_trait_maybe_send};
 use fedimint_ln_common::LightningGateway;
 use lightning_invoice::Bolt11Invoice;
+use rpc_types::error::ErrorCode;
 use rpc_types::{
     FrontendMetadata, RpcFeeDetails, RpcLightningGateway, RpcPayInvoiceResponse,
     RpcPrevPayInvoiceResult,
@@ -54,6 +55,34 @@ impl std::error::Error for Lnv2SendCreated {
     }
 }
 
+/// A fee remittance payment that failed without leaving any funds in flight:
+/// it was never funded, or its refund was claimed. Only then may the same
+/// fees be remitted another way.
+#[derive(Debug)]
+pub(crate) struct FeeRemittanceNotPaid(pub anyhow::Error);
+
+impl std::fmt::Display for FeeRemittanceNotPaid {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        std::fmt::Display::fmt(&self.0, f)
+    }
+}
+
+impl std::error::Error for FeeRemittanceNotPaid {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        self.0.chain().nth(1)
+    }
+}
+
+/// Amount a payment of `invoice` sends, on which Fedi fees are charged. Both
+/// lightning modules take the amount from the invoice itself, so invoices that
+/// leave it to the payer can't be paid yet.
+pub(crate) fn invoice_payment_amount(invoice: &Bolt11Invoice) -> Result<Amount> {
+    invoice
+        .amount_milli_satoshis()
+        .map(Amount::from_msats)
+        .ok_or_else(|| anyhow::Error::from(ErrorCode::InvoiceAmountRequired))
+}
+
 #[apply(async_trait_maybe_send!)]
 pub trait LnOps: MaybeSend + MaybeSync {
     async fn generate_invoice(
@@ -63,7 +92,7 @@ pub trait LnOps: MaybeSend + MaybeSync {
         description: String,
         expiry_time: Option<u64>,
         frontend_meta: FrontendMetadata,
-    ) -> Result<Bolt11Invoice>;
+    ) -> Result<(OperationId, Bolt11Invoice)>;
 
     async fn estimate_ln_fees(
         &self,
@@ -71,10 +100,13 @@ pub trait LnOps: MaybeSend + MaybeSync {
         invoice: &Bolt11Invoice,
     ) -> Result<RpcFeeDetails>;
 
+    /// Fails with [`ErrorCode::NetworkFeeExceedsBudget`] rather than pay a
+    /// gateway fee above `max_network_fee`.
     async fn pay_invoice(
         &self,
         fed: &FederationV2,
         invoice: &Bolt11Invoice,
+        max_network_fee: Option<Amount>,
         frontend_meta: FrontendMetadata,
     ) -> Result<RpcPayInvoiceResponse>;
 
@@ -90,7 +122,7 @@ pub trait LnOps: MaybeSend + MaybeSync {
         fed: &FederationV2,
         invoice: &Bolt11Invoice,
         remittance: FeeRemittance,
-    ) -> Result<RpcPayInvoiceResponse>;
+    ) -> Result<OperationId>;
 
     async fn get_prev_pay_invoice_result(
         &self,
@@ -132,3 +164,43 @@ pub enum Version {
     V1,
     V2,
 }
+
+#[cfg(test)]
+mod tests {
+    use bitcoin::hashes::{Hash as _, sha256};
+    use bitcoin::secp256k1::{Secp256k1, SecretKey};
+    use lightning_invoice::{Currency, InvoiceBuilder, PaymentSecret};
+
+    use super::*;
+
+    fn invoice(amount_msats: Option<u64>) -> Bolt11Invoice {
+        let secp = Secp256k1::new();
+        let key = SecretKey::from_slice(&[42; 32]).unwrap();
+        let builder = InvoiceBuilder::new(Currency::Regtest)
+            .description("donation".to_owned())
+            .payment_hash(sha256::Hash::hash(&[0; 32]))
+            .payment_secret(PaymentSecret([0; 32]))
+            .current_timestamp()
+            .min_final_cltv_expiry_delta(18);
+        let builder = match amount_msats {
+            Some(amount_msats) => builder.amount_milli_satoshis(amount_msats),
+            None => builder,
+        };
+        builder
+            .build_signed(|hash| secp.sign_ecdsa_recoverable(hash, &key))
+            .unwrap()
+    }
+
+    #[test]
+    fn test_invoice_payment_amount() {
+        assert_eq!(
+            invoice_payment_amount(&invoice(Some(21_000))).unwrap(),
+            Amount::from_msats(21_000)
+        );
+        let error = invoice_payment_amount(&invoice(None)).unwrap_err();
+        assert_eq!(
+            error.downcast_ref::<ErrorCode>(),
+            Some(&ErrorCode::InvoiceAmountRequired)
+        );
+    }
+}
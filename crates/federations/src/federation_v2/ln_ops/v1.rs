@@ -1,3 +1,5 @@
+use std::collections::BTreeSet;
+
 use anyhow::{Context, Result, anyhow, bail};
 use bitcoin::hex::DisplayHex;
 use fedimint_client::module::oplog::OperationLogEntry;
@@ -7,6 +9,7 @@ use fedimint_ln_client::{
     InternalPayState, LightningOperationMeta, LightningOperationMetaPay,
     LightningOperationMetaVariant, LnPayState, LnReceiveState, OutgoingLightningPayment, PayType,
 };
+use fedimint_ln_common::LightningGateway;
 use fedimint_ln_common::config::FeeToAmount;
 use futures::StreamExt;
 use lightning_invoice::{Bolt11Invoice, RoutingFees};
@@ -16,16 +19,22 @@ use rpc_types::{
     RpcLightningGateway, RpcLightningGatewayId, RpcPayInvoiceResponse, RpcPrevPayInvoiceResult,
     RpcTransactionDirection, RpcTransactionKind,
 };
+use runtime::utils::to_unix_time;
 use tracing::{error, info, warn};
 
-use super::{FeeRemittance, FeeRemittanceGatewayOverride, FeeRemittanceRoute, LnOps};
+use super::{
+    FeeRemittance, FeeRemittanceGatewayOverride, FeeRemittanceNotPaid, FeeRemittanceRoute, LnOps,
+    invoice_payment_amount,
+};
 use crate::federation_v2::client::ClientExt;
+use crate::federation_v2::ln_gateway_service::GatewayOutcome;
 use crate::federation_v2::{
-    FederationTransactionParts, FederationV2, FediFeeStream, GatewayPayError, display_currency,
-    get_max_spendable_amount, handle_pay_bolt11_invoice_error, internal_pay_is_bad_state,
-    internal_pay_update_sanitized_log, invoice_has_internal_payment_markers,
-    invoice_routes_back_to_federation, is_gateway_availability_error, ln_pay_update_sanitized_log,
-    ln_receive_update_sanitized_log, log_update, zero_gateway_fees,
+    FederationTransactionParts, FederationV2, FediFeeRules, FediFeeStream, GatewayPayError,
+    display_currency, get_max_spendable_amount, handle_pay_bolt11_invoice_error,
+    internal_pay_is_bad_state, internal_pay_update_sanitized_log,
+    invoice_has_internal_payment_markers, invoice_routes_back_to_federation,
+    is_gateway_availability_error, ln_pay_update_sanitized_log, ln_receive_update_sanitized_log,
+    log_update, zero_gateway_fees,
 };
 
 pub struct LnOpsV1;
@@ -46,6 +55,112 @@ fn fee_remittance_invoice_amount(
     ))
 }
 
+/// The gateway could not route the payment and the funds were refunded.
+#[derive(Debug, thiserror::Error)]
+#[error("Lightning payment failed, got refund")]
+struct LnPayRefunded;
+
+/// The payment was never funded, so nothing needs to be refunded.
+#[derive(Debug, thiserror::Error)]
+#[error("Failed to fund internal lightning payment")]
+struct LnPayFundingFailed;
+
+/// The balance covers the payment only through a cheaper gateway, if any.
+fn insufficient_balance(error: &anyhow::Error) -> Option<Amount> {
+    match error.downcast_ref::<ErrorCode>() {
+        Some(ErrorCode::InsufficientBalance(max)) => Some(max.0),
+        _ => None,
+    }
+}
+
+/// Pays through `gateways`, best first, until one does not fail in a way
+/// another gateway could avoid. An unreachable gateway makes `refresh` fetch
+/// and rank the gateways anew; a gateway that refunded the payment, or whose
+/// fees the balance does not cover, gives way to the next. After a refund the
+/// next gateway gets a fresh payment attempt, since the funds came back.
+async fn pay_with_fallback<G, K, T, Pay, PayFut, Refresh, RefreshFut>(
+    mut gateways: Vec<G>,
+    gateway_id: impl Fn(&G) -> K,
+    mut pay: Pay,
+    mut refresh: Refresh,
+) -> Result<T>
+where
+    G: Clone,
+    K: Ord + std::fmt::Debug,
+    Pay: FnMut(G) -> PayFut,
+    PayFut: Future<Output = Result<T>>,
+    Refresh: FnMut() -> RefreshFut,
+    RefreshFut: Future<Output = Result<Vec<G>>>,
+{
+    let mut tried = BTreeSet::new();
+    let mut insufficient: Option<(Amount, anyhow::Error)> = None;
+    let mut refunded = None;
+    while let Some(gateway) = gateways
+        .iter()
+        .find(|gateway| !tried.contains(&gateway_id(gateway)))
+        .cloned()
+    {
+        let id = gateway_id(&gateway);
+        match pay(gateway).await {
+            Err(error) if is_gateway_availability_error(&error) => {
+                warn!(
+                    ?error,
+                    ?id,
+                    "lightning gateway unavailable, trying the next one"
+                );
+                gateways = refresh().await?;
+            }
+            Err(error) if error.is::<LnPayRefunded>() => {
+                info!(
+                    ?id,
+                    "lightning gateway refunded the payment, trying the next one"
+                );
+                refunded = Some(error);
+            }
+            Err(error) => {
+                let Some(max) = insufficient_balance(&error) else {
+                    return Err(error);
+                };
+                info!(
+                    ?id,
+                    "balance does not cover the gateway fees, trying the next one"
+                );
+                if insufficient.as_ref().is_none_or(|(best, _)| *best < max) {
+                    insufficient = Some((max, error));
+                }
+            }
+            Ok(value) => return Ok(value),
+        }
+        tried.insert(id);
+    }
+    match (refunded, insufficient) {
+        (Some(error), _) | (None, Some((_, error))) => Err(error),
+        (None, None) => bail!(ErrorCode::NoLnGatewayAvailable),
+    }
+}
+
+/// Logic copied from fedimint-ln-client to determine when the destination of
+/// a lightning invoice is within the current federation, which needs no
+/// gateway.
+async fn is_internal_payment(fed: &FederationV2, invoice: &Bolt11Invoice) -> bool {
+    let Ok(markers) = fed.client.get_internal_payment_markers() else {
+        return false;
+    };
+    if invoice_has_internal_payment_markers(invoice, markers) {
+        return true;
+    }
+    let Ok(ln) = fed.client.ln() else {
+        return false;
+    };
+    let gateways = ln
+        .list_gateways()
+        .await
+        .into_iter()
+        .map(|g| g.info)
+        .collect::<Vec<_>>();
+    invoice_routes_back_to_federation(invoice, gateways)
+}
+
 impl LnOpsV1 {
     async fn subscribe_invoice(
         &self,
@@ -128,7 +243,7 @@ impl LnOpsV1 {
                         }
                         InternalPayState::RefundSuccess { .. } => {
                             updates.next().await;
-                            bail!("Internal lightning payment failed, got refund");
+                            bail!(LnPayRefunded);
                         }
                         InternalPayState::RefundError { .. } => {
                             updates.next().await;
@@ -136,7 +251,7 @@ impl LnOpsV1 {
                         }
                         InternalPayState::FundingFailed { .. } => {
                             updates.next().await;
-                            bail!("Failed to fund internal lightning payment");
+                            bail!(LnPayFundingFailed);
                         }
                         InternalPayState::UnexpectedError(e) => {
                             updates.next().await;
@@ -181,12 +296,12 @@ impl LnOpsV1 {
                         LnPayState::Refunded { .. } => {
                             // TODO: better error message
                             updates.next().await;
-                            bail!("Lightning payment failed, got refund")
+                            bail!(LnPayRefunded)
                         }
                         LnPayState::Canceled => {
                             updates.next().await;
                             // FIXME: is this right?
-                            bail!("Lightning payment failed, got refund")
+                            bail!(LnPayRefunded)
                         }
                         LnPayState::UnexpectedError { error_message } => {
                             updates.next().await;
@@ -206,6 +321,92 @@ impl LnOpsV1 {
             }
         }
     }
+
+    /// Pays `invoice` through `gateway` and records how the gateway did.
+    #[allow(clippy::too_many_arguments)]
+    async fn pay_invoice_through(
+        &self,
+        fed: &FederationV2,
+        invoice: &Bolt11Invoice,
+        amount: Amount,
+        fees_by_stream: &[(FediFeeStream, Amount)],
+        fee_rules: &FediFeeRules,
+        gateway: Option<LightningGateway>,
+        extra_meta: LightningSendMetadata,
+    ) -> Result<RpcPayInvoiceResponse> {
+        let fedi_fee = FederationV2::total_fedi_fee_amount(fees_by_stream);
+        let gateway_fees = gateway.as_ref().map_or_else(zero_gateway_fees, |g| g.fees);
+        let gateway_id = gateway.as_ref().map(|g| g.gateway_id);
+        let network_fee = gateway_fees.base_msat as u64
+            + (amount.msats * gateway_fees.proportional_millionths as u64)
+                .div_ceil(runtime::constants::MILLION);
+
+        let spend_guard = fed.spend_guard.lock().await;
+        let virtual_balance = fed.get_balance().await;
+        let est_total_spend = amount.msats + fedi_fee.msats + network_fee;
+        if est_total_spend > virtual_balance.msats {
+            bail!(ErrorCode::InsufficientBalance(RpcAmount(
+                get_max_spendable_amount(virtual_balance, fee_rules, None, Some(gateway_fees))
+            )));
+        }
+
+        let started = fedimint_core::time::now();
+        let OutgoingLightningPayment { payment_type, .. } = match fed
+            .client
+            .ln()?
+            .pay_bolt11_invoice(gateway, invoice.to_owned(), extra_meta.clone())
+            .await
+        {
+            Ok(v) => v,
+            Err(error) if is_gateway_availability_error(&error) => {
+                if let Some(gateway_id) = gateway_id {
+                    fed.record_gateway_outcome(gateway_id, GatewayOutcome::Failure)
+                        .await;
+                }
+                return Err(error);
+            }
+            Err(error) => handle_pay_bolt11_invoice_error(error)?,
+        };
+        // already paid
+        if fed
+            .client
+            .operation_log()
+            .get_operation(payment_type.operation_id())
+            .await
+            .is_some_and(|o| o.outcome::<crate::federation_v2::PayState>().is_some())
+        {
+            bail!(ErrorCode::PayLnInvoiceAlreadyPaid);
+        }
+
+        fed.write_pending_send_fedi_fees(payment_type.operation_id(), amount, fee_rules)
+            .await?;
+        drop(spend_guard);
+
+        let _ = fed
+            .record_tx_date_fiat_info(
+                payment_type.operation_id(),
+                Amount::from_msats(est_total_spend),
+            )
+            .await;
+        let through_gateway = gateway_id.filter(|_| matches!(payment_type, PayType::Lightning(_)));
+        let result = self
+            .subscribe_to_ln_pay(fed, payment_type, extra_meta)
+            .await;
+        if let Some(gateway_id) = through_gateway {
+            let outcome = match &result {
+                Ok(_) => GatewayOutcome::Success {
+                    fee: Amount::from_msats(network_fee),
+                    latency: fedimint_core::time::now()
+                        .duration_since(started)
+                        .unwrap_or_default(),
+                },
+                Err(error) if error.is::<LnPayRefunded>() => GatewayOutcome::Failure,
+                Err(_) => GatewayOutcome::Unknown,
+            };
+            fed.record_gateway_outcome(gateway_id, outcome).await;
+        }
+        result
+    }
 }
 
 #[apply(async_trait_maybe_send!)]
@@ -217,12 +418,12 @@ impl LnOps for LnOpsV1 {
         description: String,
         expiry_time: Option<u64>,
         frontend_meta: FrontendMetadata,
-    ) -> Result<Bolt11Invoice> {
+    ) -> Result<(OperationId, Bolt11Invoice)> {
         // some apps have issues paying invoices that are in msats
         // so round up amount to nearest sat
         let amount = Amount::from_sats(amount.0.msats.div_ceil(1000));
-        let fee_ppms = fed
-            .get_fee_ppms_by_stream(fedimint_ln_common::KIND, RpcTransactionDirection::Receive)
+        let fee_rules = fed
+            .get_fee_rules_by_stream(fedimint_ln_common::KIND, RpcTransactionDirection::Receive)
             .await?;
         let gateway = fed.select_gateway().await?;
         let (operation_id, invoice, _) = fed
@@ -239,13 +440,13 @@ impl LnOps for LnOpsV1 {
             )
             .await?;
 
-        fed.write_pending_receive_fedi_fee_ppms(operation_id, &fee_ppms)
+        fed.write_pending_receive_fedi_fee_rules(operation_id, &fee_rules)
             .await?;
         let _ = fed.record_tx_date_fiat_info(operation_id, amount).await;
         self.subscribe_invoice(fed, operation_id, invoice.clone())
             .await?;
 
-        Ok(invoice)
+        Ok((operation_id, invoice))
     }
 
     async fn estimate_ln_fees(
@@ -253,11 +454,7 @@ impl LnOps for LnOpsV1 {
         fed: &FederationV2,
         invoice: &Bolt11Invoice,
     ) -> Result<RpcFeeDetails> {
-        let amount = Amount::from_msats(
-            invoice
-                .amount_milli_satoshis()
-                .ok_or(anyhow!("Invoice missing amount"))?,
-        );
+        let amount = invoice_payment_amount(invoice)?;
 
         // Fedi app fee applies regardless of internal/external payment
         let fees_by_stream = fed
@@ -272,35 +469,18 @@ impl LnOps for LnOpsV1 {
         let fedi_guardian_fee =
             FederationV2::fedi_fee_amount_for_stream(&fees_by_stream, FediFeeStream::Guardian);
 
-        // Logic inside the if statement below is currently copied from
-        // fedimint-ln-client to determine when the destination of a lightning invoice
-        // is within the current federation so that we know to show a 0 gateway fee.
-        let mut is_internal_payment = false;
-        if let Ok(markers) = fed.client.get_internal_payment_markers() {
-            is_internal_payment = invoice_has_internal_payment_markers(invoice, markers);
-            if !is_internal_payment {
-                let gateways = fed
-                    .client
-                    .ln()?
-                    .list_gateways()
-                    .await
-                    .into_iter()
-                    .map(|g| g.info)
-                    .collect::<Vec<_>>();
-                is_internal_payment = invoice_routes_back_to_federation(invoice, gateways);
-            }
-        }
-
-        let network_fee = if is_internal_payment {
+        let network_fee = if is_internal_payment(fed, invoice).await {
             RpcAmount(Amount::ZERO)
         } else {
-            // External payments have a non-0 gateway fee in addition to Fedi app fee
+            // External payments have a non-0 gateway fee in addition to Fedi app fee.
+            // The payment is tried through the best ranked gateway first.
             let gateway = fed
-                .select_gateway()
+                .ranked_gateways(amount)
                 .await?
+                .into_iter()
+                .next()
                 .context("No gateway available")?;
-            let gateway_fees = gateway.fees;
-            RpcAmount(gateway_fees.to_amount(&amount))
+            RpcAmount(gateway.fees.to_amount(&amount))
         };
 
         Ok(RpcFeeDetails {
@@ -315,13 +495,10 @@ impl LnOps for LnOpsV1 {
         &self,
         fed: &FederationV2,
         invoice: &Bolt11Invoice,
+        max_network_fee: Option<Amount>,
         frontend_meta: FrontendMetadata,
     ) -> Result<RpcPayInvoiceResponse> {
-        // Has an amount
-        let amount_msat = invoice
-            .amount_milli_satoshis()
-            .ok_or(anyhow!("Invoice missing amount"))?;
-        let amount = Amount::from_msats(amount_msat);
+        let amount = invoice_payment_amount(invoice)?;
 
         // Same network
         let federation_network = fed
@@ -335,108 +512,62 @@ impl LnOps for LnOpsV1 {
             ))
         }
 
-        let fee_ppms = fed
-            .get_fee_ppms_by_stream(fedimint_ln_common::KIND, RpcTransactionDirection::Send)
+        let fee_rules = fed
+            .get_fee_rules_by_stream(fedimint_ln_common::KIND, RpcTransactionDirection::Send)
             .await?;
-        let fees_by_stream = fed
-            .get_fee_amounts_by_stream(
-                fedimint_ln_common::KIND,
-                RpcTransactionDirection::Send,
-                amount,
-            )
-            .await?;
-        let fedi_fee = FederationV2::total_fedi_fee_amount(&fees_by_stream);
-
-        let gateway = fed.select_gateway().await?;
-        let spend_guard = fed.spend_guard.lock().await;
-        let virtual_balance = fed.get_balance().await;
-        let ensure_spendable = |gateway_fees: RoutingFees| -> Result<u64> {
-            let network_fee = gateway_fees.base_msat as u64
-                + (amount.msats * gateway_fees.proportional_millionths as u64)
-                    .div_ceil(runtime::constants::MILLION);
-            let est_total_spend = amount.msats + fedi_fee.msats + network_fee;
-            if est_total_spend > virtual_balance.msats {
-                bail!(ErrorCode::InsufficientBalance(RpcAmount(
-                    get_max_spendable_amount(
-                        virtual_balance,
-                        FederationV2::total_fedi_fee_ppm(&fee_ppms),
-                        None,
-                        Some(gateway_fees),
-                    )
-                )));
-            }
-            Ok(est_total_spend)
-        };
-        let mut est_total_spend =
-            ensure_spendable(gateway.as_ref().map_or_else(zero_gateway_fees, |g| g.fees))?;
-
+        let fees_by_stream = fee_rules.fees_by_stream(amount);
         let extra_meta = LightningSendMetadata {
             is_fedi_fee_remittance: false,
             frontend_metadata: Some(frontend_meta),
         };
-        let ln = fed.client.ln()?;
-        let pay_result = ln
-            .pay_bolt11_invoice(gateway.clone(), invoice.to_owned(), extra_meta.clone())
-            .await;
-        let selected_gateway_id = gateway.as_ref().map(|g| g.gateway_id);
-        let OutgoingLightningPayment { payment_type, .. } = match (pay_result, selected_gateway_id)
-        {
-            (Ok(v), _) => v,
-            (Err(error), Some(failed_gateway_id)) if is_gateway_availability_error(&error) => {
-                warn!(
-                    ?error,
-                    failed_gateway_id = ?failed_gateway_id,
-                    "selected lightning gateway unavailable, refreshing gateway cache and retrying"
-                );
-                let retry_gateway = fed
-                    .refresh_cache_and_select_gateway_excluding(Some(failed_gateway_id))
-                    .await?
-                    .ok_or_else(|| anyhow!(ErrorCode::NoLnGatewayAvailable))?;
-                est_total_spend = ensure_spendable(retry_gateway.fees)?;
-                let retry_gateway_id = retry_gateway.gateway_id;
 
-                match ln
-                    .pay_bolt11_invoice(Some(retry_gateway), invoice.to_owned(), extra_meta.clone())
-                    .await
-                {
-                    Ok(v) => v,
-                    Err(error) if is_gateway_availability_error(&error) => {
-                        warn!(
-                            ?error,
-                            failed_gateway_id = ?failed_gateway_id,
-                            retry_gateway_id = ?retry_gateway_id,
-                            "retry lightning gateway unavailable"
-                        );
-                        bail!(ErrorCode::NoLnGatewayAvailable);
-                    }
-                    Err(error) => handle_pay_bolt11_invoice_error(error)?,
-                }
-            }
-            (Err(error), _) => handle_pay_bolt11_invoice_error(error)?,
+        let within_budget = |gateway: &LightningGateway| {
+            max_network_fee.is_none_or(|max| gateway.fees.to_amount(&amount) <= max)
         };
-        // already paid
-        if fed
-            .client
-            .operation_log()
-            .get_operation(payment_type.operation_id())
-            .await
-            .is_some_and(|o| o.outcome::<crate::federation_v2::PayState>().is_some())
-        {
-            bail!(ErrorCode::PayLnInvoiceAlreadyPaid);
+        let mut gateways = fed.ranked_gateways(amount).await?;
+        let internal = is_internal_payment(fed, invoice).await;
+        if !internal {
+            let available = gateways.len();
+            gateways.retain(within_budget);
+            if available != 0 && gateways.is_empty() {
+                bail!(ErrorCode::NetworkFeeExceedsBudget);
+            }
         }
-
-        fed.write_pending_send_fedi_fees(payment_type.operation_id(), &fees_by_stream)
-            .await?;
-        drop(spend_guard);
-
-        let _ = fed
-            .record_tx_date_fiat_info(
-                payment_type.operation_id(),
-                Amount::from_msats(est_total_spend),
+        let pay = |gateway| {
+            self.pay_invoice_through(
+                fed,
+                invoice,
+                amount,
+                &fees_by_stream,
+                &fee_rules,
+                gateway,
+                extra_meta.clone(),
             )
-            .await;
-        self.subscribe_to_ln_pay(fed, payment_type, extra_meta)
-            .await
+        };
+        // Payments within the federation need no gateway, so they are neither
+        // limited by the budget nor retried. Without any gateway the module
+        // tells whether one was needed.
+        if internal || gateways.is_empty() {
+            return match pay(gateways.into_iter().next()).await {
+                Err(error) if is_gateway_availability_error(&error) => {
+                    warn!(?error, "lightning gateway unavailable");
+                    bail!(ErrorCode::NoLnGatewayAvailable);
+                }
+                result => result,
+            };
+        }
+        pay_with_fallback(
+            gateways,
+            |gateway| gateway.gateway_id,
+            |gateway| pay(Some(gateway)),
+            move || async move {
+                fed.refresh_gateway_cache().await;
+                let mut gateways = fed.ranked_gateways(amount).await?;
+                gateways.retain(within_budget);
+                Ok(gateways)
+            },
+        )
+        .await
     }
 
     async fn prepare_fee_remittance(
@@ -467,7 +598,7 @@ impl LnOps for LnOpsV1 {
         fed: &FederationV2,
         invoice: &Bolt11Invoice,
         remittance: FeeRemittance,
-    ) -> Result<RpcPayInvoiceResponse> {
+    ) -> Result<OperationId> {
         let FeeRemittanceRoute::Lnv1 { gateway } = remittance.route else {
             bail!("lnv1 cannot pay lnv2 fee remittance");
         };
@@ -480,10 +611,20 @@ impl LnOps for LnOpsV1 {
             .client
             .ln()?
             .pay_bolt11_invoice(gateway, invoice.to_owned(), extra_meta.clone())
-            .await?;
+            .await
+            .map_err(|error| anyhow::Error::new(FeeRemittanceNotPaid(error)))?;
 
+        let operation_id = payment_type.operation_id();
         self.subscribe_to_ln_pay(fed, payment_type, extra_meta)
             .await
+            .map_err(|error| {
+                if error.is::<LnPayRefunded>() || error.is::<LnPayFundingFailed>() {
+                    anyhow::Error::new(FeeRemittanceNotPaid(error))
+                } else {
+                    error
+                }
+            })?;
+        Ok(operation_id)
     }
 
     async fn get_prev_pay_invoice_result(
@@ -713,6 +854,8 @@ impl LnOps for LnOpsV1 {
 
     async fn list_gateways(&self, fed: &FederationV2) -> anyhow::Result<Vec<RpcLightningGateway>> {
         let gateways = fed.client.ln()?.list_gateways().await;
+        let stats = fed.gateway_service()?.gateway_stats().await;
+        let now = to_unix_time(fedimint_core::time::now())?;
         Ok(gateways
             .into_iter()
             .map(|gw| RpcLightningGateway {
@@ -722,6 +865,13 @@ impl LnOps for LnOpsV1 {
                 api: gw.info.api.to_string(),
                 node_pub_key: rpc_types::RpcPublicKey(gw.info.node_pub_key),
                 gateway_id: rpc_types::RpcPublicKey(gw.info.gateway_id),
+                score: Some(
+                    stats
+                        .get(&gw.info.gateway_id)
+                        .cloned()
+                        .unwrap_or_default()
+                        .to_rpc(now),
+                ),
             })
             .collect())
     }
@@ -756,3 +906,106 @@ impl LnOps for LnOpsV1 {
         super::Version::V1
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+
+    use fedimint_connectors::error::ServerError;
+
+    use super::*;
+
+    fn unavailable() -> anyhow::Error {
+        anyhow!(ServerError::Connection(anyhow!("gateway offline")))
+    }
+
+    fn insufficient(msats: u64) -> anyhow::Error {
+        anyhow!(ErrorCode::InsufficientBalance(RpcAmount(
+            Amount::from_msats(msats)
+        )))
+    }
+
+    /// Pays through gateways `0..count`, each failing with `fail(gateway)` if
+    /// that returns an error. Returns the result, the gateways tried in order
+    /// and how often the gateways were refreshed.
+    async fn pay(
+        count: u8,
+        fail: impl Fn(u8) -> Option<anyhow::Error>,
+    ) -> (Result<u8>, Vec<u8>, usize) {
+        let tried = RefCell::new(vec![]);
+        let refreshed = RefCell::new(0);
+        let result = pay_with_fallback(
+            (0..count).collect(),
+            |gateway| *gateway,
+            |gateway| {
+                tried.borrow_mut().push(gateway);
+                let result = fail(gateway).map_or(Ok(gateway), Err);
+                async move { result }
+            },
+            || {
+                *refreshed.borrow_mut() += 1;
+                async move { Ok((0..count).collect()) }
+            },
+        )
+        .await;
+        (result, tried.into_inner(), refreshed.into_inner())
+    }
+
+    #[tokio::test]
+    async fn test_unavailable_gateway_refreshes_and_falls_back() {
+        let (result, tried, refreshed) = pay(3, |gateway| (gateway == 0).then(unavailable)).await;
+        assert_eq!(result.unwrap(), 1);
+        assert_eq!(tried, vec![0, 1]);
+        assert_eq!(refreshed, 1);
+
+        let (result, tried, refreshed) = pay(2, |_| Some(unavailable())).await;
+        assert_eq!(
+            result.unwrap_err().downcast_ref::<ErrorCode>(),
+            Some(&ErrorCode::NoLnGatewayAvailable)
+        );
+        assert_eq!(tried, vec![0, 1]);
+        assert_eq!(refreshed, 2);
+    }
+
+    #[tokio::test]
+    async fn test_expensive_gateway_falls_back_to_cheaper() {
+        let (result, tried, refreshed) =
+            pay(3, |gateway| (gateway == 0).then(|| insufficient(900))).await;
+        assert_eq!(result.unwrap(), 1);
+        assert_eq!(tried, vec![0, 1]);
+        assert_eq!(refreshed, 0);
+
+        // the error names the most any gateway could send
+        let (result, _, _) = pay(3, |gateway| {
+            Some(insufficient([900, 1000, 800][usize::from(gateway)]))
+        })
+        .await;
+        assert_eq!(
+            result.unwrap_err().downcast_ref::<ErrorCode>(),
+            Some(&ErrorCode::InsufficientBalance(RpcAmount(
+                Amount::from_msats(1000)
+            )))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_refund_falls_back_to_next_gateway() {
+        let (result, tried, refreshed) =
+            pay(3, |gateway| (gateway == 0).then(|| anyhow!(LnPayRefunded))).await;
+        assert_eq!(result.unwrap(), 1);
+        assert_eq!(tried, vec![0, 1]);
+        assert_eq!(refreshed, 0);
+
+        // the refund is reported once every gateway refunded or was too
+        // expensive
+        let (result, tried, _) = pay(3, |gateway| {
+            Some(match gateway {
+                1 => insufficient(900),
+                _ => anyhow!(LnPayRefunded),
+            })
+        })
+        .await;
+        assert!(result.unwrap_err().is::<LnPayRefunded>());
+        assert_eq!(tried, vec![0, 1, 2]);
+    }
+}
@@ -24,7 +24,8 @@ use rpc_types::{
 use tracing::{debug, error, warn};
 
 use super::{
-    FeeRemittance, FeeRemittanceGatewayOverride, FeeRemittanceRoute, LnOps, Lnv2SendCreated,
+    FeeRemittance, FeeRemittanceGatewayOverride, FeeRemittanceNotPaid, FeeRemittanceRoute, LnOps,
+    Lnv2SendCreated, invoice_payment_amount,
 };
 use crate::federation_v2::client::ClientExt;
 use crate::federation_v2::db::{
@@ -412,10 +413,10 @@ impl LnOps for LnOpsV2 {
         description: String,
         expiry_time: Option<u64>,
         frontend_meta: FrontendMetadata,
-    ) -> Result<Bolt11Invoice> {
+    ) -> Result<(OperationId, Bolt11Invoice)> {
         let amount = Amount::from_sats(amount.0.msats.div_ceil(1000));
-        let fee_ppms = fed
-            .get_fee_ppms_by_stream(fedimint_ln_common::KIND, RpcTransactionDirection::Receive)
+        let fee_rules = fed
+            .get_fee_rules_by_stream(fedimint_ln_common::KIND, RpcTransactionDirection::Receive)
             .await?;
         let expiry_secs = expiry_time.unwrap_or(86_400) as u32;
         let custom_meta = serde_json::to_value(BaseMetadata::from(frontend_meta.clone()))?;
@@ -430,7 +431,7 @@ impl LnOps for LnOpsV2 {
                 custom_meta,
             )
             .await?;
-        fed.write_pending_receive_fedi_fee_ppms(operation_id, &fee_ppms)
+        fed.write_pending_receive_fedi_fee_rules(operation_id, &fee_rules)
             .await?;
         let _ = fed.record_tx_date_fiat_info(operation_id, amount).await;
         self.subscribe_operation(
@@ -443,7 +444,7 @@ impl LnOps for LnOpsV2 {
                 .context("operation not found")?,
         )
         .await;
-        Ok(invoice)
+        Ok((operation_id, invoice))
     }
 
     async fn estimate_ln_fees(
@@ -451,11 +452,7 @@ impl LnOps for LnOpsV2 {
         fed: &FederationV2,
         invoice: &Bolt11Invoice,
     ) -> Result<RpcFeeDetails> {
-        let amount = Amount::from_msats(
-            invoice
-                .amount_milli_satoshis()
-                .ok_or(anyhow!("Invoice missing amount"))?,
-        );
+        let amount = invoice_payment_amount(invoice)?;
         let lnv2 = fed.client.lnv2()?;
         let routing_info = if let Some(gateway) = fed.get_lnv2_gateway_override().await? {
             lnv2.routing_info(&gateway)
@@ -490,12 +487,10 @@ impl LnOps for LnOpsV2 {
         &self,
         fed: &FederationV2,
         invoice: &Bolt11Invoice,
+        max_network_fee: Option<Amount>,
         frontend_meta: FrontendMetadata,
     ) -> Result<RpcPayInvoiceResponse> {
-        let amount_msat = invoice
-            .amount_milli_satoshis()
-            .ok_or(anyhow!("Invoice missing amount"))?;
-        let amount = Amount::from_msats(amount_msat);
+        let amount = invoice_payment_amount(invoice)?;
 
         let federation_network = fed
             .get_network()
@@ -508,13 +503,10 @@ impl LnOps for LnOpsV2 {
             ))
         }
 
-        let fees_by_stream = fed
-            .get_fee_amounts_by_stream(
-                fedimint_ln_common::KIND,
-                RpcTransactionDirection::Send,
-                amount,
-            )
+        let fee_rules = fed
+            .get_fee_rules_by_stream(fedimint_ln_common::KIND, RpcTransactionDirection::Send)
             .await?;
+        let fees_by_stream = fee_rules.fees_by_stream(amount);
         let fedi_fee = FederationV2::total_fedi_fee_amount(&fees_by_stream);
         let lnv2 = fed.client.lnv2()?;
         let gateway_override = fed.get_lnv2_gateway_override().await?;
@@ -527,6 +519,11 @@ impl LnOps for LnOpsV2 {
         };
         let (send_fee, _) = routing_info.send_parameters(invoice);
         let gateway_fee = send_fee.fee(amount.msats);
+        // the module picks a single gateway, so there is nothing to fall back to,
+        // neither when it is unavailable nor after a refund
+        if max_network_fee.is_some_and(|max_network_fee| max_network_fee < gateway_fee) {
+            bail!(ErrorCode::NetworkFeeExceedsBudget);
+        }
         let gateway_routing_fees = RoutingFees {
             base_msat: send_fee.base.msats as u32,
             proportional_millionths: send_fee.parts_per_million as u32,
@@ -538,13 +535,7 @@ impl LnOps for LnOpsV2 {
             bail!(ErrorCode::InsufficientBalance(RpcAmount(
                 get_max_spendable_amount(
                     virtual_balance,
-                    FederationV2::total_fedi_fee_ppm(
-                        &fed.get_fee_ppms_by_stream(
-                            fedimint_ln_common::KIND,
-                            RpcTransactionDirection::Send,
-                        )
-                        .await?,
-                    ),
+                    &fee_rules,
                     None,
                     Some(gateway_routing_fees),
                 )
@@ -560,7 +551,7 @@ impl LnOps for LnOpsV2 {
             .await?;
 
         async move {
-            fed.write_pending_send_fedi_fees(operation_id, &fees_by_stream)
+            fed.write_pending_send_fedi_fees(operation_id, amount, &fee_rules)
                 .await?;
             drop(spend_guard);
             let _ = fed
@@ -630,7 +621,7 @@ impl LnOps for LnOpsV2 {
         fed: &FederationV2,
         invoice: &Bolt11Invoice,
         remittance: FeeRemittance,
-    ) -> Result<RpcPayInvoiceResponse> {
+    ) -> Result<OperationId> {
         let FeeRemittanceRoute::Lnv2 { gateway } = remittance.route else {
             bail!("lnv2 cannot pay lnv1 fee remittance");
         };
@@ -646,7 +637,8 @@ impl LnOps for LnOpsV2 {
                 Some(gateway),
                 serde_json::to_value(&extra_meta)?,
             )
-            .await?;
+            .await
+            .map_err(|error| anyhow::Error::new(FeeRemittanceNotPaid(error.into())))?;
         let final_state = fed
             .client
             .lnv2()?
@@ -655,12 +647,10 @@ impl LnOps for LnOpsV2 {
         fed.update_operation_state(operation_id, final_state.clone())
             .await;
         match final_state {
-            LnV2FinalSendOperationState::Success(preimage) => Ok(RpcPayInvoiceResponse {
-                preimage: hex::encode(preimage),
-            }),
-            LnV2FinalSendOperationState::Refunded => {
-                bail!("Lightning payment failed, got refund");
-            }
+            LnV2FinalSendOperationState::Success(_) => Ok(operation_id),
+            LnV2FinalSendOperationState::Refunded => Err(anyhow::Error::new(FeeRemittanceNotPaid(
+                anyhow!("Lightning payment failed, got refund"),
+            ))),
             LnV2FinalSendOperationState::Failure => {
                 bail!("Lightning payment failed");
             }
@@ -984,6 +974,8 @@ impl LnOps for LnOpsV2 {
         }
     }
 
+    /// Unscored: payments go through the gateway the module picks, so there
+    /// are no outcomes per gateway to rank by.
     async fn list_gateways(&self, fed: &FederationV2) -> anyhow::Result<Vec<RpcLightningGateway>> {
         let urls = fed
             .client
@@ -1012,6 +1004,7 @@ impl LnOps for LnOpsV2 {
                 api: url.to_string(),
                 node_pub_key: rpc_types::RpcPublicKey(routing_info.lightning_public_key),
                 gateway_id: rpc_types::RpcPublicKey(routing_info.module_public_key),
+                score: None,
             });
         }
         Ok(gateways)
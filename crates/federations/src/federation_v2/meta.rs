@@ -1,13 +1,19 @@
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, BTreeSet};
+use std::str::FromStr as _;
 use std::time::Duration;
 
+use anyhow::ensure;
+use bitcoin::hashes::{Hash as _, sha256};
+use bitcoin::secp256k1::{self, PublicKey, schnorr};
 use fedimint_api_client::api::DynGlobalApi;
 use fedimint_client_module::meta::{
     FetchKind, MetaFieldKey, MetaFieldValue, MetaSource, MetaValues, fetch_meta_overrides,
 };
-use fedimint_core::config::ClientConfig;
+use fedimint_core::config::{ClientConfig, FederationId};
+use fedimint_core::encoding::Encodable as _;
 use fedimint_core::util::{backoff_util, retry};
-use fedimint_core::{apply, async_trait_maybe_send};
+use fedimint_core::{PeerId, apply, async_trait_maybe_send};
+use tracing::warn;
 
 pub type MetaEntries = BTreeMap<String, String>;
 
@@ -21,6 +27,112 @@ pub struct LegacyMetaSourceWithExternalUrl {
 
 pub const META_EXTERNAL_URL_FIELD: &str = "meta_external_url";
 pub const META_OVERRIDE_URL_FIELD: &str = "meta_override_url";
+/// Json object of hex schnorr signatures by peer id, made with the guardians'
+/// broadcast keys over [`meta_override_signing_message`].
+pub const META_OVERRIDE_SIGNATURES_FIELD: &str = "fedi:meta_override_signatures";
+/// Set to "true" in the consensus config meta to reject overrides that are
+/// not signed by a threshold of guardians.
+pub const META_OVERRIDE_SIGNATURES_REQUIRED_FIELD: &str = "fedi:meta_override_signatures_required";
+/// Set by the client to a [`MetaOverrideStatus`], never taken from overrides.
+pub const META_OVERRIDE_STATUS_FIELD: &str = "fedi:meta_override_status";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MetaOverrideStatus {
+    /// Signed by a threshold of guardians.
+    Verified,
+    /// Accepted without signatures, as the federation does not require them.
+    Unsigned,
+    /// Dropped for missing or invalid signatures.
+    Rejected,
+}
+
+impl MetaOverrideStatus {
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::Verified => "verified",
+            Self::Unsigned => "unsigned",
+            Self::Rejected => "rejected",
+        }
+    }
+}
+
+/// What guardians sign to vouch for the meta overrides `entries`, which
+/// exclude the signatures themselves. Values are as the client reads them
+/// from the override document.
+pub fn meta_override_signing_message(
+    federation_id: FederationId,
+    entries: &BTreeMap<String, String>,
+) -> secp256k1::Message {
+    let mut bytes = b"fedi-meta-override".to_vec();
+    bytes.extend(federation_id.consensus_encode_to_vec());
+    bytes.extend(entries.consensus_encode_to_vec());
+    secp256k1::Message::from_digest(sha256::Hash::hash(&bytes).to_byte_array())
+}
+
+fn count_valid_signatures(
+    signatures: &str,
+    message: &secp256k1::Message,
+    broadcast_keys: Option<&BTreeMap<PeerId, PublicKey>>,
+) -> anyhow::Result<usize> {
+    let signatures = serde_json::from_str::<BTreeMap<String, String>>(signatures)?;
+    let mut signers = BTreeSet::new();
+    for (key, signature) in signatures {
+        let peer_id = PeerId::from(key.parse::<u16>()?);
+        // "01" would otherwise count guardian 1 a second time
+        ensure!(
+            peer_id.to_string() == key,
+            "non-canonical guardian id {key:?}"
+        );
+        let signature = schnorr::Signature::from_str(&signature)?;
+        let Some(key) = broadcast_keys.and_then(|keys| keys.get(&peer_id)) else {
+            continue;
+        };
+        if secp256k1::SECP256K1
+            .verify_schnorr(&signature, message, &key.x_only_public_key().0)
+            .is_ok()
+        {
+            signers.insert(peer_id);
+        }
+    }
+    Ok(signers.len())
+}
+
+/// Checks the signatures carried by `overrides` against the guardians'
+/// `broadcast_keys`, dropping the overrides if they do not verify, or if
+/// they are unsigned while `signatures_required`.
+fn verify_meta_overrides(
+    federation_id: FederationId,
+    broadcast_keys: Option<&BTreeMap<PeerId, PublicKey>>,
+    peer_count: usize,
+    signatures_required: bool,
+    mut overrides: BTreeMap<String, String>,
+) -> (BTreeMap<String, String>, MetaOverrideStatus) {
+    overrides.remove(META_OVERRIDE_STATUS_FIELD);
+    let status = match overrides.remove(META_OVERRIDE_SIGNATURES_FIELD) {
+        None if signatures_required => {
+            warn!("unsigned meta overrides, but the federation requires signatures");
+            MetaOverrideStatus::Rejected
+        }
+        None => MetaOverrideStatus::Unsigned,
+        Some(signatures) => {
+            let message = meta_override_signing_message(federation_id, &overrides);
+            let valid = count_valid_signatures(&signatures, &message, broadcast_keys);
+            let threshold = (peer_count - peer_count.saturating_sub(1) / 3).max(1);
+            match valid {
+                Ok(valid) if threshold <= valid => MetaOverrideStatus::Verified,
+                valid => {
+                    warn!(?valid, threshold, "meta override signatures do not verify");
+                    MetaOverrideStatus::Rejected
+                }
+            }
+        }
+    };
+    if status == MetaOverrideStatus::Rejected {
+        overrides.clear();
+    }
+    (overrides, status)
+}
+
 #[apply(async_trait_maybe_send!)]
 impl MetaSource for LegacyMetaSourceWithExternalUrl {
     async fn wait_for_update(&self) {
@@ -57,9 +169,151 @@ impl MetaSource for LegacyMetaSourceWithExternalUrl {
             }
         })
         .await?;
+        let signatures_required = client_config
+            .global
+            .meta
+            .get(META_OVERRIDE_SIGNATURES_REQUIRED_FIELD)
+            .is_some_and(|required| required == "true");
+        let (overrides, status) = verify_meta_overrides(
+            client_config.calculate_federation_id(),
+            client_config.global.broadcast_public_keys.as_ref(),
+            client_config.global.api_endpoints.len(),
+            signatures_required,
+            overrides
+                .into_iter()
+                .map(|(key, value)| (key.0, value.0))
+                .collect(),
+        );
+        let overrides = overrides
+            .into_iter()
+            .map(|(key, value)| (MetaFieldKey(key), MetaFieldValue(value)))
+            .chain([(
+                MetaFieldKey(META_OVERRIDE_STATUS_FIELD.to_owned()),
+                MetaFieldValue(status.as_str().to_owned()),
+            )]);
         Ok(MetaValues {
             values: config_iter.chain(overrides).collect(),
             revision: last_revision.map_or(0, |r| r + 1),
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use bitcoin::secp256k1::Keypair;
+
+    use super::*;
+
+    fn sign(
+        keypairs: &[Keypair],
+        federation_id: FederationId,
+        entries: &BTreeMap<String, String>,
+    ) -> String {
+        let message = meta_override_signing_message(federation_id, entries);
+        let signatures = keypairs
+            .iter()
+            .enumerate()
+            .map(|(peer_id, keypair)| {
+                (
+                    peer_id.to_string(),
+                    secp256k1::SECP256K1
+                        .sign_schnorr_no_aux_rand(&message, keypair)
+                        .to_string(),
+                )
+            })
+            .collect::<BTreeMap<_, _>>();
+        serde_json::to_string(&signatures).unwrap()
+    }
+
+    #[test]
+    fn test_verify_meta_overrides() {
+        let federation_id = FederationId(sha256::Hash::hash(b"federation"));
+        let keypairs = (0..4)
+            .map(|_| Keypair::new(secp256k1::SECP256K1, &mut rand::thread_rng()))
+            .collect::<Vec<_>>();
+        let broadcast_keys = keypairs
+            .iter()
+            .enumerate()
+            .map(|(peer_id, keypair)| (PeerId::from(peer_id as u16), keypair.public_key()))
+            .collect::<BTreeMap<_, _>>();
+        let entries = BTreeMap::from([("fedi:guardian_fee_send_ppm".to_owned(), "100".to_owned())]);
+        let verify = |overrides: BTreeMap<String, String>, required: bool| {
+            verify_meta_overrides(federation_id, Some(&broadcast_keys), 4, required, overrides)
+        };
+        let signed = |signers: &[Keypair], entries: &BTreeMap<String, String>| {
+            let mut overrides = entries.clone();
+            overrides.insert(
+                META_OVERRIDE_SIGNATURES_FIELD.to_owned(),
+                sign(signers, federation_id, entries),
+            );
+            overrides
+        };
+
+        assert_eq!(
+            verify(signed(&keypairs[..3], &entries), true),
+            (entries.clone(), MetaOverrideStatus::Verified)
+        );
+        assert_eq!(
+            verify(signed(&keypairs[..2], &entries), false),
+            (BTreeMap::new(), MetaOverrideStatus::Rejected)
+        );
+        assert_eq!(
+            verify(entries.clone(), false),
+            (entries.clone(), MetaOverrideStatus::Unsigned)
+        );
+        assert_eq!(
+            verify(entries.clone(), true),
+            (BTreeMap::new(), MetaOverrideStatus::Rejected)
+        );
+
+        // signatures over different entries
+        let mut tampered = signed(&keypairs, &entries);
+        tampered.insert("fedi:guardian_fee_send_ppm".to_owned(), "200000".to_owned());
+        assert_eq!(verify(tampered, true).1, MetaOverrideStatus::Rejected);
+
+        // the status can't be set by the override document
+        let mut spoofed = entries.clone();
+        spoofed.insert(META_OVERRIDE_STATUS_FIELD.to_owned(), "verified".to_owned());
+        assert_eq!(
+            verify(spoofed, false),
+            (entries, MetaOverrideStatus::Unsigned)
+        );
+    }
+
+    #[test]
+    fn test_count_valid_signatures_once_per_guardian() {
+        let federation_id = FederationId(sha256::Hash::hash(b"federation"));
+        let keypairs = (0..4)
+            .map(|_| Keypair::new(secp256k1::SECP256K1, &mut rand::thread_rng()))
+            .collect::<Vec<_>>();
+        let broadcast_keys = keypairs
+            .iter()
+            .enumerate()
+            .map(|(peer_id, keypair)| (PeerId::from(peer_id as u16), keypair.public_key()))
+            .collect::<BTreeMap<_, _>>();
+        let message = meta_override_signing_message(federation_id, &BTreeMap::new());
+        let signature = |peer_id: usize| {
+            secp256k1::SECP256K1
+                .sign_schnorr_no_aux_rand(&message, &keypairs[peer_id])
+                .to_string()
+        };
+        let count = |signatures: &[(&str, String)]| {
+            let signatures = signatures.iter().cloned().collect::<BTreeMap<_, _>>();
+            count_valid_signatures(
+                &serde_json::to_string(&signatures).unwrap(),
+                &message,
+                Some(&broadcast_keys),
+            )
+        };
+
+        assert_eq!(
+            count(&[("0", signature(0)), ("1", signature(1))]).unwrap(),
+            2
+        );
+        // the same guardian under another spelling of its id
+        assert!(count(&[("1", signature(1)), ("01", signature(1))]).is_err());
+        assert!(count(&[("1", signature(1)), ("+1", signature(1))]).is_err());
+        // a signature by another guardian does not count
+        assert_eq!(count(&[("2", signature(1))]).unwrap(), 0);
+    }
+}
@@ -28,6 +28,11 @@ pub(crate) trait MintOps: MaybeSend + MaybeSync {
         frontend_meta: FrontendMetadata,
     ) -> Result<(Amount, OperationId)>;
 
+    /// Check `ecash` against the federation's keys without contacting it,
+    /// returning its amount, or None if notes can only be checked by receiving
+    /// them, in which case receives are never queued.
+    async fn validate_ecash(&self, fed: &FederationV2, ecash: &str) -> Option<Result<Amount>>;
+
     async fn subscribe_to_ecash_reissue(
         &self,
         fed: &FederationV2,
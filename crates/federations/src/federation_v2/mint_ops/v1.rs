@@ -7,6 +7,7 @@ use fedimint_client::module::oplog::OperationLogEntry;
 use fedimint_core::core::OperationId;
 use fedimint_core::task::timeout;
 use fedimint_core::{Amount, apply, async_trait_maybe_send};
+use fedimint_mint_client::config::MintClientConfig;
 use fedimint_mint_client::{
     MintOperationMeta, MintOperationMetaVariant, OOBNotes, ReissueExternalNotesState,
     SelectNotesWithAtleastAmount, SelectNotesWithExactAmount, SpendOOBState,
@@ -51,8 +52,8 @@ impl MintOps for MintOpsV1 {
         frontend_meta: FrontendMetadata,
     ) -> Result<(Amount, OperationId)> {
         let ecash = OOBNotes::from_str(&ecash)?;
-        let fee_ppms = fed
-            .get_fee_ppms_by_stream(fedimint_mint_client::KIND, RpcTransactionDirection::Receive)
+        let fee_rules = fed
+            .get_fee_rules_by_stream(fedimint_mint_client::KIND, RpcTransactionDirection::Receive)
             .await?;
         let amount = ecash.total_amount();
         let operation_id = fed
@@ -68,13 +69,36 @@ impl MintOps for MintOpsV1 {
             )
             .await
             .context(ErrorCode::EcashAlreadySpent)?;
-        fed.write_pending_receive_fedi_fee_ppms(operation_id, &fee_ppms)
+        fed.write_pending_receive_fedi_fee_rules(operation_id, &fee_rules)
             .await?;
         let _ = fed.record_tx_date_fiat_info(operation_id, amount).await;
         fed.subscribe_to_operation(operation_id).await?;
         Ok((amount, operation_id))
     }
 
+    async fn validate_ecash(&self, fed: &FederationV2, ecash: &str) -> Option<Result<Amount>> {
+        let validate = async {
+            let ecash = OOBNotes::from_str(ecash)?;
+            if ecash.federation_id_prefix() != fed.federation_id().to_prefix() {
+                bail!(ErrorCode::InvalidEcash);
+            }
+            let client_config = fed.client.config().await;
+            let (_, cfg) = client_config
+                .get_first_module_by_kind::<MintClientConfig>(fedimint_mint_client::KIND)?;
+            for (amount, note) in ecash.notes().iter_items() {
+                let valid = cfg
+                    .tbs_pks
+                    .tier(&amount)
+                    .is_ok_and(|pk| note.note().verify(*pk));
+                if !valid {
+                    bail!(ErrorCode::InvalidEcash);
+                }
+            }
+            Ok(ecash.total_amount())
+        };
+        Some(validate.await)
+    }
+
     async fn subscribe_to_ecash_reissue(
         &self,
         fed: &FederationV2,
@@ -141,18 +165,11 @@ impl MintOps for MintOpsV1 {
         frontend_meta: FrontendMetadata,
     ) -> Result<RpcGenerateEcashResponse> {
         let _guard = fed.generate_ecash_lock.lock().await;
-        let fees_by_stream = fed
-            .get_fee_amounts_by_stream(
-                fedimint_mint_client::KIND,
-                RpcTransactionDirection::Send,
-                amount,
-            )
+        let fee_rules = fed
+            .get_fee_rules_by_stream(fedimint_mint_client::KIND, RpcTransactionDirection::Send)
             .await?;
+        let fees_by_stream = fee_rules.fees_by_stream(amount);
         let fedi_fee = FederationV2::total_fedi_fee_amount(&fees_by_stream);
-        let total_fedi_fee_ppm = FederationV2::total_fedi_fee_ppm(
-            &fed.get_fee_ppms_by_stream(fedimint_mint_client::KIND, RpcTransactionDirection::Send)
-                .await?,
-        );
 
         let mint = fed.client.mint()?;
 
@@ -170,7 +187,7 @@ impl MintOps for MintOpsV1 {
             let virtual_balance = fed.get_balance().await;
             if amount + fedi_fee > virtual_balance {
                 bail!(ErrorCode::InsufficientBalance(RpcAmount(
-                    get_max_spendable_amount(virtual_balance, total_fedi_fee_ppm, None, None)
+                    get_max_spendable_amount(virtual_balance, &fee_rules, None, None)
                 )));
             }
 
@@ -236,7 +253,7 @@ impl MintOps for MintOpsV1 {
             // and retry
         };
 
-        fed.write_pending_send_fedi_fees(operation_id, &fees_by_stream)
+        fed.write_pending_send_fedi_fees(operation_id, amount, &fee_rules)
             .await?;
         // spend_guard must be dropped after writing fee since virtual balance only
         // updates once fee is written
@@ -290,7 +307,10 @@ impl MintOps for MintOpsV1 {
             .await
             .context(ErrorCode::EcashCancelFailed)?;
         let _ = fed.record_tx_date_fiat_info(operation_id, amount).await;
-        fed.subscribe_to_ecash_reissue(operation_id, amount).await?;
+        // The reissue fails if the recipient claimed the notes first
+        fed.subscribe_to_ecash_reissue(operation_id, amount)
+            .await
+            .context(ErrorCode::EcashCancelFailed)?;
         Ok(())
     }
 
@@ -152,8 +152,8 @@ impl MintOps for MintOpsV2 {
         let mintv2 = fed.client.mintv2()?;
         let ecash: MintV2ECash = decode_prefixed(FEDIMINT_PREFIX, &ecash)?;
         let amount = ecash.amount();
-        let fee_ppms = fed
-            .get_fee_ppms_by_stream(fedimint_mint_client::KIND, RpcTransactionDirection::Receive)
+        let fee_rules = fed
+            .get_fee_rules_by_stream(fedimint_mint_client::KIND, RpcTransactionDirection::Receive)
             .await?;
         let custom_meta = serde_json::to_value(EcashReceiveMetadata {
             internal: false,
@@ -161,13 +161,19 @@ impl MintOps for MintOpsV2 {
             frontend_metadata: Some(frontend_meta),
         })?;
         let operation_id = mintv2.receive(ecash, custom_meta).await?;
-        fed.write_pending_receive_fedi_fee_ppms(operation_id, &fee_ppms)
+        fed.write_pending_receive_fedi_fee_rules(operation_id, &fee_rules)
             .await?;
         let _ = fed.record_tx_date_fiat_info(operation_id, amount).await;
         fed.subscribe_to_operation(operation_id).await?;
         Ok((amount, operation_id))
     }
 
+    /// mintv2 only verifies notes when it receives them, so receives are never
+    /// queued.
+    async fn validate_ecash(&self, _fed: &FederationV2, _ecash: &str) -> Option<Result<Amount>> {
+        None
+    }
+
     async fn subscribe_to_ecash_reissue(
         &self,
         _fed: &FederationV2,
@@ -186,23 +192,16 @@ impl MintOps for MintOpsV2 {
     ) -> Result<RpcGenerateEcashResponse> {
         let _guard = fed.generate_ecash_lock.lock().await;
         let mintv2 = fed.client.mintv2()?;
-        let fees_by_stream = fed
-            .get_fee_amounts_by_stream(
-                fedimint_mint_client::KIND,
-                RpcTransactionDirection::Send,
-                amount,
-            )
+        let fee_rules = fed
+            .get_fee_rules_by_stream(fedimint_mint_client::KIND, RpcTransactionDirection::Send)
             .await?;
-        let total_fedi_fee_ppm = FederationV2::total_fedi_fee_ppm(
-            &fed.get_fee_ppms_by_stream(fedimint_mint_client::KIND, RpcTransactionDirection::Send)
-                .await?,
-        );
+        let fees_by_stream = fee_rules.fees_by_stream(amount);
         let fedi_fee = FederationV2::total_fedi_fee_amount(&fees_by_stream);
         let spend_guard = fed.spend_guard.lock().await;
         let virtual_balance = fed.get_balance().await;
         if amount + fedi_fee > virtual_balance {
             bail!(ErrorCode::InsufficientBalance(RpcAmount(
-                get_max_spendable_amount(virtual_balance, total_fedi_fee_ppm, None, None)
+                get_max_spendable_amount(virtual_balance, &fee_rules, None, None)
             )));
         }
         let custom_meta = serde_json::to_value(EcashSendMetadata {
@@ -224,15 +223,9 @@ impl MintOps for MintOpsV2 {
         .context(ErrorCode::OfflineExactEcashFailed)??;
         let sent_amount = ecash.amount();
         let ecash = encode_prefixed(FEDIMINT_PREFIX, &ecash);
-        let settled_fees_by_stream = fed
-            .get_fee_amounts_by_stream(
-                fedimint_mint_client::KIND,
-                RpcTransactionDirection::Send,
-                sent_amount,
-            )
-            .await?;
+        let settled_fees_by_stream = fee_rules.fees_by_stream(sent_amount);
         let settled_fedi_fee = FederationV2::total_fedi_fee_amount(&settled_fees_by_stream);
-        fed.write_pending_send_fedi_fees(operation_id, &settled_fees_by_stream)
+        fed.write_pending_send_fedi_fees(operation_id, sent_amount, &fee_rules)
             .await?;
         fed.write_success_send_fedi_fees(operation_id).await?;
         // Virtual balance only reflects the send once its fees are written.
@@ -260,10 +253,11 @@ impl MintOps for MintOpsV2 {
             .await
             .context(ErrorCode::EcashCancelFailed)?;
         let _ = fed.record_tx_date_fiat_info(operation_id, amount).await;
+        // Not knowing the outcome yet is not a failed cancel
         let final_state = mintv2
             .await_final_receive_operation_state(operation_id)
             .await
-            .context(ErrorCode::EcashCancelFailed)?;
+            .context("failed to await ecash cancel")?;
         fed.send_transaction_event(operation_id).await;
         match final_state {
             MintV2FinalReceiveOperationState::Success => {}
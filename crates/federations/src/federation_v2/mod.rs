@@ -1,9 +1,13 @@
 pub mod client;
 pub mod db;
+mod ecash_expiry_service;
+mod ecash_receive_queue_service;
+mod guardian_health;
 mod guardian_remittance;
-mod ln_ops;
+pub(crate) mod ln_ops;
 mod lnurl_receives_service;
 mod meta;
+mod recovery_progress;
 
 use std::any::Any;
 use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
@@ -12,7 +16,7 @@ use std::future::Future;
 use std::pin::pin;
 use std::str::FromStr;
 use std::sync::{Arc, Weak};
-use std::time::Duration;
+use std::time::{Duration, SystemTime};
 
 use ::serde::{Deserialize, Serialize};
 use anyhow::{Context, Result, anyhow, bail, ensure};
@@ -28,6 +32,8 @@ use db::{
     TransactionNotesKey,
 };
 use device_registration::DeviceRegistrationService;
+use ecash_expiry_service::EcashExpiryService;
+use ecash_receive_queue_service::{EcashReceiveQueueService, is_federation_network_error};
 use fedi_social_client::common::VerificationDocument;
 use fedi_social_client::{
     FediSocialClientInit, RecoveryFile, RecoveryId, SOCIAL_RECOVERY_SECRET_CHILD_ID, SocialBackup,
@@ -86,21 +92,24 @@ use lightning_invoice::{Bolt11Invoice, RoutingFees};
 use lnurl_receives_service::LnurlReceivesService;
 use meta::{LegacyMetaSourceWithExternalUrl, MetaEntries};
 use rand::Rng;
+use recovery_progress::RecoveryProgressTracker;
 use rpc_types::error::ErrorCode;
 use rpc_types::event::{Event, RecoveryProgressEvent, TypedEventExt};
 use rpc_types::matrix::RpcRoomId;
 use rpc_types::spv2_transfer_meta::Spv2TransferTxMeta;
 use rpc_types::{
-    FrontendMetadata, GuardianStatus, OperationFediFeeStatus, RpcAmount, RpcEventId, RpcFederation,
-    RpcFederationId, RpcFederationMaybeLoading, RpcFederationPreview, RpcFeeDetails,
-    RpcGenerateEcashResponse, RpcGuardianRemittanceAccountInfo, RpcGuardianRemittanceDashboard,
-    RpcJsonClientConfig, RpcLightningGateway, RpcLightningGatewayId, RpcOnchainDepositState,
-    RpcOperationFediFeeStatus, RpcPayInvoiceResponse, RpcPeerId, RpcPrevPayInvoiceResult,
-    RpcPublicKey, RpcReclaimLnReceiveOutcome, RpcReturningMemberStatus, RpcSPDepositState,
-    RpcSPV2DepositState, RpcSPV2TransferInState, RpcSPV2TransferOutState, RpcSPV2WithdrawalState,
-    RpcSPWithdrawState, RpcSPv2CachedSyncResponse, RpcTransaction, RpcTransactionDirection,
-    RpcTransactionKind, RpcTransactionListEntry, SPv2DepositMetadata, SPv2TransferMetadata,
-    SPv2WithdrawMetadata, SpMatrixTransferId, SpV2TransferInKind, SpV2TransferOutKind,
+    FrontendMetadata, GuardianStatus, OperationFediFeeDiscount, OperationFediFeeStatus, RpcAmount,
+    RpcEventId, RpcFederation, RpcFederationId, RpcFederationMaybeLoading, RpcFederationPreview,
+    RpcFeeDetails, RpcFeeStatement, RpcFeeStatementPeriod, RpcGenerateEcashResponse,
+    RpcGuardianRemittanceAccountInfo, RpcGuardianRemittanceDashboard, RpcJsonClientConfig,
+    RpcLightningGateway, RpcLightningGatewayId, RpcOnchainDepositState, RpcOnchainFeeQuote,
+    RpcOnchainFeeTier, RpcOperationFediFeeStatus, RpcPayInvoiceResponse, RpcPeerId,
+    RpcPrevPayInvoiceResult, RpcPublicKey, RpcReclaimLnReceiveOutcome, RpcReturningMemberStatus,
+    RpcSPDepositState, RpcSPV2DepositState, RpcSPV2TransferInState, RpcSPV2TransferOutState,
+    RpcSPV2WithdrawalState, RpcSPWithdrawState, RpcSPv2CachedSyncResponse, RpcTransaction,
+    RpcTransactionDirection, RpcTransactionKind, RpcTransactionListEntry, SPv2DepositMetadata,
+    SPv2TransferMetadata, SPv2WithdrawMetadata, SpMatrixTransferId, SpV2TransferInKind,
+    SpV2TransferOutKind,
 };
 use runtime::bridge_runtime::Runtime;
 use runtime::constants::{
@@ -144,23 +153,29 @@ use self::db::{
     TransactionDateFiatInfoKey, WalletV2AwaitingDeposit, WalletV2AwaitingDepositKey,
     WalletV2AwaitingDepositKeyPrefix,
 };
-use self::ln_gateway_service::LnGatewayService;
+use self::ln_gateway_service::{GatewayOutcome, LnGatewayService};
 use self::ln_ops::{LnOpsV1, LnOpsV2};
 use self::mint_ops::{MintOpsV1, MintOpsV2};
+use self::spv2_pay_address::Spv2PaymentAddress;
 use self::stability_pool_sweeper_service::StabilityPoolSweeperService;
 use self::wallet_ops::WalletOpsV1;
 use super::federations_locker::FederationLockGuard;
 use crate::federation_v2::wallet_ops::WalletOpsV2;
 use crate::fedi_fee::db::{
-    AppFeeStreamStateInitializedKey, NextFediFeeRemittanceDueAtByStreamKey,
+    AppFeeStreamStateInitializedKey, FediFeeVolumeKey, FediFeeVolumeKeyPrefix,
+    NextFediFeeRemittanceDueAtByStreamKey, OperationFediFeeDiscountByStreamKey,
     OperationFediFeeStatusByStreamKey, OutstandingFediFeesByStreamKey,
     OutstandingFediFeesByStreamPerTXTypeKey, OutstandingFediFeesByStreamPerTXTypeKeyPrefix,
     PendingFediFeesByStreamKey, PendingFediFeesByStreamPerTXTypeKey,
-    PendingFediFeesByStreamPerTXTypeKeyPrefix, TotalAccruedFediFeesByStreamKey,
+    PendingFediFeesByStreamPerTXTypeKeyPrefix, PendingReceiveFediFeeRuleByStreamKey,
+    PendingSendFediFeeVolumeKey, TotalAccruedFediFeesByStreamKey,
+};
+use crate::fedi_fee::statement::{
+    FediFeeLedgerEntryKind, fedi_fee_statement, record_fedi_fee_ledger_entry,
 };
 use crate::fedi_fee::{
-    FediFeeHelper, FediFeeRemittanceService, FediFeeStream, GuardianFeeRemittanceService,
-    parse_fedi_guardian_fee_config,
+    FEDI_FEE_VOLUME_WINDOW_DAYS, FediFeeHelper, FediFeeRemittanceService, FediFeeRule,
+    FediFeeRules, FediFeeStream, GuardianFeeRemittanceService, parse_fedi_guardian_fee_config,
 };
 
 // Trait for multispend notifications required by federation
@@ -208,7 +223,11 @@ mod mint_ops;
 pub mod spv2_pay_address;
 mod spv2_sweeper_service;
 mod stability_pool_sweeper_service;
+mod transaction_index;
+mod unified_receive;
 mod wallet_ops;
+mod wallet_repair;
+mod withdraw_confirmations;
 
 pub const GUARDIAN_STATUS_TIMEOUT: Duration = Duration::from_secs(10);
 pub const GUARDIAN_STATUS_CACHE_TTL_SECS: u64 = 30;
@@ -459,10 +478,16 @@ pub struct FederationV2 {
     pub spv2_sweeper_service: OnceCell<SPv2SweeperService>,
     pub multispend_services: Arc<dyn MultispendNotifications>,
     pub lnurl_receives_service: OnceCell<LnurlReceivesService>,
+    pub ecash_expiry_service: OnceCell<EcashExpiryService>,
+    pub ecash_receive_queue_service: OnceCell<EcashReceiveQueueService>,
     /// Cache for guardian status to prevent spamming servers
     #[allow(clippy::type_complexity)]
     pub guardian_status_cache:
         Mutex<Option<(std::time::SystemTime, Result<Vec<GuardianStatus>, String>)>>,
+    /// Per module progress while recovering
+    pub recovery_progress: Mutex<RecoveryProgressTracker>,
+    /// Held while `run_wallet_repair` runs
+    pub wallet_repair_lock: Mutex<()>,
     pub spt_notifications: Arc<dyn SptNotifications>,
 }
 
@@ -716,7 +741,11 @@ impl FederationV2 {
             guardian_remittance_account: Default::default(),
             spv2_sweeper_service: Default::default(),
             lnurl_receives_service: Default::default(),
+            ecash_expiry_service: Default::default(),
+            ecash_receive_queue_service: Default::default(),
             guardian_status_cache: Mutex::new(None),
+            recovery_progress: Mutex::new(RecoveryProgressTracker::default()),
+            wallet_repair_lock: Mutex::new(()),
         }))
     }
 
@@ -817,6 +846,9 @@ impl FederationV2 {
         // initialized, so recovered in-flight fee remittances can hand off to
         // their reconciliation services immediately.
         self.subscribe_to_all_operations().await;
+        self.spawn_cancellable("transaction_index_backfill", |fed| async move {
+            fed.backfill_transaction_index().await;
+        });
 
         let cached_meta = self.get_cached_meta().await;
         self.sync_guardian_fee_config_from_meta(&cached_meta).await;
@@ -906,6 +938,34 @@ impl FederationV2 {
             error!("lnurl receives service already initialized");
         }
 
+        if self
+            .ecash_expiry_service
+            .set(EcashExpiryService::new(self))
+            .is_err()
+        {
+            error!("ecash expiry service already initialized");
+        }
+
+        if self
+            .ecash_receive_queue_service
+            .set(EcashReceiveQueueService::new(self))
+            .is_err()
+        {
+            error!("ecash receive queue service already initialized");
+        }
+
+        self.spawn_cancellable("guardian_health_monitor", |fed| async move {
+            fed.monitor_guardian_health().await;
+        });
+
+        self.spawn_cancellable("wallet_repair_monitor", |fed| async move {
+            fed.monitor_wallet_repair().await;
+        });
+
+        self.spawn_cancellable("withdraw_confirmations_monitor", |fed| async move {
+            fed.monitor_withdraw_confirmations().await;
+        });
+
         self.ln_ops.start_background_services(self);
     }
 
@@ -1455,6 +1515,15 @@ impl FederationV2 {
             .with_prefix(vec![db::BridgeDbPrefix::FediFeePrefix as u8])
     }
 
+    /// Returns the prefixed database view owned by one feature inside the
+    /// shared [`db::BridgeDbPrefix::FederationDataPrefix`] partition.
+    pub fn federation_data_db(&self, prefix: db::FederationDataDbPrefix) -> Database {
+        self.client.db().with_prefix(vec![
+            db::BridgeDbPrefix::FederationDataPrefix as u8,
+            prefix as u8,
+        ])
+    }
+
     pub async fn select_gateway(&self) -> anyhow::Result<Option<LightningGateway>> {
         let gateway = self.gateway_service()?.select_gateway(&self.client).await?;
         Ok(gateway)
@@ -1471,15 +1540,42 @@ impl FederationV2 {
         Ok(gateway)
     }
 
-    async fn refresh_cache_and_select_gateway_excluding(
+    /// Gateways to pay `amount` through, best first, see
+    /// [`LnGatewayService::ranked_gateways`].
+    pub(crate) async fn ranked_gateways(
         &self,
-        excluded_gateway_id: Option<secp256k1::PublicKey>,
-    ) -> anyhow::Result<Option<LightningGateway>> {
-        let gateway = self
-            .gateway_service()?
-            .refresh_cache_and_select_gateway_excluding(&self.client, excluded_gateway_id)
-            .await?;
-        Ok(gateway)
+        amount: Amount,
+    ) -> anyhow::Result<Vec<LightningGateway>> {
+        self.gateway_service()?
+            .ranked_gateways(&self.client, amount)
+            .await
+    }
+
+    /// Fetches the gateways announced to the federation anew, e.g. after one
+    /// of them turned out to be unreachable.
+    pub(crate) async fn refresh_gateway_cache(&self) {
+        let Ok(ln) = self.client.ln() else {
+            return;
+        };
+        if let Err(error) = ln.update_gateway_cache().await {
+            warn!(?error, "updating gateway cache failed");
+        }
+    }
+
+    pub(crate) async fn record_gateway_outcome(
+        &self,
+        gateway_id: PublicKey,
+        outcome: GatewayOutcome,
+    ) {
+        let Ok(gateway_service) = self.gateway_service() else {
+            return;
+        };
+        if let Err(error) = gateway_service
+            .record_gateway_outcome(gateway_id, outcome)
+            .await
+        {
+            warn!(?error, "failed to record gateway outcome");
+        }
     }
 
     pub async fn balance_after_mint_fees(&self, raw_fedimint_balance: Amount) -> Amount {
@@ -1628,6 +1724,25 @@ impl FederationV2 {
             .await
     }
 
+    /// Fees of `stream` accrued and remitted within `[from, to)`, in unix
+    /// seconds, aggregated by `period`.
+    pub async fn get_fee_statement(
+        &self,
+        stream: FediFeeStream,
+        from: u64,
+        to: u64,
+        period: RpcFeeStatementPeriod,
+    ) -> RpcFeeStatement {
+        fedi_fee_statement(
+            &mut self.fedi_fee_db().begin_transaction_nc().await,
+            stream,
+            from,
+            to,
+            period,
+        )
+        .await
+    }
+
     /// Reads the pending fees for a given stream from the stream-scoped
     /// ledger.
     pub async fn get_pending_fedi_fees_by_stream(&self, stream: FediFeeStream) -> Amount {
@@ -1655,25 +1770,18 @@ impl FederationV2 {
             .await
     }
 
-    async fn get_fee_ppms_by_stream(
+    async fn get_fee_rules_by_stream(
         &self,
         module: ModuleKind,
         direction: RpcTransactionDirection,
-    ) -> anyhow::Result<Vec<(FediFeeStream, u64)>> {
-        let mut fee_ppms = Vec::with_capacity(2);
-        for stream in [FediFeeStream::App, FediFeeStream::Guardian] {
-            let fee_ppm = self
-                .fedi_fee_helper
-                .get_fee_ppm(
-                    stream,
-                    self.federation_id().to_string(),
-                    module.clone(),
-                    direction.clone(),
-                )
-                .await?;
-            fee_ppms.push((stream, fee_ppm));
-        }
-        Ok(fee_ppms)
+    ) -> anyhow::Result<FediFeeRules> {
+        let volume = self
+            .get_fedi_fee_volume(module.clone(), direction.clone())
+            .await;
+        Ok(self
+            .fedi_fee_helper
+            .get_fee_rules(self.federation_id().to_string(), module, direction, volume)
+            .await?)
     }
 
     async fn get_fee_amounts_by_stream(
@@ -1683,16 +1791,25 @@ impl FederationV2 {
         amount: Amount,
     ) -> anyhow::Result<Vec<(FediFeeStream, Amount)>> {
         Ok(self
-            .get_fee_ppms_by_stream(module, direction)
+            .get_fee_rules_by_stream(module, direction)
             .await?
-            .into_iter()
-            .map(|(stream, fee_ppm)| {
-                (
-                    stream,
-                    Amount::from_msats((amount.msats * fee_ppm).div_ceil(MILLION)),
-                )
-            })
-            .collect())
+            .fees_by_stream(amount))
+    }
+
+    /// Volume of the given module and direction within the window of volume
+    /// tiers.
+    async fn get_fedi_fee_volume(
+        &self,
+        module: ModuleKind,
+        direction: RpcTransactionDirection,
+    ) -> Amount {
+        Self::fedi_fee_volume_in_db(
+            &mut self.fedi_fee_db().begin_transaction_nc().await,
+            module,
+            direction,
+            fedi_fee_volume_day(fedimint_core::time::now()),
+        )
+        .await
     }
 
     fn total_fedi_fee_amount(fees_by_stream: &[(FediFeeStream, Amount)]) -> Amount {
@@ -1724,10 +1841,6 @@ impl FederationV2 {
         }
     }
 
-    fn total_fedi_fee_ppm(fee_ppms: &[(FediFeeStream, u64)]) -> u64 {
-        fee_ppms.iter().map(|(_, fee_ppm)| *fee_ppm).sum()
-    }
-
     pub async fn generate_address(&self, frontend_meta: FrontendMetadata) -> Result<String> {
         self.wallet_ops.generate_address(self, frontend_meta).await
     }
@@ -1744,6 +1857,19 @@ impl FederationV2 {
         expiry_time: Option<u64>,
         frontend_meta: FrontendMetadata,
     ) -> Result<Bolt11Invoice> {
+        self.generate_invoice_operation(amount, description, expiry_time, frontend_meta)
+            .await
+            .map(|(_, invoice)| invoice)
+    }
+
+    /// [`Self::generate_invoice`], also returning the receive operation.
+    pub(crate) async fn generate_invoice_operation(
+        &self,
+        amount: RpcAmount,
+        description: String,
+        expiry_time: Option<u64>,
+        frontend_meta: FrontendMetadata,
+    ) -> Result<(OperationId, Bolt11Invoice)> {
         self.ln_ops
             .generate_invoice(self, amount, description, expiry_time, frontend_meta)
             .await
@@ -1759,13 +1885,20 @@ impl FederationV2 {
         self.ln_ops.estimate_ln_fees(self, invoice).await
     }
 
-    /// Pay lightning invoice
+    /// Pay lightning invoice. Gateways charging more than `max_network_fee`
+    /// are not used.
     pub async fn pay_invoice(
         &self,
         invoice: &Bolt11Invoice,
+        max_network_fee: Option<Amount>,
         frontend_meta: FrontendMetadata,
     ) -> Result<RpcPayInvoiceResponse> {
-        self.ln_ops.pay_invoice(self, invoice, frontend_meta).await
+        self.limit_spend(
+            async { ln_ops::invoice_payment_amount(invoice) },
+            self.ln_ops
+                .pay_invoice(self, invoice, max_network_fee, frontend_meta),
+        )
+        .await
     }
 
     pub(crate) async fn prepare_fee_remittance(
@@ -1782,7 +1915,7 @@ impl FederationV2 {
         &self,
         invoice: &Bolt11Invoice,
         remittance: ln_ops::FeeRemittance,
-    ) -> Result<RpcPayInvoiceResponse> {
+    ) -> Result<OperationId> {
         self.ln_ops
             .pay_fee_remittance(self, invoice, remittance)
             .await
@@ -1798,21 +1931,85 @@ impl FederationV2 {
         &self,
         address: Address<NetworkUnchecked>,
         amount: bitcoin::Amount,
+        fee_tier: RpcOnchainFeeTier,
     ) -> Result<RpcFeeDetails> {
+        let network_fee = self
+            .wallet_ops
+            .network_fee_estimate(self, address, amount)
+            .await?;
         self.wallet_ops
-            .preview_pay_address(self, address, amount)
+            .preview_pay_address(self, amount, network_fee.for_tier(fee_tier))
             .await
     }
 
+    /// Fee quotes for every tier the balance covers, cheapest first. Fails
+    /// like [`Self::preview_pay_address`] if not even the cheapest one fits.
+    ///
+    /// Fetches the federation's fee estimate once for all tiers. Confirmation
+    /// times come from the Esplora API's fee estimates, if the user chose one.
+    pub async fn preview_pay_address_fee_tiers(
+        &self,
+        address: Address<NetworkUnchecked>,
+        amount: bitcoin::Amount,
+    ) -> Result<Vec<RpcOnchainFeeQuote>> {
+        let network_fee = self
+            .wallet_ops
+            .network_fee_estimate(self, address, amount)
+            .await?;
+        let fee_estimates = self.esplora_fee_estimates().await;
+        let mut quotes = vec![];
+        for tier in [
+            RpcOnchainFeeTier::Economy,
+            RpcOnchainFeeTier::Normal,
+            RpcOnchainFeeTier::Priority,
+        ] {
+            let network_fee = network_fee.for_tier(tier);
+            match self
+                .wallet_ops
+                .preview_pay_address(self, amount, network_fee)
+                .await
+            {
+                Ok(fee_details) => quotes.push(RpcOnchainFeeQuote {
+                    tier,
+                    fee_details,
+                    estimated_confirmation_mins: fee_estimates
+                        .as_ref()
+                        .zip(network_fee.sats_per_vbyte())
+                        .and_then(|(fee_estimates, sats_per_vbyte)| {
+                            withdraw_confirmations::estimated_confirmation_mins(
+                                fee_estimates,
+                                sats_per_vbyte,
+                            )
+                        }),
+                }),
+                Err(error)
+                    if !quotes.is_empty()
+                        && matches!(
+                            error.downcast_ref::<ErrorCode>(),
+                            Some(ErrorCode::InsufficientBalance(_))
+                        ) =>
+                {
+                    break;
+                }
+                Err(error) => return Err(error),
+            }
+        }
+        Ok(quotes)
+    }
+
     pub async fn pay_address(
         &self,
         address: Address<NetworkUnchecked>,
         amount: bitcoin::Amount,
+        fee_tier: RpcOnchainFeeTier,
         frontend_meta: FrontendMetadata,
     ) -> Result<OperationId> {
-        self.wallet_ops
-            .pay_address(self, address, amount, frontend_meta)
-            .await
+        self.limit_spend(
+            async { Ok(Amount::from_sats(amount.to_sat())) },
+            self.wallet_ops
+                .pay_address(self, address, amount, fee_tier, frontend_meta),
+        )
+        .await
     }
 
     /// Subscribe to updates on all active operations
@@ -1892,12 +2089,15 @@ impl FederationV2 {
             }
             STABILITY_POOL_V2_OPERATION_TYPE => match operation.meta::<StabilityPoolMeta>() {
                 StabilityPoolMeta::Deposit { extra_meta, .. } => {
-                    // Guardian fee remittances are internal maintenance
-                    // operations. The dedicated remittance service owns their
-                    // recovery and re-subscription.
+                    // Fee remittances are internal maintenance operations. The
+                    // dedicated remittance services own their recovery and
+                    // re-subscription.
                     if !matches!(
                         serde_json::from_value::<SPv2DepositMetadata>(extra_meta).ok(),
-                        Some(SPv2DepositMetadata::GuardianFeeRemittance { .. })
+                        Some(
+                            SPv2DepositMetadata::GuardianFeeRemittance { .. }
+                                | SPv2DepositMetadata::AppFeeRemittance
+                        )
                     ) {
                         self.spawn_cancellable("subscribe_spv2_deposit", move |fed| async move {
                             fed.subscribe_spv2_deposit_to_seek(operation_id).await
@@ -2070,6 +2270,7 @@ impl FederationV2 {
                     || config.is_kind(&MintV2ClientModule::kind())
             })
             .map(|(id, _)| id);
+        self.start_recovery_progress().await;
         let mut stream = pin!(self.client.subscribe_to_recovery_progress().fuse());
         loop {
             futures::select_biased! {
@@ -2083,6 +2284,14 @@ impl FederationV2 {
                         if mint_instance_id == Some(&instance_id) {
                             self.send_recovery_progress(progress);
                         }
+                        if let Some(config) = client_config.modules.get(&instance_id) {
+                            self.record_recovery_progress(
+                                instance_id,
+                                config.kind().clone(),
+                                progress,
+                            )
+                            .await;
+                        }
                     }
                 }
             }
@@ -2103,6 +2312,8 @@ impl FederationV2 {
     /// use [`Self::send_transaction_event`].
     async fn try_send_transaction_event(&self, operation_id: OperationId) -> anyhow::Result<()> {
         let transaction = self.get_transaction(operation_id).await?;
+        self.index_transaction(operation_id, None, &transaction)
+            .await;
         let event = Event::transaction(self.federation_id().to_string(), transaction);
         self.runtime.event_sink.try_typed_event(&event)
     }
@@ -2144,6 +2355,8 @@ impl FederationV2 {
             self.get_balance(),
         );
         let had_reused_ecash = self.mint_ops.had_reused_ecash(self).await;
+        let end_of_life = self.end_of_life(&meta).await;
+        let recovery_progress = self.get_recovery_progress().await;
         RpcFederation {
             balance: RpcAmount(balance),
             id,
@@ -2159,6 +2372,8 @@ impl FederationV2 {
             }),
             fedi_fee_schedule: fedi_fee_schedule.into(),
             had_reused_ecash,
+            end_of_life,
+            recovery_progress,
         }
     }
 
@@ -2276,9 +2491,22 @@ impl FederationV2 {
         ecash: String,
         frontend_meta: FrontendMetadata,
     ) -> Result<(Amount, OperationId)> {
-        self.mint_ops
-            .receive_ecash(self, ecash, frontend_meta)
+        match self
+            .mint_ops
+            .receive_ecash(self, ecash.clone(), frontend_meta.clone())
             .await
+        {
+            Err(error) if is_federation_network_error(&error) => {
+                match self.queue_ecash_receive(ecash, frontend_meta).await? {
+                    Some(queued) => {
+                        info!(?error, "federation unreachable, queued ecash receive");
+                        Ok(queued)
+                    }
+                    None => Err(error),
+                }
+            }
+            result => result,
+        }
     }
 
     pub async fn subscribe_to_ecash_reissue(
@@ -2294,28 +2522,16 @@ impl FederationV2 {
     /// Determine the maximum actual amount of e-cash that can be generated for
     /// sending taking into account fees.
     pub async fn calculate_max_generate_ecash(&self) -> Result<RpcAmount> {
-        // Let's say that amount we're looking for is max. We wish to satisfy this
-        // equation: max + fedi_fee = virtual_balance
-        //
-        // total_fedi_fee is calculated as follows:
-        // total_fedi_fee = (max * total_fedi_fee_ppm) / MILLION
-        //
-        // Plugging this into the original equation, we get:
-        // max + [(max * total_fedi_fee_ppm) / MILLION] = virtual_balance
-        //
-        // We can solve this for max as follows:
-        // max = (virtual_balance * MILLION) / (MILLION + total_fedi_fee_ppm)
-        // We use floor division here
-        let fee_ppms = self
-            .get_fee_ppms_by_stream(fedimint_mint_client::KIND, RpcTransactionDirection::Send)
+        let fee_rules = self
+            .get_fee_rules_by_stream(fedimint_mint_client::KIND, RpcTransactionDirection::Send)
             .await?;
         let virtual_balance = self.get_balance().await;
-        let max = {
-            let numerator = virtual_balance.mul_u64(MILLION).msats;
-            let denominator = MILLION + FederationV2::total_fedi_fee_ppm(&fee_ppms);
-            numerator / denominator
-        };
-        Ok(RpcAmount(Amount::from_msats(max)))
+        Ok(RpcAmount(get_max_spendable_amount(
+            virtual_balance,
+            &fee_rules,
+            None,
+            None,
+        )))
     }
 
     /// Estimates fees for generating e-cash in this federation.
@@ -2331,19 +2547,44 @@ impl FederationV2 {
     }
 
     /// Generate ecash
+    ///
+    /// With `expiry_secs`, the ecash is reclaimed automatically if it has not
+    /// been claimed by then. The mint client commits the spend on its own, so
+    /// failing to record the expiry afterwards only loses the automatic
+    /// reclaim; the ecash is still returned so it isn't lost to the user.
     pub async fn generate_ecash(
         &self,
         amount: Amount,
         include_invite: bool,
+        expiry_secs: Option<u64>,
         frontend_meta: FrontendMetadata,
     ) -> Result<RpcGenerateEcashResponse> {
-        self.mint_ops
-            .generate_ecash(self, amount, include_invite, frontend_meta)
-            .await
+        let response = self
+            .limit_spend(
+                async { Ok(amount) },
+                self.mint_ops
+                    .generate_ecash(self, amount, include_invite, frontend_meta),
+            )
+            .await?;
+        if let Some(expiry_secs) = expiry_secs {
+            let scheduled = self
+                .schedule_ecash_expiry(response.operation_id.0, response.ecash.clone(), expiry_secs)
+                .await;
+            if let Err(error) = scheduled {
+                error!(
+                    ?error,
+                    operation_id = %response.operation_id.0.fmt_short(),
+                    "failed to schedule ecash expiry, it will not be reclaimed automatically"
+                );
+            }
+        }
+        Ok(response)
     }
 
     pub async fn cancel_ecash(&self, ecash: String) -> Result<()> {
-        self.mint_ops.cancel_ecash(self, ecash).await
+        self.mint_ops.cancel_ecash(self, ecash.clone()).await?;
+        self.unschedule_ecash_expiry(&ecash).await;
+        Ok(())
     }
 
     async fn subscribe_oob_spend(&self, op_id: OperationId) -> Result<(), anyhow::Error> {
@@ -2707,11 +2948,12 @@ impl FederationV2 {
 
     async fn walletv2_awaiting_deposit_tx(&self, address: &str) -> RpcTransaction {
         let id = Self::walletv2_awaiting_deposit_id(address);
-        RpcTransaction {
+        let mut transaction = RpcTransaction {
             id: id.fmt_full().to_string(),
             amount: RpcAmount(Amount::ZERO),
             fedi_app_fee_status: None,
             fedi_guardian_fee_status: None,
+            fedi_app_fee_discount: None,
             txn_notes: self.dbtx().await.get_value(&TransactionNotesKey(id)).await,
             tx_date_fiat_info: None,
             frontend_metadata: FrontendMetadata::default(),
@@ -2721,7 +2963,11 @@ impl FederationV2 {
                 state: Some(RpcOnchainDepositState::WaitingForTransaction),
             },
             outcome_time: None,
-        }
+            unified_receive: None,
+            federation_transfer: None,
+        };
+        self.decorate_unified_receive_leg(&mut transaction).await;
+        transaction
     }
 
     /// Settling at render, not from the merge, is what covers a claim older
@@ -2869,6 +3115,8 @@ impl FederationV2 {
         if start_after.is_none() {
             self.merge_walletv2_awaiting_deposits(&mut entries, limit, oldest_op_time)
                 .await;
+            self.merge_queued_ecash_receives(&mut entries, limit, oldest_op_time)
+                .await;
         }
         entries
     }
@@ -2877,12 +3125,17 @@ impl FederationV2 {
         &self,
         operation_id: OperationId,
     ) -> anyhow::Result<RpcTransaction> {
-        let entry = self
+        let Some(entry) = self
             .client
             .operation_log()
             .get_operation(operation_id)
             .await
-            .context("transaction not found")?;
+        else {
+            return self
+                .queued_ecash_receive_tx(operation_id)
+                .await
+                .context("transaction not found");
+        };
 
         match self
             .get_transaction_inner(operation_id, entry)
@@ -2938,6 +3191,9 @@ impl FederationV2 {
                 transaction.txn_notes = Some(notes);
             }
         }
+        self.decorate_unified_receive_leg(&mut transaction).await;
+        self.decorate_federation_transfer_leg(&mut transaction)
+            .await;
         Ok(Some(transaction))
     }
 
@@ -2976,6 +3232,16 @@ impl FederationV2 {
             ))
             .await
             .map(Into::into);
+        let app_fedi_fee_discount = self
+            .fedi_fee_db()
+            .begin_transaction_nc()
+            .await
+            .get_value(&OperationFediFeeDiscountByStreamKey(
+                operation_id,
+                FediFeeStream::App,
+            ))
+            .await
+            .map(Into::into);
         let app_fedi_fee_msats = match app_fedi_fee_status {
             Some(
                 RpcOperationFediFeeStatus::PendingSend { fedi_fee }
@@ -3088,9 +3354,12 @@ impl FederationV2 {
                         serde_json::from_value::<SPv2DepositMetadata>(extra_meta.clone()).ok();
                     if matches!(
                         typed_extra_meta,
-                        Some(SPv2DepositMetadata::GuardianFeeRemittance { .. })
+                        Some(
+                            SPv2DepositMetadata::GuardianFeeRemittance { .. }
+                                | SPv2DepositMetadata::AppFeeRemittance
+                        )
                     ) {
-                        // Guardian fee remittance deposits are internal bridge
+                        // Fee remittance deposits are internal bridge
                         // maintenance operations and should not surface in the
                         // normal transaction list.
                         return Ok(None);
@@ -3387,11 +3656,14 @@ impl FederationV2 {
             amount: transaction_amount,
             fedi_app_fee_status: app_fedi_fee_status,
             fedi_guardian_fee_status: guardian_fedi_fee_status,
+            fedi_app_fee_discount: app_fedi_fee_discount,
             txn_notes: notes.or_else(|| frontend_metadata.initial_notes.clone()),
             tx_date_fiat_info,
             frontend_metadata,
             kind: transaction_kind,
             outcome_time: outcome_time.and_then(|x| to_unix_time(x).ok()),
+            unified_receive: None,
+            federation_transfer: None,
         }))
     }
 
@@ -3403,7 +3675,9 @@ impl FederationV2 {
         let mut dbtx = self.dbtx().await;
         dbtx.insert_entry(&TransactionNotesKey(transaction), &notes)
             .await;
-        dbtx.commit_tx_result().await.context("DbError")
+        dbtx.commit_tx_result().await.context("DbError")?;
+        self.reindex_transaction(transaction).await;
+        Ok(())
     }
 
     // FIXME this is busted in social recovery
@@ -3438,6 +3712,21 @@ impl FederationV2 {
         }
     }
 
+    /// Our seeker account's payment address, optionally carrying the invite
+    /// code so that payers who have not joined can join first.
+    pub async fn spv2_payment_address(&self, include_invite: bool) -> Result<Spv2PaymentAddress> {
+        let federation_invite = if include_invite {
+            self.get_invite_code().await.parse().ok()
+        } else {
+            None
+        };
+        Ok(Spv2PaymentAddress {
+            account_id: self.client.spv2()?.our_account(AccountType::Seeker).id(),
+            federation_id_prefix: self.federation_id().to_prefix(),
+            federation_invite,
+        })
+    }
+
     /// Returns the latest cached sync response representing the seeker's last
     /// know SPv2 state. Getting the cached response should be sufficient
     /// because the value only updates once per cycle, and we already have a
@@ -3543,30 +3832,19 @@ impl FederationV2 {
         frontend_meta: FrontendMetadata,
     ) -> Result<OperationId> {
         let spv2 = self.client.spv2()?;
-        let fee_ppms = self
-            .get_fee_ppms_by_stream(
+        let fee_rules = self
+            .get_fee_rules_by_stream(
                 stability_pool_client::common::KIND,
                 RpcTransactionDirection::Send,
             )
             .await?;
-        let fees_by_stream = self
-            .get_fee_amounts_by_stream(
-                stability_pool_client::common::KIND,
-                RpcTransactionDirection::Send,
-                amount,
-            )
-            .await?;
+        let fees_by_stream = fee_rules.fees_by_stream(amount);
         let fedi_fee = Self::total_fedi_fee_amount(&fees_by_stream);
         let spend_guard = self.spend_guard.lock().await;
         let virtual_balance = self.get_balance().await;
         if amount + fedi_fee > virtual_balance {
             bail!(ErrorCode::InsufficientBalance(RpcAmount(
-                get_max_spendable_amount(
-                    virtual_balance,
-                    Self::total_fedi_fee_ppm(&fee_ppms),
-                    None,
-                    None,
-                )
+                get_max_spendable_amount(virtual_balance, &fee_rules, None, None)
             )));
         }
 
@@ -3578,7 +3856,7 @@ impl FederationV2 {
                 },
             )
             .await?;
-        self.write_pending_send_fedi_fees(operation_id, &fees_by_stream)
+        self.write_pending_send_fedi_fees(operation_id, amount, &fee_rules)
             .await?;
         let _ = self
             .record_tx_date_fiat_info(operation_id, amount + fedi_fee)
@@ -3674,28 +3952,41 @@ impl FederationV2 {
         amount: FiatOrAll,
         frontend_meta: FrontendMetadata,
     ) -> Result<OperationId> {
-        let spv2 = self.client.spv2()?;
-        let fee_ppms = self
-            .get_fee_ppms_by_stream(
-                stability_pool_client::common::KIND,
-                RpcTransactionDirection::Receive,
-            )
-            .await?;
-        let (operation_id, _) = spv2
-            .withdraw(
-                AccountType::Seeker,
-                amount,
-                SPv2WithdrawMetadata::StableBalance {
-                    frontend_metadata: Some(frontend_meta),
-                },
-            )
-            .await?;
-        self.write_pending_receive_fedi_fee_ppms(operation_id, &fee_ppms)
-            .await?;
-        self.spawn_cancellable("subscribe_spv2_withdraw", move |fed| async move {
-            fed.subscribe_spv2_withdraw(operation_id).await
-        });
-        Ok(operation_id)
+        self.limit_spend(self.spv2_amount_msats(amount), async {
+            let spv2 = self.client.spv2()?;
+            let fee_rules = self
+                .get_fee_rules_by_stream(
+                    stability_pool_client::common::KIND,
+                    RpcTransactionDirection::Receive,
+                )
+                .await?;
+            let (operation_id, _) = spv2
+                .withdraw(
+                    AccountType::Seeker,
+                    amount,
+                    SPv2WithdrawMetadata::StableBalance {
+                        frontend_metadata: Some(frontend_meta),
+                    },
+                )
+                .await?;
+            self.write_pending_receive_fedi_fee_rules(operation_id, &fee_rules)
+                .await?;
+            self.spawn_cancellable("subscribe_spv2_withdraw", move |fed| async move {
+                fed.subscribe_spv2_withdraw(operation_id).await
+            });
+            Ok(operation_id)
+        })
+        .await
+    }
+
+    /// What `amount` of the stable balance is worth at the current cycle's
+    /// price.
+    async fn spv2_amount_msats(&self, amount: FiatOrAll) -> Result<Amount> {
+        let account_info = self.spv2_account_info().await?.value;
+        match amount {
+            FiatOrAll::Fiat(amount) => amount.to_btc_amount(account_info.current_cycle.start_price),
+            FiatOrAll::All => Ok(account_info.staged_balance + account_info.locked_balance),
+        }
     }
 
     async fn enable_guardian_remittance_account(&self) -> anyhow::Result<()> {
@@ -3868,9 +4159,23 @@ impl FederationV2 {
         // 1. We don't always know the amount (it could be ALL)
         // 2. The submitter of the TX might not be the sender or the recipient
 
-        let operation_id = spv2.transfer(signed_request, meta).await?;
-        self.subscribe_to_operation(operation_id).await?;
-        Ok(operation_id)
+        // requests from other accounts, e.g. multispend withdrawals, are
+        // only submitted by us and don't spend our balance
+        let amount = async {
+            let details = signed_request.details();
+            if details.from().id() == spv2.our_account(AccountType::Seeker).id() {
+                self.spv2_amount_msats(FiatOrAll::Fiat(details.amount()))
+                    .await
+            } else {
+                Ok(Amount::ZERO)
+            }
+        };
+        self.limit_spend(amount, async {
+            let operation_id = spv2.transfer(signed_request.clone(), meta).await?;
+            self.subscribe_to_operation(operation_id).await?;
+            Ok(operation_id)
+        })
+        .await
     }
 
     /// Build a SignedTransferRequest using an explicit nonce for idempotency.
@@ -4120,36 +4425,25 @@ impl FederationV2 {
     /// cycle turnover occurs, staged seeks are processed in order
     /// to produce locks.
     pub async fn stability_pool_deposit_to_seek(&self, amount: Amount) -> Result<OperationId> {
-        let fee_ppms = self
-            .get_fee_ppms_by_stream(
+        let fee_rules = self
+            .get_fee_rules_by_stream(
                 stability_pool_client_old::common::KIND,
                 RpcTransactionDirection::Send,
             )
             .await?;
-        let fees_by_stream = self
-            .get_fee_amounts_by_stream(
-                stability_pool_client_old::common::KIND,
-                RpcTransactionDirection::Send,
-                amount,
-            )
-            .await?;
+        let fees_by_stream = fee_rules.fees_by_stream(amount);
         let fedi_fee = Self::total_fedi_fee_amount(&fees_by_stream);
         let spend_guard = self.spend_guard.lock().await;
         let virtual_balance = self.get_balance().await;
         if amount + fedi_fee > virtual_balance {
             bail!(ErrorCode::InsufficientBalance(RpcAmount(
-                get_max_spendable_amount(
-                    virtual_balance,
-                    Self::total_fedi_fee_ppm(&fee_ppms),
-                    None,
-                    None,
-                )
+                get_max_spendable_amount(virtual_balance, &fee_rules, None, None)
             )));
         }
 
         let module = self.client.sp()?;
         let operation_id = module.deposit_to_seek(amount).await?;
-        self.write_pending_send_fedi_fees(operation_id, &fees_by_stream)
+        self.write_pending_send_fedi_fees(operation_id, amount, &fee_rules)
             .await?;
         let _ = self
             .record_tx_date_fiat_info(operation_id, amount + fedi_fee)
@@ -4198,8 +4492,8 @@ impl FederationV2 {
         unlocked_amount: Amount,
         locked_bps: u32,
     ) -> Result<OperationId> {
-        let fee_ppms = self
-            .get_fee_ppms_by_stream(
+        let fee_rules = self
+            .get_fee_rules_by_stream(
                 stability_pool_client_old::common::KIND,
                 RpcTransactionDirection::Receive,
             )
@@ -4209,7 +4503,7 @@ impl FederationV2 {
             .sp()?
             .withdraw(unlocked_amount, locked_bps)
             .await?;
-        self.write_pending_receive_fedi_fee_ppms(operation_id, &fee_ppms)
+        self.write_pending_receive_fedi_fee_rules(operation_id, &fee_rules)
             .await?;
         self.spawn_cancellable("subscribe_stability_pool_withdraw", move |fed| async move {
             fed.subscribe_stability_pool_withdraw(operation_id).await
@@ -4296,13 +4590,19 @@ impl FederationV2 {
     pub async fn generate_reused_ecash_proofs(
         &self,
     ) -> anyhow::Result<SerializedReusedEcashProofs> {
-        self.mint_ops.generate_reused_ecash_proofs(self).await
+        let proofs = self.mint_ops.generate_reused_ecash_proofs(self).await?;
+        if proofs.total_amount_msats != Amount::ZERO {
+            return Ok(proofs);
+        }
+        // a repair may have cleared them, bug reports should still carry them
+        Ok(self.backed_up_reused_ecash_proofs().await.unwrap_or(proofs))
     }
 
     async fn write_pending_send_fedi_fees(
         &self,
         operation_id: OperationId,
-        fees_by_stream: &[(FediFeeStream, Amount)],
+        amount: Amount,
+        fee_rules: &FediFeeRules,
     ) -> anyhow::Result<()> {
         let module = ModuleKind::clone_from_str(
             self.client
@@ -4318,9 +4618,10 @@ impl FederationV2 {
                 |dbtx, _| {
                     Box::pin({
                         let module = module.clone();
-                        let fees_by_stream = fees_by_stream.to_vec();
+                        let fee_rules = fee_rules.clone();
                         async move {
-                            for (stream, fedi_fee) in fees_by_stream {
+                            for (stream, fedi_fee_rule) in fee_rules.rules {
+                                let fedi_fee = fedi_fee_rule.fee(amount, fee_rules.volume);
                                 Self::insert_pending_send_fedi_fee_in_db(
                                     dbtx,
                                     stream,
@@ -4329,7 +4630,23 @@ impl FederationV2 {
                                     fedi_fee,
                                 )
                                 .await?;
+                                if let Some(discount) = &fedi_fee_rule.discount {
+                                    let waived_fee = fedi_fee_rule.waived_fee(
+                                        fedi_fee_rule.undiscounted_fee(amount, fee_rules.volume),
+                                    );
+                                    dbtx.insert_entry(
+                                        &OperationFediFeeDiscountByStreamKey(operation_id, stream),
+                                        &OperationFediFeeDiscount {
+                                            promotion_id: discount.promotion_id.clone(),
+                                            discount_ppm: discount.discount_ppm,
+                                            waived_fee: Some(waived_fee),
+                                        },
+                                    )
+                                    .await;
+                                }
                             }
+                            dbtx.insert_entry(&PendingSendFediFeeVolumeKey(operation_id), &amount)
+                                .await;
                             Ok::<(), anyhow::Error>(())
                         }
                     })
@@ -4381,9 +4698,21 @@ impl FederationV2 {
                                 dbtx,
                                 FediFeeStream::Guardian,
                                 operation_id,
-                                module,
+                                module.clone(),
                             )
                             .await?;
+                            if let Some(amount) = dbtx
+                                .remove_entry(&PendingSendFediFeeVolumeKey(operation_id))
+                                .await
+                            {
+                                Self::record_fedi_fee_volume_in_db(
+                                    dbtx,
+                                    module,
+                                    RpcTransactionDirection::Send,
+                                    amount,
+                                )
+                                .await;
+                            }
                             Ok::<bool, anyhow::Error>(app_changed)
                         }
                     })
@@ -4445,6 +4774,8 @@ impl FederationV2 {
                                 module,
                             )
                             .await?;
+                            dbtx.remove_entry(&PendingSendFediFeeVolumeKey(operation_id))
+                                .await;
                             Ok::<(), anyhow::Error>(())
                         }
                     })
@@ -4486,24 +4817,24 @@ impl FederationV2 {
         )
     }
 
-    async fn write_pending_receive_fedi_fee_ppms(
+    async fn write_pending_receive_fedi_fee_rules(
         &self,
         operation_id: OperationId,
-        fee_ppms: &[(FediFeeStream, u64)],
+        fee_rules: &FediFeeRules,
     ) -> anyhow::Result<()> {
         let res = self
             .fedi_fee_db()
             .autocommit(
                 |dbtx, _| {
                     Box::pin({
-                        let fee_ppms = fee_ppms.to_vec();
+                        let fee_rules = fee_rules.rules.clone();
                         async move {
-                            for (stream, fedi_fee_ppm) in fee_ppms {
-                                Self::insert_pending_receive_fedi_fee_ppm_in_db(
+                            for (stream, fedi_fee_rule) in fee_rules {
+                                Self::insert_pending_receive_fedi_fee_rule_in_db(
                                     dbtx,
                                     stream,
                                     operation_id,
-                                    fedi_fee_ppm,
+                                    fedi_fee_rule,
                                 )
                                 .await?;
                             }
@@ -4563,10 +4894,21 @@ impl FederationV2 {
                                 dbtx,
                                 FediFeeStream::Guardian,
                                 operation_id,
-                                module,
+                                module.clone(),
                                 amount,
                             )
                             .await?;
+                            // both streams charged the volume before this
+                            // receive, so it only counts from now on
+                            if app_changed {
+                                Self::record_fedi_fee_volume_in_db(
+                                    dbtx,
+                                    module,
+                                    RpcTransactionDirection::Receive,
+                                    amount,
+                                )
+                                .await;
+                            }
                             Ok::<bool, anyhow::Error>(app_changed)
                         }
                     })
@@ -4727,6 +5069,16 @@ impl FederationV2 {
                 dbtx.insert_entry(&outstanding_tx_type_key, &outstanding_tx_type_fees)
                     .await;
                 dbtx.insert_entry(&outstanding_key, &outstanding_fees).await;
+                record_fedi_fee_ledger_entry(
+                    dbtx,
+                    stream,
+                    FediFeeLedgerEntryKind::Accrual,
+                    operation_id,
+                    module.clone(),
+                    RpcTransactionDirection::Send,
+                    fedi_fee,
+                )
+                .await;
                 if stream == FediFeeStream::App {
                     let total_accrued_key = TotalAccruedFediFeesByStreamKey(FediFeeStream::App);
                     let total_accrued_fees = fedi_fee
@@ -4800,16 +5152,34 @@ impl FederationV2 {
     /// generate_address for example), and even if we do, the amount to be
     /// received (from which the fee is to be debited) is not in the user's
     /// possession until the operation completes. So for receives, we just
-    /// record the ppm, and when the operation succeeds, we debit the fee.
-    async fn insert_pending_receive_fedi_fee_ppm_in_db(
+    /// record the fee rule, and when the operation succeeds, we debit the fee.
+    async fn insert_pending_receive_fedi_fee_rule_in_db(
         dbtx: &mut DatabaseTransaction<'_>,
         stream: FediFeeStream,
         operation_id: OperationId,
-        fedi_fee_ppm: u64,
+        fedi_fee_rule: FediFeeRule,
     ) -> anyhow::Result<()> {
         dbtx.insert_entry(
             &OperationFediFeeStatusByStreamKey(operation_id, stream),
-            &OperationFediFeeStatus::PendingReceive { fedi_fee_ppm },
+            &OperationFediFeeStatus::PendingReceive {
+                fedi_fee_ppm: fedi_fee_rule.ppm,
+            },
+        )
+        .await;
+        if let Some(discount) = &fedi_fee_rule.discount {
+            dbtx.insert_entry(
+                &OperationFediFeeDiscountByStreamKey(operation_id, stream),
+                &OperationFediFeeDiscount {
+                    promotion_id: discount.promotion_id.clone(),
+                    discount_ppm: discount.discount_ppm,
+                    waived_fee: None,
+                },
+            )
+            .await;
+        }
+        dbtx.insert_entry(
+            &PendingReceiveFediFeeRuleByStreamKey(operation_id, stream),
+            &fedi_fee_rule,
         )
         .await;
         Ok(())
@@ -4832,7 +5202,25 @@ impl FederationV2 {
         let op_key = OperationFediFeeStatusByStreamKey(operation_id, stream);
         match dbtx.get_value(&op_key).await {
             Some(OperationFediFeeStatus::PendingReceive { fedi_fee_ppm }) => {
-                let fedi_fee = Amount::from_msats((amount.msats * fedi_fee_ppm).div_ceil(MILLION));
+                let fedi_fee_rule = dbtx
+                    .remove_entry(&PendingReceiveFediFeeRuleByStreamKey(operation_id, stream))
+                    .await
+                    .unwrap_or_else(|| FediFeeRule::flat(fedi_fee_ppm));
+                let volume = Self::fedi_fee_volume_in_db(
+                    dbtx,
+                    module.clone(),
+                    RpcTransactionDirection::Receive,
+                    fedi_fee_volume_day(fedimint_core::time::now()),
+                )
+                .await;
+                let fedi_fee = fedi_fee_rule.fee(amount, volume);
+                let discount_key = OperationFediFeeDiscountByStreamKey(operation_id, stream);
+                if let Some(mut discount) = dbtx.get_value(&discount_key).await {
+                    discount.waived_fee = Some(
+                        fedi_fee_rule.waived_fee(fedi_fee_rule.undiscounted_fee(amount, volume)),
+                    );
+                    dbtx.insert_entry(&discount_key, &discount).await;
+                }
                 let outstanding_tx_type_key = OutstandingFediFeesByStreamPerTXTypeKey(
                     stream,
                     module.clone(),
@@ -4854,6 +5242,16 @@ impl FederationV2 {
                 dbtx.insert_entry(&outstanding_tx_type_key, &outstanding_tx_type_fees)
                     .await;
                 dbtx.insert_entry(&outstanding_key, &outstanding_fees).await;
+                record_fedi_fee_ledger_entry(
+                    dbtx,
+                    stream,
+                    FediFeeLedgerEntryKind::Accrual,
+                    operation_id,
+                    module.clone(),
+                    RpcTransactionDirection::Receive,
+                    fedi_fee,
+                )
+                .await;
                 if stream == FediFeeStream::App {
                     let total_accrued_key = TotalAccruedFediFeesByStreamKey(FediFeeStream::App);
                     let total_accrued_fees = fedi_fee
@@ -4891,6 +5289,8 @@ impl FederationV2 {
             Some(OperationFediFeeStatus::PendingReceive { fedi_fee_ppm }) => {
                 let new_status = OperationFediFeeStatus::FailedReceive { fedi_fee_ppm };
                 dbtx.insert_entry(&key, &new_status).await;
+                dbtx.remove_entry(&PendingReceiveFediFeeRuleByStreamKey(operation_id, stream))
+                    .await;
                 Ok(true)
             }
             Some(OperationFediFeeStatus::FailedReceive { .. }) => Ok(false),
@@ -4902,6 +5302,49 @@ impl FederationV2 {
         }
     }
 
+    /// Sums the volume of the daily buckets within the window ending on
+    /// `today`.
+    async fn fedi_fee_volume_in_db<Cap: Send>(
+        dbtx: &mut DatabaseTransaction<'_, Cap>,
+        module: ModuleKind,
+        direction: RpcTransactionDirection,
+        today: u64,
+    ) -> Amount {
+        dbtx.find_by_prefix(&FediFeeVolumeKeyPrefix(module, direction))
+            .await
+            .filter(|(key, _)| std::future::ready(key.2 + FEDI_FEE_VOLUME_WINDOW_DAYS > today))
+            .fold(
+                Amount::ZERO,
+                |total, (_, volume)| async move { total + volume },
+            )
+            .await
+    }
+
+    /// Adds `amount` to today's volume and prunes the buckets that left the
+    /// window.
+    async fn record_fedi_fee_volume_in_db(
+        dbtx: &mut DatabaseTransaction<'_>,
+        module: ModuleKind,
+        direction: RpcTransactionDirection,
+        amount: Amount,
+    ) {
+        let today = fedi_fee_volume_day(fedimint_core::time::now());
+        let expired = dbtx
+            .find_by_prefix(&FediFeeVolumeKeyPrefix(module.clone(), direction.clone()))
+            .await
+            .filter_map(|(key, _)| {
+                std::future::ready((key.2 + FEDI_FEE_VOLUME_WINDOW_DAYS <= today).then_some(key))
+            })
+            .collect::<Vec<_>>()
+            .await;
+        for key in expired {
+            dbtx.remove_entry(&key).await;
+        }
+        let key = FediFeeVolumeKey(module, direction, today);
+        let volume = amount + dbtx.get_value(&key).await.unwrap_or(Amount::ZERO);
+        dbtx.insert_entry(&key, &volume).await;
+    }
+
     #[instrument(skip(self), err, ret)]
     async fn record_tx_date_fiat_info(
         &self,
@@ -5141,15 +5584,23 @@ fn get_per_federation_secret(
     federation_wallet_root_secret.child_key(ChildId(key_type))
 }
 
-// Given the current virtual balance and the Fedi fee ppm for a spend operation,
-// as well an optional gateway fee and an optional on-chain fee, returns the max
-// amount of the spend transaction such that:
+/// Day since the unix epoch that volume at `time` is bucketed into.
+fn fedi_fee_volume_day(time: SystemTime) -> u64 {
+    time.duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+        / (24 * 60 * 60)
+}
+
+// Given the current virtual balance and the Fedi fee rules for a spend
+// operation, as well an optional gateway fee and an optional on-chain fee,
+// returns the max amount of the spend transaction such that:
 //
 // (  max spend  ) + (total fee)      <=        virtual balance
 // ^return value^                ^really "=="^
 fn get_max_spendable_amount(
     virtual_balance: Amount,
-    total_fedi_fee_ppm: u64,
+    fedi_fee_rules: &FediFeeRules,
     on_chain_fee: Option<PegOutFees>,
     gateway_fee: Option<RoutingFees>,
 ) -> Amount {
@@ -5165,22 +5616,26 @@ fn get_max_spendable_amount(
 
     let gateway_base = gateway_fee.map_or(0, |f| f.base_msat as u64);
     let gateway_ppm = gateway_fee.map_or(0, |f| f.proportional_millionths as u64);
-
-    // Let's say the max spend is x, total Fedi fee ppm is (ppm_F), and virtual
-    // balance is V. Gateway fees is made up of (base) and (ppm_G).
-    //
-    // Then:
-    // x + [(x * ppm_F) / M] + base + [(X * ppm_G) / M] = V, where M is the constant
-    // for million
-    //
-    // Solving for x:
-    // x[1 + (ppm_F/M) + (ppm_G/M)] = V - base
-    //
-    // Finally:
-    // x = [(V - base) * M]/(M + ppm_F + ppm_G)
-    let numerator_msats = (virtual_balance.msats.saturating_sub(gateway_base)) * MILLION;
-    let denominator_msats = MILLION + total_fedi_fee_ppm + gateway_ppm;
-    Amount::from_msats(numerator_msats / denominator_msats)
+    // rounded up like the pay path does, so the max spend is always payable
+    let fits = |spend: u64| {
+        let gateway_fee = gateway_base + (spend * gateway_ppm).div_ceil(MILLION);
+        let fedi_fee = fedi_fee_rules.total_fee(Amount::from_msats(spend)).msats;
+        spend + fedi_fee + gateway_fee <= virtual_balance.msats
+    };
+
+    // With base fees, caps and volume tiers the Fedi fee has no closed form
+    // inverse, but the total fee never decreases as the spend grows, so the
+    // max spend can be bisected.
+    let (mut low, mut high) = (0, virtual_balance.msats);
+    while low < high {
+        let mid = low + (high - low).div_ceil(2);
+        if fits(mid) {
+            low = mid;
+        } else {
+            high = mid - 1;
+        }
+    }
+    Amount::from_msats(low)
 }
 
 // Function below is currently copied from
@@ -5336,6 +5791,33 @@ mod tests {
         assert!(ensure_supported_federation_shape(kinds.iter()).is_ok());
     }
 
+    #[test]
+    fn max_spendable_amount_pays_rounded_up_fees() {
+        let fee_rules = FediFeeRules {
+            rules: vec![(
+                FediFeeStream::App,
+                crate::fedi_fee::FediFeeRule::flat(2_100),
+            )],
+            volume: Amount::ZERO,
+        };
+        let gateway_fees = RoutingFees {
+            base_msat: 1_000,
+            proportional_millionths: 1_000,
+        };
+        let virtual_balance = Amount::from_msats(1_000_000);
+        let spend =
+            get_max_spendable_amount(virtual_balance, &fee_rules, None, Some(gateway_fees)).msats;
+        // what the pay path charges on top of the spend
+        let total_spend = |spend: u64| {
+            spend
+                + fee_rules.total_fee(Amount::from_msats(spend)).msats
+                + 1_000
+                + (spend * 1_000).div_ceil(MILLION)
+        };
+        assert!(total_spend(spend) <= virtual_balance.msats);
+        assert!(total_spend(spend + 1) > virtual_balance.msats);
+    }
+
     #[test]
     fn mixed_shape_is_rejected() {
         let kinds = shape(&[
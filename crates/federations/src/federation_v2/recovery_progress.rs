@@ -0,0 +1,217 @@
+//! Tracks how far each module got restoring the wallet and estimates when
+//! recovery completes.
+//!
+//! Recovering modules replay the federation's session history, which takes a
+//! while for old federations. The estimate extrapolates the rate observed since
+//! the app started waiting for recovery, so it only shows up once a few
+//! sessions were processed.
+
+use std::collections::BTreeMap;
+use std::time::{Duration, SystemTime};
+
+use fedimint_client::module::module::recovery::RecoveryProgress;
+use fedimint_core::core::{ModuleInstanceId, ModuleKind};
+use fedimint_core::db::IDatabaseTransactionOpsCoreTyped;
+use rpc_types::{RpcModuleRecoveryProgress, RpcRecoveryProgress};
+use runtime::db::FederationPendingRejoinFromScratchKey;
+
+use super::FederationV2;
+
+/// Progress updates arrive for every session, federation events are sent at
+/// most this often.
+const EVENT_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Default)]
+pub struct RecoveryProgressTracker {
+    from_scratch: bool,
+    modules: BTreeMap<ModuleInstanceId, ModuleRecoveryProgress>,
+    last_event_at: Option<SystemTime>,
+}
+
+#[derive(Debug)]
+struct ModuleRecoveryProgress {
+    kind: ModuleKind,
+    /// First progress seen, the rate is measured from here.
+    started_at: SystemTime,
+    started_complete: u32,
+    complete: u32,
+    total: u32,
+}
+
+impl ModuleRecoveryProgress {
+    fn eta(&self, now: SystemTime) -> Option<Duration> {
+        // total is zero until the module knows how many sessions to process
+        if self.total == 0 {
+            return None;
+        }
+        let remaining = self.total.saturating_sub(self.complete);
+        if remaining == 0 {
+            return Some(Duration::ZERO);
+        }
+        let processed = self.complete.saturating_sub(self.started_complete);
+        let elapsed = now.duration_since(self.started_at).ok()?;
+        if processed == 0 || elapsed.is_zero() {
+            return None;
+        }
+        Some(elapsed.mul_f64(f64::from(remaining) / f64::from(processed)))
+    }
+}
+
+impl RecoveryProgressTracker {
+    /// Records the progress of a module, returns whether a federation event
+    /// should be sent for it.
+    fn update(
+        &mut self,
+        instance_id: ModuleInstanceId,
+        kind: ModuleKind,
+        progress: RecoveryProgress,
+        now: SystemTime,
+    ) -> bool {
+        let module = self
+            .modules
+            .entry(instance_id)
+            .or_insert_with(|| ModuleRecoveryProgress {
+                kind,
+                started_at: now,
+                started_complete: progress.complete,
+                complete: progress.complete,
+                total: progress.total,
+            });
+        module.complete = progress.complete;
+        module.total = progress.total;
+
+        let finished = progress.total != 0 && progress.complete >= progress.total;
+        let due = self
+            .last_event_at
+            .is_none_or(|last| now.duration_since(last).unwrap_or_default() >= EVENT_INTERVAL);
+        if finished || due {
+            self.last_event_at = Some(now);
+        }
+        finished || due
+    }
+
+    /// Modules recover concurrently, so recovery takes as long as the slowest
+    /// one.
+    fn eta(&self, now: SystemTime) -> Option<Duration> {
+        if self.modules.is_empty() {
+            return None;
+        }
+        self.modules
+            .values()
+            .try_fold(Duration::ZERO, |eta, module| {
+                Some(eta.max(module.eta(now)?))
+            })
+    }
+
+    fn to_rpc(&self, now: SystemTime) -> RpcRecoveryProgress {
+        RpcRecoveryProgress {
+            from_scratch: self.from_scratch,
+            modules: self
+                .modules
+                .iter()
+                .map(|(instance_id, module)| RpcModuleRecoveryProgress {
+                    module_instance_id: *instance_id,
+                    kind: module.kind.to_string(),
+                    complete: module.complete,
+                    total: module.total,
+                })
+                .collect(),
+            eta_secs: self
+                .eta(now)
+                .map(|eta| u32::try_from(eta.as_secs()).unwrap_or(u32::MAX)),
+        }
+    }
+}
+
+impl FederationV2 {
+    /// Called when recovery starts waiting, before any progress is reported.
+    pub(super) async fn start_recovery_progress(&self) {
+        // the marker is only removed once a recovery from scratch completed
+        let from_scratch = self
+            .runtime
+            .bridge_db()
+            .begin_transaction_nc()
+            .await
+            .get_value(&FederationPendingRejoinFromScratchKey {
+                invite_code_str: self.get_invite_code().await,
+            })
+            .await
+            .is_some();
+        *self.recovery_progress.lock().await = RecoveryProgressTracker {
+            from_scratch,
+            ..Default::default()
+        };
+    }
+
+    pub(super) async fn record_recovery_progress(
+        &self,
+        instance_id: ModuleInstanceId,
+        kind: ModuleKind,
+        progress: RecoveryProgress,
+    ) {
+        let due = self.recovery_progress.lock().await.update(
+            instance_id,
+            kind,
+            progress,
+            fedimint_core::time::now(),
+        );
+        if due {
+            self.send_federation_event().await;
+        }
+    }
+
+    /// Progress of the ongoing recovery, `None` if the federation is not
+    /// recovering.
+    pub async fn get_recovery_progress(&self) -> Option<RpcRecoveryProgress> {
+        if !self.recovering() {
+            return None;
+        }
+        Some(
+            self.recovery_progress
+                .lock()
+                .await
+                .to_rpc(fedimint_core::time::now()),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn progress(complete: u32, total: u32) -> RecoveryProgress {
+        RecoveryProgress { complete, total }
+    }
+
+    #[test]
+    fn test_recovery_progress_eta() {
+        let start = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000);
+        let at = |secs| start + Duration::from_secs(secs);
+        let mint = || ModuleKind::from_static_str("mint");
+        let ln = || ModuleKind::from_static_str("ln");
+        let mut tracker = RecoveryProgressTracker::default();
+        assert_eq!(tracker.to_rpc(start).eta_secs, None);
+
+        // first event is sent right away, later ones are throttled
+        assert!(tracker.update(1, mint(), progress(0, 0), start));
+        assert!(!tracker.update(1, mint(), progress(10, 1000), start));
+        // no time passed to measure a rate
+        assert_eq!(tracker.to_rpc(start).eta_secs, None);
+
+        assert!(tracker.update(2, ln(), progress(100, 300), at(1)));
+        // ln has not made progress since it was first seen
+        assert_eq!(tracker.to_rpc(at(1)).eta_secs, None);
+
+        // mint: 100 sessions in 10s, 900 remaining
+        // ln: 200 sessions in 9s, done
+        assert!(tracker.update(1, mint(), progress(100, 1000), at(10)));
+        assert!(tracker.update(2, ln(), progress(300, 300), at(10)));
+        let rpc = tracker.to_rpc(at(10));
+        assert_eq!(rpc.eta_secs, Some(90));
+        assert!(!rpc.from_scratch);
+        assert_eq!(rpc.modules.len(), 2);
+        assert_eq!(rpc.modules[0].kind, "mint");
+        assert_eq!(rpc.modules[0].complete, 100);
+        assert_eq!(rpc.modules[1].total, 300);
+    }
+}
@@ -0,0 +1,542 @@
+//! Secondary index over the operation log used by `searchTransactions`.
+//!
+//! Rendering an [`RpcTransaction`] means walking the operation log and often
+//! asking a module for the operation's latest state, which is far too slow to
+//! do for every operation just to filter them. Instead we keep a small,
+//! chronologically ordered summary of each transaction in the federation db:
+//! it is written whenever a transaction event is sent (i.e. whenever an
+//! operation changes state), when notes are edited, and by a startup pass that
+//! indexes operations created since the previous pass and re-renders those not
+//! yet settled. Searches filter on the summaries and only render the matching
+//! page.
+
+use fedimint_client::db::ChronologicalOperationLogKey;
+use fedimint_core::core::OperationId;
+use fedimint_core::db::{Database, IDatabaseTransactionOpsCoreTyped};
+use fedimint_core::encoding::{Decodable, Encodable};
+use fedimint_core::{Amount, impl_db_lookup, impl_db_record};
+use futures::StreamExt;
+use rpc_types::{
+    RpcTransaction, RpcTransactionDirection, RpcTransactionKindTag, RpcTransactionListEntry,
+    RpcTransactionSearchFilter,
+};
+use runtime::utils::to_unix_time;
+use tracing::{info, warn};
+
+use super::FederationV2;
+use super::db::BridgeDbPrefix;
+
+/// Operations read from the operation log per page while backfilling.
+const BACKFILL_PAGE_SIZE: usize = 100;
+
+#[repr(u8)]
+pub enum TransactionIndexDbPrefix {
+    // operation id => where the operation sits in the chronological index
+    Position = 0x01,
+    // (newest first created_at, operation id) => summary of the transaction
+    Chronological = 0x02,
+    // operation id => () for entries indexed before the operation settled
+    Unsettled = 0x03,
+    // () => newest operation log entry the backfill has seen
+    BackfillMark = 0x04,
+}
+
+#[derive(Debug, Clone, Copy, Encodable, Decodable)]
+pub struct TransactionIndexPosition {
+    pub created_at: u64,
+    /// Whether the operation had an outcome when it was last indexed. Settled
+    /// entries only change when notes are edited, so the backfill skips them.
+    pub settled: bool,
+}
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct TransactionIndexPositionKey(pub OperationId);
+
+impl_db_record!(
+    key = TransactionIndexPositionKey,
+    value = TransactionIndexPosition,
+    db_prefix = TransactionIndexDbPrefix::Position,
+);
+
+#[derive(Debug, Clone, Encodable, Decodable)]
+pub struct TransactionIndexEntry {
+    pub kind: RpcTransactionKindTag,
+    pub direction: RpcTransactionDirection,
+    pub amount: Amount,
+    pub notes: Option<String>,
+    pub initial_notes: Option<String>,
+    pub sender_matrix_id: Option<String>,
+    pub recipient_matrix_id: Option<String>,
+}
+
+/// Sorts newest first, so that a page can start at its cursor with an
+/// ascending range query.
+#[derive(Debug, Encodable, Decodable)]
+pub struct ChronologicalTransactionIndexKey {
+    /// `u64::MAX - created_at`.
+    pub newest_first: u64,
+    pub operation_id: OperationId,
+}
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct ChronologicalTransactionIndexKeyPrefix;
+
+impl_db_record!(
+    key = ChronologicalTransactionIndexKey,
+    value = TransactionIndexEntry,
+    db_prefix = TransactionIndexDbPrefix::Chronological,
+);
+
+impl_db_lookup!(
+    key = ChronologicalTransactionIndexKey,
+    query_prefix = ChronologicalTransactionIndexKeyPrefix,
+);
+
+impl ChronologicalTransactionIndexKey {
+    fn new(created_at: u64, operation_id: OperationId) -> Self {
+        Self {
+            newest_first: u64::MAX - created_at,
+            operation_id,
+        }
+    }
+
+    fn created_at(&self) -> u64 {
+        u64::MAX - self.newest_first
+    }
+}
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct UnsettledTransactionKey(pub OperationId);
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct UnsettledTransactionKeyPrefix;
+
+impl_db_record!(
+    key = UnsettledTransactionKey,
+    value = (),
+    db_prefix = TransactionIndexDbPrefix::Unsettled,
+);
+
+impl_db_lookup!(
+    key = UnsettledTransactionKey,
+    query_prefix = UnsettledTransactionKeyPrefix,
+);
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct TransactionIndexBackfillMarkKey;
+
+impl_db_record!(
+    key = TransactionIndexBackfillMarkKey,
+    value = ChronologicalOperationLogKey,
+    db_prefix = TransactionIndexDbPrefix::BackfillMark,
+);
+
+impl TransactionIndexEntry {
+    fn new(transaction: &RpcTransaction) -> Self {
+        Self {
+            kind: transaction.kind.tag(),
+            direction: transaction.kind.direction(),
+            amount: transaction.amount.0,
+            notes: transaction.txn_notes.clone(),
+            initial_notes: transaction.frontend_metadata.initial_notes.clone(),
+            sender_matrix_id: transaction.frontend_metadata.sender_matrix_id.clone(),
+            recipient_matrix_id: transaction.frontend_metadata.recipient_matrix_id.clone(),
+        }
+    }
+
+    fn matches(&self, filter: &RpcTransactionSearchFilter, notes_lowercase: Option<&str>) -> bool {
+        if let Some(kinds) = &filter.kinds
+            && !kinds.is_empty()
+            && !kinds.contains(&self.kind)
+        {
+            return false;
+        }
+        if filter
+            .direction
+            .as_ref()
+            .is_some_and(|direction| *direction != self.direction)
+        {
+            return false;
+        }
+        if filter.min_amount.is_some_and(|min| self.amount < min.0)
+            || filter.max_amount.is_some_and(|max| max.0 < self.amount)
+        {
+            return false;
+        }
+        if let Some(user_id) = &filter.matrix_user_id
+            && self.sender_matrix_id.as_ref() != Some(user_id)
+            && self.recipient_matrix_id.as_ref() != Some(user_id)
+        {
+            return false;
+        }
+        if let Some(needle) = notes_lowercase
+            && ![&self.notes, &self.initial_notes].into_iter().any(|notes| {
+                notes
+                    .as_ref()
+                    .is_some_and(|notes| notes.to_lowercase().contains(needle))
+            })
+        {
+            return false;
+        }
+        true
+    }
+}
+
+impl FederationV2 {
+    fn transaction_index_db(&self) -> Database {
+        self.client
+            .db()
+            .with_prefix(vec![BridgeDbPrefix::TransactionIndexPrefix as u8])
+    }
+
+    /// Write the index entry for `transaction`. `created_at` is only known
+    /// when coming from the operation log; otherwise an existing entry keeps
+    /// its time and a new one is stamped with the current time, which for a
+    /// freshly created operation is close enough.
+    pub(super) async fn index_transaction(
+        &self,
+        operation_id: OperationId,
+        created_at: Option<u64>,
+        transaction: &RpcTransaction,
+    ) {
+        let mut dbtx = self.transaction_index_db().begin_transaction().await;
+        let previous = dbtx
+            .get_value(&TransactionIndexPositionKey(operation_id))
+            .await;
+        let created_at = match (created_at, previous) {
+            (Some(created_at), _) => created_at,
+            (None, Some(previous)) => previous.created_at,
+            (None, None) => to_unix_time(fedimint_core::time::now()).unwrap_or_default(),
+        };
+        if let Some(previous) = previous {
+            dbtx.remove_entry(&ChronologicalTransactionIndexKey::new(
+                previous.created_at,
+                operation_id,
+            ))
+            .await;
+        }
+        let settled = transaction.outcome_time.is_some();
+        dbtx.insert_entry(
+            &TransactionIndexPositionKey(operation_id),
+            &TransactionIndexPosition {
+                created_at,
+                settled,
+            },
+        )
+        .await;
+        if settled {
+            dbtx.remove_entry(&UnsettledTransactionKey(operation_id))
+                .await;
+        } else {
+            dbtx.insert_entry(&UnsettledTransactionKey(operation_id), &())
+                .await;
+        }
+        dbtx.insert_entry(
+            &ChronologicalTransactionIndexKey::new(created_at, operation_id),
+            &TransactionIndexEntry::new(transaction),
+        )
+        .await;
+        if let Err(error) = dbtx.commit_tx_result().await {
+            warn!(%error, op = %operation_id.fmt_short(), "failed to index transaction");
+        }
+    }
+
+    /// Drop the entry for `operation_id`, e.g. a queued receive that now
+    /// shows up through its own operation.
+    pub(super) async fn remove_indexed_transaction(&self, operation_id: OperationId) {
+        let mut dbtx = self.transaction_index_db().begin_transaction().await;
+        let Some(position) = dbtx
+            .remove_entry(&TransactionIndexPositionKey(operation_id))
+            .await
+        else {
+            return;
+        };
+        dbtx.remove_entry(&ChronologicalTransactionIndexKey::new(
+            position.created_at,
+            operation_id,
+        ))
+        .await;
+        dbtx.remove_entry(&UnsettledTransactionKey(operation_id))
+            .await;
+        if let Err(error) = dbtx.commit_tx_result().await {
+            warn!(%error, op = %operation_id.fmt_short(), "failed to remove indexed transaction");
+        }
+    }
+
+    /// Re-render and re-index a single operation, e.g. after its notes
+    /// changed. Ids that are not in the operation log are ignored.
+    pub(super) async fn reindex_transaction(&self, operation_id: OperationId) {
+        if self
+            .client
+            .operation_log()
+            .get_operation(operation_id)
+            .await
+            .is_none()
+        {
+            return;
+        }
+        match self.get_transaction(operation_id).await {
+            Ok(transaction) => {
+                self.index_transaction(operation_id, None, &transaction)
+                    .await;
+            }
+            Err(error) => {
+                warn!(%error, op = %operation_id.fmt_short(), "failed to reindex transaction");
+            }
+        }
+    }
+
+    /// Index the operations created since the previous backfill, then
+    /// re-render every entry that was not settled when it was last indexed.
+    pub(super) async fn backfill_transaction_index(&self) {
+        let mark = self
+            .transaction_index_db()
+            .begin_transaction_nc()
+            .await
+            .get_value(&TransactionIndexBackfillMarkKey)
+            .await
+            .map(|mark| (mark.creation_time, mark.operation_id));
+        let mut newest = None;
+        let mut failed = false;
+        let mut indexed = 0;
+        let mut start_after = None;
+        'pages: loop {
+            let page = self
+                .client
+                .operation_log()
+                .paginate_operations_rev(BACKFILL_PAGE_SIZE, start_after)
+                .await;
+            let page_len = page.len();
+            start_after = page.last().map(|(last, _)| ChronologicalOperationLogKey {
+                creation_time: last.creation_time,
+                operation_id: last.operation_id,
+            });
+            for (op_key, entry) in page {
+                if mark.is_some_and(|mark| (op_key.creation_time, op_key.operation_id) <= mark) {
+                    break 'pages;
+                }
+                if newest.is_none() {
+                    newest = Some(ChronologicalOperationLogKey {
+                        creation_time: op_key.creation_time,
+                        operation_id: op_key.operation_id,
+                    });
+                }
+                let indexed_before = self
+                    .transaction_index_db()
+                    .begin_transaction_nc()
+                    .await
+                    .get_value(&TransactionIndexPositionKey(op_key.operation_id))
+                    .await
+                    .is_some();
+                if indexed_before {
+                    continue;
+                }
+                let Ok(created_at) = to_unix_time(op_key.creation_time) else {
+                    continue;
+                };
+                match self.get_transaction_inner(op_key.operation_id, entry).await {
+                    Ok(Some(transaction)) => {
+                        self.index_transaction(op_key.operation_id, Some(created_at), &transaction)
+                            .await;
+                        indexed += 1;
+                    }
+                    Ok(None) => {}
+                    Err(error) => {
+                        warn!(
+                            %error,
+                            op = %op_key.operation_id.fmt_short(),
+                            "failed to index transaction"
+                        );
+                        failed = true;
+                    }
+                }
+            }
+            if page_len < BACKFILL_PAGE_SIZE {
+                break;
+            }
+        }
+
+        let unsettled = self
+            .transaction_index_db()
+            .begin_transaction_nc()
+            .await
+            .find_by_prefix(&UnsettledTransactionKeyPrefix)
+            .await
+            .map(|(UnsettledTransactionKey(operation_id), ())| operation_id)
+            .collect::<Vec<_>>()
+            .await;
+        for operation_id in &unsettled {
+            self.reindex_transaction(*operation_id).await;
+        }
+
+        // Keep the previous mark if anything failed so the next start retries
+        // it.
+        if let Some(newest) = newest.filter(|_| !failed) {
+            let mut dbtx = self.transaction_index_db().begin_transaction().await;
+            dbtx.insert_entry(&TransactionIndexBackfillMarkKey, &newest)
+                .await;
+            if let Err(error) = dbtx.commit_tx_result().await {
+                warn!(%error, "failed to save transaction index backfill mark");
+            }
+        }
+        info!(
+            indexed,
+            reindexed = unsettled.len(),
+            "transaction index backfill done"
+        );
+    }
+
+    /// Newest-first transactions matching `filter`, at most `limit` of them,
+    /// continuing after `start_after` (the last operation of the previous
+    /// page) if given.
+    pub async fn search_transactions(
+        &self,
+        filter: &RpcTransactionSearchFilter,
+        limit: usize,
+        start_after: Option<OperationId>,
+    ) -> anyhow::Result<Vec<Result<RpcTransactionListEntry, String>>> {
+        let mut dbtx = self.transaction_index_db().begin_transaction_nc().await;
+        let cursor = match start_after {
+            Some(operation_id) => {
+                let position = dbtx
+                    .get_value(&TransactionIndexPositionKey(operation_id))
+                    .await
+                    .ok_or_else(|| anyhow::anyhow!("unknown search cursor"))?;
+                Some((position.created_at, operation_id))
+            }
+            None => None,
+        };
+        let notes_lowercase = filter
+            .notes
+            .as_deref()
+            .filter(|notes| !notes.is_empty())
+            .map(str::to_lowercase);
+
+        // `end_time` is exclusive, so the newest entry that can match was
+        // created at `end_time - 1`.
+        let mut first = match filter.end_time {
+            Some(0) => return Ok(vec![]),
+            Some(end_time) => {
+                ChronologicalTransactionIndexKey::new(end_time - 1, OperationId([0; 32]))
+            }
+            None => ChronologicalTransactionIndexKey::new(u64::MAX, OperationId([0; 32])),
+        };
+        if let Some((created_at, operation_id)) = cursor {
+            let cursor = ChronologicalTransactionIndexKey::new(created_at, operation_id);
+            if (first.newest_first, first.operation_id) < (cursor.newest_first, cursor.operation_id)
+            {
+                first = cursor;
+            }
+        }
+        // Range ends are exclusive, so stop just past the oldest second that
+        // can match. Without a start time this only leaves out an operation
+        // created at the epoch with an id of all ones.
+        let last = match filter.start_time {
+            Some(start_time) if 0 < start_time => {
+                ChronologicalTransactionIndexKey::new(start_time - 1, OperationId([0; 32]))
+            }
+            _ => ChronologicalTransactionIndexKey::new(0, OperationId([u8::MAX; 32])),
+        };
+
+        let mut matches = vec![];
+        let mut index = dbtx.find_by_range(first..last).await;
+        while let Some((key, entry)) = index.next().await {
+            if cursor == Some((key.created_at(), key.operation_id)) {
+                continue;
+            }
+            if entry.matches(filter, notes_lowercase.as_deref()) {
+                matches.push(key);
+                if matches.len() >= limit {
+                    break;
+                }
+            }
+        }
+
+        let futures = matches.into_iter().map(|key| async move {
+            self.get_transaction(key.operation_id)
+                .await
+                .map(|transaction| RpcTransactionListEntry {
+                    created_at: key.created_at(),
+                    transaction,
+                })
+                .map_err(|e| e.to_string())
+        });
+        Ok(futures::future::join_all(futures).await)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rpc_types::RpcAmount;
+
+    use super::*;
+
+    fn entry() -> TransactionIndexEntry {
+        TransactionIndexEntry {
+            kind: RpcTransactionKindTag::LnReceive,
+            direction: RpcTransactionDirection::Receive,
+            amount: Amount::from_sats(2_100),
+            notes: Some("Coffee with Alice".to_owned()),
+            initial_notes: Some("Lunch".to_owned()),
+            sender_matrix_id: Some("@alice:example.com".to_owned()),
+            recipient_matrix_id: None,
+        }
+    }
+
+    #[test]
+    fn empty_filter_matches_everything() {
+        assert!(entry().matches(&RpcTransactionSearchFilter::default(), None));
+    }
+
+    #[test]
+    fn filters_by_kind_direction_and_amount() {
+        let filter = RpcTransactionSearchFilter {
+            kinds: Some(vec![
+                RpcTransactionKindTag::LnReceive,
+                RpcTransactionKindTag::LnRecurringdReceive,
+            ]),
+            direction: Some(RpcTransactionDirection::Receive),
+            min_amount: Some(RpcAmount(Amount::from_sats(2_100))),
+            max_amount: Some(RpcAmount(Amount::from_sats(2_100))),
+            ..Default::default()
+        };
+        assert!(entry().matches(&filter, None));
+
+        let filter = RpcTransactionSearchFilter {
+            kinds: Some(vec![RpcTransactionKindTag::LnPay]),
+            ..Default::default()
+        };
+        assert!(!entry().matches(&filter, None));
+
+        let filter = RpcTransactionSearchFilter {
+            min_amount: Some(RpcAmount(Amount::from_sats(2_101))),
+            ..Default::default()
+        };
+        assert!(!entry().matches(&filter, None));
+    }
+
+    #[test]
+    fn chronological_keys_sort_newest_first() {
+        let older = ChronologicalTransactionIndexKey::new(1_700_000_000, OperationId([1; 32]));
+        let newer = ChronologicalTransactionIndexKey::new(1_700_000_001, OperationId([0; 32]));
+        assert!(newer.consensus_encode_to_vec() < older.consensus_encode_to_vec());
+        assert_eq!(older.created_at(), 1_700_000_000);
+    }
+
+    #[test]
+    fn filters_by_counterparty_and_notes() {
+        let filter = RpcTransactionSearchFilter {
+            matrix_user_id: Some("@alice:example.com".to_owned()),
+            ..Default::default()
+        };
+        assert!(entry().matches(&filter, Some("coffee")));
+        assert!(entry().matches(&filter, Some("lunch")));
+        assert!(!entry().matches(&filter, Some("tea")));
+
+        let filter = RpcTransactionSearchFilter {
+            matrix_user_id: Some("@bob:example.com".to_owned()),
+            ..Default::default()
+        };
+        assert!(!entry().matches(&filter, None));
+    }
+}
@@ -0,0 +1,307 @@
+//! Unified receives bundle a lightning invoice, an onchain address and,
+//! optionally, our stable balance payment address into a single BIP321 URI.
+//!
+//! Each leg is still its own operation, created by the usual receive paths.
+//! We only remember which legs belong together so that, once one of them has
+//! been paid, transaction history can show the others as superseded. Like
+//! walletv2 awaiting deposits, the receive is settled when a paid leg is
+//! rendered rather than from the module state machines, which also covers
+//! payments that landed while the app was closed.
+//!
+//! The stable balance address is static and shared by every receive, so a
+//! transfer to it cannot be attributed to one of them and never settles it.
+
+use bitcoin::hashes::sha256;
+use fedimint_core::Amount;
+use fedimint_core::db::{Database, IDatabaseTransactionOpsCoreTyped};
+use fedimint_core::encoding::{Decodable, Encodable};
+use fedimint_core::impl_db_record;
+use lightning_invoice::Bolt11Invoice;
+use rpc_types::{
+    FrontendMetadata, RpcAmount, RpcLnReceiveState, RpcOnchainDepositState, RpcTransaction,
+    RpcTransactionKind, RpcUnifiedReceive, RpcUnifiedReceiveLeg,
+};
+use tracing::warn;
+
+use super::FederationV2;
+use super::db::FederationDataDbPrefix;
+
+#[repr(u8)]
+pub enum UnifiedReceiveDbPrefix {
+    // invoice payment hash => the receive
+    Receive = 0x01,
+    // onchain address => invoice payment hash
+    ByAddress = 0x02,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Encodable, Decodable)]
+pub enum UnifiedReceiveLegKind {
+    Lightning,
+    Onchain,
+}
+
+#[derive(Debug, Clone, Encodable, Decodable)]
+pub struct UnifiedReceive {
+    pub onchain_address: String,
+    pub spv2_payment_address: Option<String>,
+    /// The leg that was paid first, once one has been rendered as paid.
+    pub settled_by: Option<UnifiedReceiveLegKind>,
+}
+
+/// Receives are keyed by the payment hash of their invoice, which is also
+/// the id handed to the frontend.
+#[derive(Debug, Clone, Encodable, Decodable)]
+pub struct UnifiedReceiveKey(pub sha256::Hash);
+
+impl_db_record!(
+    key = UnifiedReceiveKey,
+    value = UnifiedReceive,
+    db_prefix = UnifiedReceiveDbPrefix::Receive,
+);
+
+#[derive(Debug, Clone, Encodable, Decodable)]
+pub struct UnifiedReceiveByAddressKey(pub String);
+
+impl_db_record!(
+    key = UnifiedReceiveByAddressKey,
+    value = sha256::Hash,
+    db_prefix = UnifiedReceiveDbPrefix::ByAddress,
+);
+
+impl FederationV2 {
+    fn unified_receive_db(&self) -> Database {
+        self.federation_data_db(FederationDataDbPrefix::UnifiedReceive)
+    }
+
+    /// Creates a lightning invoice and an onchain address for the same
+    /// `amount`, plus our stable balance address if asked, and returns them
+    /// as one BIP321 URI.
+    pub async fn generate_unified_receive(
+        &self,
+        amount: RpcAmount,
+        description: String,
+        include_spv2_payment_address: bool,
+        frontend_meta: FrontendMetadata,
+    ) -> anyhow::Result<RpcUnifiedReceive> {
+        // before creating any operation, so a federation without spv2 does
+        // not leave orphaned legs behind
+        let spv2_payment_address = if include_spv2_payment_address {
+            Some(self.spv2_payment_address(false).await?.to_string())
+        } else {
+            None
+        };
+        let invoice = self
+            .generate_invoice(amount, description.clone(), None, frontend_meta.clone())
+            .await?;
+        let onchain_address = self.generate_address(frontend_meta).await?;
+
+        let payment_hash = *invoice.payment_hash();
+        let mut dbtx = self.unified_receive_db().begin_transaction().await;
+        dbtx.insert_entry(
+            &UnifiedReceiveKey(payment_hash),
+            &UnifiedReceive {
+                onchain_address: onchain_address.clone(),
+                spv2_payment_address: spv2_payment_address.clone(),
+                settled_by: None,
+            },
+        )
+        .await;
+        dbtx.insert_entry(
+            &UnifiedReceiveByAddressKey(onchain_address.clone()),
+            &payment_hash,
+        )
+        .await;
+        dbtx.commit_tx().await;
+
+        let invoice = invoice.to_string();
+        let uri = Bip321Uri {
+            address: &onchain_address,
+            amount: amount.0,
+            label: &description,
+            lightning: &invoice,
+            sp: spv2_payment_address.as_deref(),
+        };
+        Ok(RpcUnifiedReceive {
+            receive_id: payment_hash.to_string(),
+            uri: uri.to_string(false),
+            qr_payload: uri.to_string(true),
+            invoice,
+            onchain_address,
+            spv2_payment_address,
+        })
+    }
+
+    /// Marks `transaction` if it is a leg of a unified receive, settling the
+    /// receive when this is the first leg seen paid.
+    pub(super) async fn decorate_unified_receive_leg(&self, transaction: &mut RpcTransaction) {
+        let db = self.unified_receive_db();
+        let (payment_hash, leg, paid) = match &transaction.kind {
+            RpcTransactionKind::LnReceive { ln_invoice, state } => {
+                let Ok(invoice) = ln_invoice.parse::<Bolt11Invoice>() else {
+                    return;
+                };
+                let paid = matches!(
+                    state,
+                    Some(
+                        RpcLnReceiveState::Funded
+                            | RpcLnReceiveState::AwaitingFunds
+                            | RpcLnReceiveState::Claimed
+                    )
+                );
+                (
+                    *invoice.payment_hash(),
+                    UnifiedReceiveLegKind::Lightning,
+                    paid,
+                )
+            }
+            RpcTransactionKind::OnchainDeposit {
+                onchain_address,
+                state,
+                ..
+            } => {
+                let Some(payment_hash) = db
+                    .begin_transaction_nc()
+                    .await
+                    .get_value(&UnifiedReceiveByAddressKey(onchain_address.clone()))
+                    .await
+                else {
+                    return;
+                };
+                let paid = matches!(
+                    state,
+                    Some(
+                        RpcOnchainDepositState::WaitingForConfirmation(_)
+                            | RpcOnchainDepositState::Confirmed(_)
+                            | RpcOnchainDepositState::Claimed(_)
+                    )
+                );
+                (payment_hash, UnifiedReceiveLegKind::Onchain, paid)
+            }
+            _ => return,
+        };
+
+        let key = UnifiedReceiveKey(payment_hash);
+        // every rendered ln receive reaches this, so don't make the common
+        // case a write transaction
+        let Some(receive) = db.begin_transaction_nc().await.get_value(&key).await else {
+            return;
+        };
+        let settled_by = match receive.settled_by {
+            None if paid => db
+                .autocommit(
+                    |dbtx, _| {
+                        let key = key.clone();
+                        Box::pin(async move {
+                            // re-checked inside the boundary: the other leg
+                            // may have been rendered paid concurrently
+                            let Some(mut receive) = dbtx.get_value(&key).await else {
+                                return Ok::<_, anyhow::Error>(None);
+                            };
+                            if receive.settled_by.is_none() {
+                                receive.settled_by = Some(leg);
+                                dbtx.insert_entry(&key, &receive).await;
+                            }
+                            Ok(receive.settled_by)
+                        })
+                    },
+                    Some(100),
+                )
+                .await
+                .unwrap_or_else(|error| {
+                    warn!("failed to settle unified receive: {error:?}");
+                    None
+                }),
+            settled_by => settled_by,
+        };
+        transaction.unified_receive = Some(RpcUnifiedReceiveLeg {
+            receive_id: payment_hash.to_string(),
+            superseded: !paid && settled_by.is_some_and(|settled_by| settled_by != leg),
+        });
+    }
+}
+
+/// The payment instructions of a unified receive, rendered as a BIP321 URI.
+struct Bip321Uri<'a> {
+    address: &'a str,
+    amount: Amount,
+    label: &'a str,
+    lightning: &'a str,
+    sp: Option<&'a str>,
+}
+
+impl Bip321Uri<'_> {
+    /// With `for_qr`, everything but the label is uppercased so QR encoders
+    /// can use alphanumeric mode. BIP321 makes the scheme and keys
+    /// case-insensitive and bech32 is case-insensitive too; other addresses
+    /// are left alone.
+    fn to_string(&self, for_qr: bool) -> String {
+        let case = |s: &str| {
+            if for_qr {
+                s.to_uppercase()
+            } else {
+                s.to_owned()
+            }
+        };
+        let address = if bitcoin::bech32::decode(self.address).is_ok() {
+            case(self.address)
+        } else {
+            self.address.to_owned()
+        };
+        // BIP21 amounts are in BTC, so round msats up to the next sat
+        let amount = bitcoin::Amount::from_sat(self.amount.msats.div_ceil(1000))
+            .to_string_in(bitcoin::Denomination::Bitcoin);
+
+        let mut uri = format!("{}{address}?{}={amount}", case("bitcoin:"), case("amount"));
+        if !self.label.is_empty() {
+            // wallets don't agree on whether `+` means a space, `%20` does
+            let label = url::form_urlencoded::byte_serialize(self.label.as_bytes())
+                .collect::<String>()
+                .replace('+', "%20");
+            uri.push_str(&format!("&{}={label}", case("label")));
+        }
+        uri.push_str(&format!("&{}={}", case("lightning"), case(self.lightning)));
+        // BIP321 reserves `sp` for silent payment addresses
+        if let Some(sp) = self.sp {
+            uri.push_str(&format!("&{}={}", case("spt"), case(sp)));
+        }
+        uri
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bip321_uri() {
+        let uri = Bip321Uri {
+            address: "bc1qar0srrr7xfkvy5l643lydnw9re59gtzzwf5mdq",
+            amount: Amount::from_msats(123_456),
+            label: "Coffee & cake",
+            lightning: "lnbc1234n1abc",
+            sp: Some("spt1xyz"),
+        };
+        assert_eq!(
+            uri.to_string(false),
+            "bitcoin:bc1qar0srrr7xfkvy5l643lydnw9re59gtzzwf5mdq?amount=0.00000124\
+             &label=Coffee%20%26%20cake&lightning=lnbc1234n1abc&spt=spt1xyz"
+        );
+        assert_eq!(
+            uri.to_string(true),
+            "BITCOIN:BC1QAR0SRRR7XFKVY5L643LYDNW9RE59GTZZWF5MDQ?AMOUNT=0.00000124\
+             &LABEL=Coffee%20%26%20cake&LIGHTNING=LNBC1234N1ABC&SPT=SPT1XYZ"
+        );
+
+        let legacy = Bip321Uri {
+            address: "1BvBMSEYstWetqTFn5Au4m4GFg7xJaNVN2",
+            amount: Amount::from_sats(100_000),
+            label: "",
+            lightning: "lnbc1",
+            sp: None,
+        };
+        assert_eq!(
+            legacy.to_string(true),
+            "BITCOIN:1BvBMSEYstWetqTFn5Au4m4GFg7xJaNVN2?AMOUNT=0.001&LIGHTNING=LNBC1"
+        );
+    }
+}
@@ -8,7 +8,8 @@ use fedimint_client::module::oplog::OperationLogEntry;
 use fedimint_core::core::OperationId;
 use fedimint_core::task::{MaybeSend, MaybeSync};
 use fedimint_core::{apply, async_trait_maybe_send};
-use rpc_types::{FrontendMetadata, RpcFeeDetails};
+use fedimint_wallet_client::PegOutFees;
+use rpc_types::{FrontendMetadata, RpcFeeDetails, RpcOnchainFeeTier};
 pub use v1::WalletOpsV1;
 pub use v2::WalletOpsV2;
 
@@ -38,13 +39,23 @@ pub trait WalletOps: MaybeSend + MaybeSync {
         operation_id: OperationId,
     ) -> Result<()>;
 
-    /// Returns the fee details for making a payment on-chain.
+    /// The federation's estimate of the network fee for paying `amount` to
+    /// `address`, which the fee tiers scale.
+    async fn network_fee_estimate(
+        &self,
+        fed: &FederationV2,
+        address: bitcoin::Address<NetworkUnchecked>,
+        amount: bitcoin::Amount,
+    ) -> Result<NetworkFeeEstimate>;
+
+    /// Returns the fee details for making a payment on-chain paying
+    /// `network_fee`, see [`NetworkFeeEstimate::for_tier`].
     /// Returns an error in case the amount exceeds the max spendable amount.
     async fn preview_pay_address(
         &self,
         fed: &FederationV2,
-        address: bitcoin::Address<NetworkUnchecked>,
         amount: bitcoin::Amount,
+        network_fee: NetworkFeeEstimate,
     ) -> Result<RpcFeeDetails>;
 
     /// Pay an onchain address
@@ -53,6 +64,7 @@ pub trait WalletOps: MaybeSend + MaybeSync {
         fed: &FederationV2,
         address: bitcoin::Address<NetworkUnchecked>,
         amount: bitcoin::Amount,
+        fee_tier: RpcOnchainFeeTier,
         frontend_meta: FrontendMetadata,
     ) -> Result<OperationId>;
 
@@ -75,3 +87,109 @@ pub trait WalletOps: MaybeSend + MaybeSync {
         fedi_fee_msats: u64,
     ) -> anyhow::Result<Option<FederationTransactionParts>>;
 }
+
+/// Fee rate of a tier relative to the federation's estimate, in percent. The
+/// federation rejects withdrawals paying less than its estimate, so the
+/// cheapest tier pays exactly that.
+fn fee_tier_percent(fee_tier: RpcOnchainFeeTier) -> u64 {
+    match fee_tier {
+        RpcOnchainFeeTier::Economy => 100,
+        RpcOnchainFeeTier::Normal => 150,
+        RpcOnchainFeeTier::Priority => 250,
+    }
+}
+
+/// Network fee of an onchain withdrawal, as the wallet module reports it.
+#[derive(Debug, Clone, Copy)]
+pub enum NetworkFeeEstimate {
+    /// walletv1 reports a fee rate for the peg-out transaction's weight.
+    PegOut(PegOutFees),
+    /// walletv2 only reports the fee's total.
+    Send(bitcoin::Amount),
+}
+
+impl NetworkFeeEstimate {
+    /// Scales the federation's estimate to `fee_tier`.
+    pub fn for_tier(self, fee_tier: RpcOnchainFeeTier) -> Self {
+        match self {
+            Self::PegOut(fees) => Self::PegOut(peg_out_fees_for_tier(fees, fee_tier)),
+            Self::Send(fee) => Self::Send(send_fee_for_tier(fee, fee_tier)),
+        }
+    }
+
+    pub fn amount(self) -> bitcoin::Amount {
+        match self {
+            Self::PegOut(fees) => fees.amount(),
+            Self::Send(fee) => fee,
+        }
+    }
+
+    pub fn peg_out_fees(self) -> PegOutFees {
+        match self {
+            Self::PegOut(fees) => fees,
+            Self::Send(fee) => PegOutFees::from_amount(fee),
+        }
+    }
+
+    /// `None` if the wallet module didn't report a fee rate.
+    pub fn sats_per_vbyte(self) -> Option<f64> {
+        match self {
+            Self::PegOut(fees) => Some(fees.fee_rate.sats_per_kvb as f64 / 1000.),
+            Self::Send(_) => None,
+        }
+    }
+}
+
+/// Scales the federation's estimated peg-out fees (walletv1) to `fee_tier`.
+fn peg_out_fees_for_tier(fees: PegOutFees, fee_tier: RpcOnchainFeeTier) -> PegOutFees {
+    PegOutFees::new(
+        fees.fee_rate.sats_per_kvb * fee_tier_percent(fee_tier) / 100,
+        fees.total_weight,
+    )
+}
+
+/// Scales the federation's estimated send fee (walletv2) to `fee_tier`.
+fn send_fee_for_tier(fee: bitcoin::Amount, fee_tier: RpcOnchainFeeTier) -> bitcoin::Amount {
+    bitcoin::Amount::from_sat(fee.to_sat() * fee_tier_percent(fee_tier) / 100)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fee_tiers() {
+        let fee = bitcoin::Amount::from_sat(1_000);
+        assert_eq!(send_fee_for_tier(fee, RpcOnchainFeeTier::Economy), fee);
+        assert_eq!(
+            send_fee_for_tier(fee, RpcOnchainFeeTier::Normal),
+            bitcoin::Amount::from_sat(1_500)
+        );
+        assert_eq!(
+            send_fee_for_tier(fee, RpcOnchainFeeTier::Priority),
+            bitcoin::Amount::from_sat(2_500)
+        );
+
+        assert_eq!(
+            NetworkFeeEstimate::Send(fee)
+                .for_tier(RpcOnchainFeeTier::Normal)
+                .amount(),
+            bitcoin::Amount::from_sat(1_500)
+        );
+
+        let fees = PegOutFees::new(2_000, 800);
+        assert_eq!(
+            NetworkFeeEstimate::PegOut(fees)
+                .for_tier(RpcOnchainFeeTier::Normal)
+                .sats_per_vbyte(),
+            Some(3.)
+        );
+        let priority = peg_out_fees_for_tier(fees, RpcOnchainFeeTier::Priority);
+        assert_eq!(priority.total_weight, fees.total_weight);
+        assert_eq!(priority.amount().to_sat(), fees.amount().to_sat() * 5 / 2);
+        assert_eq!(
+            peg_out_fees_for_tier(fees, RpcOnchainFeeTier::Economy).amount(),
+            fees.amount()
+        );
+    }
+}
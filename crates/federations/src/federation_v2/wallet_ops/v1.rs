@@ -7,8 +7,8 @@ use fedimint_wallet_client::{DepositStateV2, WithdrawState};
 use futures::StreamExt;
 use rpc_types::error::ErrorCode;
 use rpc_types::{
-    BaseMetadata, FrontendMetadata, RpcAmount, RpcFeeDetails, RpcTransactionDirection,
-    RpcTransactionKind,
+    BaseMetadata, FrontendMetadata, RpcAmount, RpcFeeDetails, RpcOnchainFeeTier,
+    RpcOnchainWithdrawState, RpcTransactionDirection, RpcTransactionKind,
 };
 use tracing::{error, warn};
 
@@ -16,7 +16,7 @@ use super::super::client::ClientExt;
 use super::super::{
     FederationTransactionParts, FederationV2, FediFeeStream, get_max_spendable_amount,
 };
-use super::WalletOps;
+use super::{NetworkFeeEstimate, WalletOps, peg_out_fees_for_tier};
 use crate::federation_v2::{async_trait_maybe_send, deposit_update_sanitized_log, log_update};
 
 pub struct WalletOpsV1;
@@ -125,8 +125,8 @@ impl WalletOps for WalletOpsV1 {
         frontend_meta: FrontendMetadata,
     ) -> Result<String> {
         // FIXME: add fedi fees once fedimint await primary module outputs
-        let fee_ppms = fed
-            .get_fee_ppms_by_stream(
+        let fee_rules = fed
+            .get_fee_rules_by_stream(
                 fedimint_wallet_client::KIND,
                 RpcTransactionDirection::Receive,
             )
@@ -138,7 +138,7 @@ impl WalletOps for WalletOpsV1 {
             .await?;
         let operation_id = deposit_address.operation_id;
         let address = deposit_address.address;
-        fed.write_pending_receive_fedi_fee_ppms(operation_id, &fee_ppms)
+        fed.write_pending_receive_fedi_fee_rules(operation_id, &fee_rules)
             .await?;
 
         self.subscribe_deposit(fed, operation_id);
@@ -157,33 +157,39 @@ impl WalletOps for WalletOpsV1 {
             .await
     }
 
-    /// Returns the fee details for making a payment on-chain.
-    /// Returns an error in case the amount exceeds the max spendable amount.
-    async fn preview_pay_address(
+    async fn network_fee_estimate(
         &self,
         fed: &FederationV2,
         address: bitcoin::Address<bitcoin::address::NetworkUnchecked>,
         amount: bitcoin::Amount,
-    ) -> Result<RpcFeeDetails> {
-        let fee_ppms = fed
-            .get_fee_ppms_by_stream(fedimint_wallet_client::KIND, RpcTransactionDirection::Send)
-            .await?;
-        let amount_msat = amount.to_sat() * 1000;
-        let fees_by_stream = fed
-            .get_fee_amounts_by_stream(
-                fedimint_wallet_client::KIND,
-                RpcTransactionDirection::Send,
-                Amount::from_msats(amount_msat),
-            )
-            .await?;
-        let wallet = fed.client.wallet()?;
-        let network_fees = wallet
+    ) -> Result<NetworkFeeEstimate> {
+        let network_fees = fed
+            .client
+            .wallet()?
             .get_withdraw_fees(
                 // TODO: need to verify against federation network, but where do we get it from?
                 &address.assume_checked(),
                 amount,
             )
             .await?;
+        Ok(NetworkFeeEstimate::PegOut(network_fees))
+    }
+
+    /// Returns the fee details for making a payment on-chain.
+    /// Returns an error in case the amount exceeds the max spendable amount.
+    async fn preview_pay_address(
+        &self,
+        fed: &FederationV2,
+        amount: bitcoin::Amount,
+        network_fee: NetworkFeeEstimate,
+    ) -> Result<RpcFeeDetails> {
+        let fee_rules = fed
+            .get_fee_rules_by_stream(fedimint_wallet_client::KIND, RpcTransactionDirection::Send)
+            .await?;
+        let amount_msat = amount.to_sat() * 1000;
+        let fees_by_stream = fee_rules.fees_by_stream(Amount::from_msats(amount_msat));
+        let wallet = fed.client.wallet()?;
+        let network_fees = network_fee.peg_out_fees();
         let federation_fee = wallet.get_fee_consensus().peg_out_abs;
         let fedi_fee = FederationV2::total_fedi_fee_amount(&fees_by_stream);
         let fedi_app_fee =
@@ -195,12 +201,7 @@ impl WalletOps for WalletOpsV1 {
         let virtual_balance = fed.get_balance().await;
         if est_total_spend > virtual_balance.msats {
             bail!(ErrorCode::InsufficientBalance(RpcAmount(
-                get_max_spendable_amount(
-                    virtual_balance,
-                    FederationV2::total_fedi_fee_ppm(&fee_ppms),
-                    Some(network_fees),
-                    None,
-                )
+                get_max_spendable_amount(virtual_balance, &fee_rules, Some(network_fees), None)
             )));
         }
 
@@ -218,27 +219,25 @@ impl WalletOps for WalletOpsV1 {
         fed: &FederationV2,
         address: bitcoin::Address<bitcoin::address::NetworkUnchecked>,
         amount: bitcoin::Amount,
+        fee_tier: RpcOnchainFeeTier,
         frontend_meta: FrontendMetadata,
     ) -> Result<OperationId> {
         let wallet = fed.client.wallet()?;
-        let fee_ppms = fed
-            .get_fee_ppms_by_stream(fedimint_wallet_client::KIND, RpcTransactionDirection::Send)
+        let fee_rules = fed
+            .get_fee_rules_by_stream(fedimint_wallet_client::KIND, RpcTransactionDirection::Send)
             .await?;
         let amount_msat = amount.to_sat() * 1000;
-        let fees_by_stream = fed
-            .get_fee_amounts_by_stream(
-                fedimint_wallet_client::KIND,
-                RpcTransactionDirection::Send,
-                Amount::from_msats(amount_msat),
-            )
-            .await?;
-        let network_fees = wallet
-            .get_withdraw_fees(
-                // TODO: verify
-                &address.clone().assume_checked(),
-                amount,
-            )
-            .await?;
+        let fees_by_stream = fee_rules.fees_by_stream(Amount::from_msats(amount_msat));
+        let network_fees = peg_out_fees_for_tier(
+            wallet
+                .get_withdraw_fees(
+                    // TODO: verify
+                    &address.clone().assume_checked(),
+                    amount,
+                )
+                .await?,
+            fee_tier,
+        );
         let fedi_fee = FederationV2::total_fedi_fee_amount(&fees_by_stream);
         let network_fees_msat = network_fees.amount().to_sat() * 1000;
         let est_total_spend = amount_msat + fedi_fee.msats + network_fees_msat;
@@ -247,12 +246,7 @@ impl WalletOps for WalletOpsV1 {
         let virtual_balance = fed.get_balance().await;
         if est_total_spend > virtual_balance.msats {
             bail!(ErrorCode::InsufficientBalance(RpcAmount(
-                get_max_spendable_amount(
-                    virtual_balance,
-                    FederationV2::total_fedi_fee_ppm(&fee_ppms),
-                    Some(network_fees),
-                    None,
-                )
+                get_max_spendable_amount(virtual_balance, &fee_rules, Some(network_fees), None)
             )));
         }
 
@@ -265,7 +259,7 @@ impl WalletOps for WalletOpsV1 {
                 BaseMetadata::from(frontend_meta),
             )
             .await?;
-        fed.write_pending_send_fedi_fees(operation_id, &fees_by_stream)
+        fed.write_pending_send_fedi_fees(operation_id, Amount::from_msats(amount_msat), &fee_rules)
             .await?;
         drop(spend_guard);
         let _ = fed
@@ -298,8 +292,9 @@ impl WalletOps for WalletOpsV1 {
             fed.update_operation_state(op_id, update.clone()).await;
             match update {
                 WithdrawState::Created => (),
-                WithdrawState::Succeeded(_) => {
+                WithdrawState::Succeeded(txid) => {
                     let _ = fed.write_success_send_fedi_fees(op_id).await;
+                    fed.track_withdraw_confirmations(op_id, txid).await;
                 }
                 WithdrawState::Failed(_) => {
                     let _ = fed.write_failed_send_fedi_fees(op_id).await;
@@ -391,6 +386,15 @@ impl WalletOps for WalletOpsV1 {
                         fed.client.wallet()?.subscribe_withdraw_updates(op_id).await
                     })
                     .await?;
+                let state = match outcome.map(RpcOnchainWithdrawState::from) {
+                    Some(RpcOnchainWithdrawState::Succeeded { txid, .. }) => {
+                        Some(RpcOnchainWithdrawState::Succeeded {
+                            txid,
+                            confirmations: fed.withdraw_confirmations(operation_id).await,
+                        })
+                    }
+                    state => state,
+                };
 
                 Ok(Some(FederationTransactionParts {
                     amount: RpcAmount(core_amount),
@@ -398,7 +402,7 @@ impl WalletOps for WalletOpsV1 {
                         onchain_address: address.assume_checked().to_string(),
                         onchain_fees: RpcAmount(Amount::from_sats(fee.amount().to_sat())),
                         onchain_fee_rate: fee.fee_rate.sats_per_kvb,
-                        state: outcome.map(Into::into),
+                        state,
                     },
                     frontend_metadata,
                 }))
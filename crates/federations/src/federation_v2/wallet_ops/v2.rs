@@ -11,14 +11,14 @@ use fedimint_walletv2_client::{
 use rpc_types::error::ErrorCode;
 use rpc_types::{
     FrontendMetadata, RpcAmount, RpcFeeDetails, RpcOnchainDepositState,
-    RpcOnchainDepositTransactionData, RpcOnchainWithdrawState, RpcTransactionDirection,
-    RpcTransactionKind,
+    RpcOnchainDepositTransactionData, RpcOnchainFeeTier, RpcOnchainWithdrawState,
+    RpcTransactionDirection, RpcTransactionKind,
 };
 use tracing::{error, warn};
 
 use super::super::client::ClientExt;
 use super::super::{FederationTransactionParts, FederationV2, get_max_spendable_amount};
-use super::WalletOps;
+use super::{NetworkFeeEstimate, WalletOps, send_fee_for_tier};
 use crate::federation_v2::async_trait_maybe_send;
 
 pub struct WalletOpsV2;
@@ -127,27 +127,30 @@ impl WalletOps for WalletOpsV2 {
         Ok(())
     }
 
+    async fn network_fee_estimate(
+        &self,
+        fed: &FederationV2,
+        _address: bitcoin::Address<NetworkUnchecked>,
+        _amount: bitcoin::Amount,
+    ) -> Result<NetworkFeeEstimate> {
+        let network_fee = fed.client.walletv2()?.send_fee().await?;
+        Ok(NetworkFeeEstimate::Send(network_fee))
+    }
+
     /// Returns the fee details for making a payment on-chain.
     /// Returns an error in case the amount exceeds the max spendable amount.
     async fn preview_pay_address(
         &self,
         fed: &FederationV2,
-        _address: bitcoin::Address<NetworkUnchecked>,
         amount: bitcoin::Amount,
+        network_fee: NetworkFeeEstimate,
     ) -> Result<RpcFeeDetails> {
-        let fee_ppms = fed
-            .get_fee_ppms_by_stream(fedimint_wallet_client::KIND, RpcTransactionDirection::Send)
+        let fee_rules = fed
+            .get_fee_rules_by_stream(fedimint_wallet_client::KIND, RpcTransactionDirection::Send)
             .await?;
         let amount_msat = amount.to_sat() * 1000;
-        let fees_by_stream = fed
-            .get_fee_amounts_by_stream(
-                fedimint_wallet_client::KIND,
-                RpcTransactionDirection::Send,
-                Amount::from_msats(amount_msat),
-            )
-            .await?;
-        let walletv2 = fed.client.walletv2()?;
-        let network_fee = walletv2.send_fee().await?;
+        let fees_by_stream = fee_rules.fees_by_stream(Amount::from_msats(amount_msat));
+        let network_fee = network_fee.amount();
         let fedi_fee = FederationV2::total_fedi_fee_amount(&fees_by_stream);
         let network_fee_msat = network_fee.to_sat() * 1000;
         let est_total_spend = amount_msat + fedi_fee.msats + network_fee_msat;
@@ -156,7 +159,7 @@ impl WalletOps for WalletOpsV2 {
             bail!(ErrorCode::InsufficientBalance(RpcAmount(
                 get_max_spendable_amount(
                     virtual_balance,
-                    FederationV2::total_fedi_fee_ppm(&fee_ppms),
+                    &fee_rules,
                     Some(PegOutFees::from_amount(network_fee)),
                     None,
                 )
@@ -183,21 +186,16 @@ impl WalletOps for WalletOpsV2 {
         fed: &FederationV2,
         address: bitcoin::Address<NetworkUnchecked>,
         amount: bitcoin::Amount,
+        fee_tier: RpcOnchainFeeTier,
         _frontend_meta: FrontendMetadata,
     ) -> Result<OperationId> {
         let walletv2 = fed.client.walletv2()?;
-        let fee_ppms = fed
-            .get_fee_ppms_by_stream(fedimint_wallet_client::KIND, RpcTransactionDirection::Send)
+        let fee_rules = fed
+            .get_fee_rules_by_stream(fedimint_wallet_client::KIND, RpcTransactionDirection::Send)
             .await?;
         let amount_msat = amount.to_sat() * 1000;
-        let fees_by_stream = fed
-            .get_fee_amounts_by_stream(
-                fedimint_wallet_client::KIND,
-                RpcTransactionDirection::Send,
-                Amount::from_msats(amount_msat),
-            )
-            .await?;
-        let network_fee = walletv2.send_fee().await?;
+        let fees_by_stream = fee_rules.fees_by_stream(Amount::from_msats(amount_msat));
+        let network_fee = send_fee_for_tier(walletv2.send_fee().await?, fee_tier);
         let fedi_fee = FederationV2::total_fedi_fee_amount(&fees_by_stream);
         let network_fee_msat = network_fee.to_sat() * 1000;
         let est_total_spend = amount_msat + fedi_fee.msats + network_fee_msat;
@@ -208,15 +206,18 @@ impl WalletOps for WalletOpsV2 {
             bail!(ErrorCode::InsufficientBalance(RpcAmount(
                 get_max_spendable_amount(
                     virtual_balance,
-                    FederationV2::total_fedi_fee_ppm(&fee_ppms),
+                    &fee_rules,
                     Some(PegOutFees::from_amount(network_fee)),
                     None,
                 )
             )));
         }
 
-        let operation_id = walletv2.send(address, amount, None).await?;
-        fed.write_pending_send_fedi_fees(operation_id, &fees_by_stream)
+        // without a fee the federation charges its current estimate, which is
+        // what the economy tier quoted
+        let fee = (fee_tier != RpcOnchainFeeTier::Economy).then_some(network_fee);
+        let operation_id = walletv2.send(address, amount, fee).await?;
+        fed.write_pending_send_fedi_fees(operation_id, Amount::from_msats(amount_msat), &fee_rules)
             .await?;
         drop(spend_guard);
         let _ = fed
@@ -235,8 +236,9 @@ impl WalletOps for WalletOpsV2 {
         let walletv2 = fed.client.walletv2()?;
         let final_state = walletv2.await_final_send_operation_state(op_id).await?;
         match final_state {
-            FinalSendOperationState::Success(_) => {
+            FinalSendOperationState::Success(txid) => {
                 let _ = fed.write_success_send_fedi_fees(op_id).await;
+                fed.track_withdraw_confirmations(op_id, txid).await;
             }
             FinalSendOperationState::Aborted | FinalSendOperationState::Failure => {
                 let _ = fed.write_failed_send_fedi_fees(op_id).await;
@@ -293,6 +295,7 @@ impl WalletOps for WalletOpsV2 {
                 // it back so we can surface the bitcoin txid on
                 // Success and a proper error state on Aborted/Failure.
                 // try_outcome to avoid panicking on a shape mismatch.
+                let confirmations = fed.withdraw_confirmations(operation_id).await;
                 let state = entry
                     .try_outcome::<FinalSendOperationState>()
                     .ok()
@@ -301,6 +304,7 @@ impl WalletOps for WalletOpsV2 {
                         FinalSendOperationState::Success(txid) => {
                             RpcOnchainWithdrawState::Succeeded {
                                 txid: txid.to_string(),
+                                confirmations,
                             }
                         }
                         FinalSendOperationState::Aborted => RpcOnchainWithdrawState::Failed {
@@ -0,0 +1,319 @@
+//! Finds wallets that reuse ecash nonces and repairs them in one step.
+//!
+//! A wallet restored from an outdated backup can issue notes with blind
+//! nonces it already used, which the federation refuses to sign again, so
+//! the ecash in those outputs gets stuck. A background check looks for this
+//! every few hours and sends a `walletRepairNeeded` event. `runWalletRepair`
+//! then backs up proofs of the stuck ecash for support, repairs the wallet and
+//! reports what happened to the balance.
+//!
+//! Reused ecash that a repair already backed up does not ask for another
+//! repair, whether or not the wallet still reports it afterwards.
+
+use std::collections::BTreeSet;
+use std::time::Duration;
+
+use bug_report::reused_ecash_proofs::{
+    ReusedEcashProof, ReusedEcashProofs, SerializedReusedEcashProofs,
+};
+use fedimint_core::Amount;
+use fedimint_core::db::{Database, IDatabaseTransactionOpsCoreTyped};
+use fedimint_core::encoding::{Decodable, Encodable};
+use fedimint_core::impl_db_record;
+use rpc_types::event::{Event, TypedEventExt as _};
+use rpc_types::{RpcAmount, RpcWalletRepairReport};
+use runtime::utils::to_unix_time;
+use tracing::{info, warn};
+
+use super::FederationV2;
+use super::db::FederationDataDbPrefix;
+
+const CHECK_INTERVAL: Duration = Duration::from_secs(6 * 60 * 60);
+
+#[repr(u8)]
+pub enum WalletRepairDbPrefix {
+    // present while the user was told to repair and has not done so yet
+    Notified = 0x01,
+    // proofs of all reused ecash backed up by repairs so far
+    Backup = 0x02,
+}
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct WalletRepairNotifiedKey;
+
+impl_db_record!(
+    key = WalletRepairNotifiedKey,
+    value = (),
+    db_prefix = WalletRepairDbPrefix::Notified,
+);
+
+#[derive(Debug, Clone, Encodable, Decodable)]
+pub struct WalletRepairBackup {
+    /// When the first repair backed up proofs.
+    pub backed_up_at: u64,
+    pub reused_ecash: Amount,
+    /// Consensus encoded `ReusedEcashProofs`.
+    pub proofs: Vec<u8>,
+}
+
+impl WalletRepairBackup {
+    fn proofs(&self) -> anyhow::Result<ReusedEcashProofs> {
+        Ok(ReusedEcashProofs::consensus_decode_whole(
+            &self.proofs,
+            &Default::default(),
+        )?)
+    }
+}
+
+fn blind_nonce_key(proof: &ReusedEcashProof) -> Vec<u8> {
+    proof.blind_nonce().consensus_encode_to_vec()
+}
+
+/// The reused ecash in `current` that `backed_up` does not cover.
+fn newly_reused_ecash(current: &[ReusedEcashProof], backed_up: &[ReusedEcashProof]) -> Amount {
+    let known = backed_up
+        .iter()
+        .map(blind_nonce_key)
+        .collect::<BTreeSet<_>>();
+    current
+        .iter()
+        .filter(|proof| !known.contains(&blind_nonce_key(proof)))
+        .map(ReusedEcashProof::amount)
+        .sum()
+}
+
+/// `backed_up` followed by the proofs of `current` it does not cover yet.
+fn merge_reused_ecash_proofs(
+    mut backed_up: ReusedEcashProofs,
+    current: ReusedEcashProofs,
+) -> ReusedEcashProofs {
+    let known = backed_up
+        .iter()
+        .map(blind_nonce_key)
+        .collect::<BTreeSet<_>>();
+    backed_up.extend(
+        current
+            .into_iter()
+            .filter(|proof| !known.contains(&blind_nonce_key(proof))),
+    );
+    backed_up
+}
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct WalletRepairBackupKey;
+
+impl_db_record!(
+    key = WalletRepairBackupKey,
+    value = WalletRepairBackup,
+    db_prefix = WalletRepairDbPrefix::Backup,
+);
+
+impl FederationV2 {
+    fn wallet_repair_db(&self) -> Database {
+        self.federation_data_db(FederationDataDbPrefix::WalletRepair)
+    }
+
+    pub(super) async fn monitor_wallet_repair(&self) {
+        loop {
+            if let Err(error) = self.check_wallet_repair().await {
+                warn!(?error, "failed to check for reused ecash");
+            }
+            fedimint_core::task::sleep(CHECK_INTERVAL).await;
+        }
+    }
+
+    async fn check_wallet_repair(&self) -> anyhow::Result<()> {
+        let current = self
+            .mint_ops
+            .generate_reused_ecash_proofs(self)
+            .await?
+            .deserialize()?;
+        let backed_up = self.backed_up_proofs().await?;
+        let reused_ecash = newly_reused_ecash(&current, &backed_up);
+        if reused_ecash == Amount::ZERO && self.perform_nonce_reuse_check().await {
+            return Ok(());
+        }
+
+        let mut dbtx = self.wallet_repair_db().begin_transaction().await;
+        if dbtx
+            .insert_entry(&WalletRepairNotifiedKey, &())
+            .await
+            .is_some()
+        {
+            return Ok(());
+        }
+        dbtx.commit_tx_result().await?;
+        info!(%reused_ecash, "wallet needs repair");
+        self.runtime
+            .event_sink
+            .typed_event(&Event::wallet_repair_needed(
+                self.rpc_federation_id(),
+                RpcAmount(reused_ecash),
+            ));
+        Ok(())
+    }
+
+    async fn wallet_repair_backup(&self) -> Option<WalletRepairBackup> {
+        self.wallet_repair_db()
+            .begin_transaction_nc()
+            .await
+            .get_value(&WalletRepairBackupKey)
+            .await
+    }
+
+    async fn backed_up_proofs(&self) -> anyhow::Result<ReusedEcashProofs> {
+        Ok(match self.wallet_repair_backup().await {
+            Some(backup) => backup.proofs()?,
+            None => vec![],
+        })
+    }
+
+    /// Proofs backed up by repairs, for bug reports once the wallet no
+    /// longer reports the reused ecash itself.
+    pub(super) async fn backed_up_reused_ecash_proofs(
+        &self,
+    ) -> Option<SerializedReusedEcashProofs> {
+        self.wallet_repair_backup()
+            .await
+            .map(|backup| SerializedReusedEcashProofs {
+                total_amount_msats: backup.reused_ecash,
+                reused_ecash_proofs: backup.proofs,
+            })
+    }
+
+    /// Backs up proofs of the reused ecash, repairs the wallet and reports
+    /// how the balance changed.
+    pub async fn run_wallet_repair(&self) -> anyhow::Result<RpcWalletRepairReport> {
+        let _guard = self.wallet_repair_lock.lock().await;
+        let balance_before = self.get_balance().await;
+
+        // must be stored before repairing, the proofs are all support has to
+        // refund the stuck ecash. Proofs backed up by earlier repairs are
+        // kept, the wallet may no longer report that ecash.
+        let proofs = self.mint_ops.generate_reused_ecash_proofs(self).await?;
+        let reused_ecash = proofs.total_amount_msats;
+        let current = proofs.deserialize()?;
+        let backup = self.wallet_repair_backup().await;
+        let backed_up = match &backup {
+            Some(backup) => backup.proofs()?,
+            None => vec![],
+        };
+        if newly_reused_ecash(&current, &backed_up) != Amount::ZERO {
+            let merged = merge_reused_ecash_proofs(backed_up, current);
+            let backed_up_at = match backup {
+                Some(backup) => backup.backed_up_at,
+                None => to_unix_time(fedimint_core::time::now())?,
+            };
+            let mut dbtx = self.wallet_repair_db().begin_transaction().await;
+            dbtx.insert_entry(
+                &WalletRepairBackupKey,
+                &WalletRepairBackup {
+                    backed_up_at,
+                    reused_ecash: merged.iter().map(ReusedEcashProof::amount).sum(),
+                    proofs: merged.consensus_encode_to_vec(),
+                },
+            )
+            .await;
+            dbtx.commit_tx_result().await?;
+        }
+
+        self.repair_wallet().await?;
+
+        let mut dbtx = self.wallet_repair_db().begin_transaction().await;
+        dbtx.remove_entry(&WalletRepairNotifiedKey).await;
+        dbtx.commit_tx_result().await?;
+
+        let balance_after = self.get_balance().await;
+        let recovered = balance_after.saturating_sub(balance_before);
+        let lost = balance_before.saturating_sub(balance_after);
+        info!(%reused_ecash, %balance_before, %balance_after, "wallet repaired");
+        self.send_balance_event().await;
+        self.send_federation_event().await;
+        Ok(RpcWalletRepairReport {
+            reused_ecash: RpcAmount(reused_ecash),
+            balance_before: RpcAmount(balance_before),
+            recovered: RpcAmount(recovered),
+            lost: RpcAmount(lost),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use fedimint_core::secp256k1;
+    use fedimint_core::secp256k1::rand::{Rng, thread_rng};
+    use fedimint_derive_secret::DerivableSecret;
+    use fedimint_mint_client::output::NoteIssuanceRequest;
+
+    use super::*;
+
+    fn proof(msats: u64) -> ReusedEcashProof {
+        let (key, salt): ([u8; 32], [u8; 32]) = thread_rng().r#gen();
+        let (request, blind_nonce) = NoteIssuanceRequest::new(
+            secp256k1::SECP256K1,
+            &DerivableSecret::new_root(&key, &salt),
+        );
+        ReusedEcashProof::new(Amount::from_msats(msats), request, blind_nonce)
+    }
+
+    /// Decodes a copy of `proofs`, as the wallet reports the same reuse anew
+    /// on every check.
+    fn copy(proofs: &[ReusedEcashProof]) -> ReusedEcashProofs {
+        proofs
+            .iter()
+            .map(|proof| {
+                ReusedEcashProof::consensus_decode_whole(
+                    &proof.consensus_encode_to_vec(),
+                    &Default::default(),
+                )
+                .unwrap()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_newly_reused_ecash() {
+        let backed_up = vec![proof(1_000), proof(2_000)];
+        assert_eq!(
+            newly_reused_ecash(&copy(&backed_up), &backed_up),
+            Amount::ZERO
+        );
+        assert_eq!(newly_reused_ecash(&[], &backed_up), Amount::ZERO);
+        assert_eq!(
+            newly_reused_ecash(&copy(&backed_up), &[]),
+            Amount::from_msats(3_000)
+        );
+
+        // new reuse worth less than what was backed up is still found
+        let mut current = copy(&backed_up);
+        current.truncate(1);
+        current.push(proof(500));
+        assert_eq!(
+            newly_reused_ecash(&current, &backed_up),
+            Amount::from_msats(500)
+        );
+    }
+
+    #[test]
+    fn test_merge_keeps_backed_up_proofs() {
+        let backed_up = vec![proof(1_000), proof(2_000)];
+        let mut current = copy(&backed_up);
+        current.remove(0);
+        current.push(proof(500));
+        let expected = [
+            blind_nonce_key(&backed_up[0]),
+            blind_nonce_key(&backed_up[1]),
+            blind_nonce_key(&current[1]),
+        ];
+
+        let merged = merge_reused_ecash_proofs(backed_up, current);
+        assert_eq!(
+            merged.iter().map(blind_nonce_key).collect::<Vec<_>>(),
+            expected
+        );
+        assert_eq!(
+            merged.iter().map(ReusedEcashProof::amount).sum::<Amount>(),
+            Amount::from_msats(3_500)
+        );
+    }
+}
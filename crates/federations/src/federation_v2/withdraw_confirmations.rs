@@ -0,0 +1,269 @@
+//! Follows onchain withdrawals after broadcast until they are buried deep
+//! enough, so the transaction history can show their confirmations.
+//!
+//! Neither the federation nor the wallet modules report confirmations, so
+//! they are looked up on an Esplora API. That reveals the txids to the
+//! server, so it only happens once the user chose one, see
+//! [`runtime::bridge_runtime::Runtime::esplora_url`].
+
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+use anyhow::Context as _;
+use bitcoin::Txid;
+use fedimint_core::core::OperationId;
+use fedimint_core::db::{Database, IDatabaseTransactionOpsCoreTyped};
+use fedimint_core::encoding::{Decodable, Encodable};
+use fedimint_core::{impl_db_lookup, impl_db_record};
+use futures::StreamExt;
+use serde::Deserialize;
+use tracing::{debug, warn};
+
+use super::FederationV2;
+use super::db::FederationDataDbPrefix;
+
+const POLL_INTERVAL: Duration = Duration::from_secs(60);
+/// Withdrawals are no longer followed once this deep.
+const FINAL_CONFIRMATIONS: u32 = 6;
+/// Withdrawals are no longer followed after failing this many lookups in a
+/// row, e.g. because the Esplora API is for another network.
+const MAX_FAILED_LOOKUPS: u32 = 60;
+
+#[repr(u8)]
+pub enum WithdrawConfirmationsDbPrefix {
+    // operation id of a broadcast withdrawal => its confirmations
+    Withdraw = 0x01,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Encodable, Decodable)]
+pub struct WithdrawConfirmations {
+    pub txid: Txid,
+    /// `None` until the transaction was first looked up.
+    pub confirmations: Option<u32>,
+    /// Lookups that failed since the last successful one.
+    pub failed_lookups: u32,
+}
+
+#[derive(Debug, Clone, Encodable, Decodable)]
+pub struct WithdrawConfirmationsKey(pub OperationId);
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct WithdrawConfirmationsKeyPrefix;
+
+impl_db_record!(
+    key = WithdrawConfirmationsKey,
+    value = WithdrawConfirmations,
+    db_prefix = WithdrawConfirmationsDbPrefix::Withdraw,
+);
+
+impl_db_lookup!(
+    key = WithdrawConfirmationsKey,
+    query_prefix = WithdrawConfirmationsKeyPrefix
+);
+
+#[derive(Debug, Deserialize)]
+struct EsploraTxStatus {
+    confirmed: bool,
+    block_height: Option<u32>,
+}
+
+fn confirmations(tip_height: u32, block_height: u32) -> u32 {
+    (tip_height + 1).saturating_sub(block_height)
+}
+
+impl FederationV2 {
+    fn withdraw_confirmations_db(&self) -> Database {
+        self.federation_data_db(FederationDataDbPrefix::WithdrawConfirmations)
+    }
+
+    /// Starts following a withdrawal the federation broadcast as `txid`.
+    pub(super) async fn track_withdraw_confirmations(&self, operation_id: OperationId, txid: Txid) {
+        let mut dbtx = self.withdraw_confirmations_db().begin_transaction().await;
+        let key = WithdrawConfirmationsKey(operation_id);
+        if dbtx.get_value(&key).await.is_some() {
+            return;
+        }
+        dbtx.insert_entry(
+            &key,
+            &WithdrawConfirmations {
+                txid,
+                confirmations: None,
+                failed_lookups: 0,
+            },
+        )
+        .await;
+        if let Err(error) = dbtx.commit_tx_result().await {
+            warn!(?error, "failed to track withdrawal confirmations");
+        }
+    }
+
+    pub(super) async fn withdraw_confirmations(&self, operation_id: OperationId) -> Option<u32> {
+        self.withdraw_confirmations_db()
+            .begin_transaction_nc()
+            .await
+            .get_value(&WithdrawConfirmationsKey(operation_id))
+            .await?
+            .confirmations
+    }
+
+    async fn esplora_url(&self) -> Option<String> {
+        let esplora_url = self.runtime.esplora_url().await?;
+        Some(esplora_url.trim_end_matches('/').to_owned())
+    }
+
+    /// Esplora's fee rates in sat/vB by confirmation target in blocks, or
+    /// `None` if the user didn't choose an Esplora API.
+    pub(super) async fn esplora_fee_estimates(&self) -> Option<BTreeMap<u16, f64>> {
+        let esplora_url = self.esplora_url().await?;
+        let fee_estimates = async {
+            anyhow::Ok(
+                self.runtime
+                    .http_client
+                    .get(format!("{esplora_url}/fee-estimates"))
+                    .send()
+                    .await?
+                    .error_for_status()?
+                    .json()
+                    .await?,
+            )
+        };
+        fee_estimates
+            .await
+            .inspect_err(|error| warn!(?error, "failed to fetch fee estimates"))
+            .ok()
+    }
+
+    pub(super) async fn monitor_withdraw_confirmations(&self) {
+        loop {
+            if let Err(error) = self.update_withdraw_confirmations().await {
+                warn!(?error, "failed to update withdrawal confirmations");
+            }
+            fedimint_core::task::sleep(POLL_INTERVAL).await;
+        }
+    }
+
+    async fn update_withdraw_confirmations(&self) -> anyhow::Result<()> {
+        let pending = self
+            .withdraw_confirmations_db()
+            .begin_transaction_nc()
+            .await
+            .find_by_prefix(&WithdrawConfirmationsKeyPrefix)
+            .await
+            .filter(|(_, withdraw)| {
+                std::future::ready(
+                    withdraw.failed_lookups < MAX_FAILED_LOOKUPS
+                        && withdraw
+                            .confirmations
+                            .is_none_or(|confirmations| confirmations < FINAL_CONFIRMATIONS),
+                )
+            })
+            .collect::<Vec<_>>()
+            .await;
+        if pending.is_empty() {
+            return Ok(());
+        }
+        let Some(esplora_url) = self.esplora_url().await else {
+            debug!("no esplora chosen to follow withdrawal confirmations");
+            return Ok(());
+        };
+        let reqwest = &self.runtime.http_client;
+
+        let tip_height = reqwest
+            .get(format!("{esplora_url}/blocks/tip/height"))
+            .send()
+            .await?
+            .error_for_status()?
+            .text()
+            .await?
+            .trim()
+            .parse::<u32>()
+            .context("invalid tip height")?;
+        for (key, withdraw) in pending {
+            let status = async {
+                anyhow::Ok(
+                    reqwest
+                        .get(format!("{esplora_url}/tx/{}/status", withdraw.txid))
+                        .send()
+                        .await?
+                        .error_for_status()?
+                        .json::<EsploraTxStatus>()
+                        .await?,
+                )
+            };
+            let updated = match status.await {
+                Ok(status) => {
+                    let confirmations = match status {
+                        EsploraTxStatus {
+                            confirmed: true,
+                            block_height: Some(block_height),
+                        } => confirmations(tip_height, block_height),
+                        _ => 0,
+                    };
+                    WithdrawConfirmations {
+                        confirmations: Some(confirmations),
+                        failed_lookups: 0,
+                        ..withdraw.clone()
+                    }
+                }
+                Err(error) => {
+                    warn!(txid = %withdraw.txid, ?error, "failed to look up withdrawal");
+                    WithdrawConfirmations {
+                        failed_lookups: withdraw.failed_lookups + 1,
+                        ..withdraw.clone()
+                    }
+                }
+            };
+            if updated == withdraw {
+                continue;
+            }
+            let confirmations_changed = updated.confirmations != withdraw.confirmations;
+            let mut dbtx = self.withdraw_confirmations_db().begin_transaction().await;
+            dbtx.insert_entry(&key, &updated).await;
+            if let Err(error) = dbtx.commit_tx_result().await {
+                warn!(?error, "failed to store withdrawal confirmations");
+                continue;
+            }
+            if confirmations_changed {
+                self.send_transaction_event(key.0).await;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Minutes until a transaction paying `sats_per_vbyte` likely confirms: the
+/// shortest confirmation target `fee_estimates` says it meets, at ten minutes
+/// a block. `None` if it meets none of them.
+pub(super) fn estimated_confirmation_mins(
+    fee_estimates: &BTreeMap<u16, f64>,
+    sats_per_vbyte: f64,
+) -> Option<u32> {
+    fee_estimates
+        .iter()
+        .find(|(_, fee_rate)| **fee_rate <= sats_per_vbyte)
+        .map(|(target, _)| u32::from(*target) * 10)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_confirmations() {
+        assert_eq!(confirmations(100, 100), 1);
+        assert_eq!(confirmations(105, 100), 6);
+        // esplora may see the block before its tip moved
+        assert_eq!(confirmations(99, 100), 0);
+    }
+
+    #[test]
+    fn test_estimated_confirmation_mins() {
+        let fee_estimates: BTreeMap<u16, f64> =
+            serde_json::from_str(r#"{"1": 20.5, "3": 12.0, "6": 8.1, "144": 1.0}"#).unwrap();
+        assert_eq!(estimated_confirmation_mins(&fee_estimates, 25.0), Some(10));
+        assert_eq!(estimated_confirmation_mins(&fee_estimates, 12.0), Some(30));
+        assert_eq!(estimated_confirmation_mins(&fee_estimates, 9.0), Some(60));
+        assert_eq!(estimated_confirmation_mins(&fee_estimates, 2.0), Some(1440));
+        assert_eq!(estimated_confirmation_mins(&fee_estimates, 0.5), None);
+    }
+}
@@ -5,36 +5,97 @@ use anyhow::{Context, anyhow, bail, ensure};
 use api_types::invoice_generator::GenerateInvoiceBreakdownItemV5;
 use async_recursion::async_recursion;
 use bitcoin::Network;
-use fedimint_core::core::ModuleKind;
-use fedimint_core::db::IDatabaseTransactionOpsCoreTyped;
+use fedimint_core::core::{ModuleKind, OperationId};
+use fedimint_core::db::{
+    AutocommitResultExt, DatabaseTransaction, IDatabaseTransactionOpsCoreTyped,
+};
+use fedimint_core::encoding::{Decodable, Encodable};
 use fedimint_core::util::backoff_util::custom_backoff;
 use fedimint_core::util::retry;
 use fedimint_core::{Amount, SATS_PER_BITCOIN};
 use futures::StreamExt;
 use lightning_invoice::Bolt11Invoice;
-use rpc_types::RpcTransactionDirection;
+use rpc_types::{RpcTransactionDirection, SPv2DepositMetadata};
+use stability_pool_client::StabilityPoolDepositOperationState;
+use stability_pool_client::common::{Account, BtcBalanceDepositMetadata};
 use tokio::sync::Mutex;
 use tracing::{error, info, instrument};
 
 use super::db::{
-    LastRemittedTotalAccruedFeesByStreamKey, NextFediFeeRemittanceDueAtByStreamKey,
-    OutstandingFediFeesByStreamKey, OutstandingFediFeesByStreamPerTXTypeKey,
-    OutstandingFediFeesByStreamPerTXTypeKeyPrefix, TotalAccruedFediFeesByStreamKey,
+    CurrentAppFeeRemittanceKey, LastRemittedTotalAccruedFeesByStreamKey,
+    NextFediFeeRemittanceDueAtByStreamKey, OutstandingFediFeesByStreamKey,
+    OutstandingFediFeesByStreamPerTXTypeKey, OutstandingFediFeesByStreamPerTXTypeKeyPrefix,
+    TotalAccruedFediFeesByStreamKey,
+};
+use super::guardian::{GuardianFeeDepositAction, guardian_fee_deposit_action};
+use super::guardian_metadata::{
+    GuardianFeeBreakdownItemV1, GuardianFeeRemittanceMetadataV1,
+    encrypt_guardian_remittance_metadata,
 };
+use super::statement::{FediFeeLedgerEntryKind, record_fedi_fee_ledger_entry};
 use super::{FediFeeHelper, FediFeeStream};
 use crate::federation_v2::FederationV2;
-use crate::federation_v2::db::LastFediFeesRemittanceSPv2BalanceKey;
+use crate::federation_v2::client::ClientExt;
+use crate::federation_v2::db::{BridgeDbPrefix, LastFediFeesRemittanceSPv2BalanceKey};
+use crate::federation_v2::ln_ops::{FeeRemittance, FeeRemittanceNotPaid};
 
 // Unreasonable amount of fee, nobody should pay this much fee.
 const UNREASONABLE_FEDI_FEE_AMOUNT: Amount = Amount::from_sats(SATS_PER_BITCOIN / 10);
 
-#[derive(Clone)]
-struct AppFeeBreakdownItem {
+#[derive(Debug, Clone, PartialEq, Eq, Encodable, Decodable)]
+pub struct AppFeeBreakdownItem {
     module: ModuleKind,
     tx_direction: RpcTransactionDirection,
     amount: Amount,
 }
 
+/// App fees reserved for a stable balance deposit that has not reached a
+/// final state yet.
+#[derive(Debug, Clone, PartialEq, Eq, Encodable, Decodable)]
+pub struct AppFeeRemittanceInFlight {
+    operation_id: OperationId,
+    amount: Amount,
+    breakdown: Vec<AppFeeBreakdownItem>,
+}
+
+/// How one app fee remittance pays Fedi. Generic over the prepared Lightning
+/// payment only so route selection can be tested without a gateway.
+enum AppFeeRemittanceRoute<L = FeeRemittance> {
+    /// Pay the Fedi invoice through a Lightning gateway.
+    Lightning(L),
+    /// Deposit to the Fedi-published stable balance account.
+    StableBalance(Account),
+}
+
+/// Prefers remitting over Lightning and only falls back to the stable
+/// balance account when no gateway can take the payment.
+fn remittance_route<L>(
+    lightning: anyhow::Result<L>,
+    fallback_account: Option<Account>,
+) -> anyhow::Result<AppFeeRemittanceRoute<L>> {
+    match (lightning, fallback_account) {
+        (Ok(remittance), _) => Ok(AppFeeRemittanceRoute::Lightning(remittance)),
+        (Err(e), Some(account)) => {
+            info!(
+                ?e,
+                "Lightning fee remittance unavailable, using stable balance"
+            );
+            Ok(AppFeeRemittanceRoute::StableBalance(account))
+        }
+        (Err(e), None) => Err(e),
+    }
+}
+
+/// Whether app fees whose Lightning payment failed with `error` may still be
+/// deposited to `fallback_account`. Only a payment that provably left no
+/// funds in flight qualifies, anything else could remit the fees twice.
+fn stable_balance_failover(
+    error: &anyhow::Error,
+    fallback_account: Option<Account>,
+) -> Option<Account> {
+    fallback_account.filter(|_| error.is::<FeeRemittanceNotPaid>())
+}
+
 impl FediFeeHelper {
     /// Queries the Fedi API for a federation-scoped app-fee invoice using the
     /// aggregate requested amount and the full per-type fee breakdown.
@@ -89,7 +150,14 @@ impl FediFeeRemittanceService {
                 .await
                 .unwrap_or(Amount::ZERO);
 
-            if outstanding_total > Amount::ZERO {
+            let in_flight = fed
+                .fedi_fee_db()
+                .begin_transaction_nc()
+                .await
+                .get_value(&CurrentAppFeeRemittanceKey)
+                .await;
+
+            if outstanding_total > Amount::ZERO || in_flight.is_some() {
                 service2.remit_fedi_fee_if_threshold_met(&fed).await;
             }
         });
@@ -110,6 +178,27 @@ impl FediFeeRemittanceService {
             return;
         };
 
+        // A stable balance deposit from an earlier attempt, possibly before a
+        // restart, must be settled before remitting again so that its fees
+        // are not remitted twice.
+        if let Some(in_flight) = fed
+            .fedi_fee_db()
+            .begin_transaction_nc()
+            .await
+            .get_value(&CurrentAppFeeRemittanceKey)
+            .await
+        {
+            fed.spawn_cancellable("reconcile_app_fee_remittance", move |fed2| async move {
+                if let Err(e) =
+                    Self::reconcile_stable_balance_remittance(&fed2, in_flight.operation_id).await
+                {
+                    error!(?e, "Failed to reconcile app fee remittance");
+                }
+                drop(guard);
+            });
+            return;
+        }
+
         let (outstanding_fees_total, breakdown) = match current_app_fee_breakdown(fed).await {
             Ok(snapshot) => snapshot,
             Err(e) => {
@@ -168,6 +257,9 @@ impl FediFeeRemittanceService {
 
     /// Performs one federation-scoped app-fee remittance using the entire
     /// snapshotted app outstanding breakdown as the v5 reporting payload.
+    /// Fees are paid over Lightning, or deposited to the stable balance
+    /// account from the fee schedule when no gateway can remit them or the
+    /// Lightning payment failed without leaving funds in flight.
     #[instrument(skip(fed, breakdown), err, ret)]
     async fn remit_fedi_fee(
         fed: &FederationV2,
@@ -175,16 +267,23 @@ impl FediFeeRemittanceService {
         breakdown: Vec<AppFeeBreakdownItem>,
         accrued_fee_exceeds_threshold: bool,
     ) -> anyhow::Result<()> {
-        let fee_remittance = if accrued_fee_exceeds_threshold {
+        let fallback_account = fed.fedi_fee_schedule().await.remittance_fallback_account;
+        let route = if accrued_fee_exceeds_threshold {
             info!("Accrued fee exceeds threshold");
-            Some(fed.prepare_fee_remittance(outstanding_fees_total).await?)
+            Some(remittance_route(
+                fed.prepare_fee_remittance(outstanding_fees_total).await,
+                fallback_account.clone(),
+            )?)
         } else {
             info!("Accrued fee below threshold, will request 0-amount invoice");
             None
         };
-        let amt_to_request = fee_remittance
-            .as_ref()
-            .map_or(Amount::ZERO, |remittance| remittance.invoice_amount);
+        // A stable balance remittance is reported with a 0-amount invoice
+        // that is never paid.
+        let amt_to_request = match &route {
+            Some(AppFeeRemittanceRoute::Lightning(remittance)) => remittance.invoice_amount,
+            Some(AppFeeRemittanceRoute::StableBalance(_)) | None => Amount::ZERO,
+        };
 
         let (current_spv2_balance, spv2_balance_delta_cents) = if let Ok(spv2_account_info) =
             fed.spv2_account_info().await
@@ -293,23 +392,27 @@ impl FediFeeRemittanceService {
             bail!("Fedi fee less gateway fee would be effectively 0");
         }
 
-        let invoice_amt = Amount::from_msats(
-            invoice
-                .amount_milli_satoshis()
-                .expect("amount must be present"),
-        );
-        if invoice_amt > UNREASONABLE_FEDI_FEE_AMOUNT {
-            bail!("likely bug: Fedi fee amount({invoice_amt}) is too high, we refuse to pay");
+        let route = route.context("fee remittance missing after threshold was exceeded")?;
+        let remitted_amt = match &route {
+            AppFeeRemittanceRoute::Lightning(_) => {
+                let invoice_amt = Amount::from_msats(
+                    invoice
+                        .amount_milli_satoshis()
+                        .expect("amount must be present"),
+                );
+                ensure!(
+                    invoice_amt == amt_to_request,
+                    "invoice amount must be match requested amount"
+                );
+                invoice_amt
+            }
+            AppFeeRemittanceRoute::StableBalance(_) => outstanding_fees_total,
+        };
+        if remitted_amt > UNREASONABLE_FEDI_FEE_AMOUNT {
+            bail!("likely bug: Fedi fee amount({remitted_amt}) is too high, we refuse to pay");
         }
-        ensure!(
-            invoice_amt == amt_to_request,
-            "invoice amount must be match requested amount"
-        );
         info!("fedi fee threshold exceeded, remitting");
 
-        let fee_remittance =
-            fee_remittance.context("fee remittance missing after threshold was exceeded")?;
-
         fed.fedi_fee_db()
             .autocommit(
                 |dbtx, _| {
@@ -337,40 +440,280 @@ impl FediFeeRemittanceService {
                 fedimint_core::db::AutocommitError::ClosureError { error, .. } => error,
             })?;
 
-        if let Err(e) = fed.pay_fee_remittance(&invoice, fee_remittance).await {
+        let operation_id = match route {
+            AppFeeRemittanceRoute::Lightning(remittance) => {
+                match fed.pay_fee_remittance(&invoice, remittance).await {
+                    Ok(operation_id) => operation_id,
+                    Err(e) => {
+                        if let Some(account) = stable_balance_failover(&e, fallback_account) {
+                            info!(
+                                ?e,
+                                "Lightning fee remittance failed, falling back to stable balance"
+                            );
+                            return Self::remit_to_stable_balance(
+                                fed,
+                                &account,
+                                outstanding_fees_total,
+                                breakdown,
+                            )
+                            .await;
+                        }
+                        restore_reserved_app_fees(fed, &breakdown, outstanding_fees_total).await?;
+                        return Err(e);
+                    }
+                }
+            }
+            AppFeeRemittanceRoute::StableBalance(account) => {
+                return Self::remit_to_stable_balance(
+                    fed,
+                    &account,
+                    outstanding_fees_total,
+                    breakdown,
+                )
+                .await;
+            }
+        };
+
+        // The payment already went through, so a failure to record it in the
+        // ledger must not be reported as a failed remittance.
+        if let Err(e) = fed
+            .fedi_fee_db()
+            .autocommit(
+                |dbtx, _| {
+                    Box::pin({
+                        let breakdown = breakdown.clone();
+                        async move {
+                            record_app_fee_remittance(dbtx, operation_id, &breakdown).await;
+                            Ok::<(), anyhow::Error>(())
+                        }
+                    })
+                },
+                Some(100),
+            )
+            .await
+        {
+            error!(?e, "failed to record app fee remittance in fee ledger");
+        }
+
+        Ok(())
+    }
+
+    /// Deposits the already reserved app fees to the Fedi stable balance
+    /// account and follows the deposit to its outcome. The fees are restored
+    /// right away if no deposit could be made.
+    async fn remit_to_stable_balance(
+        fed: &FederationV2,
+        account: &Account,
+        amount: Amount,
+        breakdown: Vec<AppFeeBreakdownItem>,
+    ) -> anyhow::Result<()> {
+        let operation_id =
+            match Self::deposit_to_stable_balance(fed, account, amount, breakdown.clone()).await {
+                Ok(operation_id) => operation_id,
+                Err(e) => {
+                    restore_reserved_app_fees(fed, &breakdown, amount).await?;
+                    return Err(e);
+                }
+            };
+        Self::reconcile_stable_balance_remittance(fed, operation_id).await
+    }
+
+    /// Creates the stable balance deposit and records it as in flight in the
+    /// same database transaction, so that its outcome is reconciled even if
+    /// the app restarts before the deposit is final.
+    async fn deposit_to_stable_balance(
+        fed: &FederationV2,
+        account: &Account,
+        amount: Amount,
+        breakdown: Vec<AppFeeBreakdownItem>,
+    ) -> anyhow::Result<OperationId> {
+        ensure!(
+            account.as_single().is_some(),
+            "app fee remittance account must be single-sig"
+        );
+        let spv2_instance = fed.client.spv2()?;
+        spv2_instance
+            .ensure_btc_balance_deposit_supported(account.id())
+            .await?;
+        ensure!(
+            amount >= spv2_instance.cfg.min_allowed_seek,
+            "app fee remittance is below the stable balance minimum"
+        );
+        // Guardian remittances define the envelope, Fedi decrypts the app fee
+        // breakdown with the key of its account the same way.
+        let metadata = encrypt_guardian_remittance_metadata(
+            account,
+            &GuardianFeeRemittanceMetadataV1 {
+                version: 1,
+                total_msats: amount.msats,
+                breakdown: breakdown
+                    .iter()
+                    .map(|item| GuardianFeeBreakdownItemV1 {
+                        module: item.module.to_string(),
+                        direction: item.tx_direction.clone(),
+                        amount_msats: item.amount.msats,
+                    })
+                    .collect(),
+                remitted_at_unix: fedimint_core::time::now()
+                    .duration_since(SystemTime::UNIX_EPOCH)
+                    .map_or(0, |since_epoch| since_epoch.as_secs()),
+            },
+        )?;
+
+        let spv2_instance_id = spv2_instance.id;
+        let spv2 = spv2_instance.inner().clone();
+        let account_id = account.id();
+        let operation_id = OperationId::new_random();
+        let in_flight = AppFeeRemittanceInFlight {
+            operation_id,
+            amount,
+            breakdown,
+        };
+        fed.client
+            .db()
+            .autocommit(
+                |dbtx, _| {
+                    let spv2 = spv2.clone();
+                    let metadata = metadata.clone();
+                    let in_flight = in_flight.clone();
+                    Box::pin(async move {
+                        spv2.deposit_to_btc_balance_dbtx(
+                            &mut dbtx.to_ref_with_prefix_module_id(spv2_instance_id).0,
+                            operation_id,
+                            account_id,
+                            amount,
+                            BtcBalanceDepositMetadata(metadata),
+                            SPv2DepositMetadata::AppFeeRemittance,
+                        )
+                        .await?;
+                        dbtx.to_ref_nc()
+                            .with_prefix(vec![BridgeDbPrefix::FediFeePrefix as u8])
+                            .insert_entry(&CurrentAppFeeRemittanceKey, &in_flight)
+                            .await;
+                        Ok::<(), anyhow::Error>(())
+                    })
+                },
+                None,
+            )
+            .await
+            .unwrap_autocommit()?;
+        Ok(operation_id)
+    }
+
+    /// Waits for the in-flight stable balance deposit `operation_id` to be
+    /// final, then records its fees as remitted, or restores them to
+    /// outstanding if the transaction was rejected. If the outcome can't be
+    /// observed the deposit stays in flight for the next remittance attempt.
+    async fn reconcile_stable_balance_remittance(
+        fed: &FederationV2,
+        operation_id: OperationId,
+    ) -> anyhow::Result<()> {
+        let spv2 = fed.client.spv2()?;
+        let mut updates = spv2
+            .subscribe_deposit_operation(operation_id)
+            .await?
+            .into_stream();
+        while let Some(state) = updates.next().await {
+            fed.update_operation_state(operation_id, state.clone())
+                .await;
+            let accepted = match guardian_fee_deposit_action(&state) {
+                GuardianFeeDepositAction::Wait => continue,
+                GuardianFeeDepositAction::Settle => {
+                    fed.spv2_force_sync();
+                    true
+                }
+                GuardianFeeDepositAction::Restore => false,
+            };
             fed.fedi_fee_db()
                 .autocommit(
                     |dbtx, _| {
-                        Box::pin({
-                            let breakdown = breakdown.clone();
-                            async move {
-                                apply_app_breakdown_to_outstanding(
-                                    dbtx,
-                                    &breakdown,
-                                    outstanding_fees_total,
-                                    false,
-                                )
-                                .await;
-                                Ok::<(), anyhow::Error>(())
-                            }
+                        Box::pin(async move {
+                            finish_app_fee_remittance_dbtx(dbtx, operation_id, accepted).await;
+                            Ok::<(), anyhow::Error>(())
                         })
                     },
-                    Some(100),
+                    None,
                 )
                 .await
-                .map_err(|e| match e {
-                    fedimint_core::db::AutocommitError::CommitFailed { last_error, .. } => {
-                        anyhow::anyhow!(last_error)
-                    }
-                    fedimint_core::db::AutocommitError::ClosureError { error, .. } => error,
-                })?;
-            return Err(e);
+                .unwrap_autocommit()?;
+            if let StabilityPoolDepositOperationState::TxRejected(e) = state {
+                bail!("app fee remittance deposit rejected: {e}");
+            }
+            return Ok(());
         }
+        bail!("app fee remittance deposit updates ended before a final state")
+    }
+}
 
-        Ok(())
+/// Restores app fees reserved for a remittance that made no payment.
+async fn restore_reserved_app_fees(
+    fed: &FederationV2,
+    breakdown: &[AppFeeBreakdownItem],
+    total: Amount,
+) -> anyhow::Result<()> {
+    fed.fedi_fee_db()
+        .autocommit(
+            |dbtx, _| {
+                Box::pin(async move {
+                    apply_app_breakdown_to_outstanding(dbtx, breakdown, total, false).await;
+                    Ok::<(), anyhow::Error>(())
+                })
+            },
+            Some(100),
+        )
+        .await
+        .map_err(|e| match e {
+            fedimint_core::db::AutocommitError::CommitFailed { last_error, .. } => {
+                anyhow::anyhow!(last_error)
+            }
+            fedimint_core::db::AutocommitError::ClosureError { error, .. } => error,
+        })
+}
+
+async fn record_app_fee_remittance(
+    dbtx: &mut DatabaseTransaction<'_>,
+    operation_id: OperationId,
+    breakdown: &[AppFeeBreakdownItem],
+) {
+    for item in breakdown {
+        record_fedi_fee_ledger_entry(
+            dbtx,
+            FediFeeStream::App,
+            FediFeeLedgerEntryKind::Remittance,
+            operation_id,
+            item.module.clone(),
+            item.tx_direction.clone(),
+            item.amount,
+        )
+        .await;
     }
 }
 
+/// Clears the in-flight deposit `operation_id`, recording its fees as
+/// remitted if it was `accepted` or restoring them to outstanding otherwise.
+/// A stale call is ignored because its operation no longer owns the marker.
+async fn finish_app_fee_remittance_dbtx(
+    dbtx: &mut DatabaseTransaction<'_>,
+    operation_id: OperationId,
+    accepted: bool,
+) -> bool {
+    let Some(in_flight) = dbtx
+        .get_value(&CurrentAppFeeRemittanceKey)
+        .await
+        .filter(|in_flight| in_flight.operation_id == operation_id)
+    else {
+        return false;
+    };
+    if accepted {
+        record_app_fee_remittance(dbtx, operation_id, &in_flight.breakdown).await;
+    } else {
+        apply_app_breakdown_to_outstanding(dbtx, &in_flight.breakdown, in_flight.amount, false)
+            .await;
+    }
+    dbtx.remove_entry(&CurrentAppFeeRemittanceKey).await;
+    true
+}
+
 fn next_app_fee_remittance_due_at(fed: &FederationV2) -> SystemTime {
     fedimint_core::time::now()
         .checked_add(Duration::from_secs(
@@ -460,3 +803,185 @@ async fn apply_app_breakdown_to_outstanding(
         dbtx.insert_entry(&key, &new_value).await;
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use bitcoin::secp256k1::{PublicKey, Secp256k1, SecretKey};
+    use fedimint_core::db::Database;
+    use fedimint_core::db::mem_impl::MemDatabase;
+    use stability_pool_client::common::AccountType;
+
+    use super::*;
+    use crate::fedi_fee::db::FediFeeLedgerKeyPrefix;
+
+    fn account() -> Account {
+        let secp = Secp256k1::new();
+        let secret_key = SecretKey::from_slice(&[41; 32]).expect("valid secret key");
+        Account::single(
+            PublicKey::from_secret_key(&secp, &secret_key),
+            AccountType::BtcDepositor,
+        )
+    }
+
+    fn breakdown() -> Vec<AppFeeBreakdownItem> {
+        vec![
+            AppFeeBreakdownItem {
+                module: ModuleKind::from_static_str("ln"),
+                tx_direction: RpcTransactionDirection::Send,
+                amount: Amount::from_msats(3_000),
+            },
+            AppFeeBreakdownItem {
+                module: ModuleKind::from_static_str("mint"),
+                tx_direction: RpcTransactionDirection::Receive,
+                amount: Amount::from_msats(2_000),
+            },
+        ]
+    }
+
+    async fn outstanding(db: &Database) -> (Amount, Vec<AppFeeBreakdownItem>) {
+        let mut dbtx = db.begin_transaction_nc().await;
+        let total = dbtx
+            .get_value(&OutstandingFediFeesByStreamKey(FediFeeStream::App))
+            .await
+            .unwrap_or(Amount::ZERO);
+        let breakdown = dbtx
+            .find_by_prefix(&OutstandingFediFeesByStreamPerTXTypeKeyPrefix(
+                FediFeeStream::App,
+            ))
+            .await
+            .filter_map(|(key, amount)| async move {
+                (amount > Amount::ZERO).then_some(AppFeeBreakdownItem {
+                    module: key.1,
+                    tx_direction: key.2,
+                    amount,
+                })
+            })
+            .collect::<Vec<_>>()
+            .await;
+        (total, breakdown)
+    }
+
+    async fn in_flight(db: &Database) -> Option<AppFeeRemittanceInFlight> {
+        db.begin_transaction_nc()
+            .await
+            .get_value(&CurrentAppFeeRemittanceKey)
+            .await
+    }
+
+    async fn ledger_entries(db: &Database) -> usize {
+        let mut dbtx = db.begin_transaction_nc().await;
+        dbtx.find_by_prefix(&FediFeeLedgerKeyPrefix(FediFeeStream::App))
+            .await
+            .count()
+            .await
+    }
+
+    /// Reserves the fees of `breakdown()` for a stable balance deposit the
+    /// same way `remit_fedi_fee` and `deposit_to_stable_balance` do.
+    async fn reserve_in_flight(db: &Database) -> OperationId {
+        let operation_id = OperationId::new_random();
+        let mut dbtx = db.begin_transaction().await;
+        apply_app_breakdown_to_outstanding(
+            &mut dbtx.to_ref_nc(),
+            &breakdown(),
+            Amount::from_msats(5_000),
+            false,
+        )
+        .await;
+        apply_app_breakdown_to_outstanding(
+            &mut dbtx.to_ref_nc(),
+            &breakdown(),
+            Amount::from_msats(5_000),
+            true,
+        )
+        .await;
+        dbtx.insert_entry(
+            &CurrentAppFeeRemittanceKey,
+            &AppFeeRemittanceInFlight {
+                operation_id,
+                amount: Amount::from_msats(5_000),
+                breakdown: breakdown(),
+            },
+        )
+        .await;
+        dbtx.commit_tx().await;
+        operation_id
+    }
+
+    #[test]
+    fn route_falls_back_only_when_lightning_is_unavailable() {
+        assert!(matches!(
+            remittance_route(Ok(()), Some(account())),
+            Ok(AppFeeRemittanceRoute::Lightning(()))
+        ));
+        assert!(matches!(
+            remittance_route::<()>(Err(anyhow!("no gateway")), Some(account())),
+            Ok(AppFeeRemittanceRoute::StableBalance(fallback)) if fallback == account()
+        ));
+        assert!(remittance_route::<()>(Err(anyhow!("no gateway")), None).is_err());
+    }
+
+    #[test]
+    fn failover_requires_a_payment_that_left_no_funds_in_flight() {
+        let not_paid = anyhow::Error::new(FeeRemittanceNotPaid(anyhow!("refunded")));
+        assert_eq!(
+            stable_balance_failover(&not_paid, Some(account())),
+            Some(account())
+        );
+        assert_eq!(stable_balance_failover(&not_paid, None), None);
+        assert_eq!(
+            stable_balance_failover(&not_paid.context("remitting app fees"), Some(account())),
+            Some(account())
+        );
+        assert_eq!(
+            stable_balance_failover(&anyhow!("payment update stream ended"), Some(account())),
+            None
+        );
+    }
+
+    #[tokio::test]
+    async fn rejected_stable_balance_deposit_restores_reserved_fees() {
+        let db = Database::new(MemDatabase::new(), Default::default());
+        let operation_id = reserve_in_flight(&db).await;
+        assert_eq!(outstanding(&db).await, (Amount::ZERO, vec![]));
+
+        let mut dbtx = db.begin_transaction().await;
+        assert!(finish_app_fee_remittance_dbtx(&mut dbtx.to_ref_nc(), operation_id, false).await);
+        dbtx.commit_tx().await;
+
+        assert_eq!(
+            outstanding(&db).await,
+            (Amount::from_msats(5_000), breakdown())
+        );
+        assert_eq!(ledger_entries(&db).await, 0);
+        assert!(in_flight(&db).await.is_none());
+
+        // A repeated outcome must not restore the same fees twice.
+        let mut dbtx = db.begin_transaction().await;
+        assert!(!finish_app_fee_remittance_dbtx(&mut dbtx.to_ref_nc(), operation_id, false).await);
+        dbtx.commit_tx().await;
+        assert_eq!(
+            outstanding(&db).await,
+            (Amount::from_msats(5_000), breakdown())
+        );
+    }
+
+    #[tokio::test]
+    async fn accepted_stable_balance_deposit_records_remittance() {
+        let db = Database::new(MemDatabase::new(), Default::default());
+        let operation_id = reserve_in_flight(&db).await;
+
+        // Outcomes of another operation leave the in-flight deposit alone.
+        let mut dbtx = db.begin_transaction().await;
+        assert!(
+            !finish_app_fee_remittance_dbtx(&mut dbtx.to_ref_nc(), OperationId::new_random(), true)
+                .await
+        );
+        assert!(finish_app_fee_remittance_dbtx(&mut dbtx.to_ref_nc(), operation_id, true).await);
+        dbtx.commit_tx().await;
+
+        assert_eq!(outstanding(&db).await, (Amount::ZERO, vec![]));
+        assert_eq!(ledger_entries(&db).await, breakdown().len());
+        assert!(in_flight(&db).await.is_none());
+    }
+}
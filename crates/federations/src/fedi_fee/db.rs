@@ -5,9 +5,11 @@ use std::time::SystemTime;
 use fedimint_core::core::{ModuleKind, OperationId};
 use fedimint_core::encoding::{Decodable, Encodable};
 use fedimint_core::{Amount, impl_db_lookup, impl_db_record};
-use rpc_types::{OperationFediFeeStatus, RpcTransactionDirection};
+use rpc_types::{OperationFediFeeDiscount, OperationFediFeeStatus, RpcTransactionDirection};
 
-use super::FediFeeStream;
+use super::app::AppFeeRemittanceInFlight;
+use super::statement::FediFeeLedgerEntry;
+use super::{FediFeeRule, FediFeeStream};
 
 #[repr(u8)]
 pub enum FediFeeDbPrefix {
@@ -31,6 +33,19 @@ pub enum FediFeeDbPrefix {
     LastAccruedTotalByStream = 0x09,
     // Total accrued fee counter per stream.
     TotalAccruedByStream = 0x0A,
+    // Fee rule of a pending receive keyed by `(operation_id, stream)`.
+    PendingReceiveRuleByStream = 0x0B,
+    // Amount of a pending send, counted towards the volume once it succeeds.
+    PendingSendVolume = 0x0C,
+    // Daily transaction volume keyed by `(module, direction, day)`.
+    VolumeByTxType = 0x0D,
+    // Fee promotion applied to an operation keyed by `(operation_id, stream)`.
+    OperationDiscountByStream = 0x0E,
+    // Every accrual and remittance keyed by `(stream, recorded_at, operation_id,
+    // module, direction)`.
+    LedgerByStream = 0x0F,
+    // Current in-flight app fee deposit to the stable balance account.
+    AppRemittanceOperation = 0x10,
 }
 
 #[derive(Debug, Decodable, Encodable)]
@@ -42,6 +57,15 @@ impl_db_record!(
     db_prefix = FediFeeDbPrefix::OperationStatusByStream,
 );
 
+#[derive(Debug, Decodable, Encodable)]
+pub struct OperationFediFeeDiscountByStreamKey(pub OperationId, pub FediFeeStream);
+
+impl_db_record!(
+    key = OperationFediFeeDiscountByStreamKey,
+    value = OperationFediFeeDiscount,
+    db_prefix = FediFeeDbPrefix::OperationDiscountByStream,
+);
+
 #[derive(Debug, Decodable, Encodable)]
 pub struct OutstandingFediFeesByStreamKey(pub FediFeeStream);
 
@@ -129,6 +153,86 @@ impl_db_record!(
     db_prefix = FediFeeDbPrefix::TotalAccruedByStream,
 );
 
+// Receives only learn their amount once they complete, so the rule to charge
+// them is stored when they start. Receives started before fee policies existed
+// only have the ppm in their `OperationFediFeeStatus`.
+#[derive(Debug, Decodable, Encodable)]
+pub struct PendingReceiveFediFeeRuleByStreamKey(pub OperationId, pub FediFeeStream);
+
+impl_db_record!(
+    key = PendingReceiveFediFeeRuleByStreamKey,
+    value = FediFeeRule,
+    db_prefix = FediFeeDbPrefix::PendingReceiveRuleByStream,
+);
+
+#[derive(Debug, Decodable, Encodable)]
+pub struct PendingSendFediFeeVolumeKey(pub OperationId);
+
+impl_db_record!(
+    key = PendingSendFediFeeVolumeKey,
+    value = Amount,
+    db_prefix = FediFeeDbPrefix::PendingSendVolume,
+);
+
+// Volume is bucketed by day since the unix epoch, buckets older than the
+// window are pruned as new volume is recorded.
+#[derive(Debug, Decodable, Encodable)]
+pub struct FediFeeVolumeKey(pub ModuleKind, pub RpcTransactionDirection, pub u64);
+
+#[derive(Debug, Decodable, Encodable)]
+pub struct FediFeeVolumeKeyPrefix(pub ModuleKind, pub RpcTransactionDirection);
+
+impl_db_record!(
+    key = FediFeeVolumeKey,
+    value = Amount,
+    db_prefix = FediFeeDbPrefix::VolumeByTxType,
+);
+
+impl_db_lookup!(
+    key = FediFeeVolumeKey,
+    query_prefix = FediFeeVolumeKeyPrefix,
+);
+
+// Accruals are keyed by the operation that was charged, remittances by the
+// operation that paid them and carry one entry per `(module, direction)` of
+// their breakdown. `recorded_at` is in unix seconds so entries sort by time.
+#[derive(Debug, Decodable, Encodable)]
+pub struct FediFeeLedgerKey(
+    pub FediFeeStream,
+    pub u64,
+    pub OperationId,
+    pub ModuleKind,
+    pub RpcTransactionDirection,
+);
+
+impl FediFeeLedgerKey {
+    /// Sorts before every entry of `stream` recorded at or after
+    /// `recorded_at`, and after every entry recorded before.
+    pub fn first_at(stream: FediFeeStream, recorded_at: u64) -> Self {
+        Self(
+            stream,
+            recorded_at,
+            OperationId([0; 32]),
+            ModuleKind::from_static_str(""),
+            RpcTransactionDirection::Receive,
+        )
+    }
+}
+
+#[derive(Debug, Decodable, Encodable)]
+pub struct FediFeeLedgerKeyPrefix(pub FediFeeStream);
+
+impl_db_record!(
+    key = FediFeeLedgerKey,
+    value = FediFeeLedgerEntry,
+    db_prefix = FediFeeDbPrefix::LedgerByStream,
+);
+
+impl_db_lookup!(
+    key = FediFeeLedgerKey,
+    query_prefix = FediFeeLedgerKeyPrefix,
+);
+
 // Tracks the current in-flight guardian remittance operation so the background
 // service can avoid submitting a second remittance and can recover
 // subscriptions across restarts.
@@ -141,6 +245,18 @@ impl_db_record!(
     db_prefix = FediFeeDbPrefix::GuardianRemittanceOperation,
 );
 
+// Tracks the current in-flight app fee deposit to the stable balance account,
+// so its fees are settled or restored once its outcome is known, even across
+// restarts. Lightning remittances are not tracked.
+#[derive(Debug, Decodable, Encodable)]
+pub struct CurrentAppFeeRemittanceKey;
+
+impl_db_record!(
+    key = CurrentAppFeeRemittanceKey,
+    value = AppFeeRemittanceInFlight,
+    db_prefix = FediFeeDbPrefix::AppRemittanceOperation,
+);
+
 #[derive(Debug, Decodable, Encodable)]
 pub struct AppFeeStreamStateInitializedKey;
 
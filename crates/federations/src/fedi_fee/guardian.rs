@@ -1,19 +1,20 @@
-use std::collections::BTreeMap;
 use std::time::{Duration, SystemTime};
 
-use anyhow::ensure;
 use fedimint_core::Amount;
 use fedimint_core::core::OperationId;
 use fedimint_core::db::{
     AutocommitResultExt, DatabaseTransaction, IDatabaseTransactionOpsCoreTyped,
 };
 use futures::StreamExt;
+use guardian_fee_config::{
+    FediGuardianFeeRecipient, checked_amount_sum, split_guardian_fee_remittance,
+    validate_guardian_fee_recipients,
+};
 use rand::Rng;
 use rpc_types::{
     GuardianFeeRemittanceBreakdownItem, GuardianFeeRemittanceSnapshot, SPv2DepositMetadata,
 };
-use runtime::storage::state::{FediGuardianFeeConfig, FediGuardianFeeRecipient};
-use stability_pool_client::common::{Account, AccountId, AccountType, BtcBalanceDepositMetadata};
+use stability_pool_client::common::{Account, BtcBalanceDepositMetadata};
 use stability_pool_client::{StabilityPoolDepositOperationState, StabilityPoolMeta};
 use tracing::error;
 
@@ -27,114 +28,10 @@ use super::guardian_metadata::{
     GuardianFeeBreakdownItemV1, GuardianFeeRemittanceMetadataV1,
     encrypt_guardian_remittance_metadata,
 };
+use super::statement::{FediFeeLedgerEntryKind, record_fedi_fee_ledger_entry};
 use crate::federation_v2::FederationV2;
 use crate::federation_v2::client::ClientExt;
 use crate::federation_v2::db::BridgeDbPrefix;
-// Guardians should set this to 0 to stop new guardian-fee accrual while
-// still leaving remittance config available to drain any already-accrued fee.
-pub const FEDI_GUARDIAN_FEE_SEND_PPM_META_KEY: &str = "fedi:guardian_fee_send_ppm";
-pub const FEDI_GUARDIAN_FEE_REMITTANCE_ACCOUNT_META_KEY: &str =
-    "fedi:guardian_fee_remittance_account";
-// Guardian fee config is federation-controlled metadata, so keep a very high
-// but finite sanity cap to avoid obviously broken values.
-pub(crate) const FEDI_GUARDIAN_FEE_SEND_PPM_MAX: u64 = 210_000;
-const MAX_GUARDIAN_FEE_RECIPIENTS: usize = 32;
-
-/// Manifold keeps using the legacy remittance-account meta key, but replaces
-/// its single `Account` value with this versioned weighted list for new
-/// federations.
-#[derive(serde::Deserialize)]
-#[serde(deny_unknown_fields)]
-struct GuardianFeeRecipientList {
-    version: u16,
-    recipients: Vec<FediGuardianFeeRecipient>,
-}
-
-pub fn parse_fedi_guardian_fee_config(
-    meta: &BTreeMap<String, String>,
-) -> anyhow::Result<Option<FediGuardianFeeConfig>> {
-    let guardian_fee_send_ppm = meta.get(FEDI_GUARDIAN_FEE_SEND_PPM_META_KEY);
-    let remittance_accounts = meta.get(FEDI_GUARDIAN_FEE_REMITTANCE_ACCOUNT_META_KEY);
-
-    let (Some(guardian_fee_send_ppm), Some(remittance_accounts)) =
-        (guardian_fee_send_ppm, remittance_accounts)
-    else {
-        ensure!(
-            guardian_fee_send_ppm.is_none() && remittance_accounts.is_none(),
-            "guardian fee config must define both {} and {}",
-            FEDI_GUARDIAN_FEE_SEND_PPM_META_KEY,
-            FEDI_GUARDIAN_FEE_REMITTANCE_ACCOUNT_META_KEY,
-        );
-        return Ok(None);
-    };
-
-    let send_ppm = guardian_fee_send_ppm.parse::<u64>()?;
-    ensure!(
-        send_ppm <= FEDI_GUARDIAN_FEE_SEND_PPM_MAX,
-        "guardian fee send ppm must be <= {}",
-        FEDI_GUARDIAN_FEE_SEND_PPM_MAX,
-    );
-    // Existing federations publish one Account. Manifold federations publish
-    // the versioned list under the same meta key.
-    let recipients = match serde_json::from_str::<Account>(remittance_accounts) {
-        Ok(account) => vec![FediGuardianFeeRecipient { account, weight: 1 }],
-        Err(_) => {
-            let list: GuardianFeeRecipientList = serde_json::from_str(remittance_accounts)?;
-            ensure!(
-                list.version == 1,
-                "unsupported guardian fee recipient list version"
-            );
-            list.recipients
-        }
-    };
-    let config = FediGuardianFeeConfig {
-        send_ppm,
-        recipients,
-    };
-    validate_guardian_fee_recipients(&config.recipients)?;
-
-    Ok(Some(config))
-}
-
-/// Validates the wire policy and returns its total weight.
-///
-/// Sorted, unique account ids make the metadata canonical and prevent two
-/// outputs for the same account in the single remittance transaction.
-fn validate_guardian_fee_recipients(
-    recipients: &[FediGuardianFeeRecipient],
-) -> anyhow::Result<u64> {
-    ensure!(
-        !recipients.is_empty() && recipients.len() <= MAX_GUARDIAN_FEE_RECIPIENTS,
-        "guardian fee recipient count must be between 1 and {MAX_GUARDIAN_FEE_RECIPIENTS}"
-    );
-    let mut previous: Option<AccountId> = None;
-    let mut total_weight = 0_u64;
-    for recipient in recipients {
-        ensure!(
-            recipient.account.acc_type() == AccountType::BtcDepositor,
-            "guardian fee remittance account must be a btc-balance account"
-        );
-        ensure!(
-            recipient.account.as_single().is_some(),
-            "guardian fee remittance account must be single-sig"
-        );
-        ensure!(
-            recipient.weight != 0,
-            "guardian fee recipient weight must be positive"
-        );
-        let account_id = recipient.account.id();
-        ensure!(
-            previous.as_ref().is_none_or(|last| last < &account_id),
-            "guardian fee recipients must be unique and sorted by account id"
-        );
-        previous = Some(account_id);
-        total_weight = total_weight
-            .checked_add(recipient.weight)
-            .ok_or_else(|| anyhow::anyhow!("guardian fee recipient weights overflow"))?;
-    }
-    Ok(total_weight)
-}
-
 /// One recipient's output plus the accounting breakdown encrypted for it.
 #[derive(Clone)]
 struct GuardianFeeRecipientRemittance {
@@ -150,16 +47,6 @@ struct GuardianFeeRemittancePlan {
     recipients: Vec<GuardianFeeRecipientRemittance>,
 }
 
-/// Checked equivalent of summing `Amount`s with `+`, whose inner value is a
-/// `u64`.
-fn checked_amount_sum(amounts: impl IntoIterator<Item = Amount>) -> anyhow::Result<Amount> {
-    amounts
-        .into_iter()
-        .try_fold(0_u64, |total, amount| total.checked_add(amount.msats))
-        .map(Amount::from_msats)
-        .ok_or_else(|| anyhow::anyhow!("guardian fee amount overflow"))
-}
-
 /// Checked addition used before mutating persisted fee totals.
 fn checked_amount_add(left: Amount, right: Amount) -> anyhow::Result<Amount> {
     left.msats
@@ -168,84 +55,48 @@ fn checked_amount_add(left: Amount, right: Amount) -> anyhow::Result<Amount> {
         .ok_or_else(|| anyhow::anyhow!("guardian fee amount overflow"))
 }
 
-/// Applies the recipient weights at remit time without changing accrual.
-///
-/// Each breakdown row is divided by the total weight, giving every recipient
-/// `unit * weight`. The indivisible remainder stays in the aggregate ledger
-/// for a future remittance. Returning `None` until every output reaches SPv2's
-/// minimum lets all recipients be submitted in one atomic transaction.
+/// Builds the remittance of `outstanding_breakdown` to `recipients`, keeping
+/// the breakdown of every output for its encrypted metadata. Rows too small to
+/// split stay outstanding and are left out of every snapshot.
 fn plan_guardian_fee_remittance(
     outstanding_breakdown: &[GuardianFeeRemittanceBreakdownItem],
     recipients: &[FediGuardianFeeRecipient],
     minimum: Amount,
 ) -> anyhow::Result<Option<GuardianFeeRemittancePlan>> {
-    let total_weight = validate_guardian_fee_recipients(recipients)?;
-    let mut recipient_amounts = vec![Amount::ZERO; recipients.len()];
-    let mut recipient_breakdowns = vec![Vec::new(); recipients.len()];
-    let mut remittance_breakdown = Vec::new();
-
-    for item in outstanding_breakdown {
-        let unit = item.amount.msats / total_weight;
-        if unit == 0 {
-            continue;
-        }
-        let remitted = Amount::from_msats(
-            unit.checked_mul(total_weight)
-                .ok_or_else(|| anyhow::anyhow!("guardian fee remittance amount overflow"))?,
-        );
-        remittance_breakdown.push(GuardianFeeRemittanceBreakdownItem {
-            module: item.module.clone(),
-            tx_direction: item.tx_direction.clone(),
-            amount: remitted,
-        });
-
-        for (index, recipient) in recipients.iter().enumerate() {
-            let share = Amount::from_msats(
-                unit.checked_mul(recipient.weight)
-                    .ok_or_else(|| anyhow::anyhow!("guardian fee recipient share overflow"))?,
-            );
-            recipient_amounts[index] = checked_amount_add(recipient_amounts[index], share)?;
-            recipient_breakdowns[index].push(GuardianFeeRemittanceBreakdownItem {
+    let outstanding = outstanding_breakdown
+        .iter()
+        .map(|item| item.amount)
+        .collect::<Vec<_>>();
+    let Some(split) = split_guardian_fee_remittance(&outstanding, recipients, minimum)? else {
+        return Ok(None);
+    };
+    let snapshot = |amounts: &[Amount]| GuardianFeeRemittanceSnapshot {
+        breakdown: outstanding_breakdown
+            .iter()
+            .zip(amounts)
+            .filter(|(_, amount)| **amount > Amount::ZERO)
+            .map(|(item, amount)| GuardianFeeRemittanceBreakdownItem {
                 module: item.module.clone(),
                 tx_direction: item.tx_direction.clone(),
-                amount: share,
-            });
-        }
-    }
-
-    if remittance_breakdown.is_empty() || recipient_amounts.iter().any(|amount| *amount < minimum) {
-        return Ok(None);
-    }
-
-    let mut remittances = Vec::with_capacity(recipients.len());
-    for ((recipient, amount), breakdown) in recipients
-        .iter()
-        .zip(recipient_amounts)
-        .zip(recipient_breakdowns)
-    {
-        ensure!(
-            checked_amount_sum(breakdown.iter().map(|item| item.amount))? == amount,
-            "guardian fee recipient breakdown mismatch"
-        );
-        remittances.push(GuardianFeeRecipientRemittance {
-            account: recipient.account.clone(),
-            amount,
-            snapshot: GuardianFeeRemittanceSnapshot { breakdown },
-        });
-    }
-    let remittance_amount =
-        checked_amount_sum(remittance_breakdown.iter().map(|item| item.amount))?;
-    ensure!(
-        checked_amount_sum(remittances.iter().map(|recipient| recipient.amount))?
-            == remittance_amount,
-        "guardian fee remittance plan does not conserve millisatoshis"
-    );
+                amount: *amount,
+            })
+            .collect(),
+    };
     Ok(Some(GuardianFeeRemittancePlan {
-        amount: remittance_amount,
-        snapshot: GuardianFeeRemittanceSnapshot {
-            breakdown: remittance_breakdown,
-        },
-        recipients: remittances,
+        amount: split.amount,
+        snapshot: snapshot(&split.remitted),
+        recipients: recipients
+            .iter()
+            .zip(split.recipient_amounts)
+            .zip(&split.shares)
+            .map(
+                |((recipient, amount), shares)| GuardianFeeRecipientRemittance {
+                    account: recipient.account.clone(),
+                    amount,
+                    snapshot: snapshot(shares),
+                },
+            )
+            .collect(),
     }))
 }
 
@@ -367,7 +218,7 @@ async fn restore_guardian_fee_remittance_dbtx(
 }
 
 #[derive(Debug, PartialEq, Eq)]
-enum GuardianFeeDepositAction {
+pub(super) enum GuardianFeeDepositAction {
     Wait,
     Restore,
     Settle,
@@ -376,7 +227,7 @@ enum GuardianFeeDepositAction {
 /// Only transaction rejection means the recipient outputs were not applied.
 /// `PrimaryOutputError` happens after transaction acceptance, so restoring on
 /// it would pay the same fees twice.
-fn guardian_fee_deposit_action(
+pub(super) fn guardian_fee_deposit_action(
     state: &StabilityPoolDepositOperationState,
 ) -> GuardianFeeDepositAction {
     match state {
@@ -698,23 +549,42 @@ impl GuardianFeeRemittanceService {
     }
 
     /// Finalizes a successful guardian remittance by clearing the current
-    /// in-flight operation and scheduling the next remittance. Outstanding was
-    /// already reserved when the remittance operation was created.
+    /// in-flight operation, scheduling the next remittance and recording the
+    /// remitted breakdown in the fee ledger. Outstanding was already reserved
+    /// when the remittance operation was created.
     pub async fn handle_guardian_fee_remittance_success(
         &self,
         fed: &FederationV2,
         operation_id: OperationId,
     ) -> anyhow::Result<()> {
+        let snapshot = Self::guardian_fee_remittance_snapshot_from_operation(fed, operation_id)
+            .await?
+            .map(|(_, snapshot)| snapshot);
         fed.fedi_fee_db()
             .autocommit(
                 |dbtx, _| {
+                    let snapshot = snapshot.clone();
                     Box::pin(async move {
-                        settle_guardian_fee_remittance_dbtx(
+                        let settled = settle_guardian_fee_remittance_dbtx(
                             dbtx,
                             operation_id,
                             Self::next_guardian_remittance_due_at(fed),
                         )
                         .await?;
+                        if let Some(snapshot) = snapshot.filter(|_| settled) {
+                            for item in snapshot.breakdown {
+                                record_fedi_fee_ledger_entry(
+                                    dbtx,
+                                    FediFeeStream::Guardian,
+                                    FediFeeLedgerEntryKind::Remittance,
+                                    operation_id,
+                                    item.module,
+                                    item.tx_direction,
+                                    item.amount,
+                                )
+                                .await;
+                            }
+                        }
                         Ok(())
                     })
                 },
@@ -848,11 +718,21 @@ impl GuardianFeeRemittanceService {
 
 #[cfg(test)]
 mod tests {
+    use std::collections::BTreeMap;
+
     use bitcoin::secp256k1::{PublicKey, Secp256k1, SecretKey};
     use fedimint_core::core::ModuleKind;
     use fedimint_core::db::Database;
     use fedimint_core::db::mem_impl::MemDatabase;
+    use guardian_fee_config::{
+        FEDI_FEE_MAX_FIXED_MSAT, FEDI_GUARDIAN_FEE_REMITTANCE_ACCOUNT_META_KEY,
+        FEDI_GUARDIAN_FEE_SEND_POLICY_META_KEY, FEDI_GUARDIAN_FEE_SEND_PPM_MAX,
+        FEDI_GUARDIAN_FEE_SEND_PPM_META_KEY, FediFeePolicy, FediGuardianFeeConfig,
+        fedi_guardian_fee_config_meta, parse_fedi_guardian_fee_config,
+        preview_guardian_fee_remittance, validate_guardian_fee_remittance_account,
+    };
     use rpc_types::RpcTransactionDirection;
+    use stability_pool_client::common::AccountType;
 
     use super::*;
 
@@ -1016,6 +896,57 @@ mod tests {
         assert!(parse_fedi_guardian_fee_config(&partial).is_err());
     }
 
+    #[test]
+    fn parses_and_validates_send_policy() {
+        let value = recipient_list_json(&manifold_4_1_1_recipients(7));
+        let with_policy = |policy: serde_json::Value| {
+            let mut meta = meta(value.clone());
+            meta.insert(
+                FEDI_GUARDIAN_FEE_SEND_POLICY_META_KEY.to_string(),
+                policy.to_string(),
+            );
+            parse_fedi_guardian_fee_config(&meta)
+        };
+
+        let config = parse_fedi_guardian_fee_config(&meta(value.clone()))
+            .unwrap()
+            .unwrap();
+        assert_eq!(config.send_policy, FediFeePolicy::default());
+
+        let config = with_policy(serde_json::json!({
+            "base_msat": 1_000,
+            "max_msat": 50_000,
+            "volume_tiers": [{"min_volume_msat": 10_000_000, "ppm": 100}],
+        }))
+        .unwrap()
+        .unwrap();
+        assert_eq!(config.send_policy.base_msat, 1_000);
+        assert_eq!(config.send_policy.min_msat, 0);
+        assert_eq!(config.send_policy.max_msat, Some(50_000));
+        assert_eq!(config.send_policy.volume_tiers.len(), 1);
+
+        let invalid = [
+            serde_json::json!({"min_msat": 2_000, "max_msat": 1_000}),
+            serde_json::json!({"base_msat": FEDI_FEE_MAX_FIXED_MSAT + 1}),
+            serde_json::json!({"volume_tiers": [
+                {"min_volume_msat": 10, "ppm": 100},
+                {"min_volume_msat": 10, "ppm": 50},
+            ]}),
+            serde_json::json!({"volume_tiers": [
+                {"min_volume_msat": 10, "ppm": FEDI_GUARDIAN_FEE_SEND_PPM_MAX + 1},
+            ]}),
+        ];
+        for policy in invalid {
+            assert!(with_policy(policy).is_err());
+        }
+
+        let partial = BTreeMap::from([(
+            FEDI_GUARDIAN_FEE_SEND_POLICY_META_KEY.to_string(),
+            "{}".to_string(),
+        )]);
+        assert!(parse_fedi_guardian_fee_config(&partial).is_err());
+    }
+
     #[test]
     fn plans_one_exact_atomic_weighted_remittance_and_retains_dust() {
         let entries = manifold_4_1_1_recipients(7);
@@ -1087,6 +1018,82 @@ mod tests {
         }
     }
 
+    #[test]
+    fn config_meta_round_trips_in_canonical_order() {
+        let mut recipients = manifold_4_1_1_recipients(7)
+            .into_iter()
+            .map(|(account, weight)| FediGuardianFeeRecipient { account, weight })
+            .collect::<Vec<_>>();
+        recipients.reverse();
+        let config = FediGuardianFeeConfig {
+            send_ppm: 250,
+            recipients,
+            send_policy: FediFeePolicy {
+                base_msat: 1_000,
+                ..FediFeePolicy::default()
+            },
+        };
+        let meta = fedi_guardian_fee_config_meta(&config).unwrap();
+        assert_eq!(meta.len(), 3);
+        let parsed = parse_fedi_guardian_fee_config(&meta).unwrap().unwrap();
+        assert_eq!(parsed.send_policy, config.send_policy);
+        assert!(
+            parsed
+                .recipients
+                .windows(2)
+                .all(|pair| pair[0].account.id() < pair[1].account.id())
+        );
+
+        let legacy = FediGuardianFeeConfig {
+            send_ppm: 250,
+            recipients: vec![FediGuardianFeeRecipient {
+                account: account(7),
+                weight: 1,
+            }],
+            send_policy: FediFeePolicy::default(),
+        };
+        let meta = fedi_guardian_fee_config_meta(&legacy).unwrap();
+        assert_eq!(
+            meta[FEDI_GUARDIAN_FEE_REMITTANCE_ACCOUNT_META_KEY],
+            serde_json::to_string(&account(7)).unwrap()
+        );
+        assert!(!meta.contains_key(FEDI_GUARDIAN_FEE_SEND_POLICY_META_KEY));
+
+        let seeker = Account::single(*account(9).as_single().unwrap(), AccountType::Seeker);
+        assert!(validate_guardian_fee_remittance_account(&seeker).is_err());
+        assert!(
+            fedi_guardian_fee_config_meta(&FediGuardianFeeConfig {
+                recipients: vec![FediGuardianFeeRecipient {
+                    account: seeker,
+                    weight: 1,
+                }],
+                ..legacy
+            })
+            .is_err()
+        );
+    }
+
+    #[test]
+    fn preview_reports_outputs_and_retained_dust() {
+        let recipients = manifold_4_1_1_recipients(7)
+            .into_iter()
+            .map(|(account, weight)| FediGuardianFeeRecipient { account, weight })
+            .collect::<Vec<_>>();
+        let outstanding = [Amount::from_msats(120_011)];
+        let preview =
+            preview_guardian_fee_remittance(&outstanding, &recipients, Amount::from_msats(10_000))
+                .unwrap()
+                .unwrap();
+        assert_eq!(preview.amount, Amount::from_msats(120_000));
+        assert_eq!(preview.retained, Amount::from_msats(11));
+        assert_eq!(preview.recipients.len(), recipients.len());
+        assert!(
+            preview_guardian_fee_remittance(&outstanding, &recipients, Amount::from_msats(20_000))
+                .unwrap()
+                .is_none()
+        );
+    }
+
     #[test]
     fn legacy_single_recipient_remits_as_before() {
         let recipient = FediGuardianFeeRecipient {
@@ -7,7 +7,8 @@ use rpc_types::RpcTransactionDirection;
 use stability_pool_client::common::Account;
 
 /// Human-readable breakdown item included in the plaintext guardian remittance
-/// metadata before encryption.
+/// metadata before encryption. App fee deposits to Fedi's stable balance
+/// account reuse it for their breakdown.
 #[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
 pub(crate) struct GuardianFeeBreakdownItemV1 {
     pub(crate) module: String,
@@ -16,7 +17,8 @@ pub(crate) struct GuardianFeeBreakdownItemV1 {
 }
 
 /// Versioned plaintext metadata attached to guardian remittance deposits before
-/// encryption.
+/// encryption. App fee deposits to Fedi's stable balance account use the same
+/// envelope, so Fedi decrypts both with the key of its account.
 #[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
 pub(crate) struct GuardianFeeRemittanceMetadataV1 {
     pub(crate) version: u16,
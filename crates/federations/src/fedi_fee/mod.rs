@@ -1,16 +1,22 @@
-use std::collections::HashMap;
+use std::collections::{BTreeSet, HashMap};
 use std::sync::Arc;
+use std::time::UNIX_EPOCH;
 
 use anyhow::bail;
+use api_types::invoice_generator::TransactionDirection;
 use bitcoin::Network;
+use bitcoin::hashes::{Hash as _, sha256};
+use fedimint_core::Amount;
 use fedimint_core::core::ModuleKind;
 use fedimint_core::encoding::{Decodable, Encodable};
 use futures::Stream;
 use rpc_types::{RpcFediFeeStream, RpcTransactionDirection};
 use runtime::bridge_runtime::Runtime;
-use runtime::constants::FEDI_FEE_SCHEDULE_REFRESH_DELAY;
+use runtime::constants::{FEDI_FEE_SCHEDULE_REFRESH_DELAY, MILLION};
 use runtime::nightly_panic;
-use runtime::storage::state::{FediFeeSchedule, ModuleFediFeeSchedule};
+use runtime::storage::state::{
+    FediFeePolicy, FediFeePromotion, FediFeeSchedule, ModuleFediFeeSchedule,
+};
 use tokio::sync::watch;
 use tokio_stream::wrappers::WatchStream;
 use tracing::error;
@@ -19,9 +25,11 @@ pub(crate) mod app;
 pub(crate) mod db;
 pub(crate) mod guardian;
 pub(crate) mod guardian_metadata;
+pub(crate) mod statement;
 pub use app::FediFeeRemittanceService;
-use guardian::FEDI_GUARDIAN_FEE_SEND_PPM_MAX;
-pub use guardian::{GuardianFeeRemittanceService, parse_fedi_guardian_fee_config};
+pub use guardian::GuardianFeeRemittanceService;
+pub use guardian_fee_config::parse_fedi_guardian_fee_config;
+use guardian_fee_config::{FEDI_GUARDIAN_FEE_SEND_PPM_MAX, validate_fee_policy};
 
 /// Distinguishes the independently accrued Fedi fee streams that may be
 /// charged on the same underlying transaction volume.
@@ -60,6 +68,147 @@ pub enum FediFeeHelperError {
 // prod.
 const FEDI_FEE_MAX_PPM: u64 = 2100 * 20;
 
+/// Days of transaction volume that count towards volume tiers.
+pub const FEDI_FEE_VOLUME_WINDOW_DAYS: u64 = 30;
+
+/// Fee charged by one stream for one module and direction: a ppm, possibly
+/// replaced by a volume tier, plus a base fee, clamped to the per-transaction
+/// minimum and maximum.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Encodable, Decodable)]
+pub struct FediFeeRule {
+    pub ppm: u64,
+    pub policy: FediFeePolicy,
+    pub discount: Option<FediFeeDiscount>,
+}
+
+/// Promotion that waives a share of the fee of a rule.
+#[derive(Debug, Clone, PartialEq, Eq, Encodable, Decodable)]
+pub struct FediFeeDiscount {
+    pub promotion_id: String,
+    pub discount_ppm: u64,
+}
+
+impl FediFeeRule {
+    pub fn flat(ppm: u64) -> Self {
+        Self {
+            ppm,
+            policy: FediFeePolicy::default(),
+            discount: None,
+        }
+    }
+
+    /// Ppm charged once `volume` was transacted within the window.
+    pub fn ppm_for_volume(&self, volume: Amount) -> u64 {
+        self.policy
+            .volume_tiers
+            .iter()
+            .rev()
+            .find(|tier| tier.min_volume_msat <= volume.msats)
+            .map_or(self.ppm, |tier| tier.ppm)
+    }
+
+    /// Fee on a transaction of `amount`, given the volume transacted within
+    /// the window before it.
+    pub fn fee(&self, amount: Amount, volume: Amount) -> Amount {
+        let fee = self.undiscounted_fee(amount, volume);
+        fee - self.waived_fee(fee)
+    }
+
+    /// Share of `fee` waived by the discount, rounded in favor of the user.
+    pub fn waived_fee(&self, fee: Amount) -> Amount {
+        self.discount.as_ref().map_or(Amount::ZERO, |discount| {
+            Amount::from_msats(
+                fee.msats
+                    .saturating_mul(discount.discount_ppm.min(MILLION))
+                    .div_ceil(MILLION),
+            )
+        })
+    }
+
+    /// Fee on a transaction before any promotion is applied. Never more than
+    /// the transaction itself, however large the base or minimum fee.
+    pub fn undiscounted_fee(&self, amount: Amount, volume: Amount) -> Amount {
+        if amount == Amount::ZERO {
+            return Amount::ZERO;
+        }
+        let ppm_fee = amount
+            .msats
+            .saturating_mul(self.ppm_for_volume(volume))
+            .div_ceil(MILLION);
+        let fee = self
+            .policy
+            .base_msat
+            .saturating_add(ppm_fee)
+            .max(self.policy.min_msat);
+        let fee = self.policy.max_msat.map_or(fee, |max| fee.min(max));
+        Amount::from_msats(fee.min(amount.msats))
+    }
+}
+
+/// Fee rules of every stream for one module and direction, with the volume
+/// their tiers are evaluated at.
+#[derive(Debug, Clone)]
+pub struct FediFeeRules {
+    pub rules: Vec<(FediFeeStream, FediFeeRule)>,
+    pub volume: Amount,
+}
+
+impl FediFeeRules {
+    pub fn fees_by_stream(&self, amount: Amount) -> Vec<(FediFeeStream, Amount)> {
+        self.rules
+            .iter()
+            .map(|(stream, rule)| (*stream, rule.fee(amount, self.volume)))
+            .collect()
+    }
+
+    pub fn total_fee(&self, amount: Amount) -> Amount {
+        self.rules.iter().fold(Amount::ZERO, |total, (_, rule)| {
+            total + rule.fee(amount, self.volume)
+        })
+    }
+}
+
+/// Largest discount among the promotions running at `now_secs` that match the
+/// transaction. `community_invite_codes` are the invite codes of the joined
+/// communities.
+fn best_promotion_discount<'a>(
+    promotions: &[FediFeePromotion],
+    now_secs: u64,
+    federation_id: &str,
+    module: &ModuleKind,
+    direction: &RpcTransactionDirection,
+    community_invite_codes: impl Iterator<Item = &'a String>,
+) -> Option<FediFeeDiscount> {
+    let community_hashes = community_invite_codes
+        .map(|code| sha256::Hash::hash(code.as_bytes()).to_string())
+        .collect::<BTreeSet<_>>();
+    let direction = match direction {
+        RpcTransactionDirection::Send => TransactionDirection::Send,
+        RpcTransactionDirection::Receive => TransactionDirection::Receive,
+    };
+    promotions
+        .iter()
+        .filter(|promotion| (promotion.starts_at..promotion.ends_at).contains(&now_secs))
+        .filter(|promotion| {
+            let scope = &promotion.scope;
+            scope
+                .federation_id
+                .as_deref()
+                .is_none_or(|id| id == federation_id)
+                && scope.module.as_ref().is_none_or(|kind| kind == module)
+                && scope.direction.as_ref().is_none_or(|dir| *dir == direction)
+                && scope
+                    .community_invite_code_hash
+                    .as_ref()
+                    .is_none_or(|hash| community_hashes.contains(hash))
+        })
+        .max_by_key(|promotion| promotion.discount_ppm)
+        .map(|promotion| FediFeeDiscount {
+            promotion_id: promotion.id.clone(),
+            discount_ppm: promotion.discount_ppm.min(MILLION),
+        })
+}
+
 impl FediFeeHelper {
     pub fn new(runtime: Arc<Runtime>) -> Self {
         Self {
@@ -133,54 +282,110 @@ impl FediFeeHelper {
             .await
     }
 
-    /// Returns the fee to be charged in ppm for the given stream. If either
-    /// the federation ID or the module is unknown, returns an error.
-    pub async fn get_fee_ppm(
+    /// Returns the fee rule of the given stream. If either the federation ID
+    /// or the module is unknown, returns an error.
+    ///
+    /// Rates beyond the sanity caps are clamped to them, and an invalid
+    /// policy falls back to the flat ppm. App fee rules carry the best
+    /// running promotion that matches the transaction.
+    pub async fn get_fee_rule(
         &self,
         stream: FediFeeStream,
         federation_id_str: String,
         module: ModuleKind,
         direction: RpcTransactionDirection,
-    ) -> anyhow::Result<u64, FediFeeHelperError> {
-        let fee_ppm = self
+    ) -> anyhow::Result<FediFeeRule, FediFeeHelperError> {
+        let now_secs = fedimint_core::time::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |since_epoch| since_epoch.as_secs());
+        let mut rule = self
             .runtime
             .app_state
             .with_read_lock(move |state| {
-                state
-                    .joined_federations
-                    .get(&federation_id_str)
-                    .ok_or(FediFeeHelperError::UnknownFederation(federation_id_str))
-                    .and_then(|fed_info| match stream {
-                        FediFeeStream::App => fed_info
-                            .fedi_fee_schedule
+                let Some(fed_info) = state.joined_federations.get(&federation_id_str) else {
+                    return Err(FediFeeHelperError::UnknownFederation(federation_id_str));
+                };
+                match stream {
+                    FediFeeStream::App => {
+                        let schedule = &fed_info.fedi_fee_schedule;
+                        let module_schedule = schedule
                             .modules
                             .get(&module)
-                            .ok_or(FediFeeHelperError::UnknownModule(module))
-                            .map(|module_schedule| match direction {
-                                RpcTransactionDirection::Receive => module_schedule.receive_ppm,
-                                RpcTransactionDirection::Send => module_schedule.send_ppm,
-                            }),
-                        FediFeeStream::Guardian => Ok(fed_info
-                            .guardian_fee_config
-                            .as_ref()
-                            .map(|config| match direction {
-                                RpcTransactionDirection::Send => config.send_ppm,
-                                RpcTransactionDirection::Receive => 0,
-                            })
-                            .unwrap_or(0)),
-                    })
+                            .ok_or_else(|| FediFeeHelperError::UnknownModule(module.clone()))?;
+                        let (ppm, policy) = match direction {
+                            RpcTransactionDirection::Receive => {
+                                (module_schedule.receive_ppm, &module_schedule.receive_policy)
+                            }
+                            RpcTransactionDirection::Send => {
+                                (module_schedule.send_ppm, &module_schedule.send_policy)
+                            }
+                        };
+                        Ok(FediFeeRule {
+                            ppm,
+                            policy: policy.clone(),
+                            discount: best_promotion_discount(
+                                &schedule.promotions,
+                                now_secs,
+                                &federation_id_str,
+                                &module,
+                                &direction,
+                                state.joined_communities.keys(),
+                            ),
+                        })
+                    }
+                    FediFeeStream::Guardian => Ok(fed_info
+                        .guardian_fee_config
+                        .as_ref()
+                        .map(|config| match direction {
+                            RpcTransactionDirection::Send => FediFeeRule {
+                                ppm: config.send_ppm,
+                                policy: config.send_policy.clone(),
+                                discount: None,
+                            },
+                            RpcTransactionDirection::Receive => FediFeeRule::default(),
+                        })
+                        .unwrap_or_default()),
+                }
             })
             .await?;
         let max_fee_ppm = match stream {
             FediFeeStream::App => FEDI_FEE_MAX_PPM,
             FediFeeStream::Guardian => FEDI_GUARDIAN_FEE_SEND_PPM_MAX,
         };
-        if fee_ppm >= max_fee_ppm {
-            nightly_panic!(self.runtime, "fedi fee is too high: {fee_ppm}");
-            Ok(max_fee_ppm)
-        } else {
-            Ok(fee_ppm)
+        if let Err(error) = validate_fee_policy(&rule.policy, max_fee_ppm) {
+            nightly_panic!(self.runtime, "fedi fee policy is invalid: {error}");
+            rule.policy = FediFeePolicy::default();
+        }
+        if rule.ppm >= max_fee_ppm {
+            nightly_panic!(self.runtime, "fedi fee is too high: {}", rule.ppm);
+            rule.ppm = max_fee_ppm;
         }
+        Ok(rule)
+    }
+
+    /// Returns the fee rules of every stream for the given module and
+    /// direction, with the `volume` of the same module and direction within
+    /// the window their tiers are evaluated at.
+    pub async fn get_fee_rules(
+        &self,
+        federation_id_str: String,
+        module: ModuleKind,
+        direction: RpcTransactionDirection,
+        volume: Amount,
+    ) -> anyhow::Result<FediFeeRules, FediFeeHelperError> {
+        let mut rules = Vec::with_capacity(2);
+        for stream in [FediFeeStream::App, FediFeeStream::Guardian] {
+            let rule = self
+                .get_fee_rule(
+                    stream,
+                    federation_id_str.clone(),
+                    module.clone(),
+                    direction.clone(),
+                )
+                .await?;
+            rules.push((stream, rule));
+        }
+        Ok(FediFeeRules { rules, volume })
     }
 
     /// Sets the app fee schedule for a single module. If the federation ID is
@@ -206,3 +411,231 @@ impl FediFeeHelper {
             .await?
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use guardian_fee_config::FEDI_FEE_MAX_FIXED_MSAT;
+    use runtime::storage::state::{FediFeePromotionScope, FediFeeVolumeTier};
+
+    use super::*;
+
+    fn tiered_rule() -> FediFeeRule {
+        FediFeeRule {
+            ppm: 2_100,
+            policy: FediFeePolicy {
+                base_msat: 1_000,
+                min_msat: 2_000,
+                max_msat: Some(100_000),
+                volume_tiers: vec![
+                    FediFeeVolumeTier {
+                        min_volume_msat: 1_000_000_000,
+                        ppm: 1_000,
+                    },
+                    FediFeeVolumeTier {
+                        min_volume_msat: 10_000_000_000,
+                        ppm: 500,
+                    },
+                ],
+            },
+            discount: None,
+        }
+    }
+
+    fn promotion(id: &str, discount_ppm: u64, scope: FediFeePromotionScope) -> FediFeePromotion {
+        FediFeePromotion {
+            id: id.to_string(),
+            starts_at: 1_000,
+            ends_at: 2_000,
+            discount_ppm,
+            scope,
+        }
+    }
+
+    #[test]
+    fn flat_rule_rounds_ppm_fee_up() {
+        let rule = FediFeeRule::flat(2_100);
+        assert_eq!(rule.fee(Amount::ZERO, Amount::ZERO), Amount::ZERO);
+        assert_eq!(
+            rule.fee(Amount::from_msats(1), Amount::ZERO),
+            Amount::from_msats(1)
+        );
+        assert_eq!(
+            rule.fee(Amount::from_sats(1_000), Amount::from_sats(1_000_000)),
+            Amount::from_msats(2_100)
+        );
+    }
+
+    #[test]
+    fn tiered_rule_applies_base_caps_and_tiers() {
+        let rule = tiered_rule();
+        // Zero amounts never pay the base or minimum fee.
+        assert_eq!(rule.fee(Amount::ZERO, Amount::ZERO), Amount::ZERO);
+        // Small payments pay the minimum.
+        assert_eq!(
+            rule.fee(Amount::from_msats(10_000), Amount::ZERO),
+            Amount::from_msats(2_000)
+        );
+        // Base plus ppm.
+        assert_eq!(
+            rule.fee(Amount::from_sats(1_000), Amount::ZERO),
+            Amount::from_msats(3_100)
+        );
+        // Tiers replace the ppm once the volume reaches them.
+        assert_eq!(
+            rule.fee(Amount::from_sats(10_000), Amount::from_msats(999_999_999)),
+            Amount::from_msats(22_000)
+        );
+        assert_eq!(
+            rule.fee(Amount::from_sats(10_000), Amount::from_msats(1_000_000_000)),
+            Amount::from_msats(11_000)
+        );
+        assert_eq!(
+            rule.fee(Amount::from_sats(10_000), Amount::from_sats(20_000_000)),
+            Amount::from_msats(6_000)
+        );
+        // Large payments stop at the maximum.
+        assert_eq!(
+            rule.fee(Amount::from_sats(1_000_000), Amount::ZERO),
+            Amount::from_msats(100_000)
+        );
+    }
+
+    #[test]
+    fn fixed_fees_never_exceed_amount() {
+        let rule = tiered_rule();
+        // The base and minimum fee alone would exceed these.
+        assert_eq!(
+            rule.fee(Amount::from_msats(1), Amount::ZERO),
+            Amount::from_msats(1)
+        );
+        assert_eq!(
+            rule.fee(Amount::from_msats(1_500), Amount::ZERO),
+            Amount::from_msats(1_500)
+        );
+        assert_eq!(
+            rule.fee(Amount::from_msats(2_000), Amount::ZERO),
+            Amount::from_msats(2_000)
+        );
+        assert_eq!(
+            rule.fee(Amount::from_msats(2_001), Amount::ZERO),
+            Amount::from_msats(2_000)
+        );
+    }
+
+    #[test]
+    fn total_fee_sums_streams() {
+        let rules = FediFeeRules {
+            rules: vec![
+                (FediFeeStream::App, tiered_rule()),
+                (FediFeeStream::Guardian, FediFeeRule::flat(250)),
+            ],
+            volume: Amount::ZERO,
+        };
+        let amount = Amount::from_sats(1_000);
+        assert_eq!(
+            rules.fees_by_stream(amount),
+            vec![
+                (FediFeeStream::App, Amount::from_msats(3_100)),
+                (FediFeeStream::Guardian, Amount::from_msats(250)),
+            ]
+        );
+        assert_eq!(rules.total_fee(amount), Amount::from_msats(3_350));
+    }
+
+    #[test]
+    fn discount_waives_share_of_fee() {
+        let mut rule = tiered_rule();
+        rule.discount = Some(FediFeeDiscount {
+            promotion_id: "half".to_string(),
+            discount_ppm: MILLION / 2,
+        });
+        let amount = Amount::from_sats(1_000);
+        assert_eq!(
+            rule.undiscounted_fee(amount, Amount::ZERO),
+            Amount::from_msats(3_100)
+        );
+        assert_eq!(rule.fee(amount, Amount::ZERO), Amount::from_msats(1_550));
+        // The waived share rounds up.
+        assert_eq!(
+            rule.waived_fee(Amount::from_msats(3)),
+            Amount::from_msats(2)
+        );
+
+        rule.discount = Some(FediFeeDiscount {
+            promotion_id: "free".to_string(),
+            discount_ppm: MILLION,
+        });
+        assert_eq!(rule.fee(amount, Amount::ZERO), Amount::ZERO);
+    }
+
+    #[test]
+    fn picks_best_matching_running_promotion() {
+        let community = "community-invite".to_string();
+        let community_hash = sha256::Hash::hash(community.as_bytes()).to_string();
+        let promotions = vec![
+            promotion("everyone", 100_000, FediFeePromotionScope::default()),
+            promotion(
+                "other-federation",
+                MILLION,
+                FediFeePromotionScope {
+                    federation_id: Some("other".to_string()),
+                    ..Default::default()
+                },
+            ),
+            promotion(
+                "ln-receive",
+                MILLION,
+                FediFeePromotionScope {
+                    module: Some(fedimint_ln_common::KIND),
+                    direction: Some(TransactionDirection::Receive),
+                    ..Default::default()
+                },
+            ),
+            promotion(
+                "members",
+                MILLION / 2,
+                FediFeePromotionScope {
+                    federation_id: Some("fed".to_string()),
+                    community_invite_code_hash: Some(community_hash),
+                    ..Default::default()
+                },
+            ),
+        ];
+        let best = |now_secs, communities: &[String]| {
+            best_promotion_discount(
+                &promotions,
+                now_secs,
+                "fed",
+                &fedimint_ln_common::KIND,
+                &RpcTransactionDirection::Send,
+                communities.iter(),
+            )
+            .map(|discount| discount.promotion_id)
+        };
+
+        assert_eq!(best(1_500, &[]).as_deref(), Some("everyone"));
+        assert_eq!(
+            best(1_500, &[community.clone()]).as_deref(),
+            Some("members")
+        );
+        assert_eq!(best(999, &[community.clone()]), None);
+        assert_eq!(best(2_000, &[community]), None);
+    }
+
+    #[test]
+    fn validates_fee_policy() {
+        assert!(validate_fee_policy(&FediFeePolicy::default(), FEDI_FEE_MAX_PPM).is_ok());
+        assert!(validate_fee_policy(&tiered_rule().policy, FEDI_FEE_MAX_PPM).is_ok());
+
+        let mut unsorted = tiered_rule().policy;
+        unsorted.volume_tiers.reverse();
+        let mut inverted_caps = tiered_rule().policy;
+        inverted_caps.max_msat = Some(1_000);
+        let mut huge_base = tiered_rule().policy;
+        huge_base.base_msat = FEDI_FEE_MAX_FIXED_MSAT + 1;
+        for policy in [unsorted, inverted_caps, huge_base] {
+            assert!(validate_fee_policy(&policy, FEDI_FEE_MAX_PPM).is_err());
+        }
+        assert!(validate_fee_policy(&tiered_rule().policy, 999).is_err());
+    }
+}
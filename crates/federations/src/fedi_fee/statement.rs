@@ -0,0 +1,358 @@
+//! Ledger of every Fedi fee accrual and remittance, and the statements built
+//! from it so that what was charged can be reconciled against what was
+//! remitted.
+
+use std::collections::BTreeMap;
+use std::time::UNIX_EPOCH;
+
+use fedimint_core::Amount;
+use fedimint_core::core::{ModuleKind, OperationId};
+use fedimint_core::db::{DatabaseTransaction, IDatabaseTransactionOpsCoreTyped};
+use fedimint_core::encoding::{Decodable, Encodable};
+use futures::StreamExt;
+use rpc_types::{
+    RpcAmount, RpcFediFeeStream, RpcFeeStatement, RpcFeeStatementPeriod, RpcFeeStatementRemittance,
+    RpcFeeStatementRow, RpcTransactionDirection,
+};
+use time::OffsetDateTime;
+
+use super::FediFeeStream;
+use super::db::FediFeeLedgerKey;
+use crate::history_export::{format_unix_time, push_csv_row};
+
+const SECS_PER_DAY: u64 = 24 * 60 * 60;
+
+const CSV_HEADER: &[&str] = &[
+    "period_start",
+    "module",
+    "direction",
+    "accrued_msat",
+    "remitted_msat",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Encodable, Decodable)]
+pub enum FediFeeLedgerEntryKind {
+    /// Fee became outstanding once the operation it was charged on succeeded.
+    Accrual,
+    /// Outstanding fee was paid out by a remittance operation.
+    Remittance,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Encodable, Decodable)]
+pub struct FediFeeLedgerEntry {
+    pub kind: FediFeeLedgerEntryKind,
+    pub amount: Amount,
+}
+
+/// Appends an entry to the ledger of `stream`. Zero amounts are not recorded
+/// since every operation accrues on every stream, most of them nothing.
+pub(crate) async fn record_fedi_fee_ledger_entry(
+    dbtx: &mut DatabaseTransaction<'_>,
+    stream: FediFeeStream,
+    kind: FediFeeLedgerEntryKind,
+    operation_id: OperationId,
+    module: ModuleKind,
+    direction: RpcTransactionDirection,
+    amount: Amount,
+) {
+    if amount == Amount::ZERO {
+        return;
+    }
+    let recorded_at = fedimint_core::time::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |since_epoch| since_epoch.as_secs());
+    dbtx.insert_entry(
+        &FediFeeLedgerKey(stream, recorded_at, operation_id, module, direction),
+        &FediFeeLedgerEntry { kind, amount },
+    )
+    .await;
+}
+
+/// Builds the statement of `stream` for the entries recorded within
+/// `[from, to)`, in unix seconds.
+pub(crate) async fn fedi_fee_statement<Cap: Send>(
+    dbtx: &mut DatabaseTransaction<'_, Cap>,
+    stream: FediFeeStream,
+    from: u64,
+    to: u64,
+    period: RpcFeeStatementPeriod,
+) -> RpcFeeStatement {
+    let entries = dbtx
+        .find_by_range(
+            FediFeeLedgerKey::first_at(stream, from)..FediFeeLedgerKey::first_at(stream, to),
+        )
+        .await
+        .collect::<Vec<_>>()
+        .await;
+    build_statement(stream, from, to, period, entries)
+}
+
+fn build_statement(
+    stream: FediFeeStream,
+    from: u64,
+    to: u64,
+    period: RpcFeeStatementPeriod,
+    entries: Vec<(FediFeeLedgerKey, FediFeeLedgerEntry)>,
+) -> RpcFeeStatement {
+    let mut rows = BTreeMap::<_, (Amount, Amount)>::new();
+    let mut remittances = BTreeMap::<_, Amount>::new();
+    let mut total_accrued = Amount::ZERO;
+    let mut total_remitted = Amount::ZERO;
+    for (FediFeeLedgerKey(_, recorded_at, operation_id, module, direction), entry) in entries {
+        let (accrued, remitted) = rows
+            .entry((period_start(recorded_at, period), module, direction))
+            .or_default();
+        match entry.kind {
+            FediFeeLedgerEntryKind::Accrual => {
+                *accrued += entry.amount;
+                total_accrued += entry.amount;
+            }
+            FediFeeLedgerEntryKind::Remittance => {
+                *remitted += entry.amount;
+                total_remitted += entry.amount;
+                *remittances
+                    .entry((recorded_at, operation_id))
+                    .or_insert(Amount::ZERO) += entry.amount;
+            }
+        }
+    }
+
+    let rows = rows
+        .into_iter()
+        .map(
+            |((period_start, module, direction), (accrued, remitted))| RpcFeeStatementRow {
+                period_start,
+                module: module.to_string(),
+                direction,
+                accrued: RpcAmount(accrued),
+                remitted: RpcAmount(remitted),
+            },
+        )
+        .collect::<Vec<_>>();
+    let mut csv = String::new();
+    push_csv_row(&mut csv, CSV_HEADER.iter().map(|h| h.to_string()));
+    for row in &rows {
+        push_csv_row(
+            &mut csv,
+            [
+                format_unix_time(row.period_start),
+                row.module.clone(),
+                match row.direction {
+                    RpcTransactionDirection::Send => "send",
+                    RpcTransactionDirection::Receive => "receive",
+                }
+                .to_owned(),
+                row.accrued.0.msats.to_string(),
+                row.remitted.0.msats.to_string(),
+            ],
+        );
+    }
+
+    RpcFeeStatement {
+        stream: match stream {
+            FediFeeStream::App => RpcFediFeeStream::App,
+            FediFeeStream::Guardian => RpcFediFeeStream::Guardian,
+        },
+        period,
+        from,
+        to,
+        rows,
+        remittances: remittances
+            .into_iter()
+            .map(
+                |((remitted_at, operation_id), amount)| RpcFeeStatementRemittance {
+                    operation_id: operation_id.into(),
+                    remitted_at,
+                    amount: RpcAmount(amount),
+                },
+            )
+            .collect(),
+        total_accrued: RpcAmount(total_accrued),
+        total_remitted: RpcAmount(total_remitted),
+        csv,
+    }
+}
+
+/// Start of the UTC day or month `unix_seconds` falls in.
+fn period_start(unix_seconds: u64, period: RpcFeeStatementPeriod) -> u64 {
+    let day_start = unix_seconds - unix_seconds % SECS_PER_DAY;
+    match period {
+        RpcFeeStatementPeriod::Day => day_start,
+        RpcFeeStatementPeriod::Month => i64::try_from(day_start)
+            .ok()
+            .and_then(|secs| OffsetDateTime::from_unix_timestamp(secs).ok())
+            .and_then(|dt| dt.date().replace_day(1).ok())
+            .and_then(|date| u64::try_from(date.midnight().assume_utc().unix_timestamp()).ok())
+            .unwrap_or(day_start),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use fedimint_core::db::Database;
+    use fedimint_core::db::mem_impl::MemDatabase;
+
+    use super::*;
+
+    // 2023-11-14T22:13:20Z
+    const NOV_14: u64 = 1_700_000_000;
+
+    fn entry(
+        recorded_at: u64,
+        operation: u8,
+        module: &ModuleKind,
+        direction: RpcTransactionDirection,
+        kind: FediFeeLedgerEntryKind,
+        msats: u64,
+    ) -> (FediFeeLedgerKey, FediFeeLedgerEntry) {
+        (
+            FediFeeLedgerKey(
+                FediFeeStream::App,
+                recorded_at,
+                OperationId([operation; 32]),
+                module.clone(),
+                direction,
+            ),
+            FediFeeLedgerEntry {
+                kind,
+                amount: Amount::from_msats(msats),
+            },
+        )
+    }
+
+    #[test]
+    fn period_start_rounds_down_to_utc_day_and_month() {
+        assert_eq!(
+            format_unix_time(period_start(NOV_14, RpcFeeStatementPeriod::Day)),
+            "2023-11-14T00:00:00Z"
+        );
+        assert_eq!(
+            format_unix_time(period_start(NOV_14, RpcFeeStatementPeriod::Month)),
+            "2023-11-01T00:00:00Z"
+        );
+    }
+
+    #[test]
+    fn aggregates_accruals_and_remittances_by_period() {
+        let ln = fedimint_ln_common::KIND;
+        let mint = fedimint_mint_client::KIND;
+        let entries = || {
+            vec![
+                entry(
+                    NOV_14,
+                    1,
+                    &ln,
+                    RpcTransactionDirection::Send,
+                    FediFeeLedgerEntryKind::Accrual,
+                    2_100,
+                ),
+                entry(
+                    NOV_14 + 60,
+                    2,
+                    &ln,
+                    RpcTransactionDirection::Send,
+                    FediFeeLedgerEntryKind::Accrual,
+                    900,
+                ),
+                entry(
+                    NOV_14 + 120,
+                    3,
+                    &mint,
+                    RpcTransactionDirection::Send,
+                    FediFeeLedgerEntryKind::Accrual,
+                    500,
+                ),
+                entry(
+                    NOV_14 + 2 * SECS_PER_DAY,
+                    9,
+                    &ln,
+                    RpcTransactionDirection::Send,
+                    FediFeeLedgerEntryKind::Remittance,
+                    3_000,
+                ),
+                entry(
+                    NOV_14 + 2 * SECS_PER_DAY,
+                    9,
+                    &mint,
+                    RpcTransactionDirection::Send,
+                    FediFeeLedgerEntryKind::Remittance,
+                    500,
+                ),
+            ]
+        };
+
+        let daily = build_statement(
+            FediFeeStream::App,
+            0,
+            u64::MAX,
+            RpcFeeStatementPeriod::Day,
+            entries(),
+        );
+        assert_eq!(daily.rows.len(), 4);
+        assert_eq!(daily.rows[0].accrued, RpcAmount(Amount::from_msats(3_000)));
+        assert_eq!(daily.total_accrued, RpcAmount(Amount::from_msats(3_500)));
+        assert_eq!(daily.total_remitted, RpcAmount(Amount::from_msats(3_500)));
+        assert_eq!(daily.remittances.len(), 1);
+        assert_eq!(
+            daily.remittances[0].amount,
+            RpcAmount(Amount::from_msats(3_500))
+        );
+
+        let monthly = build_statement(
+            FediFeeStream::App,
+            0,
+            u64::MAX,
+            RpcFeeStatementPeriod::Month,
+            entries(),
+        );
+        assert_eq!(monthly.rows.len(), 2);
+        let csv = monthly.csv.lines().collect::<Vec<_>>();
+        assert_eq!(
+            csv[0],
+            "period_start,module,direction,accrued_msat,remitted_msat"
+        );
+        assert_eq!(csv[1], "2023-11-01T00:00:00Z,ln,send,3000,3000");
+        assert_eq!(csv[2], "2023-11-01T00:00:00Z,mint,send,500,500");
+    }
+
+    #[tokio::test]
+    async fn statement_only_reads_its_stream_and_period() {
+        let db = Database::new(MemDatabase::new(), Default::default());
+        let ln = fedimint_ln_common::KIND;
+        let mut dbtx = db.begin_transaction().await;
+        for (recorded_at, operation, msats) in [
+            (NOV_14 - 1, 1, 1),
+            (NOV_14, 2, 10),
+            (NOV_14 + SECS_PER_DAY - 1, u8::MAX, 100),
+            (NOV_14 + SECS_PER_DAY, 3, 1_000),
+        ] {
+            let (key, value) = entry(
+                recorded_at,
+                operation,
+                &ln,
+                RpcTransactionDirection::Send,
+                FediFeeLedgerEntryKind::Accrual,
+                msats,
+            );
+            dbtx.insert_entry(&key, &value).await;
+            dbtx.insert_entry(
+                &FediFeeLedgerKey(FediFeeStream::Guardian, key.1, key.2, key.3, key.4),
+                &FediFeeLedgerEntry {
+                    amount: Amount::from_msats(msats * 2),
+                    ..value
+                },
+            )
+            .await;
+        }
+
+        let statement = fedi_fee_statement(
+            &mut dbtx,
+            FediFeeStream::App,
+            NOV_14,
+            NOV_14 + SECS_PER_DAY,
+            RpcFeeStatementPeriod::Day,
+        )
+        .await;
+        assert_eq!(statement.total_accrued, RpcAmount(Amount::from_msats(110)));
+    }
+}
@@ -0,0 +1,454 @@
+//! Export of the transaction history of every joined federation into a single
+//! file that can be handed to an accountant or imported into another wallet.
+
+use anyhow::Context;
+use rpc_types::{
+    RpcFederationId, RpcOnchainDepositState, RpcOnchainWithdrawState, RpcOperationFediFeeStatus,
+    RpcTransactionDirection, RpcTransactionHistoryExport, RpcTransactionHistoryFormat,
+    RpcTransactionKind, RpcTransactionListEntry,
+};
+use serde::Serialize;
+use time::OffsetDateTime;
+use tracing::warn;
+
+use crate::Federations;
+use crate::federation_sm::FederationState;
+
+/// msats in one bitcoin, used to turn `btc_to_fiat_hundredths` into a value
+const MSATS_PER_BTC: u128 = 100_000_000_000;
+
+const CSV_HEADER: &[&str] = &[
+    "federation_id",
+    "federation_name",
+    "transaction_id",
+    "created_at",
+    "settled_at",
+    "kind",
+    "state",
+    "direction",
+    "amount_msat",
+    "fedi_app_fee_msat",
+    "fedi_guardian_fee_msat",
+    "network_fee_msat",
+    "fiat_code",
+    "fiat_value",
+    "reference",
+    "notes",
+    "sender_matrix_id",
+    "recipient_matrix_id",
+];
+
+struct HistoryRecord {
+    federation_id: String,
+    federation_name: String,
+    entry: RpcTransactionListEntry,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct JsonLinesRecord<'a> {
+    federation_id: &'a str,
+    federation_name: &'a str,
+    #[serde(flatten)]
+    entry: &'a RpcTransactionListEntry,
+}
+
+/// One line of a BIP-329 export.
+#[derive(Serialize)]
+struct Bip329Label<'a> {
+    #[serde(rename = "type")]
+    kind: &'static str,
+    #[serde(rename = "ref")]
+    reference: &'a str,
+    label: &'a str,
+}
+
+impl Federations {
+    /// Export the full transaction history of all ready federations, oldest
+    /// transaction first.
+    pub async fn export_transaction_history(
+        &self,
+        format: RpcTransactionHistoryFormat,
+    ) -> anyhow::Result<RpcTransactionHistoryExport> {
+        let mut records = vec![];
+        let mut skipped_federations = vec![];
+        let mut failed_count = 0;
+        for (federation_id, state) in self.get_federations_map() {
+            let FederationState::Ready(federation) = state else {
+                skipped_federations.push(RpcFederationId(federation_id));
+                continue;
+            };
+            let federation_name = federation.federation_name();
+            for entry in federation.list_transactions(usize::MAX, None).await {
+                match entry {
+                    Ok(entry) => records.push(HistoryRecord {
+                        federation_id: federation_id.clone(),
+                        federation_name: federation_name.clone(),
+                        entry,
+                    }),
+                    Err(error) => {
+                        warn!(%federation_id, %error, "skipping transaction in history export");
+                        failed_count += 1;
+                    }
+                }
+            }
+        }
+        records.sort_by_key(|record| record.entry.created_at);
+
+        let (content, exported_count) = render_history(format, &records)?;
+        Ok(RpcTransactionHistoryExport {
+            format,
+            content,
+            exported_count,
+            skipped_federations,
+            failed_count,
+        })
+    }
+}
+
+/// Render `records` in `format`, returning the file content and the number of
+/// transactions it contains.
+fn render_history(
+    format: RpcTransactionHistoryFormat,
+    records: &[HistoryRecord],
+) -> anyhow::Result<(String, u64)> {
+    let mut out = String::new();
+    let mut count = 0;
+    match format {
+        RpcTransactionHistoryFormat::Csv => {
+            push_csv_row(&mut out, CSV_HEADER.iter().map(|h| h.to_string()));
+            for record in records {
+                push_csv_row(&mut out, csv_row(record));
+                count += 1;
+            }
+        }
+        RpcTransactionHistoryFormat::JsonLines => {
+            for record in records {
+                let line = serde_json::to_string(&JsonLinesRecord {
+                    federation_id: &record.federation_id,
+                    federation_name: &record.federation_name,
+                    entry: &record.entry,
+                })
+                .context("failed to serialize transaction")?;
+                out.push_str(&line);
+                out.push('\n');
+                count += 1;
+            }
+        }
+        RpcTransactionHistoryFormat::Bip329 => {
+            for record in records {
+                let labels = bip329_labels(record);
+                if labels.is_empty() {
+                    continue;
+                }
+                let label = label_text(record);
+                for (kind, reference) in labels {
+                    let line = serde_json::to_string(&Bip329Label {
+                        kind,
+                        reference: &reference,
+                        label: &label,
+                    })
+                    .context("failed to serialize label")?;
+                    out.push_str(&line);
+                    out.push('\n');
+                }
+                count += 1;
+            }
+        }
+    }
+    Ok((out, count))
+}
+
+fn csv_row(record: &HistoryRecord) -> Vec<String> {
+    let transaction = &record.entry.transaction;
+    let kind = serde_json::to_value(&transaction.kind).unwrap_or_default();
+    let fiat_info = transaction.tx_date_fiat_info.as_ref();
+    let frontend_metadata = &transaction.frontend_metadata;
+    vec![
+        record.federation_id.clone(),
+        text_cell(&record.federation_name),
+        transaction.id.clone(),
+        format_unix_time(record.entry.created_at),
+        transaction
+            .outcome_time
+            .map(format_unix_time)
+            .unwrap_or_default(),
+        kind["kind"].as_str().unwrap_or_default().to_owned(),
+        kind["state"]["type"]
+            .as_str()
+            .unwrap_or_default()
+            .to_owned(),
+        match transaction.kind.direction() {
+            RpcTransactionDirection::Send => "send",
+            RpcTransactionDirection::Receive => "receive",
+        }
+        .to_owned(),
+        transaction.amount.0.msats.to_string(),
+        fee_cell(transaction.fedi_app_fee_status.as_ref()),
+        fee_cell(transaction.fedi_guardian_fee_status.as_ref()),
+        network_fee_msats(&transaction.kind).to_string(),
+        fiat_info
+            .map(|info| info.fiat_code.clone())
+            .unwrap_or_default(),
+        fiat_info
+            .map(|info| {
+                format_fiat_hundredths(transaction.amount.0.msats, info.btc_to_fiat_hundredths)
+            })
+            .unwrap_or_default(),
+        reference(&transaction.kind).unwrap_or_default(),
+        text_cell(transaction.txn_notes.as_deref().unwrap_or_default()),
+        text_cell(
+            frontend_metadata
+                .sender_matrix_id
+                .as_deref()
+                .unwrap_or_default(),
+        ),
+        text_cell(
+            frontend_metadata
+                .recipient_matrix_id
+                .as_deref()
+                .unwrap_or_default(),
+        ),
+    ]
+}
+
+pub(crate) fn push_csv_row(out: &mut String, cells: impl IntoIterator<Item = String>) {
+    for (i, cell) in cells.into_iter().enumerate() {
+        if i > 0 {
+            out.push(',');
+        }
+        if cell.contains([',', '"', '\n', '\r']) {
+            out.push('"');
+            out.push_str(&cell.replace('"', "\"\""));
+            out.push('"');
+        } else {
+            out.push_str(&cell);
+        }
+    }
+    out.push_str("\r\n");
+}
+
+/// Free text can come from other users (ecash notes, matrix ids), so keep
+/// spreadsheets from evaluating it as a formula.
+fn text_cell(text: &str) -> String {
+    if text.starts_with(['=', '+', '-', '@']) {
+        format!("'{text}")
+    } else {
+        text.to_owned()
+    }
+}
+
+/// Only fees that were actually taken (or are reserved for a send) have an
+/// amount; failed operations and pending receives leave the cell empty.
+fn fee_cell(status: Option<&RpcOperationFediFeeStatus>) -> String {
+    match status {
+        Some(
+            RpcOperationFediFeeStatus::PendingSend { fedi_fee }
+            | RpcOperationFediFeeStatus::Success { fedi_fee },
+        ) => fedi_fee.0.msats.to_string(),
+        _ => String::new(),
+    }
+}
+
+fn network_fee_msats(kind: &RpcTransactionKind) -> u64 {
+    match kind {
+        RpcTransactionKind::LnPay { lightning_fees, .. } => lightning_fees.0.msats,
+        RpcTransactionKind::OnchainWithdraw { onchain_fees, .. } => onchain_fees.0.msats,
+        RpcTransactionKind::OnchainDeposit { peg_in_fees, .. } => peg_in_fees.0.msats,
+        _ => 0,
+    }
+}
+
+/// The external identifier a user would recognise the transaction by.
+fn reference(kind: &RpcTransactionKind) -> Option<String> {
+    match kind {
+        RpcTransactionKind::LnPay { ln_invoice, .. }
+        | RpcTransactionKind::LnReceive { ln_invoice, .. } => Some(ln_invoice.clone()),
+        RpcTransactionKind::OnchainWithdraw {
+            state: Some(RpcOnchainWithdrawState::Succeeded { txid, .. }),
+            ..
+        } => Some(txid.clone()),
+        RpcTransactionKind::OnchainWithdraw {
+            onchain_address, ..
+        }
+        | RpcTransactionKind::OnchainDeposit {
+            onchain_address, ..
+        } => Some(onchain_address.clone()),
+        _ => None,
+    }
+}
+
+/// BIP-329 `(type, ref)` pairs for the parts of a transaction that are visible
+/// on the bitcoin chain. Everything else happens inside the federation and has
+/// nothing an external wallet could attach a label to.
+fn bip329_labels(record: &HistoryRecord) -> Vec<(&'static str, String)> {
+    match &record.entry.transaction.kind {
+        RpcTransactionKind::OnchainWithdraw {
+            state: Some(RpcOnchainWithdrawState::Succeeded { txid, .. }),
+            ..
+        } => vec![("tx", txid.clone())],
+        RpcTransactionKind::OnchainDeposit {
+            onchain_address,
+            state,
+            ..
+        } => {
+            let mut labels = vec![("addr", onchain_address.clone())];
+            if let Some(
+                RpcOnchainDepositState::WaitingForConfirmation(data)
+                | RpcOnchainDepositState::Confirmed(data)
+                | RpcOnchainDepositState::Claimed(data),
+            ) = state
+            {
+                labels.push(("tx", data.txid().to_owned()));
+            }
+            labels
+        }
+        _ => vec![],
+    }
+}
+
+fn label_text(record: &HistoryRecord) -> String {
+    let transaction = &record.entry.transaction;
+    if let Some(notes) = transaction
+        .txn_notes
+        .as_deref()
+        .or(transaction.frontend_metadata.initial_notes.as_deref())
+        .filter(|notes| !notes.is_empty())
+    {
+        return notes.to_owned();
+    }
+    match transaction.kind.direction() {
+        RpcTransactionDirection::Send => format!("Withdrawal from {}", record.federation_name),
+        RpcTransactionDirection::Receive => format!("Deposit to {}", record.federation_name),
+    }
+}
+
+pub(crate) fn format_unix_time(unix_seconds: u64) -> String {
+    let Some(dt) = i64::try_from(unix_seconds)
+        .ok()
+        .and_then(|secs| OffsetDateTime::from_unix_timestamp(secs).ok())
+    else {
+        return String::new();
+    };
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        dt.year(),
+        u8::from(dt.month()),
+        dt.day(),
+        dt.hour(),
+        dt.minute(),
+        dt.second()
+    )
+}
+
+/// Fiat value of `msats` at the rate recorded when the transaction happened,
+/// formatted with two decimals.
+fn format_fiat_hundredths(msats: u64, btc_to_fiat_hundredths: u64) -> String {
+    let hundredths = u128::from(msats) * u128::from(btc_to_fiat_hundredths) / MSATS_PER_BTC;
+    format!("{}.{:02}", hundredths / 100, hundredths % 100)
+}
+
+#[cfg(test)]
+mod tests {
+    use fedimint_core::Amount;
+    use rpc_types::{FrontendMetadata, RpcAmount, RpcTransaction};
+    use runtime::storage::state::FiatFXInfo;
+
+    use super::*;
+
+    fn record(kind: RpcTransactionKind, notes: Option<&str>) -> HistoryRecord {
+        HistoryRecord {
+            federation_id: "fed".to_owned(),
+            federation_name: "Test Fed".to_owned(),
+            entry: RpcTransactionListEntry {
+                created_at: 1_700_000_000,
+                transaction: RpcTransaction {
+                    id: "op".to_owned(),
+                    amount: RpcAmount(Amount::from_sats(50_000)),
+                    fedi_app_fee_status: Some(RpcOperationFediFeeStatus::Success {
+                        fedi_fee: RpcAmount(Amount::from_msats(1_000)),
+                    }),
+                    fedi_guardian_fee_status: None,
+                    fedi_app_fee_discount: None,
+                    txn_notes: notes.map(ToOwned::to_owned),
+                    tx_date_fiat_info: Some(FiatFXInfo {
+                        fiat_code: "USD".to_owned(),
+                        btc_to_fiat_hundredths: 6_000_000,
+                    }),
+                    frontend_metadata: FrontendMetadata::default(),
+                    kind,
+                    outcome_time: None,
+                    unified_receive: None,
+                    federation_transfer: None,
+                },
+            },
+        }
+    }
+
+    fn withdraw(state: Option<RpcOnchainWithdrawState>) -> RpcTransactionKind {
+        RpcTransactionKind::OnchainWithdraw {
+            onchain_address: "bc1qaddress".to_owned(),
+            onchain_fees: RpcAmount(Amount::from_sats(300)),
+            onchain_fee_rate: 2,
+            state,
+        }
+    }
+
+    #[test]
+    fn csv_escapes_and_guards_free_text() {
+        let (csv, count) = render_history(
+            RpcTransactionHistoryFormat::Csv,
+            &[record(withdraw(None), Some("=cmd, \"quoted\""))],
+        )
+        .unwrap();
+        assert_eq!(count, 1);
+        let row = csv.lines().nth(1).unwrap();
+        assert!(row.starts_with("fed,Test Fed,op,2023-11-14T22:13:20Z,,onchainWithdraw,,send,"));
+        assert!(row.contains(",50000000,1000,,300000,USD,30.00,bc1qaddress,"));
+        assert!(row.contains(",\"'=cmd, \"\"quoted\"\"\",,"));
+    }
+
+    #[test]
+    fn bip329_only_labels_onchain_references() {
+        let txid = "f".repeat(64);
+        let (labels, count) = render_history(
+            RpcTransactionHistoryFormat::Bip329,
+            &[
+                record(
+                    withdraw(Some(RpcOnchainWithdrawState::Succeeded {
+                        txid: txid.clone(),
+                        confirmations: Some(3),
+                    })),
+                    None,
+                ),
+                record(withdraw(None), Some("not broadcast yet")),
+                record(
+                    RpcTransactionKind::OobReceive { state: None },
+                    Some("ecash"),
+                ),
+            ],
+        )
+        .unwrap();
+        assert_eq!(count, 1);
+        assert_eq!(
+            labels,
+            format!(
+                "{{\"type\":\"tx\",\"ref\":\"{txid}\",\"label\":\"Withdrawal from Test Fed\"}}\n"
+            )
+        );
+    }
+
+    #[test]
+    fn json_lines_include_federation() {
+        let (lines, count) = render_history(
+            RpcTransactionHistoryFormat::JsonLines,
+            &[record(RpcTransactionKind::OobReceive { state: None }, None)],
+        )
+        .unwrap();
+        assert_eq!(count, 1);
+        let value: serde_json::Value = serde_json::from_str(lines.trim_end()).unwrap();
+        assert_eq!(value["federationId"], "fed");
+        assert_eq!(value["kind"], "oobReceive");
+        assert_eq!(value["txDateFiatInfo"]["fiatCode"], "USD");
+    }
+}
@@ -5,11 +5,12 @@
 use std::collections::{BTreeMap, HashMap};
 use std::str::FromStr;
 use std::sync::{Arc, Mutex};
-use std::time::Duration;
+use std::time::{Duration, SystemTime};
 
 use anyhow::{Context, bail};
 use bitcoin::Network;
 use device_registration::DeviceRegistrationService;
+use federation_evacuation::Evacuations;
 use federation_sm::{FederationState, FederationStateMachine};
 use federation_v2::{FederationPrefetchedInfo, FederationV2};
 use federations_locker::FederationsLocker;
@@ -27,11 +28,19 @@ use tracing::{error, warn};
 
 use crate::federation_v2::{MultispendNotifications, SptNotifications};
 use crate::fedi_fee::FediFeeHelper;
+use crate::lnurl::LnurlClient;
 
+mod end_of_life;
+mod federation_evacuation;
 pub mod federation_sm;
+mod federation_transfers;
 pub mod federation_v2;
 pub mod federations_locker;
 pub mod fedi_fee;
+mod history_export;
+pub mod lnurl;
+mod payment_string;
+mod spending_limits;
 
 pub struct Federations {
     runtime: Arc<Runtime>,
@@ -42,6 +51,10 @@ pub struct Federations {
     spt_notifications: Arc<dyn SptNotifications>,
     device_registration_service: Arc<DeviceRegistrationService>,
     last_federation_preview_info: Mutex<Option<FederationPrefetchedInfo>>,
+    lnurl_client: LnurlClient,
+    evacuations: Evacuations,
+    /// See [`Federations::unlock_spending_limits`].
+    spending_limits_unlocked_until: Mutex<Option<SystemTime>>,
 }
 
 impl Federations {
@@ -60,6 +73,9 @@ impl Federations {
             spt_notifications,
             device_registration_service,
             last_federation_preview_info: Mutex::new(None),
+            lnurl_client: LnurlClient::default(),
+            evacuations: Evacuations::default(),
+            spending_limits_unlocked_until: Mutex::new(None),
         }
     }
 
@@ -108,11 +124,14 @@ impl Federations {
         }
         drop(federations);
 
+        let this = self.clone();
         self.runtime
             .task_group
             .clone()
             .spawn_cancellable("load federations", async move {
                 futures::future::join_all(futures).await;
+                this.resume_federation_transfers().await;
+                this.watch_end_of_life().await;
             });
 
         let this = self.clone();
@@ -0,0 +1,249 @@
+//! Client side of the LNURL protocol family: resolving what the user scanned
+//! or typed to the URL of an LNURL service, and talking to that service.
+
+mod pay;
+mod withdraw;
+
+use anyhow::{Context as _, bail, ensure};
+use bitcoin::bech32;
+pub use pay::{LnurlPayInvoice, LnurlPayParams};
+use rpc_types::error::ErrorCode;
+use serde::de::DeserializeOwned;
+use url::{Host, Url};
+pub use withdraw::LnurlWithdrawParams;
+
+/// Human readable part of bech32 encoded LNURLs (LUD-01).
+const LNURL_HRP: &str = "lnurl";
+
+/// URI schemes LNURLs may be shared with instead of bech32 (LUD-17).
+const LUD17_SCHEMES: &[&str] = &["lnurlp", "lnurlw", "lnurlc", "keyauth"];
+
+#[derive(Clone, Debug, Default)]
+pub struct LnurlClient {
+    reqwest: reqwest::Client,
+}
+
+impl LnurlClient {
+    /// GET an LNURL endpoint. Services report failures as
+    /// `{"status": "ERROR", "reason": ...}` (LUD-01), often with a non-2xx
+    /// status, so the body is checked for that before the status.
+    async fn get_json<T: DeserializeOwned>(&self, url: Url) -> anyhow::Result<T> {
+        let domain = url.host_str().unwrap_or_default().to_owned();
+        let response = self
+            .reqwest
+            .get(url)
+            .send()
+            .await
+            .with_context(|| format!("failed to reach LNURL service {domain}"))?;
+        let status = response.status();
+        let body = response
+            .json::<serde_json::Value>()
+            .await
+            .with_context(|| format!("LNURL service {domain} responded with status {status}"))?;
+        if body
+            .get("status")
+            .and_then(|status| status.as_str())
+            .is_some_and(|status| status.eq_ignore_ascii_case("ERROR"))
+        {
+            let reason = body
+                .get("reason")
+                .and_then(|reason| reason.as_str())
+                .unwrap_or("no reason given");
+            bail!(ErrorCode::LnurlServiceError(reason.to_owned()));
+        }
+        ensure!(
+            status.is_success(),
+            "LNURL service {domain} responded with status {status}"
+        );
+        serde_json::from_value(body)
+            .with_context(|| format!("unexpected response from LNURL service {domain}"))
+    }
+}
+
+/// Resolves user input to the URL of an LNURL service. Accepts bech32
+/// `LNURL1...` strings (LUD-01), `lnurlp://` style links (LUD-17) and
+/// `user@domain` lightning addresses (LUD-16), with or without a
+/// `lightning:` prefix.
+pub fn parse_lnurl(input: &str) -> anyhow::Result<Url> {
+    parse_lnurl_inner(input).context(ErrorCode::InvalidLnurl)
+}
+
+fn parse_lnurl_inner(input: &str) -> anyhow::Result<Url> {
+    let input = input.trim();
+    let input = match input.get(..10) {
+        Some(prefix) if prefix.eq_ignore_ascii_case("lightning:") => &input[10..],
+        _ => input,
+    };
+
+    if input
+        .get(..LNURL_HRP.len() + 1)
+        .is_some_and(|prefix| prefix.eq_ignore_ascii_case("lnurl1"))
+    {
+        return decode_bech32_lnurl(input);
+    }
+
+    if let Some((scheme, rest)) = input.split_once("://") {
+        let scheme = scheme.to_ascii_lowercase();
+        if LUD17_SCHEMES.contains(&scheme.as_str()) {
+            return Ok(with_transport_scheme(Url::parse(&format!(
+                "https://{rest}"
+            ))?));
+        }
+        if scheme == "https" || scheme == "http" {
+            // Plain web links may carry the LNURL in a `lightning` query
+            // parameter so they also open in a browser (LUD-01).
+            let url = Url::parse(input)?;
+            if let Some((_, lnurl)) = url
+                .query_pairs()
+                .find(|(key, _)| key.eq_ignore_ascii_case("lightning"))
+            {
+                return decode_bech32_lnurl(&lnurl);
+            }
+            bail!("web link without an LNURL");
+        }
+        bail!("unsupported scheme {scheme}");
+    }
+
+    if let Some((user, domain)) = input.split_once('@') {
+        return lightning_address_url(user, domain);
+    }
+
+    bail!("not an LNURL or lightning address")
+}
+
+fn decode_bech32_lnurl(input: &str) -> anyhow::Result<Url> {
+    let (hrp, data) = bech32::decode(&input.to_lowercase())?;
+    ensure!(hrp.to_string() == LNURL_HRP, "unexpected hrp: {hrp}");
+    let url = Url::parse(&String::from_utf8(data)?)?;
+    ensure_secure_url(&url)?;
+    Ok(url)
+}
+
+/// `user@domain` maps to `https://domain/.well-known/lnurlp/user` (LUD-16).
+fn lightning_address_url(user: &str, domain: &str) -> anyhow::Result<Url> {
+    let user = user.to_lowercase();
+    ensure!(
+        !user.is_empty()
+            && user
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.' | '+')),
+        "invalid lightning address username"
+    );
+    ensure!(
+        !domain.is_empty() && !domain.contains(['/', '?', '#', '@']),
+        "invalid lightning address domain"
+    );
+    let url = Url::parse(&format!("https://{domain}/.well-known/lnurlp/{user}"))?;
+    Ok(with_transport_scheme(url))
+}
+
+/// LNURL services must be served over https, except onion services which
+/// are reached over plain http. Loopback hosts are let through as well so
+/// the flows can be exercised against a local service.
+fn allows_plain_http(url: &Url) -> bool {
+    match url.host() {
+        Some(Host::Domain(domain)) => domain.ends_with(".onion") || domain == "localhost",
+        Some(Host::Ipv4(ip)) => ip.is_loopback(),
+        Some(Host::Ipv6(ip)) => ip.is_loopback(),
+        None => false,
+    }
+}
+
+/// Picks http or https for an https URL built from a LUD-16/17 input.
+fn with_transport_scheme(mut url: Url) -> Url {
+    if allows_plain_http(&url) {
+        url.set_scheme("http")
+            .expect("switching between http and https is always allowed");
+    }
+    url
+}
+
+/// Checks decoded LNURLs and the URLs services hand back against the same
+/// transport rules.
+fn ensure_secure_url(url: &Url) -> anyhow::Result<()> {
+    ensure!(
+        url.scheme() == "https" || (url.scheme() == "http" && allows_plain_http(url)),
+        "insecure LNURL url: {url}"
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_bech32_lnurl() {
+        // test vector from LUD-01
+        let lnurl = "LNURL1DP68GURN8GHJ7UM9WFMXJCM99E3K7MF0V9CXJ0M385EKVCENXC6R2C35XVUKXEFCV5MKVV34X5EKZD3EV56NYD3HXQURZEPEXEJXXEPNXSCRVWFNV9NXZCN9XQ6XYEFHVGCXXCMYXYMNSERXFQ5FNS";
+        let expected = "https://service.com/api?q=3fc3645b439ce8e7f2553a69e5267081d96dcd340693afabe04be7b0ccd178df";
+
+        assert_eq!(parse_lnurl(lnurl).unwrap().as_str(), expected);
+        assert_eq!(
+            parse_lnurl(&lnurl.to_lowercase()).unwrap().as_str(),
+            expected
+        );
+        assert_eq!(
+            parse_lnurl(&format!("lightning:{lnurl}")).unwrap().as_str(),
+            expected
+        );
+        assert_eq!(
+            parse_lnurl(&format!("https://example.com/pay?lightning={lnurl}"))
+                .unwrap()
+                .as_str(),
+            expected
+        );
+    }
+
+    #[test]
+    fn test_parse_lud17_and_lightning_address() {
+        assert_eq!(
+            parse_lnurl("lnurlp://service.com/api?q=1")
+                .unwrap()
+                .as_str(),
+            "https://service.com/api?q=1"
+        );
+        assert_eq!(
+            parse_lnurl("lnurlw://abcdef.onion/withdraw")
+                .unwrap()
+                .as_str(),
+            "http://abcdef.onion/withdraw"
+        );
+        assert_eq!(
+            parse_lnurl("Alice@Example.com").unwrap().as_str(),
+            "https://example.com/.well-known/lnurlp/alice"
+        );
+        assert_eq!(
+            parse_lnurl("lightning:bob@127.0.0.1:8080")
+                .unwrap()
+                .as_str(),
+            "http://127.0.0.1:8080/.well-known/lnurlp/bob"
+        );
+    }
+
+    #[test]
+    fn test_parse_lnurl_rejects_invalid_input() {
+        let plain_http = bech32::encode::<bech32::Bech32>(
+            bech32::Hrp::parse(LNURL_HRP).unwrap(),
+            b"http://service.com/api",
+        )
+        .unwrap();
+
+        for input in [
+            "",
+            "lnbc1",
+            "https://service.com/api",
+            "ftp://service.com",
+            "al ice@example.com",
+            "alice@example.com/path",
+            plain_http.as_str(),
+        ] {
+            let error = parse_lnurl(input).unwrap_err();
+            assert_eq!(
+                error.downcast_ref::<ErrorCode>(),
+                Some(&ErrorCode::InvalidLnurl),
+                "{input}"
+            );
+        }
+    }
+}
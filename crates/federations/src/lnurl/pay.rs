@@ -0,0 +1,533 @@
+//! LNURL-pay (LUD-06) and its extensions: comments (LUD-12), payer data
+//! (LUD-18) and success actions (LUD-09, LUD-10).
+
+use aes::Aes256;
+use anyhow::{Context as _, anyhow, bail, ensure};
+use base64::Engine as _;
+use base64::engine::general_purpose::STANDARD;
+use bitcoin::hashes::{Hash as _, sha256};
+use cbc::cipher::block_padding::Pkcs7;
+use cbc::cipher::{BlockDecryptMut as _, KeyIvInit as _};
+use fedimint_core::Amount;
+use lightning_invoice::{Bolt11Invoice, Bolt11InvoiceDescriptionRef};
+use rpc_types::error::ErrorCode;
+use rpc_types::lnurl::{
+    RpcLnurlPayParams, RpcLnurlPayResponse, RpcLnurlPayerData, RpcLnurlPayerDataField,
+    RpcLnurlPayerDataRequest, RpcLnurlSuccessAction,
+};
+use rpc_types::{FrontendMetadata, RpcAmount, RpcPayInvoiceResponse};
+use serde::Deserialize;
+use tracing::warn;
+use url::Url;
+
+use super::{LnurlClient, ensure_secure_url, parse_lnurl};
+use crate::Federations;
+
+const PAY_REQUEST_TAG: &str = "payRequest";
+
+type Aes256CbcDec = cbc::Decryptor<Aes256>;
+
+/// First response of an LNURL-pay service.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LnurlPayParams {
+    callback: Url,
+    min_sendable: u64,
+    max_sendable: u64,
+    /// JSON encoded array of `[mime type, content]` pairs, kept as the exact
+    /// string the service sent since invoices commit to its hash.
+    metadata: String,
+    tag: String,
+    comment_allowed: Option<u32>,
+    payer_data: Option<PayerDataSpec>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+struct PayerDataSpec {
+    name: Option<RpcLnurlPayerDataField>,
+    pubkey: Option<RpcLnurlPayerDataField>,
+    identifier: Option<RpcLnurlPayerDataField>,
+    email: Option<RpcLnurlPayerDataField>,
+    auth: Option<RpcLnurlPayerDataField>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct PayInvoiceResponse {
+    pr: String,
+    success_action: Option<serde_json::Value>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(tag = "tag", rename_all = "camelCase")]
+enum SuccessAction {
+    Message {
+        message: String,
+    },
+    Url {
+        description: String,
+        url: Url,
+    },
+    Aes {
+        description: String,
+        ciphertext: String,
+        iv: String,
+    },
+}
+
+/// Invoice handed out by the callback of an LNURL-pay service, already
+/// checked against what was asked for.
+#[derive(Debug)]
+pub struct LnurlPayInvoice {
+    pub invoice: Bolt11Invoice,
+    success_action: Option<SuccessAction>,
+}
+
+impl LnurlPayParams {
+    fn metadata_entries(&self) -> anyhow::Result<Vec<(String, String)>> {
+        let entries: Vec<Vec<serde_json::Value>> =
+            serde_json::from_str(&self.metadata).context("invalid LNURL-pay metadata")?;
+        Ok(entries
+            .into_iter()
+            .filter_map(|entry| match entry.as_slice() {
+                [
+                    serde_json::Value::String(mime),
+                    serde_json::Value::String(content),
+                    ..,
+                ] => Some((mime.clone(), content.clone())),
+                _ => None,
+            })
+            .collect())
+    }
+
+    fn metadata_entry(&self, mime: &str) -> anyhow::Result<Option<String>> {
+        Ok(self
+            .metadata_entries()?
+            .into_iter()
+            .find_map(|(entry_mime, content)| (entry_mime == mime).then_some(content)))
+    }
+
+    fn validate(&self) -> anyhow::Result<()> {
+        ensure!(self.tag == PAY_REQUEST_TAG, "not an LNURL-pay service");
+        ensure!(
+            0 < self.min_sendable && self.min_sendable <= self.max_sendable,
+            "LNURL-pay service has an invalid amount range"
+        );
+        ensure!(
+            self.metadata_entry("text/plain")?.is_some(),
+            "LNURL-pay metadata is missing a description"
+        );
+        ensure_secure_url(&self.callback)?;
+        if let Some(payer_data) = &self.payer_data {
+            // We have no linking key to offer for LUD-18 `pubkey`/`auth`.
+            ensure!(
+                !payer_data.pubkey.is_some_and(|field| field.mandatory)
+                    && !payer_data.auth.is_some_and(|field| field.mandatory),
+                "LNURL-pay service requires payer data that is not supported"
+            );
+        }
+        Ok(())
+    }
+
+    pub fn to_rpc(&self) -> anyhow::Result<RpcLnurlPayParams> {
+        Ok(RpcLnurlPayParams {
+            domain: self.callback.host_str().unwrap_or_default().to_owned(),
+            min_sendable: RpcAmount(Amount::from_msats(self.min_sendable)),
+            max_sendable: RpcAmount(Amount::from_msats(self.max_sendable)),
+            description: self.metadata_entry("text/plain")?.unwrap_or_default(),
+            long_description: self.metadata_entry("text/long-desc")?,
+            identifier: match self.metadata_entry("text/identifier")? {
+                Some(identifier) => Some(identifier),
+                None => self.metadata_entry("text/email")?,
+            },
+            comment_allowed: self.comment_allowed.unwrap_or(0),
+            payer_data: self
+                .payer_data
+                .as_ref()
+                .map(|payer_data| RpcLnurlPayerDataRequest {
+                    name: payer_data.name,
+                    identifier: payer_data.identifier,
+                    email: payer_data.email,
+                }),
+        })
+    }
+
+    /// Builds the LUD-18 `payerdata` object out of the fields the service
+    /// asked for, or `None` if it asked for none.
+    fn payer_data_json(&self, payer_data: &RpcLnurlPayerData) -> anyhow::Result<Option<String>> {
+        let Some(spec) = &self.payer_data else {
+            return Ok(None);
+        };
+        let mut object = serde_json::Map::new();
+        for (key, field, value) in [
+            ("name", spec.name, &payer_data.name),
+            ("identifier", spec.identifier, &payer_data.identifier),
+            ("email", spec.email, &payer_data.email),
+        ] {
+            let Some(field) = field else {
+                continue;
+            };
+            match value {
+                Some(value) => {
+                    object.insert(key.to_owned(), serde_json::Value::String(value.clone()));
+                }
+                None if field.mandatory => bail!("LNURL-pay service requires payer {key}"),
+                None => {}
+            }
+        }
+        if object.is_empty() {
+            return Ok(None);
+        }
+        Ok(Some(serde_json::Value::Object(object).to_string()))
+    }
+}
+
+impl LnurlClient {
+    pub async fn fetch_pay_params(&self, lnurl: &str) -> anyhow::Result<LnurlPayParams> {
+        let params: LnurlPayParams = self.get_json(parse_lnurl(lnurl)?).await?;
+        params.validate()?;
+        Ok(params)
+    }
+
+    /// Asks the service's callback for an invoice and checks it before
+    /// anything gets paid.
+    pub async fn request_pay_invoice(
+        &self,
+        params: &LnurlPayParams,
+        amount: Amount,
+        comment: Option<&str>,
+        payer_data: &RpcLnurlPayerData,
+    ) -> anyhow::Result<LnurlPayInvoice> {
+        ensure!(
+            (params.min_sendable..=params.max_sendable).contains(&amount.msats),
+            "amount must be between {} and {}",
+            Amount::from_msats(params.min_sendable),
+            Amount::from_msats(params.max_sendable),
+        );
+        let comment = comment.filter(|comment| !comment.is_empty());
+        if let Some(comment) = comment {
+            let comment_allowed = params.comment_allowed.unwrap_or(0);
+            ensure!(
+                comment.chars().count() <= comment_allowed as usize,
+                "comment is longer than the {comment_allowed} characters the service accepts"
+            );
+        }
+        let payer_data_json = params.payer_data_json(payer_data)?;
+
+        let mut callback = params.callback.clone();
+        {
+            let mut query = callback.query_pairs_mut();
+            query.append_pair("amount", &amount.msats.to_string());
+            if let Some(comment) = comment {
+                query.append_pair("comment", comment);
+            }
+            if let Some(payer_data_json) = &payer_data_json {
+                query.append_pair("payerdata", payer_data_json);
+            }
+        }
+
+        let response: PayInvoiceResponse = self.get_json(callback).await?;
+        let invoice: Bolt11Invoice = response
+            .pr
+            .trim()
+            .parse()
+            .context(ErrorCode::InvalidInvoice)?;
+        check_pay_invoice(&invoice, params, amount, payer_data_json.as_deref())?;
+
+        let success_action = match response.success_action {
+            None | Some(serde_json::Value::Null) => None,
+            Some(success_action) => match serde_json::from_value(success_action) {
+                Ok(success_action) => Some(success_action),
+                Err(error) => {
+                    // Unsupported success actions are ignored (LUD-09).
+                    warn!(%error, "ignoring unsupported LNURL-pay success action");
+                    None
+                }
+            },
+        };
+        if let Some(SuccessAction::Url { url, .. }) = &success_action {
+            ensure_secure_url(url)?;
+            ensure!(
+                url.host_str() == params.callback.host_str(),
+                "LNURL-pay success action links to a different domain"
+            );
+        }
+
+        Ok(LnurlPayInvoice {
+            invoice,
+            success_action,
+        })
+    }
+}
+
+/// The invoice must be for exactly the requested amount and commit to the
+/// hash of the metadata (plus the payer data, per LUD-18) as LUD-06 requires.
+fn check_pay_invoice(
+    invoice: &Bolt11Invoice,
+    params: &LnurlPayParams,
+    amount: Amount,
+    payer_data_json: Option<&str>,
+) -> anyhow::Result<()> {
+    ensure!(
+        invoice.amount_milli_satoshis() == Some(amount.msats),
+        "LNURL-pay service returned an invoice for the wrong amount"
+    );
+    let Bolt11InvoiceDescriptionRef::Hash(hash) = invoice.description() else {
+        bail!("LNURL-pay invoice has no description hash");
+    };
+    let metadata_hash = sha256::Hash::hash(params.metadata.as_bytes());
+    let payer_data_hash = payer_data_json.map(|payer_data_json| {
+        sha256::Hash::hash(format!("{}{payer_data_json}", params.metadata).as_bytes())
+    });
+    ensure!(
+        hash.0 == metadata_hash || Some(hash.0) == payer_data_hash,
+        "LNURL-pay invoice description hash does not match the metadata"
+    );
+    Ok(())
+}
+
+impl SuccessAction {
+    fn into_rpc(self, preimage: &[u8]) -> anyhow::Result<RpcLnurlSuccessAction> {
+        Ok(match self {
+            SuccessAction::Message { message } => RpcLnurlSuccessAction::Message { message },
+            SuccessAction::Url { description, url } => RpcLnurlSuccessAction::Url {
+                description,
+                url: url.to_string(),
+            },
+            SuccessAction::Aes {
+                description,
+                ciphertext,
+                iv,
+            } => RpcLnurlSuccessAction::Aes {
+                description,
+                plaintext: decrypt_aes_success_action(preimage, &ciphertext, &iv)?,
+            },
+        })
+    }
+}
+
+/// LUD-10: AES-256-CBC with PKCS7 padding, keyed by the payment preimage.
+fn decrypt_aes_success_action(
+    preimage: &[u8],
+    ciphertext: &str,
+    iv: &str,
+) -> anyhow::Result<String> {
+    let mut buf = STANDARD.decode(ciphertext).context("invalid ciphertext")?;
+    let iv = STANDARD.decode(iv).context("invalid iv")?;
+    let plaintext = Aes256CbcDec::new_from_slices(preimage, &iv)
+        .map_err(|_| anyhow!("invalid preimage or iv length"))?
+        .decrypt_padded_mut::<Pkcs7>(&mut buf)
+        .map_err(|_| anyhow!("failed to decrypt success action"))?;
+    Ok(String::from_utf8(plaintext.to_vec())?)
+}
+
+impl Federations {
+    /// Resolves an LNURL-pay link or lightning address to what the service
+    /// accepts, without paying anything.
+    pub async fn preview_lnurl_pay(&self, lnurl: &str) -> anyhow::Result<RpcLnurlPayParams> {
+        self.lnurl_client.fetch_pay_params(lnurl).await?.to_rpc()
+    }
+
+    /// Pays an LNURL-pay link or lightning address from the given
+    /// federation. The invoice goes through the regular lightning send path
+    /// so Fedi fees and gateway selection apply as usual.
+    pub async fn pay_lnurl(
+        &self,
+        federation_id: &str,
+        lnurl: &str,
+        amount: Amount,
+        comment: Option<String>,
+        payer_data: RpcLnurlPayerData,
+        frontend_metadata: FrontendMetadata,
+    ) -> anyhow::Result<RpcLnurlPayResponse> {
+        let federation = self.get_federation(federation_id)?;
+        let params = self.lnurl_client.fetch_pay_params(lnurl).await?;
+        let LnurlPayInvoice {
+            invoice,
+            success_action,
+        } = self
+            .lnurl_client
+            .request_pay_invoice(&params, amount, comment.as_deref(), &payer_data)
+            .await?;
+
+        let RpcPayInvoiceResponse { preimage } = federation
+            .pay_invoice(&invoice, None, frontend_metadata)
+            .await?;
+
+        // The payment went through at this point, so a success action we
+        // can't render must not turn it into an error.
+        let success_action = success_action.and_then(|success_action| {
+            let preimage = hex::decode(&preimage).ok()?;
+            success_action
+                .into_rpc(&preimage)
+                .inspect_err(|error| warn!(%error, "failed to process LNURL-pay success action"))
+                .ok()
+        });
+
+        Ok(RpcLnurlPayResponse {
+            invoice: invoice.to_string(),
+            preimage,
+            success_action,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bitcoin::secp256k1::{Secp256k1, SecretKey};
+    use cbc::cipher::BlockEncryptMut as _;
+    use lightning_invoice::{
+        Bolt11InvoiceDescription, Currency, Description, InvoiceBuilder, PaymentSecret, Sha256,
+    };
+
+    use super::*;
+
+    fn pay_params(payer_data: Option<PayerDataSpec>) -> LnurlPayParams {
+        LnurlPayParams {
+            callback: Url::parse("https://service.com/callback").unwrap(),
+            min_sendable: 1_000,
+            max_sendable: 1_000_000,
+            metadata: r#"[["text/plain","coffee"],["text/identifier","alice@service.com"]]"#
+                .to_owned(),
+            tag: PAY_REQUEST_TAG.to_owned(),
+            comment_allowed: Some(32),
+            payer_data,
+        }
+    }
+
+    #[test]
+    fn test_pay_params_to_rpc() {
+        let params = pay_params(None);
+        params.validate().unwrap();
+        let rpc = params.to_rpc().unwrap();
+        assert_eq!(rpc.domain, "service.com");
+        assert_eq!(rpc.description, "coffee");
+        assert_eq!(rpc.identifier.as_deref(), Some("alice@service.com"));
+        assert_eq!(rpc.long_description, None);
+        assert_eq!(rpc.comment_allowed, 32);
+
+        let mut withdraw = pay_params(None);
+        withdraw.tag = "withdrawRequest".to_owned();
+        assert!(withdraw.validate().is_err());
+
+        let auth = pay_params(Some(PayerDataSpec {
+            auth: Some(RpcLnurlPayerDataField { mandatory: true }),
+            ..Default::default()
+        }));
+        assert!(auth.validate().is_err());
+    }
+
+    fn invoice(amount_msats: u64, description: Bolt11InvoiceDescription) -> Bolt11Invoice {
+        let secp = Secp256k1::new();
+        let key = SecretKey::from_slice(&[42; 32]).unwrap();
+        InvoiceBuilder::new(Currency::Regtest)
+            .invoice_description(description)
+            .amount_milli_satoshis(amount_msats)
+            .payment_hash(sha256::Hash::hash(&[0; 32]))
+            .payment_secret(PaymentSecret([0; 32]))
+            .current_timestamp()
+            .min_final_cltv_expiry_delta(18)
+            .build_signed(|hash| secp.sign_ecdsa_recoverable(hash, &key))
+            .unwrap()
+    }
+
+    #[test]
+    fn test_check_pay_invoice_requires_metadata_hash() {
+        let params = pay_params(None);
+        let amount = Amount::from_msats(21_000);
+        let metadata_hash = sha256::Hash::hash(params.metadata.as_bytes());
+
+        let hashed = invoice(
+            21_000,
+            Bolt11InvoiceDescription::Hash(Sha256(metadata_hash)),
+        );
+        check_pay_invoice(&hashed, &params, amount, None).unwrap();
+        assert!(check_pay_invoice(&hashed, &params, Amount::from_msats(42_000), None).is_err());
+
+        let other_hash = invoice(
+            21_000,
+            Bolt11InvoiceDescription::Hash(Sha256(sha256::Hash::hash(b"something else"))),
+        );
+        assert!(check_pay_invoice(&other_hash, &params, amount, None).is_err());
+
+        // the inline description matching the metadata is not enough
+        let inline = invoice(
+            21_000,
+            Bolt11InvoiceDescription::Direct(Description::new(params.metadata.clone()).unwrap()),
+        );
+        assert!(check_pay_invoice(&inline, &params, amount, None).is_err());
+    }
+
+    #[test]
+    fn test_payer_data_json() {
+        let params = pay_params(Some(PayerDataSpec {
+            name: Some(RpcLnurlPayerDataField { mandatory: true }),
+            email: Some(RpcLnurlPayerDataField { mandatory: false }),
+            ..Default::default()
+        }));
+
+        assert!(
+            params
+                .payer_data_json(&RpcLnurlPayerData::default())
+                .is_err()
+        );
+
+        // identifier was not asked for and is not sent
+        let payer_data = RpcLnurlPayerData {
+            name: Some("Alice".to_owned()),
+            identifier: Some("alice@fedi.xyz".to_owned()),
+            email: None,
+        };
+        assert_eq!(
+            params.payer_data_json(&payer_data).unwrap().as_deref(),
+            Some(r#"{"name":"Alice"}"#)
+        );
+        assert_eq!(pay_params(None).payer_data_json(&payer_data).unwrap(), None);
+    }
+
+    #[test]
+    fn test_decrypt_aes_success_action() {
+        let preimage = [7u8; 32];
+        let iv = [3u8; 16];
+        let message = b"voucher code: 1234";
+
+        let mut buf = [0u8; 32];
+        buf[..message.len()].copy_from_slice(message);
+        let ciphertext = cbc::Encryptor::<Aes256>::new_from_slices(&preimage, &iv)
+            .unwrap()
+            .encrypt_padded_mut::<Pkcs7>(&mut buf, message.len())
+            .unwrap()
+            .to_vec();
+
+        let success_action = SuccessAction::Aes {
+            description: "your voucher".to_owned(),
+            ciphertext: STANDARD.encode(ciphertext),
+            iv: STANDARD.encode(iv),
+        };
+        assert_eq!(
+            success_action.clone().into_rpc(&preimage).unwrap(),
+            RpcLnurlSuccessAction::Aes {
+                description: "your voucher".to_owned(),
+                plaintext: "voucher code: 1234".to_owned(),
+            }
+        );
+        // the key is the 32 byte preimage
+        assert!(success_action.into_rpc(&preimage[..16]).is_err());
+    }
+
+    #[test]
+    fn test_parse_success_action() {
+        let action: SuccessAction = serde_json::from_str(
+            r#"{"tag":"url","description":"receipt","url":"https://service.com/r/1"}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            action,
+            SuccessAction::Url {
+                description: "receipt".to_owned(),
+                url: Url::parse("https://service.com/r/1").unwrap(),
+            }
+        );
+        assert!(serde_json::from_str::<SuccessAction>(r#"{"tag":"unknown"}"#).is_err());
+    }
+}
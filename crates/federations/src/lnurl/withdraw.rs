@@ -0,0 +1,219 @@
+//! LNURL-withdraw (LUD-03): vouchers, faucets and ATMs paying an invoice we
+//! hand them.
+
+use anyhow::ensure;
+use fedimint_core::Amount;
+use rpc_types::lnurl::RpcLnurlWithdrawParams;
+use rpc_types::{FrontendMetadata, RpcAmount, RpcReceiveSource};
+use serde::Deserialize;
+use url::Url;
+
+use super::{LnurlClient, ensure_secure_url, parse_lnurl};
+use crate::Federations;
+
+const WITHDRAW_REQUEST_TAG: &str = "withdrawRequest";
+
+/// First response of an LNURL-withdraw service.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LnurlWithdrawParams {
+    callback: Url,
+    k1: String,
+    #[serde(default)]
+    default_description: String,
+    min_withdrawable: u64,
+    max_withdrawable: u64,
+    tag: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct CallbackResponse {
+    status: String,
+}
+
+impl LnurlWithdrawParams {
+    fn validate(&self) -> anyhow::Result<()> {
+        ensure!(
+            self.tag == WITHDRAW_REQUEST_TAG,
+            "not an LNURL-withdraw service"
+        );
+        ensure!(
+            self.min_withdrawable <= self.max_withdrawable,
+            "LNURL-withdraw service has an invalid amount range"
+        );
+        ensure_secure_url(&self.callback)?;
+        Ok(())
+    }
+
+    pub fn to_rpc(&self) -> RpcLnurlWithdrawParams {
+        RpcLnurlWithdrawParams {
+            domain: self.domain(),
+            min_withdrawable: RpcAmount(Amount::from_msats(self.min_withdrawable)),
+            max_withdrawable: RpcAmount(Amount::from_msats(self.max_withdrawable)),
+            default_description: self.default_description.clone(),
+        }
+    }
+
+    fn domain(&self) -> String {
+        self.callback.host_str().unwrap_or_default().to_owned()
+    }
+
+    /// Amount to create the invoice for: what the caller asked for, or all
+    /// that is on offer. Invoices are created for whole sats, so the amount
+    /// is rounded down to stay within what the service will pay.
+    fn withdraw_amount(&self, requested: Option<Amount>) -> anyhow::Result<Amount> {
+        let requested = requested.map_or(self.max_withdrawable, |amount| amount.msats);
+        ensure!(
+            (self.min_withdrawable..=self.max_withdrawable).contains(&requested),
+            "amount must be between {} and {}",
+            Amount::from_msats(self.min_withdrawable),
+            Amount::from_msats(self.max_withdrawable),
+        );
+        let amount = Amount::from_sats(requested / 1000);
+        ensure!(
+            amount.msats != 0 && self.min_withdrawable <= amount.msats,
+            "LNURL-withdraw amount range does not contain a whole sat amount"
+        );
+        Ok(amount)
+    }
+}
+
+impl LnurlClient {
+    pub async fn fetch_withdraw_params(&self, lnurl: &str) -> anyhow::Result<LnurlWithdrawParams> {
+        let params: LnurlWithdrawParams = self.get_json(parse_lnurl(lnurl)?).await?;
+        params.validate()?;
+        Ok(params)
+    }
+
+    /// Hands the invoice to the service, which pays it asynchronously.
+    pub async fn submit_withdraw_invoice(
+        &self,
+        params: &LnurlWithdrawParams,
+        invoice: &str,
+    ) -> anyhow::Result<()> {
+        let mut callback = params.callback.clone();
+        callback
+            .query_pairs_mut()
+            .append_pair("k1", &params.k1)
+            .append_pair("pr", invoice);
+        let response: CallbackResponse = self.get_json(callback).await?;
+        ensure!(
+            response.status.eq_ignore_ascii_case("OK"),
+            "LNURL-withdraw service responded with status {}",
+            response.status
+        );
+        Ok(())
+    }
+}
+
+impl Federations {
+    /// Resolves an LNURL-withdraw link to what the service offers, without
+    /// claiming anything.
+    pub async fn preview_lnurl_withdraw(
+        &self,
+        lnurl: &str,
+    ) -> anyhow::Result<RpcLnurlWithdrawParams> {
+        Ok(self
+            .lnurl_client
+            .fetch_withdraw_params(lnurl)
+            .await?
+            .to_rpc())
+    }
+
+    /// Claims an LNURL-withdraw link into the given federation and returns
+    /// the invoice the service was asked to pay. The receive shows up in
+    /// the transaction history like any other, tagged with where the funds
+    /// came from.
+    pub async fn claim_lnurl_withdraw(
+        &self,
+        federation_id: &str,
+        lnurl: &str,
+        amount: Option<Amount>,
+        mut frontend_metadata: FrontendMetadata,
+    ) -> anyhow::Result<String> {
+        let federation = self.get_federation(federation_id)?;
+        let params = self.lnurl_client.fetch_withdraw_params(lnurl).await?;
+        let amount = params.withdraw_amount(amount)?;
+
+        frontend_metadata.receive_source = Some(RpcReceiveSource::LnurlWithdraw {
+            domain: params.domain(),
+        });
+        let invoice = federation
+            .generate_invoice(
+                RpcAmount(amount),
+                params.default_description.clone(),
+                None,
+                frontend_metadata,
+            )
+            .await?
+            .to_string();
+
+        self.lnurl_client
+            .submit_withdraw_invoice(&params, &invoice)
+            .await?;
+        Ok(invoice)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn withdraw_params(min_withdrawable: u64, max_withdrawable: u64) -> LnurlWithdrawParams {
+        LnurlWithdrawParams {
+            callback: Url::parse("https://atm.example.com/withdraw").unwrap(),
+            k1: "k1".to_owned(),
+            default_description: "ATM withdrawal".to_owned(),
+            min_withdrawable,
+            max_withdrawable,
+            tag: WITHDRAW_REQUEST_TAG.to_owned(),
+        }
+    }
+
+    #[test]
+    fn test_withdraw_params() {
+        let params: LnurlWithdrawParams = serde_json::from_str(
+            r#"{
+                "tag": "withdrawRequest",
+                "callback": "https://atm.example.com/withdraw",
+                "k1": "abcd",
+                "defaultDescription": "ATM withdrawal",
+                "minWithdrawable": 1000,
+                "maxWithdrawable": 50000
+            }"#,
+        )
+        .unwrap();
+        params.validate().unwrap();
+        let rpc = params.to_rpc();
+        assert_eq!(rpc.domain, "atm.example.com");
+        assert_eq!(rpc.max_withdrawable, RpcAmount(Amount::from_sats(50)));
+
+        let mut pay = withdraw_params(1_000, 50_000);
+        pay.tag = "payRequest".to_owned();
+        assert!(pay.validate().is_err());
+
+        let mut insecure = withdraw_params(1_000, 50_000);
+        insecure.callback = Url::parse("http://atm.example.com/withdraw").unwrap();
+        assert!(insecure.validate().is_err());
+    }
+
+    #[test]
+    fn test_withdraw_amount() {
+        let params = withdraw_params(1_000, 50_500);
+        // defaults to everything on offer, rounded down to whole sats
+        assert_eq!(params.withdraw_amount(None).unwrap(), Amount::from_sats(50));
+        assert_eq!(
+            params
+                .withdraw_amount(Some(Amount::from_msats(20_999)))
+                .unwrap(),
+            Amount::from_sats(20)
+        );
+        assert!(params.withdraw_amount(Some(Amount::from_sats(51))).is_err());
+        assert!(
+            params
+                .withdraw_amount(Some(Amount::from_msats(999)))
+                .is_err()
+        );
+        assert!(withdraw_params(1_500, 1_900).withdraw_amount(None).is_err());
+    }
+}
@@ -0,0 +1,415 @@
+//! Classification of whatever the user scanned or pasted, so the frontend
+//! does not have to try every parser in turn.
+
+use std::collections::BTreeSet;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use anyhow::{Context as _, bail, ensure};
+use bitcoin::Network;
+use bitcoin::address::NetworkUnchecked;
+use fedimint_core::Amount;
+use fedimint_core::invite_code::InviteCode;
+use lightning_invoice::Bolt11Invoice;
+use matrix_sdk::ruma::matrix_uri::{MatrixId, MatrixToUri, MatrixUri};
+use matrix_sdk::ruma::{RoomId, UserId};
+use rpc_types::communities::CommunityInvite;
+use rpc_types::matrix::RpcUserId;
+use rpc_types::payment_string::{RpcParsedPaymentString, RpcParsedSpv2PaymentAddress};
+use rpc_types::sp_transfer::RpcAccountId;
+use rpc_types::{RpcAmount, RpcBolt12Offer, RpcFederationId, RpcParsedInvoice};
+use stability_pool_client::common::AccountType;
+
+use crate::Federations;
+use crate::federation_sm::FederationState;
+use crate::federation_v2::FederationV2;
+use crate::federation_v2::spv2_pay_address::Spv2PaymentAddress;
+use crate::lnurl::parse_lnurl;
+
+/// Parts of a `bitcoin:` URI (BIP21), including the payment instructions
+/// BIP321 added as query parameters.
+#[derive(Debug, Default, PartialEq)]
+struct Bip21Uri {
+    address: Option<String>,
+    amount: Option<bitcoin::Amount>,
+    label: Option<String>,
+    message: Option<String>,
+    lightning: Option<String>,
+    offer: Option<String>,
+    /// Stable balance payment address, under `spt` since BIP321 reserves
+    /// `sp` for silent payments.
+    sp: Option<String>,
+}
+
+/// Matrix ids a user or room link resolves to.
+#[derive(Debug, PartialEq)]
+enum MatrixLink {
+    User(String),
+    Room(String),
+}
+
+impl Federations {
+    /// Works out what `input` is. Parsing happens locally, except for ecash
+    /// which needs nothing beyond our joined federations either.
+    pub async fn parse_payment_string(
+        &self,
+        input: &str,
+    ) -> anyhow::Result<RpcParsedPaymentString> {
+        let input = input.trim();
+        let lowercase = input.to_lowercase();
+        let lightning = lowercase.strip_prefix("lightning:").unwrap_or(&lowercase);
+
+        if let Ok(invoice) = Bolt11Invoice::from_str(lightning) {
+            return Ok(RpcParsedPaymentString::Bolt11 {
+                payable_by: self.federations_paying_invoice(&invoice),
+                invoice: RpcParsedInvoice::from(invoice),
+            });
+        }
+        if lightning.starts_with("lno1") {
+            return Ok(RpcParsedPaymentString::Bolt12 {
+                offer: lightning.parse()?,
+                payable_by: vec![],
+            });
+        }
+        if lowercase.starts_with("bitcoin:") {
+            return self.parse_bip21(input);
+        }
+        if let Ok(invite) = CommunityInvite::from_str(input) {
+            return Ok(RpcParsedPaymentString::CommunityInvite {
+                invite_code: invite.to_string(),
+            });
+        }
+        if let Ok(invite) = InviteCode::from_str(&lowercase) {
+            let federation_id = invite.federation_id().to_string();
+            return Ok(RpcParsedPaymentString::FederationInvite {
+                invite_code: input.to_owned(),
+                joined: self.get_federations_map().contains_key(&federation_id),
+                federation_id: RpcFederationId(federation_id),
+            });
+        }
+        if let Ok(address) = Spv2PaymentAddress::from_str(&lowercase) {
+            let (address, payable_by) = self.spv2_payment_address(input, address)?;
+            return Ok(RpcParsedPaymentString::Spv2PaymentAddress {
+                address,
+                payable_by,
+            });
+        }
+        if let Some(link) = parse_matrix_link(input) {
+            return Ok(match link {
+                MatrixLink::User(user_id) => RpcParsedPaymentString::MatrixUser {
+                    user_id: RpcUserId(user_id),
+                },
+                MatrixLink::Room(room) => RpcParsedPaymentString::MatrixRoom { room },
+            });
+        }
+        if let Ok(url) = parse_lnurl(input) {
+            let payable_by = self.federations_paying_lightning(None);
+            return Ok(if is_lightning_address(lightning) {
+                RpcParsedPaymentString::LightningAddress {
+                    address: lightning.to_owned(),
+                    payable_by,
+                }
+            } else {
+                RpcParsedPaymentString::Lnurl {
+                    lnurl: input.to_owned(),
+                    url: url.to_string(),
+                    payable_by,
+                }
+            });
+        }
+        if let Ok(info) = self.validate_ecash(input.to_owned()).await {
+            return Ok(RpcParsedPaymentString::Ecash {
+                ecash: input.to_owned(),
+                info,
+            });
+        }
+        if let Ok(address) = input.parse::<bitcoin::Address<NetworkUnchecked>>() {
+            return Ok(RpcParsedPaymentString::OnchainAddress {
+                payable_by: self.federations_paying_address(&address),
+                address: input.to_owned(),
+            });
+        }
+
+        bail!("unrecognized payment string")
+    }
+
+    fn parse_bip21(&self, input: &str) -> anyhow::Result<RpcParsedPaymentString> {
+        let uri = parse_bip21_uri(input)?;
+
+        let address = uri
+            .address
+            .as_deref()
+            .map(|address| {
+                address
+                    .parse::<bitcoin::Address<NetworkUnchecked>>()
+                    .context("invalid bitcoin address")
+            })
+            .transpose()?;
+        let lightning = uri
+            .lightning
+            .as_deref()
+            .map(|invoice| Bolt11Invoice::from_str(&invoice.to_lowercase()))
+            .transpose()
+            .context("invalid lightning invoice")?;
+        let offer = uri
+            .offer
+            .as_deref()
+            .map(RpcBolt12Offer::from_str)
+            .transpose()?;
+        let sp = uri
+            .sp
+            .as_deref()
+            .map(|sp| {
+                let address = Spv2PaymentAddress::from_str(&sp.to_lowercase())?;
+                self.spv2_payment_address(sp, address)
+            })
+            .transpose()?;
+        ensure!(
+            address.is_some() || lightning.is_some() || offer.is_some() || sp.is_some(),
+            "bitcoin URI without any payment method"
+        );
+
+        let mut payable_by = vec![];
+        if let Some(address) = &address {
+            payable_by.extend(self.federations_paying_address(address));
+        }
+        if let Some(invoice) = &lightning {
+            payable_by.extend(self.federations_paying_invoice(invoice));
+        }
+        if let Some((_, sp_payable_by)) = &sp {
+            payable_by.extend(sp_payable_by.iter().cloned());
+        }
+        let mut seen = BTreeSet::new();
+        payable_by.retain(|id| seen.insert(id.0.clone()));
+
+        Ok(RpcParsedPaymentString::Bip21 {
+            address: uri.address,
+            amount: uri
+                .amount
+                .map(|amount| RpcAmount(Amount::from_sats(amount.to_sat()))),
+            label: uri.label,
+            message: uri.message,
+            lightning: lightning.map(RpcParsedInvoice::from),
+            offer,
+            sp: sp.map(|(address, _)| address),
+            payable_by,
+        })
+    }
+
+    fn spv2_payment_address(
+        &self,
+        input: &str,
+        address: Spv2PaymentAddress,
+    ) -> anyhow::Result<(RpcParsedSpv2PaymentAddress, Vec<RpcFederationId>)> {
+        ensure!(
+            address.account_id.acc_type() == AccountType::Seeker,
+            "invalid account type"
+        );
+        let federation_id = self.find_federation_id_for_prefix(address.federation_id_prefix);
+        let payable_by = federation_id
+            .as_deref()
+            .and_then(|id| self.ready_federation(id))
+            .map(|federation| RpcFederationId(federation.federation_id().to_string()))
+            .into_iter()
+            .collect();
+        let parsed = RpcParsedSpv2PaymentAddress {
+            address: input.to_owned(),
+            account_id: RpcAccountId(address.account_id.to_string()),
+            federation_invite: match federation_id {
+                Some(_) => None,
+                None => address.federation_invite.map(|invite| invite.to_string()),
+            },
+        };
+        Ok((parsed, payable_by))
+    }
+
+    /// Joined federations that are done loading and recovering, the only
+    /// ones that can pay anything.
+    fn ready_federations(&self) -> Vec<Arc<FederationV2>> {
+        self.get_federations_map()
+            .into_values()
+            .filter_map(|state| match state {
+                FederationState::Ready(federation) => Some(federation),
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn ready_federation(&self, federation_id: &str) -> Option<Arc<FederationV2>> {
+        match self.get_federation_state(federation_id) {
+            Ok(FederationState::Ready(federation)) => Some(federation),
+            _ => None,
+        }
+    }
+
+    /// Federations that can pay over lightning, restricted to `network` when
+    /// the destination is tied to one.
+    fn federations_paying_lightning(&self, network: Option<Network>) -> Vec<RpcFederationId> {
+        self.ready_federations()
+            .into_iter()
+            .filter(|federation| {
+                federation.get_network().is_some_and(|federation_network| {
+                    network.is_none_or(|network| network == federation_network)
+                })
+            })
+            .map(|federation| RpcFederationId(federation.federation_id().to_string()))
+            .collect()
+    }
+
+    /// None for invoices without an amount, which `payInvoice` refuses.
+    fn federations_paying_invoice(&self, invoice: &Bolt11Invoice) -> Vec<RpcFederationId> {
+        if invoice.amount_milli_satoshis().is_none() {
+            return vec![];
+        }
+        self.federations_paying_lightning(Some(invoice.network()))
+    }
+
+    fn federations_paying_address(
+        &self,
+        address: &bitcoin::Address<NetworkUnchecked>,
+    ) -> Vec<RpcFederationId> {
+        self.ready_federations()
+            .into_iter()
+            .filter(|federation| {
+                federation
+                    .get_network()
+                    .is_some_and(|network| address.is_valid_for_network(network))
+            })
+            .map(|federation| RpcFederationId(federation.federation_id().to_string()))
+            .collect()
+    }
+}
+
+fn is_lightning_address(input: &str) -> bool {
+    input
+        .split_once('@')
+        .is_some_and(|(user, domain)| !user.is_empty() && !domain.is_empty())
+}
+
+/// Splits a `bitcoin:` URI into its parts. Unknown parameters are ignored
+/// unless prefixed with `req-`, which BIP21 says must be rejected.
+fn parse_bip21_uri(input: &str) -> anyhow::Result<Bip21Uri> {
+    let rest = input
+        .get(..8)
+        .filter(|scheme| scheme.eq_ignore_ascii_case("bitcoin:"))
+        .map(|_| &input[8..])
+        .context("not a bitcoin URI")?;
+    let (address, query) = rest.split_once('?').unwrap_or((rest, ""));
+
+    let mut uri = Bip21Uri {
+        address: (!address.is_empty()).then(|| address.to_owned()),
+        ..Bip21Uri::default()
+    };
+    for (key, value) in url::form_urlencoded::parse(query.as_bytes()) {
+        let value = value.into_owned();
+        match key.to_lowercase().as_str() {
+            "amount" => {
+                uri.amount = Some(
+                    bitcoin::Amount::from_str_in(&value, bitcoin::Denomination::Bitcoin)
+                        .context("invalid amount")?,
+                );
+            }
+            "label" => uri.label = Some(value),
+            "message" => uri.message = Some(value),
+            "lightning" => uri.lightning = Some(value),
+            "lno" => uri.offer = Some(value),
+            "spt" => uri.sp = Some(value),
+            key if key.starts_with("req-") => bail!("unsupported required parameter {key}"),
+            _ => {}
+        }
+    }
+    Ok(uri)
+}
+
+/// Recognizes our own `fedi:user:`/`fedi:room:` links as well as
+/// `https://matrix.to/#/...` and `matrix:` URIs.
+fn parse_matrix_link(input: &str) -> Option<MatrixLink> {
+    let fedi_link = input
+        .get(..5)
+        .filter(|scheme| scheme.eq_ignore_ascii_case("fedi:"))
+        .map(|_| &input[5..]);
+    if let Some(link) = fedi_link {
+        if let Some(user_id) = link.strip_prefix("user:") {
+            return UserId::parse(user_id)
+                .ok()
+                .map(|user_id| MatrixLink::User(user_id.to_string()));
+        }
+        if let Some(room_id) = link.strip_prefix("room:") {
+            // room links are terminated by `:::`
+            let room_id = room_id.strip_suffix(":::").unwrap_or(room_id);
+            return RoomId::parse(room_id)
+                .ok()
+                .map(|room_id| MatrixLink::Room(room_id.to_string()));
+        }
+        return None;
+    }
+
+    let id = MatrixToUri::parse(input)
+        .map(|uri| uri.id().clone())
+        .or_else(|_| MatrixUri::parse(input).map(|uri| uri.id().clone()))
+        .ok()?;
+    match id {
+        MatrixId::User(user_id) => Some(MatrixLink::User(user_id.to_string())),
+        MatrixId::Room(room_id) => Some(MatrixLink::Room(room_id.to_string())),
+        MatrixId::RoomAlias(alias) => Some(MatrixLink::Room(alias.to_string())),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_bip21_uri() {
+        let uri = parse_bip21_uri(
+            "BITCOIN:bc1qar0srrr7xfkvy5l643lydnw9re59gtzzwf5mdq?amount=0.0005&label=Fedi%20Cafe&lightning=lnbc1&foo=bar",
+        )
+        .unwrap();
+        assert_eq!(
+            uri,
+            Bip21Uri {
+                address: Some("bc1qar0srrr7xfkvy5l643lydnw9re59gtzzwf5mdq".to_owned()),
+                amount: Some(bitcoin::Amount::from_sat(50_000)),
+                label: Some("Fedi Cafe".to_owned()),
+                lightning: Some("lnbc1".to_owned()),
+                ..Bip21Uri::default()
+            }
+        );
+
+        // BIP321 allows leaving out the address
+        let uri = parse_bip21_uri("bitcoin:?spt=spt1abc").unwrap();
+        assert_eq!(uri.address, None);
+        assert_eq!(uri.sp.as_deref(), Some("spt1abc"));
+
+        // `sp` is a silent payment address, which we don't support
+        let uri = parse_bip21_uri("bitcoin:?sp=sp1abc").unwrap();
+        assert_eq!(uri.sp, None);
+
+        assert!(parse_bip21_uri("bitcoin:bc1q?req-pop=1").is_err());
+        assert!(parse_bip21_uri("bitcoin:bc1q?amount=abc").is_err());
+        assert!(parse_bip21_uri("lightning:lnbc1").is_err());
+    }
+
+    #[test]
+    fn test_parse_matrix_link() {
+        assert_eq!(
+            parse_matrix_link("fedi:user:@alice:example.com"),
+            Some(MatrixLink::User("@alice:example.com".to_owned()))
+        );
+        assert_eq!(
+            parse_matrix_link("fedi:room:!abc:example.com:::"),
+            Some(MatrixLink::Room("!abc:example.com".to_owned()))
+        );
+        assert_eq!(
+            parse_matrix_link("https://matrix.to/#/@alice:example.com"),
+            Some(MatrixLink::User("@alice:example.com".to_owned()))
+        );
+        assert_eq!(
+            parse_matrix_link("matrix:r/fedi:example.com"),
+            Some(MatrixLink::Room("#fedi:example.com".to_owned()))
+        );
+        assert_eq!(parse_matrix_link("alice@example.com"), None);
+        assert_eq!(parse_matrix_link("fedi:user:alice"), None);
+    }
+}
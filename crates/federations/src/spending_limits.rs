@@ -0,0 +1,479 @@
+//! Limits on outgoing payments, so a mistake or a compromised mini-app cannot
+//! drain the wallet.
+//!
+//! Global limits live in the app state, per-federation limits in the
+//! federation's database, and a payment has to fit within both. Daily and
+//! weekly limits are checked against a log of recent payments kept in the
+//! global database. Payments are only logged while some limit applies to
+//! them, so a newly set limit starts with its full allowance.
+//!
+//! A payment is logged before it is attempted and removed again if the
+//! attempt fails right away, so concurrent payments cannot both slip under a
+//! limit. Payments that fail later on, e.g. a lightning payment the gateway
+//! gives up on, stay logged.
+//!
+//! Limits can always be tightened. Loosening or removing them requires calling
+//! [`Federations::unlock_spending_limits`] first, which the frontend puts
+//! behind the user's PIN, so a mini-app cannot lift them by itself.
+
+use std::future::Future;
+use std::time::Duration;
+
+use anyhow::{Context as _, anyhow, bail};
+use fedimint_core::Amount;
+use fedimint_core::db::{Database, IDatabaseTransactionOpsCoreTyped};
+use fedimint_core::encoding::{Decodable, Encodable};
+use fedimint_core::{impl_db_lookup, impl_db_record};
+use futures::StreamExt;
+use rpc_types::error::ErrorCode;
+use runtime::storage::state::{FiatFXInfo, SpendingLimit, SpendingLimits};
+use runtime::utils::{PoisonedLockExt as _, to_unix_time};
+use tracing::warn;
+
+use crate::Federations;
+use crate::federation_v2::FederationV2;
+use crate::federation_v2::db::FederationDataDbPrefix;
+
+/// How long [`Federations::unlock_spending_limits`] allows loosening limits.
+const UNLOCK_DURATION: Duration = Duration::from_secs(5 * 60);
+
+const DAY_SECS: u64 = 24 * 60 * 60;
+const WEEK_SECS: u64 = 7 * DAY_SECS;
+
+#[repr(u8)]
+pub enum SpendingLimitsDbPrefix {
+    // per-federation limits
+    Limits = 0x01,
+}
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct FederationSpendingLimitsKey;
+
+impl_db_record!(
+    key = FederationSpendingLimitsKey,
+    value = SpendingLimits,
+    db_prefix = SpendingLimitsDbPrefix::Limits,
+);
+
+#[repr(u8)]
+pub enum SpendingLogDbPrefix {
+    // (time, random id) => payment
+    Entry = 0x01,
+}
+
+#[derive(Debug, Clone, Encodable, Decodable)]
+pub struct SpendingLogKey {
+    /// Unix time in seconds.
+    pub time: u64,
+    /// The payment's operation does not exist yet when it is logged.
+    pub id: [u8; 32],
+}
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct SpendingLogKeyPrefix;
+
+#[derive(Debug, Clone, Encodable, Decodable)]
+pub struct SpendingLogEntry {
+    pub federation_id: String,
+    pub amount: Amount,
+}
+
+impl_db_record!(
+    key = SpendingLogKey,
+    value = SpendingLogEntry,
+    db_prefix = SpendingLogDbPrefix::Entry,
+);
+
+impl_db_lookup!(key = SpendingLogKey, query_prefix = SpendingLogKeyPrefix);
+
+impl Federations {
+    /// Allows loosening spending limits once within the next few minutes.
+    pub fn unlock_spending_limits(&self) {
+        *self.spending_limits_unlocked_until.ensure_lock() =
+            Some(fedimint_core::time::now() + UNLOCK_DURATION);
+    }
+
+    /// Global limits when `federation_id` is None, otherwise the limits of
+    /// that federation alone.
+    pub async fn get_spending_limits(
+        &self,
+        federation_id: Option<&str>,
+    ) -> anyhow::Result<SpendingLimits> {
+        match federation_id {
+            None => Ok(self
+                .runtime
+                .app_state
+                .with_read_lock(|state| state.spending_limits.clone())
+                .await),
+            Some(federation_id) => Ok(self
+                .get_federation_maybe_recovering(federation_id)?
+                .federation_spending_limits()
+                .await),
+        }
+    }
+
+    /// Replaces the limits read by [`Self::get_spending_limits`]. Loosening
+    /// them fails with [`ErrorCode::SpendingLimitsLocked`] unless unlocked, and
+    /// uses up the unlock.
+    pub async fn set_spending_limits(
+        &self,
+        federation_id: Option<&str>,
+        limits: SpendingLimits,
+    ) -> anyhow::Result<()> {
+        let current = self.get_spending_limits(federation_id).await?;
+        if !spending_limits_tightened(&current, &limits) {
+            let mut unlocked_until = self.spending_limits_unlocked_until.ensure_lock();
+            if !unlocked_until.is_some_and(|until| fedimint_core::time::now() < until) {
+                bail!(ErrorCode::SpendingLimitsLocked);
+            }
+            *unlocked_until = None;
+        }
+        match federation_id {
+            None => {
+                self.runtime
+                    .app_state
+                    .with_write_lock(|state| state.spending_limits = limits)
+                    .await
+            }
+            Some(federation_id) => {
+                let federation = self.get_federation_maybe_recovering(federation_id)?;
+                let mut dbtx = federation.spending_limits_db().begin_transaction().await;
+                dbtx.insert_entry(&FederationSpendingLimitsKey, &limits)
+                    .await;
+                dbtx.commit_tx_result().await
+            }
+        }
+    }
+}
+
+impl FederationV2 {
+    fn spending_limits_db(&self) -> Database {
+        self.federation_data_db(FederationDataDbPrefix::SpendingLimits)
+    }
+
+    async fn federation_spending_limits(&self) -> SpendingLimits {
+        self.spending_limits_db()
+            .begin_transaction_nc()
+            .await
+            .get_value(&FederationSpendingLimitsKey)
+            .await
+            .unwrap_or_default()
+    }
+
+    /// Runs `spend` if `amount` fits within the global and this federation's
+    /// spending limits, failing with [`ErrorCode::SpendingLimitExceeded`]
+    /// otherwise. `amount` is only awaited when some limit is set.
+    pub(crate) async fn limit_spend<T>(
+        &self,
+        amount: impl Future<Output = anyhow::Result<Amount>>,
+        spend: impl Future<Output = anyhow::Result<T>>,
+    ) -> anyhow::Result<T> {
+        let global_limits = self
+            .runtime
+            .app_state
+            .with_read_lock(|state| state.spending_limits.clone())
+            .await;
+        let federation_limits = self.federation_spending_limits().await;
+        if global_limits == SpendingLimits::default()
+            && federation_limits == SpendingLimits::default()
+        {
+            return spend.await;
+        }
+
+        let amount = amount.await?;
+        let fiat_fx_info = self.runtime.app_state.get_cached_fiat_fx_info().await;
+        let federation_id = self.rpc_federation_id().0;
+        let now = to_unix_time(fedimint_core::time::now())?;
+        let key = SpendingLogKey {
+            time: now,
+            id: rand::random(),
+        };
+        self.runtime
+            .spending_log_db()
+            .autocommit(
+                |dbtx, _| {
+                    let key = key.clone();
+                    let federation_id = federation_id.clone();
+                    let global_limits = global_limits.clone();
+                    let federation_limits = federation_limits.clone();
+                    let fiat_fx_info = fiat_fx_info.clone();
+                    Box::pin(async move {
+                        let log = dbtx
+                            .find_by_prefix(&SpendingLogKeyPrefix)
+                            .await
+                            .collect::<Vec<_>>()
+                            .await;
+                        let mut global_spent = SpentAmounts::default();
+                        let mut federation_spent = SpentAmounts::default();
+                        for (entry_key, entry) in log {
+                            if entry_key.time + WEEK_SECS <= now {
+                                dbtx.remove_entry(&entry_key).await;
+                                continue;
+                            }
+                            global_spent.add(now, entry_key.time, entry.amount);
+                            if entry.federation_id == federation_id {
+                                federation_spent.add(now, entry_key.time, entry.amount);
+                            }
+                        }
+                        check_spending_limits(
+                            &global_limits,
+                            fiat_fx_info.as_ref(),
+                            amount,
+                            global_spent,
+                        )
+                        .context("global spending limit")?;
+                        check_spending_limits(
+                            &federation_limits,
+                            fiat_fx_info.as_ref(),
+                            amount,
+                            federation_spent,
+                        )
+                        .context("federation spending limit")?;
+                        dbtx.insert_entry(
+                            &key,
+                            &SpendingLogEntry {
+                                federation_id,
+                                amount,
+                            },
+                        )
+                        .await;
+                        Ok::<_, anyhow::Error>(())
+                    })
+                },
+                Some(100),
+            )
+            .await
+            .map_err(|e| match e {
+                fedimint_core::db::AutocommitError::CommitFailed { last_error, .. } => {
+                    anyhow!(last_error)
+                }
+                fedimint_core::db::AutocommitError::ClosureError { error, .. } => error,
+            })?;
+
+        let result = spend.await;
+        if result.is_err() {
+            let mut dbtx = self.runtime.spending_log_db().begin_transaction().await;
+            dbtx.remove_entry(&key).await;
+            if let Err(error) = dbtx.commit_tx_result().await {
+                warn!(?error, "failed to remove failed payment from spending log");
+            }
+        }
+        result
+    }
+}
+
+/// Amounts already spent within the rolling windows.
+#[derive(Debug, Default, Clone, Copy)]
+struct SpentAmounts {
+    day: Amount,
+    week: Amount,
+}
+
+impl SpentAmounts {
+    fn add(&mut self, now: u64, time: u64, amount: Amount) {
+        if time + DAY_SECS > now {
+            self.day += amount;
+        }
+        if time + WEEK_SECS > now {
+            self.week += amount;
+        }
+    }
+}
+
+/// Checks whether spending `amount` on top of `spent` stays within `limits`.
+fn check_spending_limits(
+    limits: &SpendingLimits,
+    fiat_fx_info: Option<&FiatFXInfo>,
+    amount: Amount,
+    spent: SpentAmounts,
+) -> anyhow::Result<()> {
+    for (name, limit, total) in [
+        ("per-transaction", &limits.per_transaction, amount),
+        ("daily", &limits.daily, spent.day + amount),
+        ("weekly", &limits.weekly, spent.week + amount),
+    ] {
+        let Some(limit) = limit else {
+            continue;
+        };
+        let limit = spending_limit_amount(limit, fiat_fx_info)?;
+        if limit < total {
+            return Err(anyhow!(ErrorCode::SpendingLimitExceeded))
+                .with_context(|| format!("{name} limit of {limit} would be exceeded"));
+        }
+    }
+    Ok(())
+}
+
+/// Whether `new` allows no more spending than `current`. Limits in different
+/// units can't be compared, so switching units counts as loosening.
+fn spending_limits_tightened(current: &SpendingLimits, new: &SpendingLimits) -> bool {
+    [
+        (&current.per_transaction, &new.per_transaction),
+        (&current.daily, &new.daily),
+        (&current.weekly, &new.weekly),
+    ]
+    .into_iter()
+    .all(|(current, new)| match (current, new) {
+        (None, _) => true,
+        (Some(_), None) => false,
+        (
+            Some(SpendingLimit::Msats { msats: current }),
+            Some(SpendingLimit::Msats { msats: new }),
+        ) => new <= current,
+        (
+            Some(SpendingLimit::Fiat {
+                fiat_code: current_code,
+                hundredths: current,
+            }),
+            Some(SpendingLimit::Fiat {
+                fiat_code: new_code,
+                hundredths: new,
+            }),
+        ) => new_code == current_code && new <= current,
+        (Some(_), Some(_)) => false,
+    })
+}
+
+/// Fiat limits can't be enforced without a rate for their currency, in
+/// which case nothing may be spent rather than everything.
+fn spending_limit_amount(
+    limit: &SpendingLimit,
+    fiat_fx_info: Option<&FiatFXInfo>,
+) -> anyhow::Result<Amount> {
+    match limit {
+        SpendingLimit::Msats { msats } => Ok(Amount::from_msats(*msats)),
+        SpendingLimit::Fiat {
+            fiat_code,
+            hundredths,
+        } => {
+            let Some(fiat_fx_info) =
+                fiat_fx_info.filter(|fiat_fx_info| &fiat_fx_info.fiat_code == fiat_code)
+            else {
+                return Err(anyhow!(ErrorCode::SpendingLimitExceeded)).with_context(|| {
+                    format!("no exchange rate for the {fiat_code} spending limit")
+                });
+            };
+            let msats = u128::from(*hundredths) * 100_000_000_000
+                / u128::from(fiat_fx_info.btc_to_fiat_hundredths.max(1));
+            Ok(Amount::from_msats(u64::try_from(msats).unwrap_or(u64::MAX)))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn secs_ago(secs: u64) -> u64 {
+        to_unix_time(fedimint_core::time::now()).unwrap() - secs
+    }
+
+    fn msats(msats: u64) -> Option<SpendingLimit> {
+        Some(SpendingLimit::Msats { msats })
+    }
+
+    #[test]
+    fn test_check_spending_limits() {
+        let limits = SpendingLimits {
+            per_transaction: msats(1_000),
+            daily: msats(2_000),
+            weekly: msats(5_000),
+        };
+        let now = secs_ago(0);
+        let mut spent = SpentAmounts::default();
+        spent.add(now, secs_ago(60), Amount::from_msats(1_000));
+        spent.add(now, secs_ago(2 * DAY_SECS), Amount::from_msats(3_000));
+        assert_eq!(spent.day, Amount::from_msats(1_000));
+        assert_eq!(spent.week, Amount::from_msats(4_000));
+
+        assert!(check_spending_limits(&limits, None, Amount::from_msats(1_000), spent).is_ok());
+        for amount in [1_001, 1_500] {
+            let error = check_spending_limits(&limits, None, Amount::from_msats(amount), spent)
+                .unwrap_err();
+            assert_eq!(
+                error.downcast_ref::<ErrorCode>(),
+                Some(&ErrorCode::SpendingLimitExceeded)
+            );
+        }
+        spent.week = Amount::from_msats(4_500);
+        assert!(check_spending_limits(&limits, None, Amount::from_msats(1_000), spent).is_err());
+        assert!(
+            check_spending_limits(
+                &SpendingLimits::default(),
+                None,
+                Amount::from_msats(u64::MAX),
+                spent
+            )
+            .is_ok()
+        );
+    }
+
+    #[test]
+    fn test_spending_limits_tightened() {
+        let limits = SpendingLimits {
+            per_transaction: msats(1_000),
+            daily: None,
+            weekly: Some(SpendingLimit::Fiat {
+                fiat_code: "USD".to_owned(),
+                hundredths: 10_000,
+            }),
+        };
+        assert!(spending_limits_tightened(&limits, &limits));
+        assert!(spending_limits_tightened(
+            &SpendingLimits::default(),
+            &limits
+        ));
+        assert!(!spending_limits_tightened(
+            &limits,
+            &SpendingLimits::default()
+        ));
+
+        let tighter = SpendingLimits {
+            per_transaction: msats(500),
+            daily: msats(2_000),
+            weekly: Some(SpendingLimit::Fiat {
+                fiat_code: "USD".to_owned(),
+                hundredths: 5_000,
+            }),
+        };
+        assert!(spending_limits_tightened(&limits, &tighter));
+        assert!(!spending_limits_tightened(&tighter, &limits));
+
+        let other_currency = SpendingLimits {
+            weekly: Some(SpendingLimit::Fiat {
+                fiat_code: "EUR".to_owned(),
+                hundredths: 1,
+            }),
+            ..limits.clone()
+        };
+        assert!(!spending_limits_tightened(&limits, &other_currency));
+        let other_unit = SpendingLimits {
+            weekly: msats(1),
+            ..limits.clone()
+        };
+        assert!(!spending_limits_tightened(&limits, &other_unit));
+    }
+
+    #[test]
+    fn test_fiat_spending_limit() {
+        let limit = SpendingLimit::Fiat {
+            fiat_code: "USD".to_owned(),
+            hundredths: 100,
+        };
+        let usd = FiatFXInfo {
+            fiat_code: "USD".to_owned(),
+            btc_to_fiat_hundredths: 10_000_000,
+        };
+        // $1 at $100k per btc is 1000 sats
+        assert_eq!(
+            spending_limit_amount(&limit, Some(&usd)).unwrap(),
+            Amount::from_sats(1_000)
+        );
+        let eur = FiatFXInfo {
+            fiat_code: "EUR".to_owned(),
+            ..usd
+        };
+        assert!(spending_limit_amount(&limit, Some(&eur)).is_err());
+        assert!(spending_limit_amount(&limit, None).is_err());
+    }
+}
@@ -0,0 +1,211 @@
+//! `fedimint-cli guardian-fee` builds and checks the guardian fee meta entries
+//! so guardians don't have to hand-write the JSON that apps later parse.
+
+use std::collections::BTreeMap;
+use std::str::FromStr;
+
+use anyhow::{Context, bail};
+use bitcoin::secp256k1::PublicKey;
+use clap::Parser;
+use fedimint_core::Amount;
+use fedimint_core::core::ModuleKind;
+use guardian_fee_config::{
+    FediFeePolicy, FediGuardianFeeConfig, FediGuardianFeeRecipient, fedi_guardian_fee_config_meta,
+    parse_fedi_guardian_fee_config, preview_guardian_fee_remittance,
+    validate_guardian_fee_remittance_account,
+};
+use serde_json::json;
+use stability_pool_client::common::{Account, AccountType};
+
+pub const SUBCOMMAND: &str = "guardian-fee";
+
+#[derive(Parser)]
+#[command(name = "fedimint-cli guardian-fee")]
+pub enum GuardianFeeCommand {
+    /// Check that a published account can receive guardian fee remittances.
+    CheckAccount {
+        /// The account as JSON, as published in the federation meta.
+        account: String,
+    },
+    /// Build the meta entries to publish for a guardian fee config.
+    BuildMeta {
+        /// Guardian fee charged on sends, in ppm.
+        #[arg(long)]
+        send_ppm: u64,
+        /// `<pubkey>:<weight>` of one recipient's btc-balance account, once
+        /// per recipient.
+        #[arg(long = "recipient", required = true, value_parser = parse_recipient)]
+        recipients: Vec<FediGuardianFeeRecipient>,
+        /// Base fee, caps and volume tiers on top of `send_ppm`, as JSON.
+        #[arg(long)]
+        send_policy: Option<String>,
+    },
+    /// Validate published meta entries and preview the remittance they would
+    /// make out of the given outstanding fees.
+    PreviewRemittance {
+        /// The federation meta as a JSON object of strings.
+        #[arg(long)]
+        meta: String,
+        /// `<module>:<send|receive>:<msats>` of outstanding guardian fees,
+        /// once per module and direction.
+        #[arg(long = "outstanding", value_parser = parse_outstanding)]
+        outstanding: Vec<OutstandingGuardianFee>,
+        /// Smallest output the stability pool accepts, its `min_allowed_seek`.
+        #[arg(long, default_value_t = 0)]
+        minimum_msats: u64,
+    },
+}
+
+pub fn run(command: GuardianFeeCommand) -> anyhow::Result<serde_json::Value> {
+    match command {
+        GuardianFeeCommand::CheckAccount { account } => {
+            let account = serde_json::from_str::<Account>(&account).context("invalid account")?;
+            validate_guardian_fee_remittance_account(&account)?;
+            Ok(json!({ "account_id": account.id().to_string() }))
+        }
+        GuardianFeeCommand::BuildMeta {
+            send_ppm,
+            recipients,
+            send_policy,
+        } => {
+            let send_policy = match send_policy {
+                Some(send_policy) => serde_json::from_str::<FediFeePolicy>(&send_policy)
+                    .context("invalid send policy")?,
+                None => FediFeePolicy::default(),
+            };
+            let meta = fedi_guardian_fee_config_meta(&FediGuardianFeeConfig {
+                send_ppm,
+                recipients,
+                send_policy,
+            })?;
+            Ok(json!({ "meta": meta }))
+        }
+        GuardianFeeCommand::PreviewRemittance {
+            meta,
+            outstanding,
+            minimum_msats,
+        } => {
+            let meta = serde_json::from_str::<BTreeMap<String, String>>(&meta)
+                .context("meta must be a JSON object of strings")?;
+            let Some(config) = parse_fedi_guardian_fee_config(&meta)? else {
+                bail!("meta has no guardian fee config");
+            };
+            let preview = preview_guardian_fee_remittance(
+                &outstanding.iter().map(|fee| fee.amount).collect::<Vec<_>>(),
+                &config.recipients,
+                Amount::from_msats(minimum_msats),
+            )?;
+            Ok(json!({
+                "send_ppm": config.send_ppm,
+                "total_weight": config
+                    .recipients
+                    .iter()
+                    .map(|recipient| recipient.weight)
+                    .sum::<u64>(),
+                "outstanding": outstanding
+                    .iter()
+                    .map(|fee| json!({
+                        "module": fee.module.to_string(),
+                        "direction": if fee.send { "send" } else { "receive" },
+                        "amount_msats": fee.amount.msats,
+                    }))
+                    .collect::<Vec<_>>(),
+                "remittance": preview.map(|preview| json!({
+                    "amount_msats": preview.amount.msats,
+                    "retained_msats": preview.retained.msats,
+                    "recipients": config
+                        .recipients
+                        .iter()
+                        .zip(preview.recipients)
+                        .map(|(recipient, (account_id, amount))| json!({
+                            "account_id": account_id.to_string(),
+                            "weight": recipient.weight,
+                            "amount_msats": amount.msats,
+                        }))
+                        .collect::<Vec<_>>(),
+                })),
+            }))
+        }
+    }
+}
+
+fn parse_recipient(value: &str) -> anyhow::Result<FediGuardianFeeRecipient> {
+    let (pubkey, weight) = value
+        .split_once(':')
+        .context("recipient must be <pubkey>:<weight>")?;
+    Ok(FediGuardianFeeRecipient {
+        account: Account::single(PublicKey::from_str(pubkey)?, AccountType::BtcDepositor),
+        weight: weight.parse()?,
+    })
+}
+
+/// Guardian fees outstanding for one module and direction, which the
+/// remittance splits between the recipients on its own.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OutstandingGuardianFee {
+    module: ModuleKind,
+    send: bool,
+    amount: Amount,
+}
+
+fn parse_outstanding(value: &str) -> anyhow::Result<OutstandingGuardianFee> {
+    let mut parts = value.splitn(3, ':');
+    let (Some(module), Some(direction), Some(msats)) = (parts.next(), parts.next(), parts.next())
+    else {
+        bail!("outstanding fees must be <module>:<send|receive>:<msats>");
+    };
+    Ok(OutstandingGuardianFee {
+        module: ModuleKind::clone_from_str(module),
+        send: match direction {
+            "send" => true,
+            "receive" => false,
+            _ => bail!("direction must be send or receive"),
+        },
+        amount: Amount::from_msats(msats.parse()?),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use bitcoin::secp256k1::{Secp256k1, SecretKey};
+
+    use super::*;
+
+    #[test]
+    fn parses_recipient_pubkey_and_weight() {
+        let secp = Secp256k1::new();
+        let public_key =
+            PublicKey::from_secret_key(&secp, &SecretKey::from_slice(&[7; 32]).unwrap());
+        let recipient = parse_recipient(&format!("{public_key}:4")).unwrap();
+        assert_eq!(
+            recipient,
+            FediGuardianFeeRecipient {
+                account: Account::single(public_key, AccountType::BtcDepositor),
+                weight: 4,
+            }
+        );
+
+        assert!(parse_recipient(&public_key.to_string()).is_err());
+        assert!(parse_recipient(&format!("{public_key}:heavy")).is_err());
+        assert!(parse_recipient(&format!("{public_key}:-1")).is_err());
+        assert!(parse_recipient("not-a-pubkey:1").is_err());
+    }
+
+    #[test]
+    fn parses_outstanding_fees_per_module_and_direction() {
+        assert_eq!(
+            parse_outstanding("ln:send:1500").unwrap(),
+            OutstandingGuardianFee {
+                module: ModuleKind::from_static_str("ln"),
+                send: true,
+                amount: Amount::from_msats(1_500),
+            }
+        );
+        assert!(!parse_outstanding("mint:receive:0").unwrap().send);
+
+        assert!(parse_outstanding("ln:send").is_err());
+        assert!(parse_outstanding("ln:sideways:1500").is_err());
+        assert!(parse_outstanding("ln:send:1.5").is_err());
+        assert!(parse_outstanding("ln:send:1500:extra").is_err());
+    }
+}
@@ -1,8 +1,25 @@
+mod guardian_fee;
+
+use clap::Parser;
 use fedi_social_client::FediSocialClientInit;
 use fedimint_cli::FedimintCli;
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
+    // Guardian fee helpers need no client, so they are handled before
+    // fedimint-cli parses the arguments.
+    if std::env::args_os()
+        .nth(1)
+        .is_some_and(|arg| arg == guardian_fee::SUBCOMMAND)
+    {
+        let command = guardian_fee::GuardianFeeCommand::parse_from(std::env::args_os().skip(1));
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&guardian_fee::run(command)?)?
+        );
+        return Ok(());
+    }
+
     FedimintCli::new(env!("FEDIMINT_BUILD_CODE_VERSION"))?
         .with_default_modules()
         .with_module(FediSocialClientInit)
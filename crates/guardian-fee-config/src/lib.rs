@@ -0,0 +1,425 @@
+//! Guardian fee config as guardians publish it in the federation meta and apps
+//! read it back, plus the recipient split of a remittance. Kept free of
+//! client and bridge dependencies so guardian tooling can build and check the
+//! exact meta entries apps parse.
+
+use std::collections::BTreeMap;
+
+use anyhow::ensure;
+use api_types::fee_schedule::FeePolicyV0;
+use fedimint_core::Amount;
+use fedimint_core::encoding::{Decodable, Encodable};
+use serde::{Deserialize, Serialize};
+use stability_pool_common::{Account, AccountId, AccountType};
+
+// Guardians should set this to 0 to stop new guardian-fee accrual while
+// still leaving remittance config available to drain any already-accrued fee.
+pub const FEDI_GUARDIAN_FEE_SEND_PPM_META_KEY: &str = "fedi:guardian_fee_send_ppm";
+pub const FEDI_GUARDIAN_FEE_REMITTANCE_ACCOUNT_META_KEY: &str =
+    "fedi:guardian_fee_remittance_account";
+// Optional, a JSON `FediFeePolicy` with a base fee, caps and volume tiers on
+// top of the send ppm.
+pub const FEDI_GUARDIAN_FEE_SEND_POLICY_META_KEY: &str = "fedi:guardian_fee_send_policy";
+// Guardian fee config is federation-controlled metadata, so keep a very high
+// but finite sanity cap to avoid obviously broken values.
+pub const FEDI_GUARDIAN_FEE_SEND_PPM_MAX: u64 = 210_000;
+const MAX_GUARDIAN_FEE_RECIPIENTS: usize = 32;
+
+// maximum fixed fedi fee (base or minimum) that bridge would pay on a single
+// transaction.
+pub const FEDI_FEE_MAX_FIXED_MSAT: u64 = 1_000_000;
+
+#[derive(Clone, Serialize, Deserialize, PartialEq)]
+#[serde(from = "FediGuardianFeeConfigSerde")]
+pub struct FediGuardianFeeConfig {
+    /// Guardian fee charged on send-side transactions, in ppm.
+    pub send_ppm: u64,
+    /// Weighted destination accounts for guardian-fee remittances.
+    pub recipients: Vec<FediGuardianFeeRecipient>,
+    /// Base fee, caps and volume tiers applied on top of `send_ppm`.
+    pub send_policy: FediFeePolicy,
+}
+
+/// Deserialize-only adapter for app state written before weighted recipients.
+/// Runtime state and all new serialization use only `recipients`.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum FediGuardianFeeConfigSerde {
+    Current {
+        send_ppm: u64,
+        recipients: Vec<FediGuardianFeeRecipient>,
+        #[serde(default)]
+        send_policy: FediFeePolicy,
+    },
+    Legacy {
+        send_ppm: u64,
+        remittance_account: Account,
+    },
+}
+
+impl From<FediGuardianFeeConfigSerde> for FediGuardianFeeConfig {
+    fn from(config: FediGuardianFeeConfigSerde) -> Self {
+        match config {
+            FediGuardianFeeConfigSerde::Current {
+                send_ppm,
+                recipients,
+                send_policy,
+            } => Self {
+                send_ppm,
+                recipients,
+                send_policy,
+            },
+            FediGuardianFeeConfigSerde::Legacy {
+                send_ppm,
+                remittance_account,
+            } => Self {
+                send_ppm,
+                recipients: vec![FediGuardianFeeRecipient {
+                    account: remittance_account,
+                    weight: 1,
+                }],
+                send_policy: FediFeePolicy::default(),
+            },
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct FediGuardianFeeRecipient {
+    pub account: Account,
+    pub weight: u64,
+}
+
+/// Turns the flat ppm of a fee into a tiered and capped one. The default
+/// policy leaves the flat ppm as is.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq, Encodable, Decodable)]
+pub struct FediFeePolicy {
+    /// Fixed fee added to the ppm fee of every transaction.
+    #[serde(default)]
+    pub base_msat: u64,
+
+    /// Least fee charged on a transaction.
+    #[serde(default)]
+    pub min_msat: u64,
+
+    /// Most fee charged on a transaction, so that large payments stop paying
+    /// ppm once they reach it.
+    #[serde(default)]
+    pub max_msat: Option<u64>,
+
+    /// Replace the flat ppm once the transaction volume over the rolling
+    /// window reaches their threshold. Sorted by threshold.
+    #[serde(default)]
+    pub volume_tiers: Vec<FediFeeVolumeTier>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Encodable, Decodable)]
+pub struct FediFeeVolumeTier {
+    pub min_volume_msat: u64,
+    pub ppm: u64,
+}
+
+impl From<FeePolicyV0> for FediFeePolicy {
+    fn from(policy: FeePolicyV0) -> Self {
+        Self {
+            base_msat: policy.base_msat,
+            min_msat: policy.min_msat,
+            max_msat: policy.max_msat,
+            volume_tiers: policy
+                .volume_tiers
+                .into_iter()
+                .map(|tier| FediFeeVolumeTier {
+                    min_volume_msat: tier.min_volume_msat,
+                    ppm: tier.ppm,
+                })
+                .collect(),
+        }
+    }
+}
+
+/// Checks a fee policy against the sanity caps of its stream.
+pub fn validate_fee_policy(policy: &FediFeePolicy, max_ppm: u64) -> anyhow::Result<()> {
+    ensure!(
+        policy.base_msat <= FEDI_FEE_MAX_FIXED_MSAT && policy.min_msat <= FEDI_FEE_MAX_FIXED_MSAT,
+        "fedi fee base and minimum must be <= {FEDI_FEE_MAX_FIXED_MSAT} msat"
+    );
+    ensure!(
+        policy.max_msat.is_none_or(|max| max >= policy.min_msat),
+        "fedi fee maximum must not be below the minimum"
+    );
+    ensure!(
+        policy
+            .volume_tiers
+            .windows(2)
+            .all(|tiers| tiers[0].min_volume_msat < tiers[1].min_volume_msat),
+        "fedi fee volume tiers must be sorted by volume"
+    );
+    ensure!(
+        policy.volume_tiers.iter().all(|tier| tier.ppm <= max_ppm),
+        "fedi fee volume tier ppm must be <= {max_ppm}"
+    );
+    Ok(())
+}
+
+/// Manifold keeps using the legacy remittance-account meta key, but replaces
+/// its single `Account` value with this versioned weighted list for new
+/// federations.
+#[derive(Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+struct GuardianFeeRecipientList {
+    version: u16,
+    recipients: Vec<FediGuardianFeeRecipient>,
+}
+
+pub fn parse_fedi_guardian_fee_config(
+    meta: &BTreeMap<String, String>,
+) -> anyhow::Result<Option<FediGuardianFeeConfig>> {
+    let guardian_fee_send_ppm = meta.get(FEDI_GUARDIAN_FEE_SEND_PPM_META_KEY);
+    let remittance_accounts = meta.get(FEDI_GUARDIAN_FEE_REMITTANCE_ACCOUNT_META_KEY);
+    let send_policy = meta.get(FEDI_GUARDIAN_FEE_SEND_POLICY_META_KEY);
+
+    let (Some(guardian_fee_send_ppm), Some(remittance_accounts)) =
+        (guardian_fee_send_ppm, remittance_accounts)
+    else {
+        ensure!(
+            guardian_fee_send_ppm.is_none() && remittance_accounts.is_none(),
+            "guardian fee config must define both {} and {}",
+            FEDI_GUARDIAN_FEE_SEND_PPM_META_KEY,
+            FEDI_GUARDIAN_FEE_REMITTANCE_ACCOUNT_META_KEY,
+        );
+        ensure!(
+            send_policy.is_none(),
+            "{} requires a guardian fee config",
+            FEDI_GUARDIAN_FEE_SEND_POLICY_META_KEY,
+        );
+        return Ok(None);
+    };
+
+    let send_ppm = guardian_fee_send_ppm.parse::<u64>()?;
+    ensure!(
+        send_ppm <= FEDI_GUARDIAN_FEE_SEND_PPM_MAX,
+        "guardian fee send ppm must be <= {}",
+        FEDI_GUARDIAN_FEE_SEND_PPM_MAX,
+    );
+    // Existing federations publish one Account. Manifold federations publish
+    // the versioned list under the same meta key.
+    let recipients = match serde_json::from_str::<Account>(remittance_accounts) {
+        Ok(account) => vec![FediGuardianFeeRecipient { account, weight: 1 }],
+        Err(_) => {
+            let list: GuardianFeeRecipientList = serde_json::from_str(remittance_accounts)?;
+            ensure!(
+                list.version == 1,
+                "unsupported guardian fee recipient list version"
+            );
+            list.recipients
+        }
+    };
+    let send_policy = match send_policy {
+        Some(send_policy) => serde_json::from_str::<FediFeePolicy>(send_policy)?,
+        None => FediFeePolicy::default(),
+    };
+    validate_fee_policy(&send_policy, FEDI_GUARDIAN_FEE_SEND_PPM_MAX)?;
+    let config = FediGuardianFeeConfig {
+        send_ppm,
+        recipients,
+        send_policy,
+    };
+    validate_guardian_fee_recipients(&config.recipients)?;
+
+    Ok(Some(config))
+}
+
+/// Meta entries that publish `config`, with recipients in the canonical order
+/// [`parse_fedi_guardian_fee_config`] requires. A lone recipient is published
+/// as a bare `Account` so apps predating weighted recipients can still read
+/// it.
+pub fn fedi_guardian_fee_config_meta(
+    config: &FediGuardianFeeConfig,
+) -> anyhow::Result<BTreeMap<String, String>> {
+    let mut config = config.clone();
+    config
+        .recipients
+        .sort_by_key(|recipient| recipient.account.id());
+    let remittance_accounts = match config.recipients.as_slice() {
+        [recipient] if recipient.weight == 1 => serde_json::to_string(&recipient.account)?,
+        recipients => serde_json::to_string(&GuardianFeeRecipientList {
+            version: 1,
+            recipients: recipients.to_vec(),
+        })?,
+    };
+    let mut meta = BTreeMap::from([
+        (
+            FEDI_GUARDIAN_FEE_SEND_PPM_META_KEY.to_owned(),
+            config.send_ppm.to_string(),
+        ),
+        (
+            FEDI_GUARDIAN_FEE_REMITTANCE_ACCOUNT_META_KEY.to_owned(),
+            remittance_accounts,
+        ),
+    ]);
+    if config.send_policy != FediFeePolicy::default() {
+        meta.insert(
+            FEDI_GUARDIAN_FEE_SEND_POLICY_META_KEY.to_owned(),
+            serde_json::to_string(&config.send_policy)?,
+        );
+    }
+    ensure!(
+        parse_fedi_guardian_fee_config(&meta)?.as_ref() == Some(&config),
+        "guardian fee config does not round trip through its meta entries"
+    );
+    Ok(meta)
+}
+
+/// Checks that `account` can receive guardian fee remittances, which are
+/// deposits to single-sig btc-balance accounts.
+pub fn validate_guardian_fee_remittance_account(account: &Account) -> anyhow::Result<()> {
+    ensure!(
+        account.acc_type() == AccountType::BtcDepositor,
+        "guardian fee remittance account must be a btc-balance account"
+    );
+    ensure!(
+        account.as_single().is_some(),
+        "guardian fee remittance account must be single-sig"
+    );
+    Ok(())
+}
+
+/// Validates the wire policy and returns its total weight.
+///
+/// Sorted, unique account ids make the metadata canonical and prevent two
+/// outputs for the same account in the single remittance transaction.
+pub fn validate_guardian_fee_recipients(
+    recipients: &[FediGuardianFeeRecipient],
+) -> anyhow::Result<u64> {
+    ensure!(
+        !recipients.is_empty() && recipients.len() <= MAX_GUARDIAN_FEE_RECIPIENTS,
+        "guardian fee recipient count must be between 1 and {MAX_GUARDIAN_FEE_RECIPIENTS}"
+    );
+    let mut previous: Option<AccountId> = None;
+    let mut total_weight = 0_u64;
+    for recipient in recipients {
+        validate_guardian_fee_remittance_account(&recipient.account)?;
+        ensure!(
+            recipient.weight != 0,
+            "guardian fee recipient weight must be positive"
+        );
+        let account_id = recipient.account.id();
+        ensure!(
+            previous.as_ref().is_none_or(|last| last < &account_id),
+            "guardian fee recipients must be unique and sorted by account id"
+        );
+        previous = Some(account_id);
+        total_weight = total_weight
+            .checked_add(recipient.weight)
+            .ok_or_else(|| anyhow::anyhow!("guardian fee recipient weights overflow"))?;
+    }
+    Ok(total_weight)
+}
+
+/// Checked equivalent of summing `Amount`s with `+`, whose inner value is a
+/// `u64`.
+pub fn checked_amount_sum(amounts: impl IntoIterator<Item = Amount>) -> anyhow::Result<Amount> {
+    amounts
+        .into_iter()
+        .try_fold(0_u64, |total, amount| total.checked_add(amount.msats))
+        .map(Amount::from_msats)
+        .ok_or_else(|| anyhow::anyhow!("guardian fee amount overflow"))
+}
+
+/// How one remittance divides the outstanding fee rows between the weighted
+/// recipients.
+pub struct GuardianFeeRemittanceSplit {
+    /// Sum of all recipient outputs.
+    pub amount: Amount,
+    /// Part of every outstanding row that is remitted, in row order. Zero for
+    /// rows too small to split.
+    pub remitted: Vec<Amount>,
+    /// Output of every recipient, in recipient order.
+    pub recipient_amounts: Vec<Amount>,
+    /// Every recipient's share of every outstanding row, in recipient and
+    /// then row order.
+    pub shares: Vec<Vec<Amount>>,
+}
+
+/// Applies the recipient weights at remit time without changing accrual.
+///
+/// Each outstanding row is divided by the total weight, giving every recipient
+/// `unit * weight`. The indivisible remainder stays in the aggregate ledger
+/// for a future remittance. Returning `None` until every output reaches SPv2's
+/// minimum lets all recipients be submitted in one atomic transaction.
+pub fn split_guardian_fee_remittance(
+    outstanding: &[Amount],
+    recipients: &[FediGuardianFeeRecipient],
+    minimum: Amount,
+) -> anyhow::Result<Option<GuardianFeeRemittanceSplit>> {
+    let total_weight = validate_guardian_fee_recipients(recipients)?;
+    let mut remitted = Vec::with_capacity(outstanding.len());
+    let mut shares = vec![Vec::with_capacity(outstanding.len()); recipients.len()];
+
+    for amount in outstanding {
+        let unit = amount.msats / total_weight;
+        remitted.push(Amount::from_msats(
+            unit.checked_mul(total_weight)
+                .ok_or_else(|| anyhow::anyhow!("guardian fee remittance amount overflow"))?,
+        ));
+        for (recipient, recipient_shares) in recipients.iter().zip(&mut shares) {
+            recipient_shares.push(Amount::from_msats(
+                unit.checked_mul(recipient.weight)
+                    .ok_or_else(|| anyhow::anyhow!("guardian fee recipient share overflow"))?,
+            ));
+        }
+    }
+
+    let amount = checked_amount_sum(remitted.iter().copied())?;
+    let recipient_amounts = shares
+        .iter()
+        .map(|recipient_shares| checked_amount_sum(recipient_shares.iter().copied()))
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    if amount == Amount::ZERO || recipient_amounts.iter().any(|amount| *amount < minimum) {
+        return Ok(None);
+    }
+    ensure!(
+        checked_amount_sum(recipient_amounts.iter().copied())? == amount,
+        "guardian fee remittance split does not conserve millisatoshis"
+    );
+    Ok(Some(GuardianFeeRemittanceSplit {
+        amount,
+        remitted,
+        recipient_amounts,
+        shares,
+    }))
+}
+
+/// What the next guardian remittance would pay out, for operators checking
+/// their recipient policy before publishing it.
+pub struct GuardianFeeRemittancePreview {
+    /// Sum of all recipient outputs.
+    pub amount: Amount,
+    /// Indivisible dust that stays outstanding for a later remittance.
+    pub retained: Amount,
+    /// Output of every recipient, in recipient order.
+    pub recipients: Vec<(AccountId, Amount)>,
+}
+
+/// Splits the `outstanding` fee rows without touching any ledger. Returns
+/// `None` while some recipient's share is still below `minimum`, in which
+/// case nothing would be remitted yet.
+pub fn preview_guardian_fee_remittance(
+    outstanding: &[Amount],
+    recipients: &[FediGuardianFeeRecipient],
+    minimum: Amount,
+) -> anyhow::Result<Option<GuardianFeeRemittancePreview>> {
+    let total = checked_amount_sum(outstanding.iter().copied())?;
+    let Some(split) = split_guardian_fee_remittance(outstanding, recipients, minimum)? else {
+        return Ok(None);
+    };
+    Ok(Some(GuardianFeeRemittancePreview {
+        amount: split.amount,
+        retained: total.saturating_sub(split.amount),
+        recipients: recipients
+            .iter()
+            .map(|recipient| recipient.account.id())
+            .zip(split.recipient_amounts)
+            .collect(),
+    }))
+}
@@ -68,6 +68,20 @@ pub enum ErrorCode {
     PinnedMessageLimitExceeded,
     #[error("Message reaction limit exceeded")]
     MatrixReactionLimitExceeded,
+    #[error("Invalid LNURL or lightning address")]
+    InvalidLnurl,
+    #[error("LNURL service returned an error: {0}")]
+    LnurlServiceError(String),
+    #[error("Invoice has no amount, paying it is not supported yet")]
+    InvoiceAmountRequired,
+    #[error("Spending limit exceeded")]
+    SpendingLimitExceeded,
+    #[error("Spending limits must be unlocked before they can be loosened")]
+    SpendingLimitsLocked,
+    #[error("Ecash notes are not valid for this federation")]
+    InvalidEcash,
+    #[error("No Lightning gateway can route the payment within the network fee budget")]
+    NetworkFeeExceedsBudget,
 }
 
 #[derive(Debug, Serialize, Deserialize, TS, Clone)]
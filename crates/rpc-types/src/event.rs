@@ -10,8 +10,8 @@ use ts_rs::TS;
 
 use crate::communities::RpcCommunity;
 use crate::{
-    RpcAmount, RpcFederationId, RpcFederationMaybeLoading, RpcOperationId, RpcTransaction,
-    SocialRecoveryApproval,
+    RpcAmount, RpcFederationEndOfLife, RpcFederationId, RpcFederationMaybeLoading,
+    RpcGuardianHealthLevel, RpcOperationId, RpcTransaction, SocialRecoveryApproval,
 };
 
 #[derive(Serialize, Deserialize, Debug, TS)]
@@ -263,6 +263,89 @@ pub struct NonceReuseCheckFailedEvent {
     pub federation_id: RpcFederationId,
 }
 
+/// Sent once per warning level as the announced end of a federation
+/// approaches.
+#[derive(Serialize, Debug, TS)]
+#[serde(rename_all = "camelCase")]
+#[ts(export)]
+pub struct FederationEndOfLifeEvent {
+    pub federation_id: RpcFederationId,
+    pub warning: FederationEndOfLifeWarning,
+    pub end_of_life: RpcFederationEndOfLife,
+}
+
+#[derive(Serialize, Debug, TS)]
+#[serde(rename_all = "camelCase")]
+#[ts(export)]
+pub enum FederationEndOfLifeWarning {
+    /// Ends within 30 days.
+    Upcoming,
+    /// Ends within 7 days.
+    Imminent,
+    /// Ends within a day.
+    Final,
+    Ended,
+}
+
+/// Sent when the guardians online in a federation fall toward or below the
+/// consensus threshold, and again once they recover.
+#[derive(Serialize, Debug, TS)]
+#[serde(rename_all = "camelCase")]
+#[ts(export)]
+pub struct GuardianHealthEvent {
+    pub federation_id: RpcFederationId,
+    pub level: RpcGuardianHealthLevel,
+    pub online: u32,
+    pub threshold: u32,
+    pub total: u32,
+}
+
+/// Sent once when a wallet is found to have reused ecash nonces, until it is
+/// repaired with `runWalletRepair`.
+#[derive(Serialize, Debug, TS)]
+#[serde(rename_all = "camelCase")]
+#[ts(export)]
+pub struct WalletRepairNeededEvent {
+    pub federation_id: RpcFederationId,
+    /// Ecash stuck in outputs with reused nonces. Zero if only the nonces the
+    /// wallet would use next are already spent.
+    pub reused_ecash: RpcAmount,
+}
+
+/// Progress of `evacuateFederation`, reported once per step.
+#[derive(Serialize, Debug, TS)]
+#[serde(rename_all = "camelCase")]
+#[ts(export)]
+pub struct FederationEvacuationEvent {
+    pub federation_id: RpcFederationId,
+    pub state: FederationEvacuationState,
+}
+
+#[derive(Serialize, Debug, TS)]
+#[serde(
+    tag = "type",
+    rename_all = "camelCase",
+    rename_all_fields = "camelCase"
+)]
+#[ts(export)]
+pub enum FederationEvacuationState {
+    WithdrawingStableBalance,
+    /// Reported again whenever the number of pending operations changes.
+    WaitingForPendingOperations {
+        remaining: u32,
+    },
+    /// Sending `amount` to the evacuation target, fees excluded.
+    Sweeping {
+        amount: RpcAmount,
+    },
+    Leaving,
+    Left,
+    /// The evacuation stopped. Funds moved so far stay where they are.
+    Failed {
+        error: String,
+    },
+}
+
 #[derive(Debug, TS, VariantNames)]
 #[ts(export)]
 #[ts(rename_all = "camelCase")]
@@ -285,6 +368,10 @@ pub enum Event {
     CommunityMetadataUpdated(CommunityMetadataUpdatedEvent),
     NonceReuseCheckFailed(NonceReuseCheckFailedEvent),
     CommunityMigratedToV2(CommunityMigratedToV2Event),
+    FederationEvacuation(FederationEvacuationEvent),
+    FederationEndOfLife(FederationEndOfLifeEvent),
+    GuardianHealth(GuardianHealthEvent),
+    WalletRepairNeeded(WalletRepairNeededEvent),
 }
 
 impl Event {
@@ -466,6 +553,51 @@ impl Event {
     pub fn nonce_reuse_check_failed(federation_id: RpcFederationId) -> Self {
         Self::NonceReuseCheckFailed(NonceReuseCheckFailedEvent { federation_id })
     }
+
+    pub fn federation_evacuation(
+        federation_id: RpcFederationId,
+        state: FederationEvacuationState,
+    ) -> Self {
+        Self::FederationEvacuation(FederationEvacuationEvent {
+            federation_id,
+            state,
+        })
+    }
+
+    pub fn federation_end_of_life(
+        federation_id: RpcFederationId,
+        warning: FederationEndOfLifeWarning,
+        end_of_life: RpcFederationEndOfLife,
+    ) -> Self {
+        Self::FederationEndOfLife(FederationEndOfLifeEvent {
+            federation_id,
+            warning,
+            end_of_life,
+        })
+    }
+
+    pub fn guardian_health(
+        federation_id: RpcFederationId,
+        level: RpcGuardianHealthLevel,
+        online: u32,
+        threshold: u32,
+        total: u32,
+    ) -> Self {
+        Self::GuardianHealth(GuardianHealthEvent {
+            federation_id,
+            level,
+            online,
+            threshold,
+            total,
+        })
+    }
+
+    pub fn wallet_repair_needed(federation_id: RpcFederationId, reused_ecash: RpcAmount) -> Self {
+        Self::WalletRepairNeeded(WalletRepairNeededEvent {
+            federation_id,
+            reused_ecash,
+        })
+    }
 }
 
 pub trait TypedEventExt: IEventSink {
@@ -506,6 +638,10 @@ fn serialize_event(event: &Event) -> (String, String) {
         Event::CommunityMetadataUpdated(event) => ("communityMetadataUpdated".into(), body(event)),
         Event::CommunityMigratedToV2(event) => ("communityMigratedToV2".into(), body(event)),
         Event::NonceReuseCheckFailed(event) => ("nonceReuseCheckFailed".into(), body(event)),
+        Event::FederationEvacuation(event) => ("federationEvacuation".into(), body(event)),
+        Event::FederationEndOfLife(event) => ("federationEndOfLife".into(), body(event)),
+        Event::GuardianHealth(event) => ("guardianHealth".into(), body(event)),
+        Event::WalletRepairNeeded(event) => ("walletRepairNeeded".into(), body(event)),
     }
 }
 
@@ -16,7 +16,7 @@ use fedimint_mint_client::{ReissueExternalNotesState, SpendOOBState};
 use fedimint_wallet_client::{DepositStateV2, WithdrawState};
 use matrix::RpcRoomId;
 use runtime::api::RegisteredDevice;
-use runtime::storage::state::{FediFeeSchedule, FiatFXInfo};
+use runtime::storage::state::{FediFeePolicy, FediFeeSchedule, FiatFXInfo};
 use runtime::utils::to_unix_time;
 use serde::de::{self, MapAccess, Visitor};
 use serde::{Deserialize, Deserializer, Serialize};
@@ -31,9 +31,11 @@ use crate::error::RpcError;
 pub mod communities;
 pub mod error;
 pub mod event;
+pub mod lnurl;
 pub mod matrix;
 pub mod multispend;
 pub mod nostril;
+pub mod payment_string;
 pub mod sp_transfer;
 pub mod spv2_transfer_meta;
 
@@ -124,6 +126,66 @@ pub struct RpcFederation {
     pub client_config: Option<RpcJsonClientConfig>,
     pub fedi_fee_schedule: RpcFediFeeSchedule,
     pub had_reused_ecash: bool,
+    /// Set when the federation announced in its meta that it shuts down.
+    pub end_of_life: Option<RpcFederationEndOfLife>,
+    /// Set while `recovering`.
+    pub recovery_progress: Option<RpcRecoveryProgress>,
+}
+
+/// How far restoring a federation's wallet got, see `getRecoveryProgress`.
+#[derive(Debug, Clone, Serialize, Deserialize, TS)]
+#[serde(rename_all = "camelCase")]
+#[ts(export)]
+pub struct RpcRecoveryProgress {
+    /// Whether the wallet is recovered from scratch after failing the nonce
+    /// reuse check, see `listFederationsPendingRejoinFromScratch`.
+    pub from_scratch: bool,
+    /// Modules that reported progress so far.
+    pub modules: Vec<RpcModuleRecoveryProgress>,
+    /// Estimated seconds until every module has recovered, `null` until
+    /// enough sessions were processed to tell.
+    pub eta_secs: Option<u32>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, TS)]
+#[serde(rename_all = "camelCase")]
+#[ts(export)]
+pub struct RpcModuleRecoveryProgress {
+    pub module_instance_id: u16,
+    pub kind: String,
+    /// Sessions processed.
+    pub complete: u32,
+    /// Sessions to process, `0` if the module has not started yet.
+    pub total: u32,
+}
+
+/// Outcome of `runWalletRepair`.
+#[derive(Debug, Clone, Serialize, Deserialize, TS)]
+#[serde(rename_all = "camelCase")]
+#[ts(export)]
+pub struct RpcWalletRepairReport {
+    /// Ecash stuck in outputs with reused nonces. Proofs for it were backed up
+    /// and are attached to bug reports, so support can refund it.
+    pub reused_ecash: RpcAmount,
+    pub balance_before: RpcAmount,
+    /// Balance the repair added, from ecash it found unspent.
+    pub recovered: RpcAmount,
+    /// Balance the repair found to be already spent.
+    pub lost: RpcAmount,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, TS)]
+#[serde(rename_all = "camelCase")]
+#[ts(export)]
+pub struct RpcFederationEndOfLife {
+    /// Unix time in seconds.
+    #[ts(type = "number")]
+    pub ends_at: u64,
+    pub successor_invite_code: Option<String>,
+    pub withdrawal_instructions: Option<String>,
+    /// Whether the user opted into evacuating into the successor once the
+    /// final warning is sent, see `setFederationAutoEvacuate`.
+    pub auto_evacuate: bool,
 }
 
 #[derive(Debug, Serialize, Deserialize, TS)]
@@ -202,6 +264,54 @@ pub enum GuardianStatus {
     Timeout { guardian: String, elapsed: String },
 }
 
+/// Guardian reachability as recorded by the federation's health monitor, see
+/// `getGuardianHealthHistory`.
+#[derive(Debug, Clone, Serialize, Deserialize, TS)]
+#[serde(rename_all = "camelCase")]
+#[ts(export)]
+pub struct RpcGuardianHealthHistory {
+    pub total: u32,
+    /// Guardians that must be online for the federation to reach consensus.
+    pub threshold: u32,
+    /// Level of the latest check, `null` before the first one.
+    pub level: Option<RpcGuardianHealthLevel>,
+    /// Oldest first.
+    pub checks: Vec<RpcGuardianHealthCheck>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, TS)]
+#[serde(rename_all = "camelCase")]
+#[ts(export)]
+pub struct RpcGuardianHealthCheck {
+    /// Unix time in seconds.
+    #[ts(type = "number")]
+    pub checked_at: u64,
+    pub guardians: Vec<RpcGuardianHealth>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, TS)]
+#[serde(rename_all = "camelCase")]
+#[ts(export)]
+pub struct RpcGuardianHealth {
+    pub guardian: String,
+    /// `null` if the guardian did not answer.
+    pub latency_ms: Option<u32>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, TS)]
+#[serde(rename_all = "camelCase")]
+#[ts(export)]
+pub enum RpcGuardianHealthLevel {
+    /// All guardians are online.
+    Healthy,
+    /// Some guardians are offline, but more than the threshold are online.
+    Degraded,
+    /// Exactly the threshold is online, one more failure stops consensus.
+    AtRisk,
+    /// Fewer than the threshold are online, payments will fail.
+    BelowThreshold,
+}
+
 #[derive(Debug, Serialize, Deserialize, TS)]
 #[serde(rename_all = "camelCase")]
 #[ts(export)]
@@ -316,21 +426,176 @@ impl TryFrom<lightning_invoice::Bolt11Invoice> for RpcInvoice {
             .ok_or(anyhow!("Invoice missing amount"))?;
         let amount = fedimint_core::Amount::from_msats(amount_msat);
 
-        // We might get no description
-        let description = match invoice.description() {
-            lightning_invoice::Bolt11InvoiceDescriptionRef::Direct(desc) => desc.to_string(),
-            lightning_invoice::Bolt11InvoiceDescriptionRef::Hash(_) => "".to_string(),
-        };
-
         Ok(RpcInvoice {
             amount: RpcAmount(amount),
-            description,
+            description: bolt11_description(&invoice),
             invoice: invoice.to_string(),
             payment_hash: invoice.payment_hash().to_string(),
         })
     }
 }
 
+/// Result of `parseInvoice`. Invoices without an amount leave it to the
+/// payer and cannot be paid yet, nor can BOLT12 offers.
+#[derive(Clone, Debug, Serialize, TS)]
+#[serde(
+    rename_all = "camelCase",
+    rename_all_fields = "camelCase",
+    tag = "type"
+)]
+#[ts(export)]
+pub enum RpcParsedInvoice {
+    Fixed(RpcInvoice),
+    AmountRequired {
+        payment_hash: String,
+        description: String,
+        invoice: String,
+    },
+    Offer(RpcBolt12Offer),
+}
+
+impl From<lightning_invoice::Bolt11Invoice> for RpcParsedInvoice {
+    fn from(invoice: lightning_invoice::Bolt11Invoice) -> Self {
+        if invoice.amount_milli_satoshis().is_none() {
+            return RpcParsedInvoice::AmountRequired {
+                description: bolt11_description(&invoice),
+                invoice: invoice.to_string(),
+                payment_hash: invoice.payment_hash().to_string(),
+            };
+        }
+        RpcParsedInvoice::Fixed(
+            RpcInvoice::try_from(invoice).expect("invoice amount was checked above"),
+        )
+    }
+}
+
+fn bolt11_description(invoice: &lightning_invoice::Bolt11Invoice) -> String {
+    // We might get no description
+    match invoice.description() {
+        lightning_invoice::Bolt11InvoiceDescriptionRef::Direct(desc) => desc.to_string(),
+        lightning_invoice::Bolt11InvoiceDescriptionRef::Hash(_) => "".to_string(),
+    }
+}
+
+/// A single request that can be paid over lightning, onchain or, optionally,
+/// into the stable balance.
+#[derive(Clone, Debug, Serialize, TS)]
+#[serde(rename_all = "camelCase")]
+#[ts(export)]
+pub struct RpcUnifiedReceive {
+    pub receive_id: String,
+    /// BIP321 `bitcoin:` URI carrying every leg.
+    pub uri: String,
+    /// Same as `uri`, with the bech32 parts uppercased so the QR code can
+    /// use the denser alphanumeric mode.
+    pub qr_payload: String,
+    pub invoice: String,
+    pub onchain_address: String,
+    pub spv2_payment_address: Option<String>,
+}
+
+/// A lightning payment from one joined federation to another, see
+/// `transferBetweenFederations`.
+#[derive(Clone, Debug, Serialize, TS)]
+#[serde(rename_all = "camelCase")]
+#[ts(export)]
+pub struct RpcFederationTransfer {
+    pub transfer_id: String,
+    pub from_federation_id: RpcFederationId,
+    pub to_federation_id: RpcFederationId,
+    pub amount: RpcAmount,
+    /// Fees the source federation was expected to charge when the transfer
+    /// started.
+    pub estimated_fees: RpcAmount,
+    pub state: RpcFederationTransferState,
+}
+
+#[derive(Clone, Debug, Serialize, TS)]
+#[serde(rename_all = "camelCase")]
+#[serde(tag = "type")]
+#[ts(export)]
+pub enum RpcFederationTransferState {
+    /// The source federation is paying the invoice of the destination.
+    Paying,
+    /// Paid, waiting for the destination to claim the payment.
+    Paid,
+    Completed,
+    Failed {
+        error: String,
+    },
+}
+
+/// Where `evacuateFederation` sends the balance of the federation it leaves.
+#[derive(Clone, Debug, Serialize, Deserialize, TS)]
+#[serde(
+    tag = "type",
+    rename_all = "camelCase",
+    rename_all_fields = "camelCase"
+)]
+#[ts(export)]
+pub enum RpcEvacuationTarget {
+    /// Another joined federation, paid over lightning.
+    Federation {
+        federation_id: RpcFederationId,
+    },
+    Onchain {
+        address: String,
+    },
+}
+
+/// What can be learned from a BOLT12 offer without contacting the offering
+/// node.
+#[derive(Clone, Debug, Serialize, TS)]
+#[serde(rename_all = "camelCase")]
+#[ts(export)]
+pub struct RpcBolt12Offer {
+    pub offer: String,
+    /// Absent for offers that let the payer choose the amount.
+    pub amount: Option<RpcAmount>,
+    pub description: Option<String>,
+    pub issuer: Option<String>,
+    /// Unix timestamp in seconds.
+    #[ts(type = "number | null")]
+    pub expires_at: Option<u64>,
+}
+
+impl std::str::FromStr for RpcBolt12Offer {
+    type Err = anyhow::Error;
+
+    fn from_str(offer: &str) -> anyhow::Result<Self> {
+        let offer = offer.trim().to_lowercase();
+        let offer = offer.strip_prefix("lightning:").unwrap_or(&offer);
+        let offer = offer
+            .parse::<lightning::offers::offer::Offer>()
+            .map_err(|e| anyhow!("Invalid offer: {e:?}"))?;
+        Self::try_from(offer)
+    }
+}
+
+impl TryFrom<lightning::offers::offer::Offer> for RpcBolt12Offer {
+    type Error = anyhow::Error;
+
+    fn try_from(offer: lightning::offers::offer::Offer) -> anyhow::Result<Self> {
+        let amount = match offer.amount() {
+            None => None,
+            Some(lightning::offers::offer::Amount::Bitcoin { amount_msats }) => {
+                Some(RpcAmount(Amount::from_msats(amount_msats)))
+            }
+            Some(lightning::offers::offer::Amount::Currency { .. }) => {
+                anyhow::bail!("Offers denominated in fiat currencies are not supported")
+            }
+        };
+
+        Ok(RpcBolt12Offer {
+            amount,
+            description: offer.description().map(|d| d.to_string()),
+            issuer: offer.issuer().map(|i| i.to_string()),
+            expires_at: offer.absolute_expiry().map(|expiry| expiry.as_secs()),
+            offer: offer.to_string(),
+        })
+    }
+}
+
 #[derive(Clone, Debug, Serialize, TS)]
 #[serde(rename_all = "camelCase")]
 #[ts(export)]
@@ -341,6 +606,30 @@ pub struct RpcFeeDetails {
     pub federation_fee: RpcAmount,
 }
 
+/// Speed versus cost of an onchain withdrawal.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, TS)]
+#[serde(rename_all = "camelCase")]
+#[ts(export)]
+pub enum RpcOnchainFeeTier {
+    /// Pays the federation's own fee estimate, the least it accepts.
+    #[default]
+    Economy,
+    Normal,
+    Priority,
+}
+
+#[derive(Clone, Debug, Serialize, TS)]
+#[serde(rename_all = "camelCase")]
+#[ts(export)]
+pub struct RpcOnchainFeeQuote {
+    pub tier: RpcOnchainFeeTier,
+    pub fee_details: RpcFeeDetails,
+    /// Rough estimate, the actual time depends on the mempool. `None` without
+    /// an Esplora API to estimate it with, or when the wallet module doesn't
+    /// report a fee rate.
+    pub estimated_confirmation_mins: Option<u32>,
+}
+
 #[derive(Debug, Serialize, TS)]
 #[serde(rename_all = "camelCase")]
 #[ts(export)]
@@ -371,6 +660,27 @@ pub struct RpcLightningGateway {
     pub node_pub_key: RpcPublicKey,
     pub gateway_id: RpcPublicKey,
     pub api: String, // TODO: url::Ur;
+    /// How payments through the gateway went, `null` for lnv2 gateways which
+    /// are not scored.
+    pub score: Option<RpcGatewayScore>,
+}
+
+/// Payments this client sent through a gateway. Gateways are preferred by
+/// `score_ppm`, then by their fee.
+#[derive(Debug, Serialize, TS)]
+#[serde(rename_all = "camelCase")]
+#[ts(export)]
+pub struct RpcGatewayScore {
+    /// Estimated chance in ppm that a payment through the gateway succeeds.
+    pub score_ppm: u32,
+    pub attempts: u32,
+    pub successes: u32,
+    /// The gateway was unreachable or could not route the payment.
+    pub failures: u32,
+    /// Network fees of successful payments.
+    pub fees_paid: RpcAmount,
+    /// Of successful payments, `null` before the first one.
+    pub average_latency_ms: Option<u32>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, TS, PartialEq, Eq)]
@@ -498,6 +808,8 @@ pub struct RpcTransaction {
     pub amount: RpcAmount,
     pub fedi_app_fee_status: Option<RpcOperationFediFeeStatus>,
     pub fedi_guardian_fee_status: Option<RpcOperationFediFeeStatus>,
+    /// Set when a fee promotion discounted the app fee.
+    pub fedi_app_fee_discount: Option<RpcOperationFediFeeDiscount>,
     pub txn_notes: Option<String>,
     pub tx_date_fiat_info: Option<FiatFXInfo>,
     pub frontend_metadata: FrontendMetadata,
@@ -506,6 +818,31 @@ pub struct RpcTransaction {
     /// time when this operation was settled.
     #[ts(type = "number | null")]
     pub outcome_time: Option<u64>,
+    /// Set when this transaction is one leg of a `generateUnifiedReceive`.
+    pub unified_receive: Option<RpcUnifiedReceiveLeg>,
+    /// Set when this transaction is one leg of a `transferBetweenFederations`.
+    pub federation_transfer: Option<RpcFederationTransferLeg>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, TS)]
+#[serde(rename_all = "camelCase")]
+#[ts(export)]
+pub struct RpcUnifiedReceiveLeg {
+    /// Shared by all legs of the same unified receive.
+    pub receive_id: String,
+    /// Another leg was paid first, so this one is not expected to be paid
+    /// anymore.
+    pub superseded: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, TS)]
+#[serde(rename_all = "camelCase")]
+#[ts(export)]
+pub struct RpcFederationTransferLeg {
+    /// Shared by both legs of the same transfer.
+    pub transfer_id: String,
+    /// The federation on the other side of the transfer.
+    pub counterparty_federation_id: RpcFederationId,
 }
 
 #[derive(Debug, Deserialize, Serialize, TS)]
@@ -518,6 +855,33 @@ pub struct RpcTransactionListEntry {
     pub transaction: RpcTransaction,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, TS)]
+#[serde(rename_all = "camelCase")]
+#[ts(export)]
+pub enum RpcTransactionHistoryFormat {
+    Csv,
+    JsonLines,
+    /// BIP-329 wallet labels. Only transactions that touched the bitcoin
+    /// chain (onchain deposits and withdrawals) have something to label.
+    Bip329,
+}
+
+#[derive(Debug, Serialize, Deserialize, TS)]
+#[serde(rename_all = "camelCase")]
+#[ts(export)]
+pub struct RpcTransactionHistoryExport {
+    pub format: RpcTransactionHistoryFormat,
+    pub content: String,
+    #[ts(type = "number")]
+    pub exported_count: u64,
+    /// Federations that could not be exported because they are still
+    /// loading, recovering or failed to load.
+    pub skipped_federations: Vec<RpcFederationId>,
+    /// Transactions that failed to render and are missing from `content`.
+    #[ts(type = "number")]
+    pub failed_count: u64,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize, TS)]
 #[serde(rename_all = "camelCase")]
 #[serde(tag = "kind")]
@@ -583,6 +947,95 @@ pub enum RpcTransactionKind {
     },
 }
 
+impl RpcTransactionKind {
+    pub fn tag(&self) -> RpcTransactionKindTag {
+        match self {
+            RpcTransactionKind::LnPay { .. } => RpcTransactionKindTag::LnPay,
+            RpcTransactionKind::LnReceive { .. } => RpcTransactionKindTag::LnReceive,
+            RpcTransactionKind::LnRecurringdReceive { .. } => {
+                RpcTransactionKindTag::LnRecurringdReceive
+            }
+            RpcTransactionKind::OnchainWithdraw { .. } => RpcTransactionKindTag::OnchainWithdraw,
+            RpcTransactionKind::OnchainDeposit { .. } => RpcTransactionKindTag::OnchainDeposit,
+            RpcTransactionKind::OobSend { .. } => RpcTransactionKindTag::OobSend,
+            RpcTransactionKind::OobReceive { .. } => RpcTransactionKindTag::OobReceive,
+            RpcTransactionKind::OobCancel { .. } => RpcTransactionKindTag::OobCancel,
+            RpcTransactionKind::SpDeposit { .. } => RpcTransactionKindTag::SpDeposit,
+            RpcTransactionKind::SpWithdraw { .. } => RpcTransactionKindTag::SpWithdraw,
+            RpcTransactionKind::SPV2Deposit { .. } => RpcTransactionKindTag::SPV2Deposit,
+            RpcTransactionKind::SPV2Withdrawal { .. } => RpcTransactionKindTag::SPV2Withdrawal,
+            RpcTransactionKind::SPV2TransferOut { .. } => RpcTransactionKindTag::SPV2TransferOut,
+            RpcTransactionKind::SPV2TransferIn { .. } => RpcTransactionKindTag::SPV2TransferIn,
+        }
+    }
+
+    /// Whether the transaction moves funds out of or into the user's bitcoin
+    /// balance. Stable balance deposits count as sends and withdrawals as
+    /// receives.
+    pub fn direction(&self) -> RpcTransactionDirection {
+        match self {
+            RpcTransactionKind::LnPay { .. }
+            | RpcTransactionKind::OnchainWithdraw { .. }
+            | RpcTransactionKind::OobSend { .. }
+            | RpcTransactionKind::SpDeposit { .. }
+            | RpcTransactionKind::SPV2Deposit { .. }
+            | RpcTransactionKind::SPV2TransferOut { .. } => RpcTransactionDirection::Send,
+            RpcTransactionKind::LnReceive { .. }
+            | RpcTransactionKind::LnRecurringdReceive { .. }
+            | RpcTransactionKind::OnchainDeposit { .. }
+            | RpcTransactionKind::OobReceive { .. }
+            | RpcTransactionKind::OobCancel { .. }
+            | RpcTransactionKind::SpWithdraw { .. }
+            | RpcTransactionKind::SPV2Withdrawal { .. }
+            | RpcTransactionKind::SPV2TransferIn { .. } => RpcTransactionDirection::Receive,
+        }
+    }
+}
+
+/// The `kind` tag of [`RpcTransactionKind`] without its payload.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, TS, Encodable, Decodable)]
+#[serde(rename_all = "camelCase")]
+#[ts(export)]
+pub enum RpcTransactionKindTag {
+    LnPay,
+    LnReceive,
+    LnRecurringdReceive,
+    OnchainWithdraw,
+    OnchainDeposit,
+    OobSend,
+    OobReceive,
+    OobCancel,
+    SpDeposit,
+    SpWithdraw,
+    SPV2Deposit,
+    SPV2Withdrawal,
+    SPV2TransferOut,
+    SPV2TransferIn,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, TS)]
+#[serde(rename_all = "camelCase")]
+#[ts(export)]
+pub struct RpcTransactionSearchFilter {
+    /// Only match these kinds. Empty or missing matches every kind.
+    pub kinds: Option<Vec<RpcTransactionKindTag>>,
+    pub direction: Option<RpcTransactionDirection>,
+    /// Inclusive lower bound on the transaction amount.
+    pub min_amount: Option<RpcAmount>,
+    /// Inclusive upper bound on the transaction amount.
+    pub max_amount: Option<RpcAmount>,
+    /// Unix time (seconds), inclusive.
+    #[ts(type = "number | null")]
+    pub start_time: Option<u64>,
+    /// Unix time (seconds), exclusive.
+    #[ts(type = "number | null")]
+    pub end_time: Option<u64>,
+    /// Matches either the sender or the recipient matrix id.
+    pub matrix_user_id: Option<String>,
+    /// Case-insensitive substring of the transaction notes.
+    pub notes: Option<String>,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize, TS)]
 #[serde(rename_all = "camelCase")]
 #[serde(tag = "type")]
@@ -729,6 +1182,7 @@ impl From<WithdrawState> for RpcOnchainWithdrawState {
             WithdrawState::Created => RpcOnchainWithdrawState::Created,
             WithdrawState::Succeeded(txid) => RpcOnchainWithdrawState::Succeeded {
                 txid: txid.to_string(),
+                confirmations: None,
             },
             WithdrawState::Failed(error) => RpcOnchainWithdrawState::Failed { error },
         }
@@ -760,6 +1214,10 @@ impl RpcOnchainDepositTransactionData {
             txid: outpoint.txid.to_string(),
         }
     }
+
+    pub fn txid(&self) -> &str {
+        &self.txid
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, TS)]
@@ -768,8 +1226,15 @@ impl RpcOnchainDepositTransactionData {
 #[ts(export)]
 pub enum RpcOnchainWithdrawState {
     Created,
-    Succeeded { txid: String },
-    Failed { error: String },
+    Succeeded {
+        txid: String,
+        /// `null` until the transaction was first looked up on chain.
+        #[serde(default)]
+        confirmations: Option<u32>,
+    },
+    Failed {
+        error: String,
+    },
 }
 
 impl From<LnReceiveState> for RpcLnReceiveState {
@@ -896,10 +1361,15 @@ pub enum RpcOOBSpendState {
 #[serde(tag = "type")]
 #[ts(export)]
 pub enum RpcOOBReissueState {
+    /// Accepted while the federation was unreachable, reissued once it is
+    /// reachable again.
+    Offline,
     Created,
     Issuing,
     Done,
-    Failed { error: String },
+    Failed {
+        error: String,
+    },
 }
 
 impl From<SpendOOBState> for RpcOOBSpendState {
@@ -941,6 +1411,20 @@ pub struct FrontendMetadata {
     pub initial_notes: Option<String>,
     pub recipient_matrix_id: Option<String>,
     pub sender_matrix_id: Option<String>,
+    /// Where the funds of a receive came from, set by the bridge when it
+    /// pulls funds in on the user's behalf.
+    #[ts(optional)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub receive_source: Option<RpcReceiveSource>,
+}
+
+#[derive(Serialize, Deserialize, Debug, TS, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+#[serde(tag = "type")]
+#[ts(export)]
+pub enum RpcReceiveSource {
+    /// Claimed from an LNURL-withdraw link (LUD-03) served by `domain`.
+    LnurlWithdraw { domain: String },
 }
 
 #[derive(Serialize, Deserialize, Clone)]
@@ -1054,6 +1538,9 @@ pub enum SPv2DepositMetadata {
     GuardianFeeRemittance {
         snapshot: GuardianFeeRemittanceSnapshot,
     },
+    /// Internal app fee remittance deposit, made when no Lightning gateway
+    /// could remit the app fees.
+    AppFeeRemittance,
 }
 
 /// Guardian fee amounts snapshotted into the atomic remittance deposit
@@ -1331,6 +1818,87 @@ impl From<OperationFediFeeStatus> for RpcOperationFediFeeStatus {
     }
 }
 
+/// Fee promotion applied to an operation. Receives only know the waived fee
+/// once they succeed.
+#[derive(Debug, Encodable, Decodable, Clone)]
+pub struct OperationFediFeeDiscount {
+    pub promotion_id: String,
+    pub discount_ppm: u64,
+    pub waived_fee: Option<Amount>,
+}
+
+#[derive(Debug, Serialize, Deserialize, TS)]
+#[serde(rename_all = "camelCase")]
+#[ts(export)]
+pub struct RpcOperationFediFeeDiscount {
+    pub promotion_id: String,
+    #[ts(type = "number")]
+    pub discount_ppm: u64,
+    pub waived_fee: Option<RpcAmount>,
+}
+
+impl From<OperationFediFeeDiscount> for RpcOperationFediFeeDiscount {
+    fn from(value: OperationFediFeeDiscount) -> Self {
+        Self {
+            promotion_id: value.promotion_id,
+            discount_ppm: value.discount_ppm,
+            waived_fee: value.waived_fee.map(RpcAmount),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, TS)]
+#[serde(rename_all = "camelCase")]
+#[ts(export)]
+pub enum RpcFeeStatementPeriod {
+    Day,
+    Month,
+}
+
+/// Fees accrued and remitted by one stream between `from` and `to`.
+#[derive(Debug, Serialize, Deserialize, TS)]
+#[serde(rename_all = "camelCase")]
+#[ts(export)]
+pub struct RpcFeeStatement {
+    pub stream: RpcFediFeeStream,
+    pub period: RpcFeeStatementPeriod,
+    #[ts(type = "number")]
+    pub from: u64,
+    #[ts(type = "number")]
+    pub to: u64,
+    /// One row per period, module and direction, oldest period first.
+    pub rows: Vec<RpcFeeStatementRow>,
+    /// Every remittance within the statement, oldest first.
+    pub remittances: Vec<RpcFeeStatementRemittance>,
+    pub total_accrued: RpcAmount,
+    pub total_remitted: RpcAmount,
+    /// `rows` rendered as CSV.
+    pub csv: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, TS)]
+#[serde(rename_all = "camelCase")]
+#[ts(export)]
+pub struct RpcFeeStatementRow {
+    /// Unix timestamp of the start of the day or month, in UTC.
+    #[ts(type = "number")]
+    pub period_start: u64,
+    pub module: String,
+    pub direction: RpcTransactionDirection,
+    pub accrued: RpcAmount,
+    pub remitted: RpcAmount,
+}
+
+#[derive(Debug, Serialize, Deserialize, TS)]
+#[serde(rename_all = "camelCase")]
+#[ts(export)]
+pub struct RpcFeeStatementRemittance {
+    pub operation_id: RpcOperationId,
+    #[ts(type = "number")]
+    pub remitted_at: u64,
+    pub amount: RpcAmount,
+}
+
 #[derive(Debug, Serialize, Deserialize, TS)]
 #[serde(rename_all = "camelCase")]
 #[ts(export)]
@@ -1348,6 +1916,47 @@ pub struct RpcModuleFediFeeSchedule {
     pub send_ppm: u64,
     #[ts(type = "number")]
     pub receive_ppm: u64,
+    pub send_policy: RpcFediFeePolicy,
+    pub receive_policy: RpcFediFeePolicy,
+}
+
+/// Fixed fees, caps and volume tiers applied on top of the flat ppm.
+#[derive(Debug, Serialize, Deserialize, TS)]
+#[serde(rename_all = "camelCase")]
+#[ts(export)]
+pub struct RpcFediFeePolicy {
+    pub base: RpcAmount,
+    pub min: RpcAmount,
+    pub max: Option<RpcAmount>,
+    pub volume_tiers: Vec<RpcFediFeeVolumeTier>,
+}
+
+/// Once the rolling volume reaches `min_volume`, `ppm` replaces the flat ppm.
+#[derive(Debug, Serialize, Deserialize, TS)]
+#[serde(rename_all = "camelCase")]
+#[ts(export)]
+pub struct RpcFediFeeVolumeTier {
+    pub min_volume: RpcAmount,
+    #[ts(type = "number")]
+    pub ppm: u64,
+}
+
+impl From<FediFeePolicy> for RpcFediFeePolicy {
+    fn from(value: FediFeePolicy) -> Self {
+        Self {
+            base: RpcAmount(Amount::from_msats(value.base_msat)),
+            min: RpcAmount(Amount::from_msats(value.min_msat)),
+            max: value.max_msat.map(|max| RpcAmount(Amount::from_msats(max))),
+            volume_tiers: value
+                .volume_tiers
+                .into_iter()
+                .map(|tier| RpcFediFeeVolumeTier {
+                    min_volume: RpcAmount(Amount::from_msats(tier.min_volume_msat)),
+                    ppm: tier.ppm,
+                })
+                .collect(),
+        }
+    }
 }
 
 impl From<FediFeeSchedule> for RpcFediFeeSchedule {
@@ -1363,6 +1972,8 @@ impl From<FediFeeSchedule> for RpcFediFeeSchedule {
                         RpcModuleFediFeeSchedule {
                             send_ppm: v.send_ppm,
                             receive_ppm: v.receive_ppm,
+                            send_policy: v.send_policy.into(),
+                            receive_policy: v.receive_policy.into(),
                         },
                     )
                 })
@@ -1416,3 +2027,28 @@ pub struct RpcPrevPayInvoiceResult {
 pub struct RpcParseInviteCodeResult {
     pub federation_id: RpcFederationId,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_bolt12_offer() {
+        // test vector from BOLT12
+        let offer: RpcBolt12Offer = "lno1pg257enxv4ezqcneype82um50ynhxgrwdajx293pqglnyxw6q0hzngfdusg8umzuxe8kquuz7pjl90ldj8wadwgs0xlmc"
+            .parse()
+            .unwrap();
+        assert_eq!(offer.amount, None);
+        assert_eq!(offer.description.as_deref(), Some("Offer by rusty's node"));
+        assert_eq!(offer.issuer, None);
+
+        let offer: RpcBolt12Offer = "lightning:LNO1PQPVX5Q2QE3K7ENXV4J3YZ2XV4JXJGZRV9NX293PQGLNYXW6Q0HZNGFDUSG8UMZUXE8KQUUZ7PJL90LDJ8WADWGS0XLMC"
+            .parse()
+            .unwrap();
+        assert_eq!(offer.amount, Some(RpcAmount(Amount::from_msats(50_000))));
+        assert_eq!(offer.description.as_deref(), Some("coffee"));
+        assert_eq!(offer.issuer.as_deref(), Some("Fedi Cafe"));
+
+        assert!("lnbc1".parse::<RpcBolt12Offer>().is_err());
+    }
+}
@@ -0,0 +1,96 @@
+use serde::{Deserialize, Serialize};
+use ts_rs::TS;
+
+use crate::RpcAmount;
+
+/// What an LNURL-pay service (LUD-06) accepts, shown to the user before
+/// they pick an amount.
+#[derive(Debug, Serialize, Deserialize, Clone, TS)]
+#[serde(rename_all = "camelCase")]
+#[ts(export)]
+pub struct RpcLnurlPayParams {
+    /// Host of the service, which is who the user is actually paying.
+    pub domain: String,
+    pub min_sendable: RpcAmount,
+    pub max_sendable: RpcAmount,
+    /// `text/plain` entry of the service metadata.
+    pub description: String,
+    /// `text/long-desc` entry of the service metadata, if any.
+    pub long_description: Option<String>,
+    /// Lightning address the service claims to belong to (LUD-16), taken
+    /// from the `text/identifier` or `text/email` metadata entry.
+    pub identifier: Option<String>,
+    /// Maximum comment length the service accepts (LUD-12), 0 if comments
+    /// are not supported.
+    pub comment_allowed: u32,
+    /// Payer data the service asks for (LUD-18).
+    pub payer_data: Option<RpcLnurlPayerDataRequest>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, TS)]
+#[serde(rename_all = "camelCase")]
+#[ts(export)]
+pub struct RpcLnurlPayerDataRequest {
+    pub name: Option<RpcLnurlPayerDataField>,
+    pub identifier: Option<RpcLnurlPayerDataField>,
+    pub email: Option<RpcLnurlPayerDataField>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, TS)]
+#[serde(rename_all = "camelCase")]
+#[ts(export)]
+pub struct RpcLnurlPayerDataField {
+    pub mandatory: bool,
+}
+
+/// Payer data to send along with an LNURL payment (LUD-18). Only fields the
+/// service asked for are sent.
+#[derive(Debug, Serialize, Deserialize, Clone, Default, TS)]
+#[serde(rename_all = "camelCase")]
+#[ts(export)]
+pub struct RpcLnurlPayerData {
+    pub name: Option<String>,
+    pub identifier: Option<String>,
+    pub email: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, TS)]
+#[serde(rename_all = "camelCase")]
+#[ts(export)]
+pub struct RpcLnurlPayResponse {
+    pub invoice: String,
+    pub preimage: String,
+    pub success_action: Option<RpcLnurlSuccessAction>,
+}
+
+/// Success action (LUD-09) to show once the payment went through. AES
+/// payloads (LUD-10) are already decrypted with the payment preimage.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, TS)]
+#[serde(rename_all = "camelCase")]
+#[serde(tag = "type")]
+#[ts(export)]
+pub enum RpcLnurlSuccessAction {
+    Message {
+        message: String,
+    },
+    Url {
+        description: String,
+        url: String,
+    },
+    Aes {
+        description: String,
+        plaintext: String,
+    },
+}
+
+/// What an LNURL-withdraw service (LUD-03) offers.
+#[derive(Debug, Serialize, Deserialize, Clone, TS)]
+#[serde(rename_all = "camelCase")]
+#[ts(export)]
+pub struct RpcLnurlWithdrawParams {
+    /// Host of the service the funds are pulled from.
+    pub domain: String,
+    pub min_withdrawable: RpcAmount,
+    pub max_withdrawable: RpcAmount,
+    pub default_description: String,
+}
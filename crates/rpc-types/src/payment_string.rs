@@ -0,0 +1,89 @@
+use serde::Serialize;
+use ts_rs::TS;
+
+use crate::matrix::RpcUserId;
+use crate::sp_transfer::RpcAccountId;
+use crate::{RpcAmount, RpcBolt12Offer, RpcEcashInfo, RpcFederationId, RpcParsedInvoice};
+
+/// Result of `parsePaymentString`: what the scanned or pasted input is.
+///
+/// Variants that can be paid carry `payableBy`, the joined federations able
+/// to pay them. It is empty when none of them can.
+#[derive(Debug, Clone, Serialize, TS)]
+#[serde(
+    tag = "type",
+    rename_all = "camelCase",
+    rename_all_fields = "camelCase"
+)]
+#[ts(export)]
+pub enum RpcParsedPaymentString {
+    Bolt11 {
+        invoice: RpcParsedInvoice,
+        payable_by: Vec<RpcFederationId>,
+    },
+    /// Offers cannot be paid yet, so `payableBy` is always empty.
+    Bolt12 {
+        offer: RpcBolt12Offer,
+        payable_by: Vec<RpcFederationId>,
+    },
+    /// Whether this is a pay or withdraw link is only known once the service
+    /// has been asked, see `previewLnurlPay` and `previewLnurlWithdraw`.
+    Lnurl {
+        lnurl: String,
+        url: String,
+        payable_by: Vec<RpcFederationId>,
+    },
+    LightningAddress {
+        address: String,
+        payable_by: Vec<RpcFederationId>,
+    },
+    /// `bitcoin:` URI (BIP21/BIP321). Any of the payment methods may be
+    /// missing, `payableBy` covers all that are present.
+    Bip21 {
+        address: Option<String>,
+        amount: Option<RpcAmount>,
+        label: Option<String>,
+        message: Option<String>,
+        lightning: Option<RpcParsedInvoice>,
+        offer: Option<RpcBolt12Offer>,
+        sp: Option<RpcParsedSpv2PaymentAddress>,
+        payable_by: Vec<RpcFederationId>,
+    },
+    OnchainAddress {
+        address: String,
+        payable_by: Vec<RpcFederationId>,
+    },
+    Ecash {
+        ecash: String,
+        info: RpcEcashInfo,
+    },
+    FederationInvite {
+        invite_code: String,
+        federation_id: RpcFederationId,
+        joined: bool,
+    },
+    CommunityInvite {
+        invite_code: String,
+    },
+    Spv2PaymentAddress {
+        address: RpcParsedSpv2PaymentAddress,
+        payable_by: Vec<RpcFederationId>,
+    },
+    MatrixUser {
+        user_id: RpcUserId,
+    },
+    /// Either a room id (`!...`) or a room alias (`#...`).
+    MatrixRoom {
+        room: String,
+    },
+}
+
+#[derive(Debug, Clone, Serialize, TS)]
+#[serde(rename_all = "camelCase")]
+#[ts(export)]
+pub struct RpcParsedSpv2PaymentAddress {
+    pub address: String,
+    pub account_id: RpcAccountId,
+    /// Only set when the address names a federation we have not joined.
+    pub federation_invite: Option<String>,
+}
@@ -20,6 +20,7 @@ use fedimint_core::task::{MaybeSend, MaybeSync};
 use fedimint_core::{Amount, apply, async_trait_maybe_send};
 use lightning_invoice::Bolt11Invoice;
 use reqwest::{Client, StatusCode};
+use tracing::warn;
 
 use crate::constants::{
     FEDI_FEE_API_URL_MAINNET, FEDI_FEE_API_URL_MUTINYNET, FEDI_INVOICE_API_URL_MAINNET,
@@ -199,46 +200,42 @@ impl IFediApi for LiveFediApi {
         .await?;
 
         let remittance_threshold_msat = fee_schedule_v0.remittance_threshold_msat;
-        let mut modules = BTreeMap::new();
+        let mut modules: BTreeMap<_, ModuleFediFeeSchedule> = BTreeMap::new();
         modules.insert(
             fedimint_mint_client::KIND,
-            ModuleFediFeeSchedule {
-                send_ppm: fee_schedule_v0.modules.mint.send_ppm,
-                receive_ppm: fee_schedule_v0.modules.mint.receive_ppm,
-            },
-        );
-        modules.insert(
-            fedimint_ln_common::KIND,
-            ModuleFediFeeSchedule {
-                send_ppm: fee_schedule_v0.modules.ln.send_ppm,
-                receive_ppm: fee_schedule_v0.modules.ln.receive_ppm,
-            },
+            fee_schedule_v0.modules.mint.into(),
         );
+        modules.insert(fedimint_ln_common::KIND, fee_schedule_v0.modules.ln.into());
         modules.insert(
             fedimint_wallet_client::KIND,
-            ModuleFediFeeSchedule {
-                send_ppm: fee_schedule_v0.modules.wallet.send_ppm,
-                receive_ppm: fee_schedule_v0.modules.wallet.receive_ppm,
-            },
+            fee_schedule_v0.modules.wallet.into(),
         );
         modules.insert(
             stability_pool_client_old::common::KIND,
-            ModuleFediFeeSchedule {
-                send_ppm: fee_schedule_v0.modules.stability_pool.send_ppm,
-                receive_ppm: fee_schedule_v0.modules.stability_pool.receive_ppm,
-            },
+            fee_schedule_v0.modules.stability_pool.into(),
         );
         modules.insert(
             stability_pool_client::common::KIND,
-            ModuleFediFeeSchedule {
-                send_ppm: fee_schedule_v0.modules.multi_sig_stability_pool.send_ppm,
-                receive_ppm: fee_schedule_v0.modules.multi_sig_stability_pool.receive_ppm,
-            },
+            fee_schedule_v0.modules.multi_sig_stability_pool.into(),
         );
 
         Ok(FediFeeSchedule {
             remittance_threshold_msat,
             modules,
+            promotions: fee_schedule_v0
+                .promotions
+                .into_iter()
+                .map(Into::into)
+                .collect(),
+            // A malformed account only disables the fallback route, the
+            // schedule itself is still usable.
+            remittance_fallback_account: fee_schedule_v0.remittance_fallback_account.and_then(
+                |account| {
+                    serde_json::from_value(account)
+                        .inspect_err(|e| warn!(?e, "Invalid fee remittance fallback account"))
+                        .ok()
+                },
+            ),
         })
     }
 
@@ -1,5 +1,7 @@
 use std::sync::Arc;
 
+use anyhow::Context as _;
+
 use fedimint_connectors::ConnectorRegistry;
 use fedimint_core::db::Database;
 use fedimint_core::task::TaskGroup;
@@ -30,6 +32,8 @@ pub struct Runtime {
     pub stream_pool: RpcStreamPool,
     pub connectors: ConnectorRegistry,
     pub remote_features: RemoteFeaturesService,
+    /// Shared client for HTTP APIs outside Fedi's servers.
+    pub http_client: reqwest::Client,
 }
 
 impl Runtime {
@@ -64,6 +68,7 @@ impl Runtime {
             feature_catalog,
             stream_pool,
             remote_features,
+            http_client: reqwest::Client::new(),
         }
     }
 
@@ -87,6 +92,22 @@ impl Runtime {
         ])
     }
 
+    /// DB for the spending limits log.
+    pub fn spending_log_db(&self) -> Database {
+        self.global_db.with_prefix(vec![
+            BRIDGE_DB_PREFIX,
+            BridgeDbPrefix::SpendingLogPrefix as u8,
+        ])
+    }
+
+    /// DB for transfers between joined federations.
+    pub fn federation_transfers_db(&self) -> Database {
+        self.global_db.with_prefix(vec![
+            BRIDGE_DB_PREFIX,
+            BridgeDbPrefix::FederationTransfersPrefix as u8,
+        ])
+    }
+
     /// Enable logging of potentially sensitive information.
     pub async fn sensitive_log(&self) -> bool {
         self.app_state
@@ -102,4 +123,24 @@ impl Runtime {
             .await?;
         Ok(())
     }
+
+    /// Esplora API the user chose to follow onchain withdrawals with. Unset
+    /// by default, since the lookups reveal the user's txids to the server.
+    pub async fn esplora_url(&self) -> Option<String> {
+        self.app_state
+            .with_read_lock(|state| state.esplora_url.clone())
+            .await
+    }
+
+    pub async fn set_esplora_url(&self, esplora_url: Option<String>) -> anyhow::Result<()> {
+        if let Some(esplora_url) = &esplora_url {
+            reqwest::Url::parse(esplora_url).context("Invalid Esplora URL")?;
+        }
+        self.app_state
+            .with_write_lock(|state| {
+                state.esplora_url = esplora_url;
+            })
+            .await?;
+        Ok(())
+    }
 }
@@ -27,6 +27,11 @@ pub enum BridgeDbPrefix {
     SpTransfersPrefix = 0x05,
     // Remote features last fetched value
     RemoteFeaturesLastFetched = 0x06,
+    // Prefix for the log of outgoing payments that spending limits are
+    // checked against, shared by all federations
+    SpendingLogPrefix = 0x07,
+    // Prefix for transfers between joined federations
+    FederationTransfersPrefix = 0x08,
 }
 
 #[derive(Debug, Decodable, Encodable)]
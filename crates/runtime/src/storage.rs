@@ -17,7 +17,8 @@ use fedimint_derive_secret::DerivableSecret;
 use rand::rngs::OsRng;
 use state::{
     AppStateJson, AppStateJsonBase, AppStateJsonOnboarded, AppStateJsonOnboarding,
-    DeviceIdentifier, OnboardingMethod, OnboardingStage, default_next_federation_prefix,
+    DeviceIdentifier, OnboardingMethod, OnboardingStage, SpendingLimits,
+    default_next_federation_prefix,
 };
 use tokio::sync::RwLock;
 
@@ -516,6 +517,8 @@ impl AppStateOnboarding {
                         next_federation_db_prefix: default_next_federation_prefix(),
                         matrix_display_name: None,
                         cached_fiat_fx_info: None,
+                        spending_limits: SpendingLimits::default(),
+                        esplora_url: None,
                         last_device_registration_timestamp: None,
                     },
                 });
@@ -13,7 +13,8 @@ use std::str::FromStr;
 use std::time::SystemTime;
 
 use anyhow::{Context, anyhow, ensure};
-use api_types::invoice_generator::FirstCommunityInviteCodeState;
+use api_types::fee_schedule::{FeePromotionV0, PPMs};
+use api_types::invoice_generator::{FirstCommunityInviteCodeState, TransactionDirection};
 use fedi_social_client::SocialRecoveryState;
 use fedimint_aead::{LessSafeKey, decrypt};
 use fedimint_bip39::Bip39RootSecretStrategy;
@@ -30,6 +31,10 @@ use ts_rs::TS;
 use super::FIRST_FEDERATION_DB_PREFIX;
 use crate::constants::{DEVICE_IDENTIFIER_FIXED_LENGTH, DEVICE_REGISTRATION_CHILD_ID};
 
+pub use guardian_fee_config::{
+    FediFeePolicy, FediFeeVolumeTier, FediGuardianFeeConfig, FediGuardianFeeRecipient,
+};
+
 #[derive(Serialize, Deserialize, Clone, PartialEq)]
 #[serde(tag = "kind")]
 #[allow(clippy::large_enum_variant)]
@@ -261,6 +266,16 @@ pub struct AppStateJsonBase {
     /// info is used to attach historical fiat values to TXs as they
     /// are recorded.
     pub cached_fiat_fx_info: Option<FiatFXInfo>,
+
+    /// Limits on outgoing payments across all federations. Federations can
+    /// have their own limits on top of these, stored in their database.
+    #[serde(default)]
+    pub spending_limits: SpendingLimits,
+
+    /// Esplora API to follow onchain withdrawals until they confirm. Only set
+    /// by the user, see [`crate::bridge_runtime::Runtime::esplora_url`].
+    #[serde(default)]
+    pub esplora_url: Option<String>,
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -337,71 +352,113 @@ pub struct FediFeeSchedule {
     /// known module (identified by ModuleKind) has its own fee
     /// schedule for its transactions.
     pub modules: BTreeMap<ModuleKind, ModuleFediFeeSchedule>,
+
+    /// Time-boxed discounts on top of the module schedules.
+    #[serde(default)]
+    pub promotions: Vec<FediFeePromotion>,
+
+    /// Stable balance account that app fees are deposited to when no
+    /// Lightning gateway can remit them.
+    #[serde(default)]
+    pub remittance_fallback_account: Option<Account>,
 }
 
 #[derive(Clone, Serialize, Deserialize, PartialEq)]
-#[serde(from = "FediGuardianFeeConfigSerde")]
-pub struct FediGuardianFeeConfig {
-    /// Guardian fee charged on send-side transactions, in ppm.
+pub struct ModuleFediFeeSchedule {
+    /// Represents the fee to charge on the amount in ppm whenever a module
+    /// contributes an input to a transaction.
     pub send_ppm: u64,
-    /// Weighted destination accounts for guardian-fee remittances.
-    pub recipients: Vec<FediGuardianFeeRecipient>,
-}
-
-/// Deserialize-only adapter for app state written before weighted recipients.
-/// Runtime state and all new serialization use only `recipients`.
-#[derive(Deserialize)]
-#[serde(untagged)]
-enum FediGuardianFeeConfigSerde {
-    Current {
-        send_ppm: u64,
-        recipients: Vec<FediGuardianFeeRecipient>,
-    },
-    Legacy {
-        send_ppm: u64,
-        remittance_account: Account,
-    },
+
+    /// Represents the fee to charge on the amount in ppm whenever a module
+    /// contributes an output to a transaction.
+    pub receive_ppm: u64,
+
+    /// Base fee, caps and volume tiers applied on top of `send_ppm`.
+    #[serde(default)]
+    pub send_policy: FediFeePolicy,
+
+    /// Base fee, caps and volume tiers applied on top of `receive_ppm`.
+    #[serde(default)]
+    pub receive_policy: FediFeePolicy,
 }
 
-impl From<FediGuardianFeeConfigSerde> for FediGuardianFeeConfig {
-    fn from(config: FediGuardianFeeConfigSerde) -> Self {
-        match config {
-            FediGuardianFeeConfigSerde::Current {
-                send_ppm,
-                recipients,
-            } => Self {
-                send_ppm,
-                recipients,
-            },
-            FediGuardianFeeConfigSerde::Legacy {
-                send_ppm,
-                remittance_account,
-            } => Self {
-                send_ppm,
-                recipients: vec![FediGuardianFeeRecipient {
-                    account: remittance_account,
-                    weight: 1,
-                }],
-            },
+impl ModuleFediFeeSchedule {
+    /// Schedule charging a flat ppm in each direction.
+    pub fn flat(send_ppm: u64, receive_ppm: u64) -> Self {
+        Self {
+            send_ppm,
+            receive_ppm,
+            send_policy: FediFeePolicy::default(),
+            receive_policy: FediFeePolicy::default(),
         }
     }
 }
 
+impl From<PPMs> for ModuleFediFeeSchedule {
+    fn from(ppms: PPMs) -> Self {
+        Self {
+            send_ppm: ppms.send_ppm,
+            receive_ppm: ppms.receive_ppm,
+            send_policy: ppms.send_policy.map(Into::into).unwrap_or_default(),
+            receive_policy: ppms.receive_policy.map(Into::into).unwrap_or_default(),
+        }
+    }
+}
+
+/// Waives a share of the fee of matching transactions between `starts_at`
+/// and `ends_at`.
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
-pub struct FediGuardianFeeRecipient {
-    pub account: Account,
-    pub weight: u64,
+pub struct FediFeePromotion {
+    /// Identifies the promotion on the transactions it discounted.
+    pub id: String,
+
+    /// Unix timestamp in seconds, inclusive.
+    pub starts_at: u64,
+
+    /// Unix timestamp in seconds, exclusive.
+    pub ends_at: u64,
+
+    /// Share of the fee waived, MILLION waives the whole fee.
+    pub discount_ppm: u64,
+
+    #[serde(default)]
+    pub scope: FediFeePromotionScope,
 }
 
-#[derive(Clone, Serialize, Deserialize, PartialEq)]
-pub struct ModuleFediFeeSchedule {
-    /// Represents the fee to charge on the amount in ppm whenever a module
-    /// contributes an input to a transaction.
-    pub send_ppm: u64,
+/// Transactions a promotion applies to. Unset fields match every transaction.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct FediFeePromotionScope {
+    #[serde(default)]
+    pub federation_id: Option<String>,
 
-    /// Represents the fee to charge on the amount in ppm whenever a module
-    /// contributes an output to a transaction.
-    pub receive_ppm: u64,
+    #[serde(default)]
+    pub module: Option<ModuleKind>,
+
+    #[serde(default)]
+    pub direction: Option<TransactionDirection>,
+
+    /// Hex sha256 of the invite code of a community the user has joined.
+    #[serde(default)]
+    pub community_invite_code_hash: Option<String>,
+}
+
+impl From<FeePromotionV0> for FediFeePromotion {
+    fn from(promotion: FeePromotionV0) -> Self {
+        Self {
+            id: promotion.id,
+            starts_at: promotion.starts_at,
+            ends_at: promotion.ends_at,
+            discount_ppm: promotion.discount_ppm,
+            scope: FediFeePromotionScope {
+                federation_id: promotion.federation_id,
+                module: promotion
+                    .module
+                    .map(|module| ModuleKind::clone_from_str(&module)),
+                direction: promotion.direction,
+                community_invite_code_hash: promotion.community_invite_code_hash,
+            },
+        }
+    }
 }
 
 #[derive(Clone, Serialize, Deserialize, PartialEq)]
@@ -460,6 +517,41 @@ pub struct FiatFXInfo {
     pub btc_to_fiat_hundredths: u64,
 }
 
+/// Limits on outgoing payments, each one optional. The daily and weekly
+/// limits apply to a rolling window ending now, not to calendar days.
+#[derive(
+    Debug, Clone, Default, Serialize, Deserialize, TS, Encodable, Decodable, PartialEq, Eq,
+)]
+#[serde(rename_all = "camelCase")]
+#[ts(export)]
+pub struct SpendingLimits {
+    pub per_transaction: Option<SpendingLimit>,
+    pub daily: Option<SpendingLimit>,
+    pub weekly: Option<SpendingLimit>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, TS, Encodable, Decodable, PartialEq, Eq)]
+#[serde(
+    tag = "type",
+    rename_all = "camelCase",
+    rename_all_fields = "camelCase"
+)]
+#[ts(export)]
+pub enum SpendingLimit {
+    Msats {
+        #[ts(type = "MSats")]
+        msats: u64,
+    },
+    /// Converted at the cached display currency rate, so it can only be
+    /// enforced while `fiatCode` is the display currency.
+    Fiat {
+        fiat_code: String,
+        /// Amount in hundredths, such as cents.
+        #[ts(type = "number")]
+        hundredths: u64,
+    },
+}
+
 impl AppStateJson {
     pub(crate) fn from_v0(
         value: AppStateJsonV0,
@@ -606,42 +698,29 @@ impl Default for FediFeeSchedule {
         let default_send_ppm = 2100; // 21 BPS
         modules.insert(
             fedimint_mint_client::KIND,
-            ModuleFediFeeSchedule {
-                send_ppm: default_send_ppm,
-                receive_ppm: 0,
-            },
+            ModuleFediFeeSchedule::flat(default_send_ppm, 0),
         );
         modules.insert(
             fedimint_ln_common::KIND,
-            ModuleFediFeeSchedule {
-                send_ppm: default_send_ppm,
-                receive_ppm: 0,
-            },
+            ModuleFediFeeSchedule::flat(default_send_ppm, 0),
         );
         modules.insert(
             fedimint_wallet_client::KIND,
-            ModuleFediFeeSchedule {
-                send_ppm: 0,
-                receive_ppm: 0,
-            },
+            ModuleFediFeeSchedule::flat(0, 0),
         );
         modules.insert(
             stability_pool_client_old::common::KIND,
-            ModuleFediFeeSchedule {
-                send_ppm: default_send_ppm,
-                receive_ppm: 0,
-            },
+            ModuleFediFeeSchedule::flat(default_send_ppm, 0),
         );
         modules.insert(
             stability_pool_client::common::KIND,
-            ModuleFediFeeSchedule {
-                send_ppm: default_send_ppm,
-                receive_ppm: 0,
-            },
+            ModuleFediFeeSchedule::flat(default_send_ppm, 0),
         );
         Self {
             remittance_threshold_msat: 100_000,
             modules,
+            promotions: vec![],
+            remittance_fallback_account: None,
         }
     }
 }
@@ -673,6 +752,7 @@ mod tests {
 
         let config = federation.guardian_fee_config.as_ref().unwrap();
         assert_eq!(config.send_ppm, 250);
+        assert_eq!(config.send_policy, FediFeePolicy::default());
         assert_eq!(
             config.recipients,
             vec![FediGuardianFeeRecipient {
@@ -686,4 +766,53 @@ mod tests {
         assert!(config.get("remittance_account").is_none());
         assert_eq!(config["recipients"].as_array().unwrap().len(), 1);
     }
+
+    #[test]
+    fn flat_module_fee_schedule_loads_with_default_policies() {
+        let schedule: ModuleFediFeeSchedule = serde_json::from_value(serde_json::json!({
+            "send_ppm": 2100,
+            "receive_ppm": 0,
+        }))
+        .unwrap();
+        assert!(schedule == ModuleFediFeeSchedule::flat(2100, 0));
+
+        let schedule: ModuleFediFeeSchedule = serde_json::from_value(serde_json::json!({
+            "send_ppm": 2100,
+            "receive_ppm": 0,
+            "send_policy": {
+                "base_msat": 1000,
+                "volume_tiers": [{"min_volume_msat": 1_000_000_000, "ppm": 1000}],
+            },
+        }))
+        .unwrap();
+        assert_eq!(schedule.send_policy.base_msat, 1000);
+        assert_eq!(schedule.send_policy.max_msat, None);
+        assert_eq!(schedule.send_policy.volume_tiers[0].ppm, 1000);
+        assert_eq!(schedule.receive_policy, FediFeePolicy::default());
+    }
+
+    #[test]
+    fn fee_schedule_remittance_fallback_account_is_optional() {
+        let mut stored = serde_json::to_value(FediFeeSchedule::default()).unwrap();
+        stored
+            .as_object_mut()
+            .unwrap()
+            .remove("remittance_fallback_account");
+        let schedule: FediFeeSchedule = serde_json::from_value(stored).unwrap();
+        assert!(schedule.remittance_fallback_account.is_none());
+
+        let secp = Secp256k1::new();
+        let secret_key = SecretKey::from_slice(&[9; 32]).unwrap();
+        let account = Account::single(
+            PublicKey::from_secret_key(&secp, &secret_key),
+            AccountType::BtcDepositor,
+        );
+        let schedule = FediFeeSchedule {
+            remittance_fallback_account: Some(account.clone()),
+            ..FediFeeSchedule::default()
+        };
+        let schedule: FediFeeSchedule =
+            serde_json::from_value(serde_json::to_value(schedule).unwrap()).unwrap();
+        assert_eq!(schedule.remittance_fallback_account, Some(account));
+    }
 }
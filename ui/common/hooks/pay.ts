@@ -171,9 +171,13 @@ export function useOmniPaymentState(
                     const decoded = await fedimint.parseInvoice(
                         input.data.invoice,
                     )
-                    if (decoded.amount) {
-                        setInputAmount(amountUtils.msatToSat(decoded.amount))
+                    if (decoded.type !== 'fixed') {
+                        setError(
+                            t('feature.parser.unsupported-bolt11-zero-amount'),
+                        )
+                        return
                     }
+                    setInputAmount(amountUtils.msatToSat(decoded.amount))
                     setInvoice(decoded)
                     const fee = await fedimint.estimateLnFees(
                         input.data.invoice,
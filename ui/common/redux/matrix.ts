@@ -1407,7 +1407,7 @@ export const sendMatrixMessage = createAsyncThunk<
                     )
                     // make sure to do this only if we have an amount
                     // TODO: support amount-less invoices
-                    if (decoded.amount) {
+                    if (decoded.type === 'fixed' && decoded.amount) {
                         const sats = amountUtils.msatToSat(decoded.amount)
                         const paymentId = uuidv4()
                         // bolt11 there's no operation ID - cannot get historical value
@@ -30,6 +30,7 @@ describe('useLightningInvoiceAmount hook', () => {
             federationId,
         )
         const invoice = await fedimint.parseInvoice(invoiceString)
+        if (invoice.type !== 'fixed') throw new Error('expected an amount')
         const invoiceSats = amountUtils.msatToSat(invoice.amount)
 
         const { result } = renderHookWithBridge(
@@ -39,6 +39,7 @@ describe('useMinMaxSendAmount hook', () => {
                 federationId,
             )
             const invoice = await fedimint.parseInvoice(invoiceStr)
+            if (invoice.type !== 'fixed') throw new Error('expected an amount')
             const { result } = renderHookWithBridge(
                 () =>
                     useMinMaxSendAmount({
@@ -73,6 +74,7 @@ describe('useMinMaxSendAmount hook', () => {
                 federationId,
             )
             const invoice = await fedimint.parseInvoice(invoiceStr)
+            if (invoice.type !== 'fixed') throw new Error('expected an amount')
 
             const { result } = renderHookWithBridge(
                 () =>
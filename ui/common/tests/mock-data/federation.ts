@@ -25,6 +25,8 @@ export const mockFederation1 = {
         remittanceThresholdMsat: 10000,
     },
     hadReusedEcash: false,
+    endOfLife: null,
+    recoveryProgress: null,
 } as const satisfies Federation
 
 export const mockFederationWithSPV1: LoadedFederation = {
@@ -61,6 +63,8 @@ export const mockFederation2: Federation = {
         remittanceThresholdMsat: 10000,
     },
     hadReusedEcash: false,
+    endOfLife: null,
+    recoveryProgress: null,
 } as const satisfies Federation
 
 export const mockFederationWithSPV2: LoadedFederation = {
@@ -11,6 +11,7 @@ const MOCK_TRANSACTION: RpcTransaction = {
     amount: 1000000 as MSats,
     fediAppFeeStatus: null,
     fediGuardianFeeStatus: null,
+    fediAppFeeDiscount: null,
     txnNotes: 'test',
     txDateFiatInfo: null,
     frontendMetadata: {
@@ -19,6 +20,8 @@ const MOCK_TRANSACTION: RpcTransaction = {
         senderMatrixId: null,
     },
     outcomeTime: Date.now(),
+    unifiedReceive: null,
+    federationTransfer: null,
     kind: 'lnReceive' as const,
     ln_invoice: 'lnbc123',
     state: { type: 'claimed' } as RpcLnReceiveState,
@@ -9,6 +9,7 @@ const gateway: RpcLightningGateway = {
     nodePubKey: '02'.padEnd(66, '0'),
     gatewayId: '03'.padEnd(66, '0'),
     api: 'https://gateway.example',
+    score: null,
 }
 
 describe('listGateways', () => {
@@ -31,6 +31,8 @@ const baseFed: LoadedFederation = {
         modules: {},
     },
     hadReusedEcash: false,
+    endOfLife: null,
+    recoveryProgress: null,
     status: 'online',
     init_state: 'ready',
 }
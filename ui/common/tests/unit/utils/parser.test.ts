@@ -369,10 +369,10 @@ describe('parseUserInput', () => {
     const fedimint = {
         parseInvoice: async (invoice: string) => {
             if (invoice === simpleBolt11) {
-                return simpleBolt11Invoice
+                return { type: 'fixed', ...simpleBolt11Invoice }
             }
             if (invoice === complexBolt11) {
-                return complexBolt11Invoice
+                return { type: 'fixed', ...complexBolt11Invoice }
             }
         },
         parseEcash: async (ecash: string) => {
@@ -61,6 +61,7 @@ export function makeTestTxnEntry<T extends TransactionListEntry['kind']>(
         amount: 0 as MSats,
         fediAppFeeStatus: null,
         fediGuardianFeeStatus: null,
+        fediAppFeeDiscount: null,
         txnNotes: null,
         txDateFiatInfo: null,
         frontendMetadata: {
@@ -69,6 +70,8 @@ export function makeTestTxnEntry<T extends TransactionListEntry['kind']>(
             senderMatrixId: null,
         },
         outcomeTime: null,
+        unifiedReceive: null,
+        federationTransfer: null,
     }
 
     switch (kind) {
@@ -253,7 +256,7 @@ export function makeTestOnchainWithdrawState(
         case 'created':
             return { type }
         case 'succeeded':
-            return { type, txid: TEST_TXID }
+            return { type, txid: TEST_TXID, confirmations: null }
         case 'failed':
             return { type, error: TEST_ERROR }
     }
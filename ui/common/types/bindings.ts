@@ -105,7 +105,14 @@ export type ErrorCode =
   | "offlineExactEcashFailed"
   | "communityDeleted"
   | "pinnedMessageLimitExceeded"
-  | "matrixReactionLimitExceeded";
+  | "matrixReactionLimitExceeded"
+  | "invalidLnurl"
+  | { lnurlServiceError: string }
+  | "invoiceAmountRequired"
+  | "spendingLimitExceeded"
+  | "spendingLimitsLocked"
+  | "invalidEcash"
+  | "networkFeeExceedsBudget";
 
 export type Event =
   | { transaction: TransactionEvent }
@@ -126,7 +133,11 @@ export type Event =
     }
   | { communityMetadataUpdated: CommunityMetadataUpdatedEvent }
   | { nonceReuseCheckFailed: NonceReuseCheckFailedEvent }
-  | { communityMigratedToV2: CommunityMigratedToV2Event };
+  | { communityMigratedToV2: CommunityMigratedToV2Event }
+  | { federationEvacuation: FederationEvacuationEvent }
+  | { federationEndOfLife: FederationEndOfLifeEvent }
+  | { guardianHealth: GuardianHealthEvent }
+  | { walletRepairNeeded: WalletRepairNeededEvent };
 
 /**
  * We represent the catalog of all the features for a given runtime as a
@@ -240,6 +251,38 @@ export type FeatureCatalog = {
   update_screen: UpdateScreenConfig | null;
 };
 
+/**
+ * Sent once per warning level as the announced end of a federation
+ * approaches.
+ */
+export type FederationEndOfLifeEvent = {
+  federationId: RpcFederationId;
+  warning: FederationEndOfLifeWarning;
+  endOfLife: RpcFederationEndOfLife;
+};
+
+export type FederationEndOfLifeWarning =
+  | "upcoming"
+  | "imminent"
+  | "final"
+  | "ended";
+
+/**
+ * Progress of `evacuateFederation`, reported once per step.
+ */
+export type FederationEvacuationEvent = {
+  federationId: RpcFederationId;
+  state: FederationEvacuationState;
+};
+
+export type FederationEvacuationState =
+  | { type: "withdrawingStableBalance" }
+  | { type: "waitingForPendingOperations"; remaining: number }
+  | { type: "sweeping"; amount: RpcAmount }
+  | { type: "leaving" }
+  | { type: "left" }
+  | { type: "failed"; error: string };
+
 export type FediFeeConfig = {
   /**
    * How long (max) are we willing to wait before requesting a 0-amount
@@ -283,6 +326,11 @@ export type FrontendMetadata = {
   initialNotes: string | null;
   recipientMatrixId: string | null;
   senderMatrixId: string | null;
+  /**
+   * Where the funds of a receive came from, set by the bridge when it
+   * pulls funds in on the user's behalf.
+   */
+  receiveSource?: RpcReceiveSource;
 };
 
 export type GlobalCommunityFeatureConfig = { invite_code: string };
@@ -305,6 +353,18 @@ export type GroupInvitationWithKeys = {
   federationId: RpcFederationId;
 };
 
+/**
+ * Sent when the guardians online in a federation fall toward or below the
+ * consensus threshold, and again once they recover.
+ */
+export type GuardianHealthEvent = {
+  federationId: RpcFederationId;
+  level: RpcGuardianHealthLevel;
+  online: number;
+  threshold: number;
+  total: number;
+};
+
 export type GuardianStatus =
   | { online: { guardian: string; latency_ms: number } }
   | { error: { guardian: string; error: string } }
@@ -522,6 +582,24 @@ export type RpcBitcoinNetwork =
   | "regtest"
   | "unknown";
 
+/**
+ * What can be learned from a BOLT12 offer without contacting the offering
+ * node.
+ */
+export type RpcBolt12Offer = {
+  offer: string;
+  /**
+   * Absent for offers that let the payer choose the amount.
+   */
+  amount: RpcAmount | null;
+  description: string | null;
+  issuer: string | null;
+  /**
+   * Unix timestamp in seconds.
+   */
+  expiresAt: number | null;
+};
+
 export type RpcBridgeStatus =
   | { type: "onboarded"; onboarding_method: OnboardingMethod | null }
   | { type: "onboarding"; stage: RpcOnboardingStage }
@@ -581,6 +659,13 @@ export type RpcError = {
   errorCode: ErrorCode | null;
 };
 
+/**
+ * Where `evacuateFederation` sends the balance of the federation it leaves.
+ */
+export type RpcEvacuationTarget =
+  | { type: "federation"; federationId: RpcFederationId }
+  | { type: "onchain"; address: string };
+
 export type RpcEventId = string;
 
 export type RpcFederation = {
@@ -595,6 +680,28 @@ export type RpcFederation = {
   clientConfig: RpcJsonClientConfig | null;
   fediFeeSchedule: RpcFediFeeSchedule;
   hadReusedEcash: boolean;
+  /**
+   * Set when the federation announced in its meta that it shuts down.
+   */
+  endOfLife: RpcFederationEndOfLife | null;
+  /**
+   * Set while `recovering`.
+   */
+  recoveryProgress: RpcRecoveryProgress | null;
+};
+
+export type RpcFederationEndOfLife = {
+  /**
+   * Unix time in seconds.
+   */
+  endsAt: number;
+  successorInviteCode: string | null;
+  withdrawalInstructions: string | null;
+  /**
+   * Whether the user opted into evacuating into the successor once the
+   * final warning is sent, see `setFederationAutoEvacuate`.
+   */
+  autoEvacuate: boolean;
 };
 
 export type RpcFederationId = string;
@@ -612,6 +719,50 @@ export type RpcFederationPreview = {
   returningMemberStatus: RpcReturningMemberStatus;
 };
 
+/**
+ * A lightning payment from one joined federation to another, see
+ * `transferBetweenFederations`.
+ */
+export type RpcFederationTransfer = {
+  transferId: string;
+  fromFederationId: RpcFederationId;
+  toFederationId: RpcFederationId;
+  amount: RpcAmount;
+  /**
+   * Fees the source federation was expected to charge when the transfer
+   * started.
+   */
+  estimatedFees: RpcAmount;
+  state: RpcFederationTransferState;
+};
+
+export type RpcFederationTransferLeg = {
+  /**
+   * Shared by both legs of the same transfer.
+   */
+  transferId: string;
+  /**
+   * The federation on the other side of the transfer.
+   */
+  counterpartyFederationId: RpcFederationId;
+};
+
+export type RpcFederationTransferState =
+  | { type: "paying" }
+  | { type: "paid" }
+  | { type: "completed" }
+  | { type: "failed"; error: string };
+
+/**
+ * Fixed fees, caps and volume tiers applied on top of the flat ppm.
+ */
+export type RpcFediFeePolicy = {
+  base: RpcAmount;
+  min: RpcAmount;
+  max: RpcAmount | null;
+  volumeTiers: Array<RpcFediFeeVolumeTier>;
+};
+
 export type RpcFediFeeSchedule = {
   remittanceThresholdMsat: number;
   modules: { [key in string]?: RpcModuleFediFeeSchedule };
@@ -619,6 +770,11 @@ export type RpcFediFeeSchedule = {
 
 export type RpcFediFeeStream = "app" | "guardian";
 
+/**
+ * Once the rolling volume reaches `min_volume`, `ppm` replaces the flat ppm.
+ */
+export type RpcFediFeeVolumeTier = { minVolume: RpcAmount; ppm: number };
+
 export type RpcFeeDetails = {
   fediAppFee: RpcAmount;
   fediGuardianFee: RpcAmount;
@@ -626,6 +782,49 @@ export type RpcFeeDetails = {
   federationFee: RpcAmount;
 };
 
+/**
+ * Fees accrued and remitted by one stream between `from` and `to`.
+ */
+export type RpcFeeStatement = {
+  stream: RpcFediFeeStream;
+  period: RpcFeeStatementPeriod;
+  from: number;
+  to: number;
+  /**
+   * One row per period, module and direction, oldest period first.
+   */
+  rows: Array<RpcFeeStatementRow>;
+  /**
+   * Every remittance within the statement, oldest first.
+   */
+  remittances: Array<RpcFeeStatementRemittance>;
+  totalAccrued: RpcAmount;
+  totalRemitted: RpcAmount;
+  /**
+   * `rows` rendered as CSV.
+   */
+  csv: string;
+};
+
+export type RpcFeeStatementPeriod = "day" | "month";
+
+export type RpcFeeStatementRemittance = {
+  operationId: RpcOperationId;
+  remittedAt: number;
+  amount: RpcAmount;
+};
+
+export type RpcFeeStatementRow = {
+  /**
+   * Unix timestamp of the start of the day or month, in UTC.
+   */
+  periodStart: number;
+  module: string;
+  direction: RpcTransactionDirection;
+  accrued: RpcAmount;
+  remitted: RpcAmount;
+};
+
 export type RpcFiatAmount = number;
 
 export type RpcFiatAndBtcAmount = { fiat: RpcFiatAmount; btc: RpcAmount };
@@ -680,11 +879,78 @@ export type RpcFormType = "text" | "radio" | "button";
 
 export type RpcFormattedBody = { format: string; formattedBody: string };
 
+/**
+ * Payments this client sent through a gateway. Gateways are preferred by
+ * `score_ppm`, then by their fee.
+ */
+export type RpcGatewayScore = {
+  /**
+   * Estimated chance in ppm that a payment through the gateway succeeds.
+   */
+  scorePpm: number;
+  attempts: number;
+  successes: number;
+  /**
+   * The gateway was unreachable or could not route the payment.
+   */
+  failures: number;
+  /**
+   * Network fees of successful payments.
+   */
+  feesPaid: RpcAmount;
+  /**
+   * Of successful payments, `null` before the first one.
+   */
+  averageLatencyMs: number | null;
+};
+
 export type RpcGenerateEcashResponse = {
   ecash: string;
   operationId: RpcOperationId;
 };
 
+export type RpcGuardianHealth = {
+  guardian: string;
+  /**
+   * `null` if the guardian did not answer.
+   */
+  latencyMs: number | null;
+};
+
+export type RpcGuardianHealthCheck = {
+  /**
+   * Unix time in seconds.
+   */
+  checkedAt: number;
+  guardians: Array<RpcGuardianHealth>;
+};
+
+/**
+ * Guardian reachability as recorded by the federation's health monitor, see
+ * `getGuardianHealthHistory`.
+ */
+export type RpcGuardianHealthHistory = {
+  total: number;
+  /**
+   * Guardians that must be online for the federation to reach consensus.
+   */
+  threshold: number;
+  /**
+   * Level of the latest check, `null` before the first one.
+   */
+  level: RpcGuardianHealthLevel | null;
+  /**
+   * Oldest first.
+   */
+  checks: Array<RpcGuardianHealthCheck>;
+};
+
+export type RpcGuardianHealthLevel =
+  | "healthy"
+  | "degraded"
+  | "atRisk"
+  | "belowThreshold";
+
 export type RpcGuardianRemittanceAccountInfo = { serializedAccount: string };
 
 export type RpcGuardianRemittanceDashboard = {
@@ -744,6 +1010,11 @@ export type RpcLightningGateway = {
   nodePubKey: RpcPublicKey;
   gatewayId: RpcPublicKey;
   api: string;
+  /**
+   * How payments through the gateway went, `null` for lnv2 gateways which
+   * are not scored.
+   */
+  score: RpcGatewayScore | null;
 };
 
 export type RpcLightningGatewayId =
@@ -768,6 +1039,87 @@ export type RpcLnReceiveState =
   | { type: "awaitingFunds" }
   | { type: "claimed" };
 
+/**
+ * What an LNURL-pay service (LUD-06) accepts, shown to the user before
+ * they pick an amount.
+ */
+export type RpcLnurlPayParams = {
+  /**
+   * Host of the service, which is who the user is actually paying.
+   */
+  domain: string;
+  minSendable: RpcAmount;
+  maxSendable: RpcAmount;
+  /**
+   * `text/plain` entry of the service metadata.
+   */
+  description: string;
+  /**
+   * `text/long-desc` entry of the service metadata, if any.
+   */
+  longDescription: string | null;
+  /**
+   * Lightning address the service claims to belong to (LUD-16), taken
+   * from the `text/identifier` or `text/email` metadata entry.
+   */
+  identifier: string | null;
+  /**
+   * Maximum comment length the service accepts (LUD-12), 0 if comments
+   * are not supported.
+   */
+  commentAllowed: number;
+  /**
+   * Payer data the service asks for (LUD-18).
+   */
+  payerData: RpcLnurlPayerDataRequest | null;
+};
+
+export type RpcLnurlPayResponse = {
+  invoice: string;
+  preimage: string;
+  successAction: RpcLnurlSuccessAction | null;
+};
+
+/**
+ * Payer data to send along with an LNURL payment (LUD-18). Only fields the
+ * service asked for are sent.
+ */
+export type RpcLnurlPayerData = {
+  name: string | null;
+  identifier: string | null;
+  email: string | null;
+};
+
+export type RpcLnurlPayerDataField = { mandatory: boolean };
+
+export type RpcLnurlPayerDataRequest = {
+  name: RpcLnurlPayerDataField | null;
+  identifier: RpcLnurlPayerDataField | null;
+  email: RpcLnurlPayerDataField | null;
+};
+
+/**
+ * Success action (LUD-09) to show once the payment went through. AES
+ * payloads (LUD-10) are already decrypted with the payment preimage.
+ */
+export type RpcLnurlSuccessAction =
+  | { type: "message"; message: string }
+  | { type: "url"; description: string; url: string }
+  | { type: "aes"; description: string; plaintext: string };
+
+/**
+ * What an LNURL-withdraw service (LUD-03) offers.
+ */
+export type RpcLnurlWithdrawParams = {
+  /**
+   * Host of the service the funds are pulled from.
+   */
+  domain: string;
+  minWithdrawable: RpcAmount;
+  maxWithdrawable: RpcAmount;
+  defaultDescription: string;
+};
+
 export type RpcLockedSeek = {
   currCycleBeginningLockedAmount: RpcAmount;
   initialAmount: RpcAmount;
@@ -854,14 +1206,23 @@ export type RpcMethods = {
   joinFederation: [joinFederation, RpcFederation];
   federationPreview: [federationPreview, RpcFederationPreview];
   leaveFederation: [leaveFederation, null];
+  evacuateFederation: [evacuateFederation, null];
+  setFederationAutoEvacuate: [setFederationAutoEvacuate, null];
   listFederations: [listFederations, Array<RpcFederationMaybeLoading>];
   getGuardianStatus: [getGuardianStatus, Array<GuardianStatus>];
+  getGuardianHealthHistory: [
+    getGuardianHealthHistory,
+    RpcGuardianHealthHistory,
+  ];
+  getRecoveryProgress: [getRecoveryProgress, RpcRecoveryProgress | null];
   listFederationsPendingRejoinFromScratch: [
     listFederationsPendingRejoinFromScratch,
     Array<string>,
   ];
   generateInvoice: [generateInvoice, string];
-  parseInvoice: [parseInvoice, RpcInvoice];
+  generateUnifiedReceive: [generateUnifiedReceive, RpcUnifiedReceive];
+  parseInvoice: [parseInvoice, RpcParsedInvoice];
+  parseOffer: [parseOffer, RpcBolt12Offer];
   estimateLnFees: [estimateLnFees, RpcFeeDetails];
   payInvoice: [payInvoice, RpcPayInvoiceResponse];
   getPrevPayInvoiceResult: [getPrevPayInvoiceResult, RpcPrevPayInvoiceResult];
@@ -873,6 +1234,10 @@ export type RpcMethods = {
   getPegInFees: [getPegInFees, RpcAmount];
   recheckPeginAddress: [recheckPeginAddress, null];
   previewPayAddress: [previewPayAddress, RpcFeeDetails];
+  previewPayAddressFeeTiers: [
+    previewPayAddressFeeTiers,
+    Array<RpcOnchainFeeQuote>,
+  ];
   payAddress: [payAddress, RpcOperationId];
   calculateMaxGenerateEcash: [calculateMaxGenerateEcash, RpcAmount];
   estimateEcashFees: [estimateEcashFees, RpcFeeDetails];
@@ -880,16 +1245,34 @@ export type RpcMethods = {
   receiveEcash: [receiveEcash, [RpcAmount, RpcOperationId]];
   parseEcash: [parseEcash, RpcEcashInfo];
   parseInviteCode: [parseInviteCode, RpcParseInviteCodeResult];
+  parsePaymentString: [parsePaymentString, RpcParsedPaymentString];
   cancelEcash: [cancelEcash, null];
   repairWallet: [repairWallet, null];
+  runWalletRepair: [runWalletRepair, RpcWalletRepairReport];
   reclaimLnReceive: [reclaimLnReceive, RpcReclaimLnReceiveOutcome];
   updateCachedFiatFXInfo: [updateCachedFiatFXInfo, null];
   listTransactions: [
     listTransactions,
     Array<{ Ok: RpcTransactionListEntry } | { Err: string }>,
   ];
+  searchTransactions: [
+    searchTransactions,
+    Array<{ Ok: RpcTransactionListEntry } | { Err: string }>,
+  ];
   getTransaction: [getTransaction, RpcTransaction];
   updateTransactionNotes: [updateTransactionNotes, null];
+  exportTransactionHistory: [
+    exportTransactionHistory,
+    RpcTransactionHistoryExport,
+  ];
+  getSpendingLimits: [getSpendingLimits, SpendingLimits];
+  unlockSpendingLimits: [unlockSpendingLimits, null];
+  setSpendingLimits: [setSpendingLimits, null];
+  transferBetweenFederations: [
+    transferBetweenFederations,
+    RpcFederationTransfer,
+  ];
+  getFederationTransfer: [getFederationTransfer, RpcFederationTransfer];
   backupNow: [backupNow, null];
   getMnemonic: [getMnemonic, Array<string>];
   checkMnemonic: [checkMnemonic, boolean];
@@ -912,6 +1295,10 @@ export type RpcMethods = {
   setGuardianPassword: [setGuardianPassword, null];
   getGuardianPassword: [getGuardianPassword, string];
   signLnurlMessage: [signLnurlMessage, RpcSignedLnurlMessage];
+  previewLnurlPay: [previewLnurlPay, RpcLnurlPayParams];
+  payLnurl: [payLnurl, RpcLnurlPayResponse];
+  previewLnurlWithdraw: [previewLnurlWithdraw, RpcLnurlWithdrawParams];
+  claimLnurlWithdraw: [claimLnurlWithdraw, string];
   supportsRecurringdLnurl: [supportsRecurringdLnurl, boolean];
   getRecurringdLnurl: [getRecurringdLnurl, string];
   getNostrPubkey: [getNostrPubkey, RpcNostrPubkey];
@@ -966,6 +1353,8 @@ export type RpcMethods = {
   spv2StartFastSync: [spv2StartFastSync, null];
   getSensitiveLog: [getSensitiveLog, boolean];
   setSensitiveLog: [setSensitiveLog, null];
+  getEsploraUrl: [getEsploraUrl, string | null];
+  setEsploraUrl: [setEsploraUrl, null];
   internalMarkBridgeExport: [internalMarkBridgeExport, null];
   internalExportBridgeState: [internalExportBridgeState, null];
   setMintModuleFediFeeSchedule: [setMintModuleFediFeeSchedule, null];
@@ -984,6 +1373,7 @@ export type RpcMethods = {
     getAccruedPendingFediFeesPerTXTypeByStream,
     Array<[string, RpcTransactionDirection, RpcAmount]>,
   ];
+  getFeeStatement: [getFeeStatement, RpcFeeStatement];
   dumpDb: [dumpDb, string];
   fetchRegisteredDevices: [fetchRegisteredDevices, Array<RpcRegisteredDevice>];
   onboardRegisterAsNewDevice: [onboardRegisterAsNewDevice, null];
@@ -1125,7 +1515,25 @@ export type RpcMethods = {
   evilSpamAddress: [evilSpamAddress, null];
 };
 
-export type RpcModuleFediFeeSchedule = { sendPpm: number; receivePpm: number };
+export type RpcModuleFediFeeSchedule = {
+  sendPpm: number;
+  receivePpm: number;
+  sendPolicy: RpcFediFeePolicy;
+  receivePolicy: RpcFediFeePolicy;
+};
+
+export type RpcModuleRecoveryProgress = {
+  moduleInstanceId: number;
+  kind: string;
+  /**
+   * Sessions processed.
+   */
+  complete: number;
+  /**
+   * Sessions to process, `0` if the module has not started yet.
+   */
+  total: number;
+};
 
 export type RpcMsgLikeKind =
   | ({ msgtype: "m.text" } & RpcTextLikeContent)
@@ -1166,6 +1574,7 @@ export type RpcNostrPubkey = { hex: string; npub: string };
 export type RpcNostrSecret = { hex: string; nsec: string };
 
 export type RpcOOBReissueState =
+  | { type: "offline" }
   | { type: "created" }
   | { type: "issuing" }
   | { type: "done" }
@@ -1195,11 +1604,41 @@ export type RpcOnchainDepositState =
 
 export type RpcOnchainDepositTransactionData = { txid: string };
 
+export type RpcOnchainFeeQuote = {
+  tier: RpcOnchainFeeTier;
+  feeDetails: RpcFeeDetails;
+  /**
+   * Rough estimate, the actual time depends on the mempool. `None` without
+   * an Esplora API to estimate it with, or when the wallet module doesn't
+   * report a fee rate.
+   */
+  estimatedConfirmationMins: number | null;
+};
+
+export type RpcOnchainFeeTier =
+  /**
+   * Pays the federation's own fee estimate, the least it accepts.
+   */
+  "economy" | "normal" | "priority";
+
 export type RpcOnchainWithdrawState =
   | { type: "created" }
-  | { type: "succeeded"; txid: string }
+  | {
+      type: "succeeded";
+      txid: string;
+      /**
+       * `null` until the transaction was first looked up on chain.
+       */
+      confirmations: number | null;
+    }
   | { type: "failed"; error: string };
 
+export type RpcOperationFediFeeDiscount = {
+  promotionId: string;
+  discountPpm: number;
+  waivedFee: RpcAmount | null;
+};
+
 export type RpcOperationFediFeeStatus =
   | { type: "pendingSend"; fedi_fee: RpcAmount }
   | { type: "pendingReceive"; fedi_fee_ppm: number }
@@ -1211,6 +1650,89 @@ export type RpcOperationId = string;
 
 export type RpcParseInviteCodeResult = { federationId: RpcFederationId };
 
+/**
+ * Result of `parseInvoice`. Invoices without an amount leave it to the
+ * payer and cannot be paid yet, nor can BOLT12 offers.
+ */
+export type RpcParsedInvoice =
+  | ({ type: "fixed" } & RpcInvoice)
+  | {
+      type: "amountRequired";
+      paymentHash: string;
+      description: string;
+      invoice: string;
+    }
+  | ({ type: "offer" } & RpcBolt12Offer);
+
+/**
+ * Result of `parsePaymentString`: what the scanned or pasted input is.
+ *
+ * Variants that can be paid carry `payableBy`, the joined federations able
+ * to pay them. It is empty when none of them can.
+ */
+export type RpcParsedPaymentString =
+  | {
+      type: "bolt11";
+      invoice: RpcParsedInvoice;
+      payableBy: Array<RpcFederationId>;
+    }
+  | {
+      type: "bolt12";
+      offer: RpcBolt12Offer;
+      payableBy: Array<RpcFederationId>;
+    }
+  | {
+      type: "lnurl";
+      lnurl: string;
+      url: string;
+      payableBy: Array<RpcFederationId>;
+    }
+  | {
+      type: "lightningAddress";
+      address: string;
+      payableBy: Array<RpcFederationId>;
+    }
+  | {
+      type: "bip21";
+      address: string | null;
+      amount: RpcAmount | null;
+      label: string | null;
+      message: string | null;
+      lightning: RpcParsedInvoice | null;
+      offer: RpcBolt12Offer | null;
+      sp: RpcParsedSpv2PaymentAddress | null;
+      payableBy: Array<RpcFederationId>;
+    }
+  | {
+      type: "onchainAddress";
+      address: string;
+      payableBy: Array<RpcFederationId>;
+    }
+  | { type: "ecash"; ecash: string; info: RpcEcashInfo }
+  | {
+      type: "federationInvite";
+      inviteCode: string;
+      federationId: RpcFederationId;
+      joined: boolean;
+    }
+  | { type: "communityInvite"; inviteCode: string }
+  | {
+      type: "spv2PaymentAddress";
+      address: RpcParsedSpv2PaymentAddress;
+      payableBy: Array<RpcFederationId>;
+    }
+  | { type: "matrixUser"; userId: RpcUserId }
+  | { type: "matrixRoom"; room: string };
+
+export type RpcParsedSpv2PaymentAddress = {
+  address: string;
+  accountId: RpcAccountId;
+  /**
+   * Only set when the address names a federation we have not joined.
+   */
+  federationInvite: string | null;
+};
+
 export type RpcPayAddressResponse = { txid: string };
 
 export type RpcPayInvoiceResponse = { preimage: string };
@@ -1266,6 +1788,8 @@ export type RpcPublicRoomInfo = {
 
 export type RpcPusher = JSONObject;
 
+export type RpcReceiveSource = { type: "lnurlWithdraw"; domain: string };
+
 /**
  * Outcome of a manual [`reclaim_ln_receive`] break-glass attempt.
  *
@@ -1280,6 +1804,26 @@ export type RpcReclaimLnReceiveOutcome =
 
 export type RpcRecoveryId = string;
 
+/**
+ * How far restoring a federation's wallet got, see `getRecoveryProgress`.
+ */
+export type RpcRecoveryProgress = {
+  /**
+   * Whether the wallet is recovered from scratch after failing the nonce
+   * reuse check, see `listFederationsPendingRejoinFromScratch`.
+   */
+  fromScratch: boolean;
+  /**
+   * Modules that reported progress so far.
+   */
+  modules: Array<RpcModuleRecoveryProgress>;
+  /**
+   * Estimated seconds until every module has recovered, `null` until
+   * enough sessions were processed to tell.
+   */
+  etaSecs: number | null;
+};
+
 export type RpcRegisteredDevice = {
   deviceIndex: number;
   deviceIdentifier: string;
@@ -1594,6 +2138,10 @@ export type RpcTransaction = {
   amount: RpcAmount;
   fediAppFeeStatus: RpcOperationFediFeeStatus | null;
   fediGuardianFeeStatus: RpcOperationFediFeeStatus | null;
+  /**
+   * Set when a fee promotion discounted the app fee.
+   */
+  fediAppFeeDiscount: RpcOperationFediFeeDiscount | null;
   txnNotes: string | null;
   txDateFiatInfo: FiatFXInfo | null;
   frontendMetadata: FrontendMetadata;
@@ -1601,6 +2149,14 @@ export type RpcTransaction = {
    * time when this operation was settled.
    */
   outcomeTime: number | null;
+  /**
+   * Set when this transaction is one leg of a `generateUnifiedReceive`.
+   */
+  unifiedReceive: RpcUnifiedReceiveLeg | null;
+  /**
+   * Set when this transaction is one leg of a `transferBetweenFederations`.
+   */
+  federationTransfer: RpcFederationTransferLeg | null;
 } & (
   | {
       kind: "lnPay";
@@ -1651,6 +2207,23 @@ export type RpcTransaction = {
 
 export type RpcTransactionDirection = "receive" | "send";
 
+export type RpcTransactionHistoryExport = {
+  format: RpcTransactionHistoryFormat;
+  content: string;
+  exportedCount: number;
+  /**
+   * Federations that could not be exported because they are still
+   * loading, recovering or failed to load.
+   */
+  skippedFederations: Array<RpcFederationId>;
+  /**
+   * Transactions that failed to render and are missing from `content`.
+   */
+  failedCount: number;
+};
+
+export type RpcTransactionHistoryFormat = "csv" | "jsonLines" | "bip329";
+
 export type RpcTransactionId = string;
 
 export type RpcTransactionKind =
@@ -1700,12 +2273,35 @@ export type RpcTransactionKind =
   | { kind: "sPV2TransferOut"; state: RpcSPV2TransferOutState }
   | { kind: "sPV2TransferIn"; state: RpcSPV2TransferInState };
 
+/**
+ * The `kind` tag of [`RpcTransactionKind`] without its payload.
+ */
+export type RpcTransactionKindTag =
+  | "lnPay"
+  | "lnReceive"
+  | "lnRecurringdReceive"
+  | "onchainWithdraw"
+  | "onchainDeposit"
+  | "oobSend"
+  | "oobReceive"
+  | "oobCancel"
+  | "spDeposit"
+  | "spWithdraw"
+  | "sPV2Deposit"
+  | "sPV2Withdrawal"
+  | "sPV2TransferOut"
+  | "sPV2TransferIn";
+
 export type RpcTransactionListEntry = {
   createdAt: number;
   id: string;
   amount: RpcAmount;
   fediAppFeeStatus: RpcOperationFediFeeStatus | null;
   fediGuardianFeeStatus: RpcOperationFediFeeStatus | null;
+  /**
+   * Set when a fee promotion discounted the app fee.
+   */
+  fediAppFeeDiscount: RpcOperationFediFeeDiscount | null;
   txnNotes: string | null;
   txDateFiatInfo: FiatFXInfo | null;
   frontendMetadata: FrontendMetadata;
@@ -1713,6 +2309,14 @@ export type RpcTransactionListEntry = {
    * time when this operation was settled.
    */
   outcomeTime: number | null;
+  /**
+   * Set when this transaction is one leg of a `generateUnifiedReceive`.
+   */
+  unifiedReceive: RpcUnifiedReceiveLeg | null;
+  /**
+   * Set when this transaction is one leg of a `transferBetweenFederations`.
+   */
+  federationTransfer: RpcFederationTransferLeg | null;
 } & (
   | {
       kind: "lnPay";
@@ -1761,8 +2365,68 @@ export type RpcTransactionListEntry = {
   | { kind: "sPV2TransferIn"; state: RpcSPV2TransferInState }
 );
 
+export type RpcTransactionSearchFilter = {
+  /**
+   * Only match these kinds. Empty or missing matches every kind.
+   */
+  kinds: Array<RpcTransactionKindTag> | null;
+  direction: RpcTransactionDirection | null;
+  /**
+   * Inclusive lower bound on the transaction amount.
+   */
+  minAmount: RpcAmount | null;
+  /**
+   * Inclusive upper bound on the transaction amount.
+   */
+  maxAmount: RpcAmount | null;
+  /**
+   * Unix time (seconds), inclusive.
+   */
+  startTime: number | null;
+  /**
+   * Unix time (seconds), exclusive.
+   */
+  endTime: number | null;
+  /**
+   * Matches either the sender or the recipient matrix id.
+   */
+  matrixUserId: string | null;
+  /**
+   * Case-insensitive substring of the transaction notes.
+   */
+  notes: string | null;
+};
+
 export type RpcTransferRequestId = string;
 
+export type RpcUnifiedReceive = {
+  receiveId: string;
+  /**
+   * BIP321 `bitcoin:` URI carrying every leg.
+   */
+  uri: string;
+  /**
+   * Same as `uri`, with the bech32 parts uppercased so the QR code can
+   * use the denser alphanumeric mode.
+   */
+  qrPayload: string;
+  invoice: string;
+  onchainAddress: string;
+  spv2PaymentAddress: string | null;
+};
+
+export type RpcUnifiedReceiveLeg = {
+  /**
+   * Shared by all legs of the same unified receive.
+   */
+  receiveId: string;
+  /**
+   * Another leg was paid first, so this one is not expected to be paid
+   * anymore.
+   */
+  superseded: boolean;
+};
+
 export type RpcUserId = string;
 
 export type RpcUserPowerLevel =
@@ -1789,6 +2453,26 @@ export type RpcVideoMessageContent = {
   source: RpcMediaSource;
 };
 
+/**
+ * Outcome of `runWalletRepair`.
+ */
+export type RpcWalletRepairReport = {
+  /**
+   * Ecash stuck in outputs with reused nonces. Proofs for it were backed up
+   * and are attached to bug reports, so support can refund it.
+   */
+  reusedEcash: RpcAmount;
+  balanceBefore: RpcAmount;
+  /**
+   * Balance the repair added, from ecash it found unspent.
+   */
+  recovered: RpcAmount;
+  /**
+   * Balance the repair found to be already spent.
+   */
+  lost: RpcAmount;
+};
+
 export type SPv2DepositEvent = {
   federationId: RpcFederationId;
   operationId: RpcOperationId;
@@ -1874,6 +2558,23 @@ export type SpV2TransferOutKind =
   | "spTransferUi"
   | "unknown";
 
+export type SpendingLimit =
+  | { type: "msats"; msats: MSats }
+  | {
+      type: "fiat";
+      fiatCode: string;
+      /**
+       * Amount in hundredths, such as cents.
+       */
+      hundredths: number;
+    };
+
+export type SpendingLimits = {
+  perTransaction: SpendingLimit | null;
+  daily: SpendingLimit | null;
+  weekly: SpendingLimit | null;
+};
+
 export type StabilityPoolDepositEvent = {
   federationId: RpcFederationId;
   operationId: RpcOperationId;
@@ -2005,6 +2706,19 @@ export type VectorDiff<T> =
       };
     };
 
+/**
+ * Sent once when a wallet is found to have reused ecash nonces, until it is
+ * repaired with `runWalletRepair`.
+ */
+export type WalletRepairNeededEvent = {
+  federationId: RpcFederationId;
+  /**
+   * Ecash stuck in outputs with reused nonces. Zero if only the nonces the
+   * wallet would use next are already spent.
+   */
+  reusedEcash: RpcAmount;
+};
+
 /**
  * Withdrawal request with extra data accumulated over events.
  */
@@ -2047,6 +2761,13 @@ export type cancelSocialRecovery = {};
 
 export type checkMnemonic = { mnemonic: Array<string> };
 
+export type claimLnurlWithdraw = {
+  federationId: RpcFederationId;
+  lnurl: string;
+  amount: RpcAmount | null;
+  frontendMetadata: FrontendMetadata;
+};
+
 export type communityPreview = { inviteCode: string };
 
 export type completeOnboardingNewSeed = {};
@@ -2075,10 +2796,17 @@ export type estimateStabilityPoolDepositFees = {
   amount: RpcAmount;
 };
 
+export type evacuateFederation = {
+  federationId: RpcFederationId;
+  target: RpcEvacuationTarget;
+};
+
 export type evilSpamAddress = { federationId: RpcFederationId };
 
 export type evilSpamInvoices = { federationId: RpcFederationId };
 
+export type exportTransactionHistory = { format: RpcTransactionHistoryFormat };
+
 export type federationPreview = { inviteCode: string };
 
 export type fedimintVersion = {};
@@ -2094,6 +2822,7 @@ export type generateEcash = {
   federationId: RpcFederationId;
   amount: RpcAmount;
   includeInvite: boolean;
+  expiry: number | null;
   frontendMetadata: FrontendMetadata;
 };
 
@@ -2107,6 +2836,14 @@ export type generateInvoice = {
 
 export type generateReusedEcashProofs = { federationId: RpcFederationId };
 
+export type generateUnifiedReceive = {
+  federationId: RpcFederationId;
+  amount: RpcAmount;
+  description: string;
+  includeStableBalanceAddress: boolean;
+  frontendMetadata: FrontendMetadata;
+};
+
 export type getAccruedOutstandingFediFeesPerTXTypeByStream = {
   federationId: RpcFederationId;
   stream: RpcFediFeeStream;
@@ -2119,8 +2856,20 @@ export type getAccruedPendingFediFeesPerTXTypeByStream = {
 
 export type getFeatureCatalog = {};
 
+export type getFederationTransfer = { transferId: string };
+
+export type getFeeStatement = {
+  federationId: RpcFederationId;
+  stream: RpcFediFeeStream;
+  from: number;
+  to: number;
+  period: RpcFeeStatementPeriod;
+};
+
 export type getGatewayOverride = { federationId: RpcFederationId };
 
+export type getGuardianHealthHistory = { federationId: RpcFederationId };
+
 export type getGuardianPassword = {
   federationId: RpcFederationId;
   peerId: RpcPeerId;
@@ -2141,10 +2890,16 @@ export type getPrevPayInvoiceResult = {
   invoice: string;
 };
 
+export type getRecoveryProgress = { federationId: RpcFederationId };
+
 export type getRecurringdLnurl = { federationId: RpcFederationId };
 
+export type getEsploraUrl = {};
+
 export type getSensitiveLog = {};
 
+export type getSpendingLimits = { federationId: RpcFederationId | null };
+
 export type getTransaction = {
   federationId: RpcFederationId;
   operationId: RpcOperationId;
@@ -2497,23 +3252,49 @@ export type parseInviteCode = { inviteCode: string };
 
 export type parseInvoice = { invoice: string };
 
+export type parseOffer = { offer: string };
+
+export type parsePaymentString = { paymentString: string };
+
 export type payAddress = {
   federationId: RpcFederationId;
   address: string;
   sats: bigint;
+  feeTier: RpcOnchainFeeTier | null;
   frontendMetadata: FrontendMetadata;
 };
 
 export type payInvoice = {
   federationId: RpcFederationId;
   invoice: string;
+  maxNetworkFee: RpcAmount | null;
+  frontendMetadata: FrontendMetadata;
+};
+
+export type payLnurl = {
+  federationId: RpcFederationId;
+  lnurl: string;
+  amount: RpcAmount;
+  comment: string | null;
+  payerData: RpcLnurlPayerData | null;
   frontendMetadata: FrontendMetadata;
 };
 
+export type previewLnurlPay = { lnurl: string };
+
+export type previewLnurlWithdraw = { lnurl: string };
+
 export type previewPayAddress = {
   federationId: RpcFederationId;
   address: string;
   sats: bigint;
+  feeTier: RpcOnchainFeeTier | null;
+};
+
+export type previewPayAddressFeeTiers = {
+  federationId: RpcFederationId;
+  address: string;
+  sats: bigint;
 };
 
 export type receiveEcash = {
@@ -2540,6 +3321,20 @@ export type resetUnrecognizedSeed = {};
 
 export type restoreMnemonic = { mnemonic: Array<string> };
 
+export type runWalletRepair = { federationId: RpcFederationId };
+
+export type searchTransactions = {
+  federationId: RpcFederationId;
+  filter: RpcTransactionSearchFilter;
+  startAfter: RpcOperationId | null;
+  limit: number | null;
+};
+
+export type setFederationAutoEvacuate = {
+  federationId: RpcFederationId;
+  enabled: boolean;
+};
+
 export type setGatewayOverride = {
   federationId: RpcFederationId;
   gatewayId: RpcLightningGatewayId | null;
@@ -2569,8 +3364,15 @@ export type setSPv2ModuleFediFeeSchedule = {
   receivePpm: bigint;
 };
 
+export type setEsploraUrl = { esploraUrl: string | null };
+
 export type setSensitiveLog = { enable: boolean };
 
+export type setSpendingLimits = {
+  federationId: RpcFederationId | null;
+  limits: SpendingLimits;
+};
+
 export type setStabilityPoolModuleFediFeeSchedule = {
   federationId: RpcFederationId;
   sendPpm: bigint;
@@ -2694,6 +3496,14 @@ export type supportsRecurringdLnurl = { federationId: RpcFederationId };
 
 export type supportsSafeOnchainDeposit = { federationId: RpcFederationId };
 
+export type transferBetweenFederations = {
+  fromFederationId: RpcFederationId;
+  toFederationId: RpcFederationId;
+  amount: RpcAmount;
+};
+
+export type unlockSpendingLimits = {};
+
 export type updateCachedFiatFXInfo = {
   fiatCode: string;
   btcToFiatHundredths: bigint;
@@ -27,6 +27,10 @@ import {
     MultispendListedEvent,
     RpcStreamUpdate,
     CommunityMigratedToV2Event,
+    FederationEvacuationEvent,
+    FederationEndOfLifeEvent,
+    GuardianHealthEvent,
+    WalletRepairNeededEvent,
 } from './bindings'
 import { MultispendDepositEvent, MultispendWithdrawalEvent } from './matrix'
 import { MSats, Usd, UsdCents } from './units'
@@ -53,6 +57,7 @@ export type CommonTxnFields = Pick<
     | 'txDateFiatInfo'
     | 'fediAppFeeStatus'
     | 'fediGuardianFeeStatus'
+    | 'fediAppFeeDiscount'
     | 'frontendMetadata'
     | 'outcomeTime'
 >
@@ -366,6 +371,10 @@ export type FedimintBridgeEventMap = {
     communityMetadataUpdated: CommunityMetadataUpdatedEvent
     nonceReuseCheckFailed: NonceReuseCheckFailedEvent
     communityMigratedToV2: CommunityMigratedToV2Event
+    federationEvacuation: FederationEvacuationEvent
+    federationEndOfLife: FederationEndOfLifeEvent
+    guardianHealth: GuardianHealthEvent
+    walletRepairNeeded: WalletRepairNeededEvent
 }
 
 export type StabilityPoolTxn = {
@@ -18,6 +18,8 @@ import {
     RpcFeeDetails,
     RpcMediaSource,
     RpcMentions,
+    RpcOnchainFeeQuote,
+    RpcOnchainFeeTier,
     RpcOperationId,
     RpcPayAddressResponse,
     RpcRoomId,
@@ -397,6 +399,10 @@ export class FedimintBridge {
         return this.rpcTyped('repairWallet', { federationId })
     }
 
+    async runWalletRepair(federationId: string) {
+        return this.rpcTyped('runWalletRepair', { federationId })
+    }
+
     async reclaimLnReceive(args: bindings.RpcPayload<'reclaimLnReceive'>) {
         return this.rpcTyped('reclaimLnReceive', args)
     }
@@ -445,10 +451,16 @@ export class FedimintBridge {
         return this.rpcTyped('estimateEcashFees', { amount, federationId })
     }
 
-    async payInvoice(invoice: string, federationId: string, notes?: string) {
+    async payInvoice(
+        invoice: string,
+        federationId: string,
+        notes?: string,
+        maxNetworkFee?: MSats,
+    ) {
         return this.rpcTyped('payInvoice', {
             invoice,
             federationId,
+            maxNetworkFee: maxNetworkFee ?? null,
             frontendMetadata: {
                 initialNotes: notes || null,
                 recipientMatrixId: null,
@@ -486,12 +498,31 @@ export class FedimintBridge {
         })
     }
 
-    async previewPayAddress(address: string, sats: Sats, federationId: string) {
+    async previewPayAddress(
+        address: string,
+        sats: Sats,
+        federationId: string,
+        feeTier: RpcOnchainFeeTier | null = null,
+    ) {
         // FIXME: sats must be bigint to use this.rpcTyped
         return this.rpc<RpcFeeDetails>('previewPayAddress', {
             address,
             sats,
             federationId,
+            feeTier,
+        })
+    }
+
+    async previewPayAddressFeeTiers(
+        address: string,
+        sats: Sats,
+        federationId: string,
+    ) {
+        // FIXME: sats must be bigint to use this.rpcTyped
+        return this.rpc<RpcOnchainFeeQuote[]>('previewPayAddressFeeTiers', {
+            address,
+            sats,
+            federationId,
         })
     }
 
@@ -506,12 +537,14 @@ export class FedimintBridge {
         sats: Sats,
         federationId: string,
         notes?: string,
+        feeTier: RpcOnchainFeeTier | null = null,
     ) {
         // FIXME: sats must be bigint to use this.rpcTyped
         return this.rpc<RpcPayAddressResponse>('payAddress', {
             address,
             sats,
             federationId,
+            feeTier,
             frontendMetadata: {
                 initialNotes: notes || null,
                 recipientMatrixId: null,
@@ -533,11 +566,13 @@ export class FedimintBridge {
             recipientMatrixId: null,
             senderMatrixId: null,
         },
+        expiry: number | null = null,
     ) {
         return this.rpcTyped('generateEcash', {
             federationId,
             amount,
             includeInvite,
+            expiry,
             frontendMetadata,
         })
     }
@@ -767,6 +802,14 @@ export class FedimintBridge {
         return this.rpcTyped('setSensitiveLog', { enable })
     }
 
+    async getEsploraUrl() {
+        return this.rpcTyped('getEsploraUrl', {})
+    }
+
+    async setEsploraUrl(esploraUrl: string | null) {
+        return this.rpcTyped('setEsploraUrl', { esploraUrl })
+    }
+
     async internalMarkBridgeExport() {
         return this.rpcTyped('internalMarkBridgeExport', {})
     }
@@ -1193,6 +1236,10 @@ export class FedimintBridge {
         return this.rpcTyped('getAccruedPendingFediFeesPerTXTypeByStream', args)
     }
 
+    async getFeeStatement(args: bindings.RpcPayload<'getFeeStatement'>) {
+        return this.rpcTyped('getFeeStatement', args)
+    }
+
     async matrixGetMediaPreview(
         args: bindings.RpcPayload<'matrixGetMediaPreview'>,
     ) {
@@ -1007,6 +1007,7 @@ export const coerceMultispendTxn = (
         amount: 0 as MSats,
         fediAppFeeStatus: null,
         fediGuardianFeeStatus: null,
+        fediAppFeeDiscount: null,
         txnNotes: '',
         txDateFiatInfo: null,
         frontendMetadata: {
@@ -1015,6 +1016,8 @@ export const coerceMultispendTxn = (
             senderMatrixId: null,
         },
         outcomeTime: null,
+        unifiedReceive: null,
+        federationTransfer: null,
     }
     if (isMultispendDepositEvent(event)) {
         return {
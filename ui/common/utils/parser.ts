@@ -476,14 +476,14 @@ export async function parseBolt11(
     try {
         const decoded = await fedimint.parseInvoice(lnRaw)
 
-        return {
-            type: ParserDataType.Bolt11,
-            data: decoded,
-        }
-    } catch (err) {
-        // Attempt to parse error messages for meaningful failures
-        if (err instanceof Error) {
-            if (err.message.includes('Invoice missing amount')) {
+        switch (decoded.type) {
+            case 'fixed':
+                return {
+                    type: ParserDataType.Bolt11,
+                    data: decoded,
+                }
+            case 'amountRequired':
+                // The send flows can only pay invoices with an amount
                 return {
                     type: ParserDataType.Unknown,
                     data: {
@@ -492,9 +492,12 @@ export async function parseBolt11(
                         ),
                     },
                 }
-            }
+            case 'offer':
+                // Left to parseBolt12
+                return
         }
-        // Otherwise, return nothing and let other parsers try
+    } catch (err) {
+        // Return nothing and let other parsers try
         log.warn('parseBolt11 error', err)
     }
 }
@@ -562,12 +565,14 @@ async function parseBip21(
             // TODO: allow parsing with no federation ID
             if (!federationId) return
             const invoice = await fedimint.parseInvoice(bolt11)
-            return {
-                type: ParserDataType.Bolt11,
-                data: {
-                    fallbackAddress: btcAddress,
-                    ...invoice,
-                },
+            if (invoice.type === 'fixed') {
+                return {
+                    type: ParserDataType.Bolt11,
+                    data: {
+                        fallbackAddress: btcAddress,
+                        ...invoice,
+                    },
+                }
             }
         } catch (err) {
             /* no-op, return bip21 as-is */
@@ -602,6 +602,8 @@ export const makeTxnStatusText = (
             }
         case 'oobReceive':
             switch (txn.state.type) {
+                case 'offline':
+                    return t('words.offline')
                 case 'created':
                 case 'issuing':
                     return t('words.pending')
@@ -796,7 +798,7 @@ export const makeTxnStatusBadge = (
                 case 'failed':
                     return 'failed'
                 default:
-                    txn.state.type satisfies 'created' | 'issuing'
+                    txn.state.type satisfies 'offline' | 'created' | 'issuing'
                     return 'pending'
             }
         case 'oobCancel':
@@ -1429,7 +1431,11 @@ export function shouldShowAskFedi(txn: TransactionListEntry): boolean {
                 case 'done':
                     return false
                 default:
-                    txn.state.type satisfies 'created' | 'issuing' | 'failed'
+                    txn.state.type satisfies
+                        | 'offline'
+                        | 'created'
+                        | 'issuing'
+                        | 'failed'
                     return true
             }
         case 'oobCancel':
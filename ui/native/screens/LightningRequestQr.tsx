@@ -58,7 +58,7 @@ const LightningRequestQr: React.FC<Props> = ({ route, navigation }: Props) => {
     useEffect(() => {
         fedimint
             .parseInvoice(invoice)
-            .then(setDecoded)
+            .then(parsed => parsed.type === 'fixed' && setDecoded(parsed))
             .finally(() => setIsDecodingInvoice(false))
     }, [invoice, fedimint])
 
@@ -84,6 +84,8 @@ function storeWithJoinedFederation() {
                         modules: {},
                     },
                     hadReusedEcash: false,
+                    endOfLife: null,
+                    recoveryProgress: null,
                 },
             ],
             communities: [],
@@ -224,6 +224,10 @@ export function useIFrameListener(
                         const invoice = await fedimint.parseInvoice(
                             payload as string,
                         )
+                        if (invoice.type !== 'fixed') {
+                            sendError(event, 'SendPayment error')
+                            break
+                        }
                         dispatch(setInvoiceToPay(invoice))
                         setOverlayId(InjectionMessageType.webln_sendPayment)
                     } catch {
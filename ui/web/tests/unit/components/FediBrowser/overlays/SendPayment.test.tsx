@@ -19,6 +19,7 @@ const mockLightningGateways: RpcLightningGateway[] = [
         nodePubKey: 'nodePubKey',
         gatewayId: 'gatewayId',
         api: 'https://gateway.com',
+        score: null,
     },
 ]
 
@@ -16,6 +16,7 @@ jest.mock('@fedi/common/hooks/fedimint', () => ({
     useFedimint: () => ({
         generateInvoice: mockGenerateInvoice,
         parseInvoice: () => ({
+            type: 'fixed',
             paymentHash: 'hash',
             amount: 100000,
             description: 'desc',